@@ -0,0 +1,92 @@
+//! Spawning entities from xml files together with changes to their components.
+
+use eyre::{eyre, Context};
+
+use crate::{Component, EntityID};
+
+type Override = Box<dyn FnOnce(EntityID) -> eyre::Result<()>>;
+
+/// Loads an entity file and applies overrides to it in one go.
+/// If any override fails, the entity is killed, so that half set up entities don't stay in the world.
+///
+/// ```ignore
+/// let rat = EntityBuilder::load("data/entities/animals/rat.xml")
+///     .at(x, y)
+///     .with::<DamageModelComponent>(|damage| damage.set_hp(10.0))
+///     .tag("boss")
+///     .spawn()?;
+/// ```
+pub struct EntityBuilder {
+    filename: String,
+    pos: (f64, f64),
+    overrides: Vec<Override>,
+}
+
+impl EntityBuilder {
+    pub fn load(filename: impl Into<String>) -> Self {
+        Self {
+            filename: filename.into(),
+            pos: (0.0, 0.0),
+            overrides: Vec::new(),
+        }
+    }
+
+    pub fn at(mut self, x: f64, y: f64) -> Self {
+        self.pos = (x, y);
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        let tag = tag.into();
+        self.overrides.push(Box::new(move |entity| entity.add_tag(tag)));
+        self
+    }
+
+    /// Changes the first component of type `C`. Spawning fails if the entity doesn't have one.
+    pub fn with<C: Component + 'static>(
+        mut self,
+        f: impl FnOnce(C) -> eyre::Result<()> + 'static,
+    ) -> Self {
+        self.overrides.push(Box::new(move |entity| {
+            let component = entity
+                .try_get_first_component_including_disabled::<C>(None)?
+                .ok_or_else(|| eyre!("Entity has no component {}", C::NAME_STR))?;
+            f(component)
+        }));
+        self
+    }
+
+    /// Changes every component of type `C`.
+    pub fn with_all<C: Component + 'static>(
+        mut self,
+        mut f: impl FnMut(C) -> eyre::Result<()> + 'static,
+    ) -> Self {
+        self.overrides.push(Box::new(move |entity| {
+            entity
+                .iter_all_components_of_type_including_disabled::<C>(None)?
+                .try_for_each(&mut f)
+        }));
+        self
+    }
+
+    /// Adds a new component of type `C` and sets it up with `f`.
+    pub fn add_component<C: Component + 'static>(
+        mut self,
+        f: impl FnOnce(C) -> eyre::Result<()> + 'static,
+    ) -> Self {
+        self.overrides
+            .push(Box::new(move |entity| f(entity.add_component::<C>()?)));
+        self
+    }
+
+    pub fn spawn(self) -> eyre::Result<EntityID> {
+        let entity = EntityID::load(&self.filename, Some(self.pos.0), Some(self.pos.1))?;
+        for apply in self.overrides {
+            if let Err(err) = apply(entity) {
+                entity.kill();
+                return Err(err).wrap_err_with(|| eyre!("Failed to set up {}", self.filename));
+            }
+        }
+        Ok(entity)
+    }
+}
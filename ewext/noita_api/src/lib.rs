@@ -4,15 +4,20 @@ use std::{
 };
 
 use eyre::{eyre, Context, OptionExt};
+use lua::{LuaGetValue, LuaPutValue};
 use shared::{GameEffectData, GameEffectEnum};
 
+pub mod builder;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod fake_world;
 pub mod lua;
+pub mod query;
 pub mod serialize;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct EntityID(pub NonZero<isize>);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ComponentID(pub NonZero<isize>);
 
 pub struct Obj(pub usize);
@@ -28,6 +33,29 @@ pub trait Component: From<ComponentID> + Into<ComponentID> {
 
 noita_api_macro::generate_components!();
 
+/// Config object nested in a component, e.g. `gun_config` of `AbilityComponent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentObject {
+    component: ComponentID,
+    object: &'static str,
+}
+
+impl ComponentObject {
+    pub fn new(component: ComponentID, object: &'static str) -> Self {
+        Self { component, object }
+    }
+
+    /// Corresponds to ComponentObjectGetValue2 from lua api.
+    pub fn get<T: LuaGetValue>(self, field: &str) -> eyre::Result<T> {
+        raw::component_object_get_value(self.component, self.object, field)
+    }
+
+    /// Corresponds to ComponentObjectSetValue2 from lua api.
+    pub fn set<T: LuaPutValue>(self, field: &str, value: T) -> eyre::Result<()> {
+        raw::component_object_set_value(self.component, self.object, field, value)
+    }
+}
+
 impl EntityID {
     /// Returns true if entity is alive.
     ///
@@ -115,6 +143,18 @@ impl EntityID {
             .filter_map(|x| x.map(C::from)))
     }
 
+    pub fn iter_all_components_of_type_including_disabled<C: Component>(
+        self,
+        tag: Option<Cow<'_, str>>,
+    ) -> eyre::Result<impl Iterator<Item = C>> {
+        Ok(
+            raw::entity_get_component_including_disabled(self, C::NAME_STR.into(), tag)?
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|x| x.map(C::from)),
+        )
+    }
+
     pub fn add_component<C: Component>(self) -> eyre::Result<C> {
         raw::entity_add_component::<C>(self)?.ok_or_eyre("Couldn't create a component")
     }
@@ -271,6 +311,46 @@ pub mod raw {
         Ok(())
     }
 
+    pub(crate) fn component_object_get_value<T>(
+        component: ComponentID,
+        object: &str,
+        field: &str,
+    ) -> eyre::Result<T>
+    where
+        T: LuaGetValue,
+    {
+        let lua = LuaState::current()?;
+        lua.get_global(c"ComponentObjectGetValue2");
+        lua.push_integer(component.0.into());
+        lua.push_string(object);
+        lua.push_string(field);
+        lua.call(3, T::size_on_stack())
+            .wrap_err("Failed to call ComponentObjectGetValue2")?;
+        let ret = T::get(lua, -1);
+        lua.pop_last_n(T::size_on_stack());
+        ret.wrap_err_with(|| eyre!("Getting {object}.{field} for {component:?}"))
+    }
+
+    pub(crate) fn component_object_set_value<T>(
+        component: ComponentID,
+        object: &str,
+        field: &str,
+        value: T,
+    ) -> eyre::Result<()>
+    where
+        T: LuaPutValue,
+    {
+        let lua = LuaState::current()?;
+        lua.get_global(c"ComponentObjectSetValue2");
+        lua.push_integer(component.0.into());
+        lua.push_string(object);
+        lua.push_string(field);
+        value.put(lua);
+        lua.call((3 + T::SIZE_ON_STACK).try_into()?, 0)
+            .wrap_err("Failed to call ComponentObjectSetValue2")?;
+        Ok(())
+    }
+
     pub fn physics_body_id_get_transform(
         body: PhysicsBodyID,
     ) -> eyre::Result<Option<(f32, f32, f32, f32, f32, f32)>> {
@@ -296,14 +376,21 @@ pub mod raw {
     }
 
     pub fn entity_add_component<C: Component>(entity: EntityID) -> eyre::Result<Option<C>> {
+        Ok(entity_add_component_by_name(entity, C::NAME_STR)?.map(C::from))
+    }
+
+    pub(crate) fn entity_add_component_by_name(
+        entity: EntityID,
+        component_type: &str,
+    ) -> eyre::Result<Option<ComponentID>> {
         let lua = LuaState::current()?;
         lua.get_global(c"EntityAddComponent");
         lua.push_integer(entity.raw());
-        lua.push_string(C::NAME_STR);
+        lua.push_string(component_type);
         lua.call(2, 1)
             .wrap_err("Failed to call EntityAddComponent")?;
         let c = lua.to_integer(-1);
         lua.pop_last_n(1);
-        Ok(NonZero::new(c).map(ComponentID).map(C::from))
+        Ok(NonZero::new(c).map(ComponentID))
     }
 }
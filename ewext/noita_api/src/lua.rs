@@ -1,3 +1,5 @@
+#[cfg(any(test, feature = "test-utils"))]
+pub mod fake;
 pub mod lua_bindings;
 
 use std::{
@@ -10,6 +12,8 @@ use std::{
 };
 
 use eyre::{bail, Context, OptionExt};
+#[cfg(any(test, feature = "test-utils"))]
+use fake::{LuaBackend, LuaValue};
 use lua_bindings::{lua_CFunction, lua_State, Lua51, LUA_GLOBALSINDEX};
 
 use crate::{Color, ComponentID, EntityID, GameEffectEnum, Obj, PhysicsBodyID};
@@ -23,14 +27,44 @@ pub static LUA: LazyLock<Lua51> = LazyLock::new(|| unsafe {
     Lua51::from_library(lib).expect("library to be lua")
 });
 
+/// Returns `$fake` from the current function if this is a fake lua state.
+/// Expands to nothing unless the `test-utils` feature is enabled.
+macro_rules! if_fake {
+    ($self:ident, $fake:expr) => {
+        #[cfg(any(test, feature = "test-utils"))]
+        if $self.fake {
+            #[allow(clippy::needless_return)]
+            return $fake;
+        }
+    };
+}
+
 #[derive(Clone, Copy)]
 pub struct LuaState {
     lua: *mut lua_State,
+    /// Stack lives in a thread local instead, see [`fake`].
+    #[cfg(any(test, feature = "test-utils"))]
+    fake: bool,
 }
 
 impl LuaState {
     pub fn new(lua: *mut lua_State) -> Self {
-        Self { lua }
+        Self {
+            lua,
+            #[cfg(any(test, feature = "test-utils"))]
+            fake: false,
+        }
+    }
+
+    /// Creates a fake lua state for this thread, with calls to lua globals handled by `backend`.
+    /// Replaces the previous fake state, if any.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_fake(backend: impl LuaBackend + 'static) -> Self {
+        fake::install(Box::new(backend));
+        Self {
+            lua: std::ptr::null_mut(),
+            fake: true,
+        }
     }
 
     /// Returns a lua state that is considered "current". Usually set when we get called from noita.
@@ -44,35 +78,37 @@ impl LuaState {
         CURRENT_LUA_STATE.set(Some(self));
     }
 
+    /// Null for a fake lua state.
     pub fn raw(&self) -> *mut lua_State {
         self.lua
     }
 
     pub fn to_integer(&self, index: i32) -> isize {
+        if_fake!(self, fake::with(|fake| fake.get(index).to_number() as isize));
         unsafe { LUA.lua_tointeger(self.lua, index) }
     }
 
     pub fn to_number(&self, index: i32) -> f64 {
+        if_fake!(self, fake::with(|fake| fake.get(index).to_number()));
         unsafe { LUA.lua_tonumber(self.lua, index) }
     }
 
     pub fn to_bool(&self, index: i32) -> bool {
+        if_fake!(self, fake::with(|fake| fake.get(index).to_bool()));
         unsafe { LUA.lua_toboolean(self.lua, index) > 0 }
     }
 
     pub fn to_string(&self, index: i32) -> eyre::Result<String> {
-        let mut size = 0;
-        let buf = unsafe { LUA.lua_tolstring(self.lua, index, &mut size) };
-        if buf.is_null() {
-            bail!("Expected a string, but got a null pointer");
-        }
-        let slice = unsafe { slice::from_raw_parts(buf as *const u8, size) };
-
-        Ok(String::from_utf8(slice.to_owned())
-            .wrap_err("Attempting to get lua string, expecting it to be utf-8")?)
+        String::from_utf8(self.to_raw_string(index)?)
+            .wrap_err("Attempting to get lua string, expecting it to be utf-8")
     }
 
     pub fn to_raw_string(&self, index: i32) -> eyre::Result<Vec<u8>> {
+        if_fake!(
+            self,
+            fake::with(|fake| fake.get(index).to_raw_string())
+                .ok_or_eyre("Expected a string, but got a null pointer")
+        );
         let mut size = 0;
         let buf = unsafe { LUA.lua_tolstring(self.lua, index, &mut size) };
         if buf.is_null() {
@@ -83,39 +119,51 @@ impl LuaState {
         Ok(slice.to_owned())
     }
 
+    /// Always None for a fake lua state.
     pub fn to_cfunction(&self, index: i32) -> lua_CFunction {
+        if_fake!(self, None);
         unsafe { LUA.lua_tocfunction(self.lua, index) }
     }
 
     pub fn push_number(&self, val: f64) {
+        if_fake!(self, fake::with(|fake| fake.push(LuaValue::Number(val))));
         unsafe { LUA.lua_pushnumber(self.lua, val) };
     }
 
     pub fn push_integer(&self, val: isize) {
+        if_fake!(
+            self,
+            fake::with(|fake| fake.push(LuaValue::Number(val as f64)))
+        );
         unsafe { LUA.lua_pushinteger(self.lua, val) };
     }
 
     pub fn push_bool(&self, val: bool) {
+        if_fake!(self, fake::with(|fake| fake.push(LuaValue::Bool(val))));
         unsafe { LUA.lua_pushboolean(self.lua, val as i32) };
     }
 
     pub fn push_string(&self, s: &str) {
-        unsafe {
-            LUA.lua_pushlstring(self.lua, s.as_bytes().as_ptr() as *const c_char, s.len());
-        }
+        self.push_raw_string(s.as_bytes());
     }
 
     pub fn push_raw_string(&self, s: &[u8]) {
+        if_fake!(
+            self,
+            fake::with(|fake| fake.push(LuaValue::String(s.to_owned())))
+        );
         unsafe {
             LUA.lua_pushlstring(self.lua, s.as_ptr() as *const c_char, s.len());
         }
     }
 
     pub fn push_nil(&self) {
+        if_fake!(self, fake::with(|fake| fake.push(LuaValue::Nil)));
         unsafe { LUA.lua_pushnil(self.lua) }
     }
 
     pub fn call(&self, nargs: i32, nresults: i32) -> eyre::Result<()> {
+        if_fake!(self, fake::call(nargs, nresults));
         let ret = unsafe { LUA.lua_pcall(self.lua, nargs, nresults, 0) };
         if ret == 0 {
             Ok(())
@@ -128,14 +176,23 @@ impl LuaState {
     }
 
     pub fn get_global(&self, name: &CStr) {
+        if_fake!(
+            self,
+            fake::with(|fake| fake.push(LuaValue::Global(name.to_string_lossy().into_owned())))
+        );
         unsafe { LUA.lua_getfield(self.lua, LUA_GLOBALSINDEX, name.as_ptr()) };
     }
 
     pub fn objlen(&self, index: i32) -> usize {
+        if_fake!(self, fake::with(|fake| fake.get(index).len()));
         unsafe { LUA.lua_objlen(self.lua, index) }
     }
 
     pub fn index_table(&self, table_index: i32, index_in_table: usize) {
+        if_fake!(
+            self,
+            fake::with(|fake| fake.index_table(table_index, index_in_table))
+        );
         self.push_integer(index_in_table as isize);
         if table_index < 0 {
             unsafe { LUA.lua_gettable(self.lua, table_index - 1) };
@@ -145,9 +202,10 @@ impl LuaState {
     }
 
     pub fn pop_last(&self) {
-        unsafe { LUA.lua_settop(self.lua, -2) };
+        self.pop_last_n(1);
     }
     pub fn pop_last_n(&self, n: i32) {
+        if_fake!(self, fake::with(|fake| fake.pop_n(n as usize)));
         unsafe { LUA.lua_settop(self.lua, -1 - (n)) };
     }
 
@@ -155,6 +213,10 @@ impl LuaState {
     ///
     /// This takes String so that it gets deallocated properly, as this functions doesn't return.
     unsafe fn raise_error(&self, s: String) -> ! {
+        #[cfg(any(test, feature = "test-utils"))]
+        if self.fake {
+            panic!("Lua error: {s}");
+        }
         self.push_string(&s);
         drop(s);
         unsafe { LUA.lua_error(self.lua) };
@@ -163,18 +225,25 @@ impl LuaState {
     }
 
     pub fn is_nil_or_none(&self, index: i32) -> bool {
+        if_fake!(self, fake::with(|fake| fake.get(index).is_nil()));
         (unsafe { LUA.lua_type(self.lua, index) }) <= 0
     }
 
     pub fn create_table(&self, narr: c_int, nrec: c_int) {
+        if_fake!(self, fake::with(|fake| fake.push(LuaValue::table([]))));
         unsafe { LUA.lua_createtable(self.lua, narr, nrec) };
     }
 
     pub fn rawset_table(&self, table_index: i32, index_in_table: i32) {
+        if_fake!(
+            self,
+            fake::with(|fake| fake.rawset_table(table_index, index_in_table))
+        );
         unsafe { LUA.lua_rawseti(self.lua, table_index, index_in_table) };
     }
 
     pub fn checkstack(&self, sz: i32) -> bool {
+        if_fake!(self, true);
         unsafe { LUA.lua_checkstack(self.lua, sz) > 0 }
     }
 }
@@ -312,6 +381,12 @@ impl LuaPutValue for str {
     }
 }
 
+impl LuaPutValue for String {
+    fn put(&self, lua: LuaState) {
+        lua.push_string(self);
+    }
+}
+
 impl LuaPutValue for EntityID {
     fn put(&self, lua: LuaState) {
         isize::from(self.0).put(lua);
@@ -368,6 +443,38 @@ impl LuaPutValue for (f32, f32) {
     }
 }
 
+// A.k.a. ivec2
+impl LuaPutValue for (i32, i32) {
+    const SIZE_ON_STACK: u32 = 2;
+    fn put(&self, lua: LuaState) {
+        lua.push_integer(self.0 as isize);
+        lua.push_integer(self.1 as isize);
+    }
+}
+
+// A.k.a. types::aabb
+impl LuaPutValue for (f32, f32, f32, f32) {
+    const SIZE_ON_STACK: u32 = 4;
+    fn put(&self, lua: LuaState) {
+        lua.push_number(self.0 as f64);
+        lua.push_number(self.1 as f64);
+        lua.push_number(self.2 as f64);
+        lua.push_number(self.3 as f64);
+    }
+}
+
+/// Slices are passed as a single table.
+impl<T: LuaPutValue> LuaPutValue for &[T] {
+    fn put(&self, lua: LuaState) {
+        const { assert!(T::SIZE_ON_STACK == 1) }
+        lua.create_table(self.len() as c_int, 0);
+        for (i, val) in self.iter().enumerate() {
+            val.put(lua);
+            lua.rawset_table(-2, i as i32 + 1);
+        }
+    }
+}
+
 impl LuaPutValue for GameEffectEnum {
     fn put(&self, lua: LuaState) {
         lua.push_string(self.into());
@@ -0,0 +1,292 @@
+//! In-memory stand-in for the lua state, so that code using `noita_api` can run outside of the game, e.g. in `cargo test`.
+//!
+//! Values are pushed to and read from a plain stack, and calls to lua globals are forwarded to a [`LuaBackend`].
+
+use std::{
+    cell::RefCell,
+    fmt::{self, Display},
+    num::NonZero,
+    rc::Rc,
+};
+
+use eyre::{bail, eyre, Context};
+
+use crate::{ComponentID, EntityID};
+
+/// A lua value on the fake stack.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum LuaValue {
+    #[default]
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Vec<u8>),
+    /// Only the array part of tables is supported.
+    Table(Rc<RefCell<Vec<LuaValue>>>),
+    /// Pushed by `get_global`, so that `call` knows what to call.
+    Global(String),
+}
+
+impl LuaValue {
+    pub fn table(values: impl IntoIterator<Item = LuaValue>) -> Self {
+        Self::Table(Rc::new(RefCell::new(values.into_iter().collect())))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, LuaValue::Nil)
+    }
+
+    /// Same conversion rules as lua_toboolean.
+    pub fn to_bool(&self) -> bool {
+        !matches!(self, LuaValue::Nil | LuaValue::Bool(false))
+    }
+
+    /// Same conversion rules as lua_tonumber: strings are parsed, everything else is 0.
+    pub fn to_number(&self) -> f64 {
+        match self {
+            LuaValue::Number(n) => *n,
+            LuaValue::String(s) => std::str::from_utf8(s)
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+                .unwrap_or(0.0),
+            _ => 0.0,
+        }
+    }
+
+    /// Same conversion rules as lua_tolstring: numbers are formatted, everything else is None.
+    pub fn to_raw_string(&self) -> Option<Vec<u8>> {
+        match self {
+            LuaValue::String(s) => Some(s.clone()),
+            LuaValue::Number(n) => Some(LuaValue::format_number(*n).into_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(&self) -> eyre::Result<String> {
+        let raw = self
+            .to_raw_string()
+            .ok_or_else(|| eyre!("Expected a string, got {self}"))?;
+        String::from_utf8(raw).wrap_err("Expected string to be utf-8")
+    }
+
+    /// Entities and components are passed around as plain numbers, with 0 meaning "none".
+    pub fn to_entity(&self) -> Option<EntityID> {
+        EntityID::try_from(self.to_number() as isize).ok()
+    }
+
+    pub fn to_component(&self) -> Option<ComponentID> {
+        NonZero::new(self.to_number() as isize).map(ComponentID)
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            LuaValue::String(s) => s.len(),
+            LuaValue::Table(t) => t.borrow().len(),
+            _ => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a copy of the array part of a table, or an error if this isn't a table.
+    pub fn to_vec(&self) -> eyre::Result<Vec<LuaValue>> {
+        match self {
+            LuaValue::Table(t) => Ok(t.borrow().clone()),
+            _ => bail!("Expected a table, got {self}"),
+        }
+    }
+
+    fn format_number(n: f64) -> String {
+        if n.fract() == 0.0 && n.abs() < 1e15 {
+            format!("{}", n as i64)
+        } else {
+            format!("{n}")
+        }
+    }
+}
+
+impl Display for LuaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaValue::Nil => write!(f, "nil"),
+            LuaValue::Bool(b) => write!(f, "{b}"),
+            LuaValue::Number(n) => write!(f, "{}", LuaValue::format_number(*n)),
+            LuaValue::String(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            LuaValue::Table(t) => write!(f, "table of {}", t.borrow().len()),
+            LuaValue::Global(name) => write!(f, "global {name}"),
+        }
+    }
+}
+
+impl From<bool> for LuaValue {
+    fn from(value: bool) -> Self {
+        LuaValue::Bool(value)
+    }
+}
+
+impl From<f64> for LuaValue {
+    fn from(value: f64) -> Self {
+        LuaValue::Number(value)
+    }
+}
+
+impl From<f32> for LuaValue {
+    fn from(value: f32) -> Self {
+        LuaValue::Number(value.into())
+    }
+}
+
+impl From<i32> for LuaValue {
+    fn from(value: i32) -> Self {
+        LuaValue::Number(value.into())
+    }
+}
+
+impl From<isize> for LuaValue {
+    fn from(value: isize) -> Self {
+        LuaValue::Number(value as f64)
+    }
+}
+
+impl From<&str> for LuaValue {
+    fn from(value: &str) -> Self {
+        LuaValue::String(value.as_bytes().to_owned())
+    }
+}
+
+impl From<String> for LuaValue {
+    fn from(value: String) -> Self {
+        LuaValue::String(value.into_bytes())
+    }
+}
+
+impl From<EntityID> for LuaValue {
+    fn from(value: EntityID) -> Self {
+        value.raw().into()
+    }
+}
+
+impl From<ComponentID> for LuaValue {
+    fn from(value: ComponentID) -> Self {
+        isize::from(value.0).into()
+    }
+}
+
+impl<T: Into<LuaValue>> From<Option<T>> for LuaValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(LuaValue::Nil, Into::into)
+    }
+}
+
+/// Handles calls to lua globals made through a fake lua state.
+pub trait LuaBackend {
+    /// Called instead of the lua global `name`.
+    /// Missing return values are filled with nils, extra ones are dropped.
+    fn call(&mut self, name: &str, args: Vec<LuaValue>) -> eyre::Result<Vec<LuaValue>>;
+}
+
+/// Allows keeping a handle to the backend, e.g. to check its state in tests.
+impl<T: LuaBackend> LuaBackend for Rc<RefCell<T>> {
+    fn call(&mut self, name: &str, args: Vec<LuaValue>) -> eyre::Result<Vec<LuaValue>> {
+        self.borrow_mut().call(name, args)
+    }
+}
+
+pub(crate) struct FakeLua {
+    stack: Vec<LuaValue>,
+    /// Taken out while a call is in progress.
+    backend: Option<Box<dyn LuaBackend>>,
+}
+
+thread_local! {
+    static FAKE_LUA: RefCell<Option<FakeLua>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn install(backend: Box<dyn LuaBackend>) {
+    FAKE_LUA.set(Some(FakeLua {
+        stack: Vec::new(),
+        backend: Some(backend),
+    }));
+}
+
+pub(crate) fn with<T>(f: impl FnOnce(&mut FakeLua) -> T) -> T {
+    FAKE_LUA.with_borrow_mut(|fake| f(fake.as_mut().expect("fake lua state to be installed")))
+}
+
+impl FakeLua {
+    fn abs_index(&self, index: i32) -> Option<usize> {
+        if index < 0 {
+            self.stack.len().checked_sub(index.unsigned_abs() as usize)
+        } else {
+            (index as usize).checked_sub(1)
+        }
+    }
+
+    pub(crate) fn get(&self, index: i32) -> LuaValue {
+        self.abs_index(index)
+            .and_then(|i| self.stack.get(i))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn push(&mut self, value: LuaValue) {
+        self.stack.push(value);
+    }
+
+    pub(crate) fn pop_n(&mut self, n: usize) {
+        self.stack.truncate(self.stack.len().saturating_sub(n));
+    }
+
+    pub(crate) fn index_table(&mut self, table_index: i32, index_in_table: usize) {
+        let value = match self.get(table_index) {
+            LuaValue::Table(t) => index_in_table
+                .checked_sub(1)
+                .and_then(|i| t.borrow().get(i).cloned())
+                .unwrap_or_default(),
+            _ => LuaValue::Nil,
+        };
+        self.push(value);
+    }
+
+    pub(crate) fn rawset_table(&mut self, table_index: i32, index_in_table: i32) {
+        let table = self.get(table_index);
+        let value = self.stack.pop().unwrap_or_default();
+        let (LuaValue::Table(t), Some(i)) = (table, (index_in_table as usize).checked_sub(1)) else {
+            return;
+        };
+        let mut t = t.borrow_mut();
+        if t.len() <= i {
+            t.resize(i + 1, LuaValue::Nil);
+        }
+        t[i] = value;
+    }
+}
+
+pub(crate) fn call(nargs: i32, nresults: i32) -> eyre::Result<()> {
+    let (function, args, mut backend) = with(|fake| {
+        let args = fake
+            .stack
+            .split_off(fake.stack.len().saturating_sub(nargs as usize));
+        let function = fake.stack.pop().unwrap_or_default();
+        (function, args, fake.backend.take())
+    });
+    let LuaValue::Global(name) = function else {
+        with(|fake| fake.backend = backend);
+        bail!("Attempted to call {function}, only globals can be called on a fake lua state");
+    };
+    let Some(backend_ref) = backend.as_mut() else {
+        bail!("Fake lua backend called {name} while already handling a call");
+    };
+    let ret = backend_ref.call(&name, args);
+    with(|fake| {
+        fake.backend = backend;
+        let mut values = ret.wrap_err_with(|| eyre!("Error while calling lua function {name}"))?;
+        if nresults >= 0 {
+            values.resize(nresults as usize, LuaValue::Nil);
+        }
+        fake.stack.extend(values);
+        Ok(())
+    })
+}
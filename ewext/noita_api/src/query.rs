@@ -0,0 +1,161 @@
+//! Typed entity queries, e.g. "all entities in radius with tag X that have `DamageModelComponent`".
+
+use crate::{raw, Component, EntityID};
+
+enum Source {
+    InRadius { x: f64, y: f64, radius: f64 },
+    WithTag(String),
+}
+
+/// Finds entities by position or tag, then filters them by tags and components.
+///
+/// ```ignore
+/// for (entity, damage) in EntityQuery::in_radius(x, y, 300.0)
+///     .with_tag("enemy")
+///     .components::<DamageModelComponent>()?
+/// {
+///     damage.set_hp(damage.hp()? - 1.0)?;
+/// }
+/// ```
+pub struct EntityQuery {
+    source: Source,
+    with_tags: Vec<String>,
+    without_tags: Vec<String>,
+    with_components: Vec<&'static str>,
+}
+
+impl EntityQuery {
+    /// Entities with their position in radius, corresponds to EntityGetInRadius from lua api.
+    pub fn in_radius(x: f64, y: f64, radius: f64) -> Self {
+        Self::new(Source::InRadius { x, y, radius })
+    }
+
+    /// All entities with a tag, corresponds to EntityGetWithTag from lua api.
+    pub fn all_with_tag(tag: impl Into<String>) -> Self {
+        Self::new(Source::WithTag(tag.into()))
+    }
+
+    fn new(source: Source) -> Self {
+        Self {
+            source,
+            with_tags: Vec::new(),
+            without_tags: Vec::new(),
+            with_components: Vec::new(),
+        }
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.with_tags.push(tag.into());
+        self
+    }
+
+    pub fn without_tag(mut self, tag: impl Into<String>) -> Self {
+        self.without_tags.push(tag.into());
+        self
+    }
+
+    /// Only keep entities that have an enabled component of this type.
+    pub fn with_component<C: Component>(mut self) -> Self {
+        self.with_components.push(C::NAME_STR);
+        self
+    }
+
+    pub fn entities(&self) -> eyre::Result<impl Iterator<Item = EntityID> + '_> {
+        let candidates = match &self.source {
+            // Lua api can filter by one tag by itself, which saves checking the rest of entities.
+            Source::InRadius { x, y, radius } => match self.with_tags.first() {
+                Some(tag) => {
+                    raw::entity_get_in_radius_with_tag(*x, *y, *radius, tag.into())?
+                }
+                None => raw::entity_get_in_radius(*x, *y, *radius)?,
+            },
+            Source::WithTag(tag) => raw::entity_get_with_tag(tag.into())?,
+        };
+        Ok(candidates
+            .into_iter()
+            .flatten()
+            .filter(|entity| self.matches(*entity)))
+    }
+
+    /// Matching entities that have a component of type `C`, together with the first such component.
+    pub fn components<C: Component>(&self) -> eyre::Result<Vec<(EntityID, C)>> {
+        let mut found = Vec::new();
+        for entity in self.entities()? {
+            if let Some(component) = entity.try_get_first_component::<C>(None)? {
+                found.push((entity, component));
+            }
+        }
+        Ok(found)
+    }
+
+    fn matches(&self, entity: EntityID) -> bool {
+        self.with_tags.iter().all(|tag| entity.has_tag(tag))
+            && !self.without_tags.iter().any(|tag| entity.has_tag(tag))
+            && self.with_components.iter().all(|name| {
+                raw::entity_get_first_component(entity, (*name).into(), None)
+                    .is_ok_and(|component| component.flatten().is_some())
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        builder::EntityBuilder,
+        fake_world::{FakeEntityFile, FakeWorld},
+        lua::LuaState,
+        DamageModelComponent, ItemComponent,
+    };
+
+    use super::*;
+
+    #[test]
+    fn query_spawned_entities() -> eyre::Result<()> {
+        let mut world = FakeWorld::default();
+        world.add_file(
+            "rat.xml",
+            FakeEntityFile::default()
+                .with_tag("enemy")
+                .with_component("DamageModelComponent", [("hp", vec![1.0.into()])]),
+        );
+        world.add_file("wand.xml", FakeEntityFile::default().with_tag("enemy"));
+        let world = Rc::new(RefCell::new(world));
+        LuaState::new_fake(world.clone()).make_current();
+
+        let near = EntityBuilder::load("rat.xml")
+            .at(10.0, 0.0)
+            .with::<DamageModelComponent>(|damage| damage.set_hp(5.0))
+            .spawn()?;
+        let boss = EntityBuilder::load("rat.xml")
+            .at(0.0, 20.0)
+            .tag("boss")
+            .spawn()?;
+        EntityBuilder::load("rat.xml").at(500.0, 0.0).spawn()?;
+        EntityBuilder::load("wand.xml").at(0.0, 0.0).spawn()?;
+        // Failed overrides don't leave entities behind.
+        assert!(EntityBuilder::load("wand.xml")
+            .with::<ItemComponent>(|_| Ok(()))
+            .spawn()
+            .is_err());
+        assert_eq!(world.borrow().entities().count(), 4);
+
+        let hps: Vec<_> = EntityQuery::in_radius(0.0, 0.0, 100.0)
+            .with_tag("enemy")
+            .components::<DamageModelComponent>()?
+            .into_iter()
+            .map(|(entity, damage)| Ok((entity, damage.hp()?)))
+            .collect::<eyre::Result<_>>()?;
+        assert_eq!(hps, [(near, 5.0), (boss, 1.0)]);
+
+        let not_boss: Vec<_> = EntityQuery::all_with_tag("enemy")
+            .with_component::<DamageModelComponent>()
+            .without_tag("boss")
+            .entities()?
+            .collect();
+        assert_eq!(not_boss.len(), 2);
+        assert!(!not_boss.contains(&boss));
+        Ok(())
+    }
+}
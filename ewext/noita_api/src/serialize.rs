@@ -1,7 +1,122 @@
+//! Serializes entities by reading their components through the lua api, see [`shared::des::serialized`] for the format.
+
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, num::NonZero};
+
+use eyre::{bail, eyre, Context, OptionExt};
+use shared::des::serialized::{FieldValue, SerializedComponent, SerializedEntity, Transform};
+
 use crate::lua::{LuaGetValue, LuaPutValue, LuaState};
-use crate::EntityID;
-use eyre::{Context, OptionExt};
+use crate::{
+    raw, serializable_fields, ComponentID, ComponentObject, EntityID, WorldStateComponent,
+};
+
+/// Type of a serializable component field, generated from `components.json`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FieldKind {
+    Bool,
+    Int,
+    UInt,
+    Int64,
+    Float,
+    Double,
+    String,
+    Vec2,
+    IVec2,
+    Aabb,
+    Floats,
+    Ints,
+    Strings,
+    Entity,
+}
+
+/// Field saved by the entity serializer, generated from `components.json` and `objects.json`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SerializableField {
+    /// Config object this field is in, None for fields of the component itself.
+    pub(crate) object: Option<&'static str>,
+    pub(crate) field: &'static str,
+    pub(crate) kind: FieldKind,
+}
+
+impl SerializableField {
+    /// Name the field is saved under, `object.field` for fields of config objects.
+    fn key(&self) -> Cow<'static, str> {
+        match self.object {
+            Some(object) => format!("{object}.{}", self.field).into(),
+            None => self.field.into(),
+        }
+    }
+}
+
+/// Component or one of its config objects, fields of both are serialized the same way.
+#[derive(Debug, Clone, Copy)]
+enum FieldOwner {
+    Component(ComponentID),
+    Object(ComponentObject),
+}
+
+impl FieldOwner {
+    fn new(component: ComponentID, object: Option<&'static str>) -> Self {
+        match object {
+            Some(object) => FieldOwner::Object(ComponentObject::new(component, object)),
+            None => FieldOwner::Component(component),
+        }
+    }
+
+    /// Finds the owner of a saved field by its key, and returns it with the name of the field.
+    fn from_key<'a>(
+        component: ComponentID,
+        type_name: &str,
+        key: &'a str,
+    ) -> eyre::Result<(Self, &'a str)> {
+        let Some((object, field)) = key.split_once('.') else {
+            return Ok((FieldOwner::Component(component), key));
+        };
+        // Object names have to be static, so they're taken from the generated table.
+        let object = serializable_fields(type_name)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|field| field.object.filter(|name| *name == object))
+            .ok_or_else(|| eyre!("{type_name} has no object {object}"))?;
+        Ok((Self::new(component, Some(object)), field))
+    }
+
+    fn get<T: LuaGetValue>(self, field: &str) -> eyre::Result<T> {
+        match self {
+            FieldOwner::Component(component) => raw::component_get_value(component, field),
+            FieldOwner::Object(object) => object.get(field),
+        }
+    }
+
+    fn set<T: LuaPutValue>(self, field: &str, value: T) -> eyre::Result<()> {
+        match self {
+            FieldOwner::Component(component) => raw::component_set_value(component, field, value),
+            FieldOwner::Object(object) => object.set(field, value),
+        }
+    }
+}
+
+thread_local! {
+    /// `Component.field` of fields that failed to read, so that each one is only logged once.
+    static UNREADABLE_FIELDS: RefCell<HashSet<String>> = RefCell::default();
+}
+
 pub fn serialize_entity(entity: EntityID) -> eyre::Result<Vec<u8>> {
+    // Serialized entities usually get sent to other clients, and it's a very bad idea to try and send them another WorldState.
+    if entity.has_tag("world_state")
+        || entity
+            .try_get_first_component_including_disabled::<WorldStateComponent>(None)?
+            .is_some()
+    {
+        bail!("Tried to serialize WorldStateEntity");
+    }
+    Ok(read_entity(entity)?.encode())
+}
+
+/// Serializes an entity with a single engine call (`np.SerializeEntity` through the `EwextSerialize` lua function).
+/// Much faster than [`serialize_entity`], which reads every field through lua, but the result can only be spawned, not inspected.
+/// Used for projectiles, which get serialized every time they're fired.
+pub fn serialize_entity_engine(entity: EntityID) -> eyre::Result<Vec<u8>> {
     let lua = LuaState::current()?;
     lua.get_global(c"EwextSerialize");
     entity.put(lua);
@@ -12,11 +127,285 @@ pub fn serialize_entity(entity: EntityID) -> eyre::Result<Vec<u8>> {
     res
 }
 
+pub fn read_entity(entity: EntityID) -> eyre::Result<SerializedEntity> {
+    // Entity references are saved as indices into the tree, in the order `SerializedEntity::encode` flattens it.
+    fn collect_tree(entity: EntityID, tree: &mut Vec<EntityID>) {
+        tree.push(entity);
+        for child in entity.children() {
+            collect_tree(child, tree);
+        }
+    }
+    let mut tree = Vec::new();
+    collect_tree(entity, &mut tree);
+    read_node(entity, &tree)
+}
+
+fn read_node(entity: EntityID, tree: &[EntityID]) -> eyre::Result<SerializedEntity> {
+    let (x, y, rotation, scale_x, scale_y) = raw::entity_get_transform(entity)?;
+    let components = raw::entity_get_all_components(entity)?
+        .into_iter()
+        .filter_map(|id| NonZero::new(id as isize).map(ComponentID))
+        .map(|component| read_component(component, tree))
+        .collect::<eyre::Result<_>>()?;
+    let children = entity
+        .children()
+        .into_iter()
+        .map(|child| read_node(child, tree))
+        .collect::<eyre::Result<_>>()?;
+    Ok(SerializedEntity {
+        name: raw::entity_get_name(entity)?.into_owned(),
+        tags: split_tags(raw::entity_get_tags(entity)?),
+        transform: Transform {
+            x: x as f32,
+            y: y as f32,
+            rotation: rotation as f32,
+            scale_x: scale_x as f32,
+            scale_y: scale_y as f32,
+        },
+        components,
+        children,
+    })
+}
+
+fn read_component(component: ComponentID, tree: &[EntityID]) -> eyre::Result<SerializedComponent> {
+    let type_name = raw::component_get_type_name(component)?.into_owned();
+    let fields = serializable_fields(&type_name)
+        .unwrap_or_default()
+        .iter()
+        // Some fields can't be read even though their type is supported, and references to entities outside of the tree can't be kept.
+        // These keep their default value.
+        .filter_map(|field| {
+            let key = field.key();
+            match read_field(
+                FieldOwner::new(component, field.object),
+                field.field,
+                field.kind,
+                tree,
+            ) {
+                Ok(value) => Some((key.into_owned(), value)),
+                Err(err) => {
+                    let name = format!("{type_name}.{key}");
+                    if UNREADABLE_FIELDS.with_borrow_mut(|logged| logged.insert(name.clone())) {
+                        println!("Can't serialize {name}: {err:?}");
+                    }
+                    None
+                }
+            }
+        })
+        .collect();
+    Ok(SerializedComponent {
+        enabled: raw::component_get_is_enabled(component)?,
+        tags: split_tags(raw::component_get_tags(component)?),
+        type_name,
+        fields,
+    })
+}
+
+fn read_field(
+    owner: FieldOwner,
+    field: &str,
+    kind: FieldKind,
+    tree: &[EntityID],
+) -> eyre::Result<FieldValue> {
+    Ok(match kind {
+        FieldKind::Bool => FieldValue::Bool(owner.get(field)?),
+        FieldKind::Int => FieldValue::Int(owner.get(field)?),
+        FieldKind::UInt => FieldValue::UInt(owner.get(field)?),
+        FieldKind::Int64 => FieldValue::Int64(owner.get(field)?),
+        FieldKind::Float => FieldValue::Float(owner.get(field)?),
+        FieldKind::Double => FieldValue::Double(owner.get(field)?),
+        FieldKind::String => {
+            FieldValue::String(owner.get::<Cow<'static, str>>(field)?.into_owned())
+        }
+        FieldKind::Vec2 => {
+            let (x, y) = owner.get(field)?;
+            FieldValue::Vec2(x, y)
+        }
+        FieldKind::IVec2 => {
+            let (x, y) = owner.get(field)?;
+            FieldValue::IVec2(x, y)
+        }
+        FieldKind::Aabb => {
+            let (min_x, min_y, max_x, max_y) = owner.get(field)?;
+            FieldValue::Aabb(min_x, min_y, max_x, max_y)
+        }
+        FieldKind::Floats => FieldValue::Floats(owner.get(field)?),
+        FieldKind::Ints => FieldValue::Ints(owner.get(field)?),
+        FieldKind::Strings => FieldValue::Strings(
+            owner
+                .get::<Vec<Cow<'static, str>>>(field)?
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect(),
+        ),
+        FieldKind::Entity => FieldValue::Entity(
+            owner
+                .get::<Option<EntityID>>(field)?
+                .map(|target| {
+                    tree.iter()
+                        .position(|entity| *entity == target)
+                        .map(|index| index as u32)
+                        .ok_or_else(|| eyre!("{target:?} isn't part of the serialized entity"))
+                })
+                .transpose()?,
+        ),
+    })
+}
+
+/// `tree` is the spawned entities in pre-order, which entity references point into.
+fn write_field(
+    owner: FieldOwner,
+    field: &str,
+    value: &FieldValue,
+    tree: &[EntityID],
+) -> eyre::Result<()> {
+    match value {
+        FieldValue::Bool(v) => owner.set(field, *v),
+        FieldValue::Int(v) => owner.set(field, *v),
+        FieldValue::UInt(v) => owner.set(field, *v),
+        FieldValue::Int64(v) => owner.set(field, *v),
+        FieldValue::Float(v) => owner.set(field, *v),
+        FieldValue::Double(v) => owner.set(field, *v),
+        FieldValue::String(v) => owner.set(field, Cow::from(v.as_str())),
+        FieldValue::Vec2(x, y) => owner.set(field, (*x, *y)),
+        FieldValue::IVec2(x, y) => owner.set(field, (*x, *y)),
+        FieldValue::Aabb(min_x, min_y, max_x, max_y) => {
+            owner.set(field, (*min_x, *min_y, *max_x, *max_y))
+        }
+        FieldValue::Floats(v) => owner.set(field, v.as_slice()),
+        FieldValue::Ints(v) => owner.set(field, v.as_slice()),
+        FieldValue::Strings(v) => owner.set(field, v.as_slice()),
+        FieldValue::Entity(index) => {
+            let target = index
+                .map(|index| {
+                    tree.get(index as usize)
+                        .copied()
+                        .ok_or_else(|| eyre!("Serialized entity has no node {index}"))
+                })
+                .transpose()?;
+            owner.set(field, target)
+        }
+    }
+}
+
+/// Entity reference that's set once the whole tree is spawned.
+struct PendingReference<'a> {
+    owner: FieldOwner,
+    type_name: &'a str,
+    key: &'a str,
+    field: &'a str,
+    value: &'a FieldValue,
+}
+
+/// Spawns a serialized entity, with the root entity moved to `pos` if it's given.
+pub fn spawn_entity(
+    serialized: &SerializedEntity,
+    pos: Option<(f32, f32)>,
+) -> eyre::Result<EntityID> {
+    let offset = pos.map_or((0.0, 0.0), |(x, y)| {
+        (x - serialized.transform.x, y - serialized.transform.y)
+    });
+    let mut tree = Vec::new();
+    let mut references = Vec::new();
+    let entity = spawn_entity_inner(serialized, offset, &mut tree, &mut references)?;
+    for reference in references {
+        if let Err(err) = write_field(reference.owner, reference.field, reference.value, &tree) {
+            entity.kill();
+            return Err(err.wrap_err(format!(
+                "Failed to set {}.{}",
+                reference.type_name, reference.key
+            )));
+        }
+    }
+    Ok(entity)
+}
+
+fn spawn_entity_inner<'a>(
+    serialized: &'a SerializedEntity,
+    offset: (f32, f32),
+    tree: &mut Vec<EntityID>,
+    references: &mut Vec<PendingReference<'a>>,
+) -> eyre::Result<EntityID> {
+    let entity = raw::entity_create_new(Some(serialized.name.as_str().into()))?
+        .ok_or_eyre("Failed to create entity")?;
+    tree.push(entity);
+    let mut spawn = || -> eyre::Result<()> {
+        let Transform {
+            x,
+            y,
+            rotation,
+            scale_x,
+            scale_y,
+        } = serialized.transform;
+        raw::entity_set_transform(
+            entity,
+            (x + offset.0).into(),
+            Some((y + offset.1).into()),
+            Some(rotation.into()),
+            Some(scale_x.into()),
+            Some(scale_y.into()),
+        )?;
+        for tag in &serialized.tags {
+            entity.add_tag(tag)?;
+        }
+        for com in &serialized.components {
+            let component = raw::entity_add_component_by_name(entity, &com.type_name)?
+                .ok_or_else(|| eyre!("Failed to add {}", com.type_name))?;
+            for tag in &com.tags {
+                component.add_tag(tag)?;
+            }
+            for (key, value) in &com.fields {
+                let (owner, field) = FieldOwner::from_key(component, &com.type_name, key)
+                    .wrap_err_with(|| eyre!("Failed to set {}.{key}", com.type_name))?;
+                if let FieldValue::Entity(_) = value {
+                    references.push(PendingReference {
+                        owner,
+                        type_name: &com.type_name,
+                        key,
+                        field,
+                        value,
+                    });
+                    continue;
+                }
+                write_field(owner, field, value, tree)
+                    .wrap_err_with(|| eyre!("Failed to set {}.{key}", com.type_name))?;
+            }
+            if !com.enabled {
+                raw::entity_set_component_is_enabled(entity, component, false)?;
+            }
+        }
+        for child in &serialized.children {
+            let child = spawn_entity_inner(child, offset, tree, references)?;
+            raw::entity_add_child(entity.raw() as i32, child.raw() as i32)?;
+        }
+        Ok(())
+    };
+    if let Err(err) = spawn() {
+        entity.kill();
+        return Err(err);
+    }
+    Ok(entity)
+}
+
+fn split_tags(tags: Option<Cow<'_, str>>) -> Vec<String> {
+    tags.as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Data made by the old serializer is spawned by `EwextDeserialize` lua function, so that it's still possible to load old save states.
 pub(crate) fn try_deserialize_entity(
     entity_data: &[u8],
     x: f32,
     y: f32,
 ) -> eyre::Result<Option<EntityID>> {
+    if SerializedEntity::is_native(entity_data) {
+        let serialized = SerializedEntity::decode(entity_data)?;
+        return spawn_entity(&serialized, Some((x, y))).map(Some);
+    }
     let lua = LuaState::current()?;
     lua.get_global(c"EwextDeserialize");
     lua.push_raw_string(entity_data);
@@ -32,3 +421,140 @@ pub(crate) fn try_deserialize_entity(
 pub fn deserialize_entity(entity_data: &[u8], x: f32, y: f32) -> eyre::Result<EntityID> {
     try_deserialize_entity(entity_data, x, y)?.ok_or_eyre("Failed to deserialize entity")
 }
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        builder::EntityBuilder,
+        fake_world::{FakeEntityFile, FakeWorld},
+        lua::LuaState,
+        AbilityComponent, DamageModelComponent, Inventory2Component, ItemActionComponent,
+        VelocityComponent,
+    };
+
+    use super::*;
+
+    #[test]
+    fn spawn_serialized_copy() -> eyre::Result<()> {
+        let mut world = FakeWorld::default();
+        world.add_file(
+            "rat.xml",
+            FakeEntityFile::default()
+                .with_tag("enemy")
+                .with_component("DamageModelComponent", [("hp", vec![1.0.into()])]),
+        );
+        let world = Rc::new(RefCell::new(world));
+        LuaState::new_fake(world.clone()).make_current();
+
+        let rat = EntityBuilder::load("rat.xml")
+            .at(10.0, 20.0)
+            .with::<DamageModelComponent>(|damage| damage.set_hp(5.0))
+            .add_component::<VelocityComponent>(|velocity| {
+                ComponentID::from(velocity).add_tag("fast")?;
+                velocity.set_m_velocity((3.0, 4.0))
+            })
+            .spawn()?;
+        let child = EntityBuilder::load("rat.xml").at(12.0, 20.0).spawn()?;
+        raw::entity_add_child(rat.raw() as i32, child.raw() as i32)?;
+        let velocity = rat.get_first_component::<VelocityComponent>(None)?;
+        raw::entity_set_component_is_enabled(rat, velocity.into(), false)?;
+
+        let data = serialize_entity(rat)?;
+        assert!(SerializedEntity::is_native(&data));
+        let serialized = SerializedEntity::decode(&data)?;
+        assert_eq!(serialized.tags, ["enemy"]);
+        assert_eq!(serialized.children.len(), 1);
+
+        let copy = deserialize_entity(&data, 100.0, 0.0)?;
+        assert_eq!(copy.position()?, (100.0, 0.0));
+        assert!(copy.has_tag("enemy"));
+        let damage = copy.get_first_component::<DamageModelComponent>(None)?;
+        assert_eq!(damage.hp()?, 5.0);
+        let velocity = copy
+            .try_get_first_component_including_disabled::<VelocityComponent>(None)?
+            .unwrap();
+        assert!(!raw::component_get_is_enabled(velocity.into())?);
+        assert!(ComponentID::from(velocity).has_tag("fast"));
+        assert_eq!(velocity.m_velocity()?, (3.0, 4.0));
+        assert_eq!(copy.children()[0].position()?, (102.0, 0.0));
+        assert_eq!(world.borrow().entities().count(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn wand_round_trip() -> eyre::Result<()> {
+        let mut world = FakeWorld::default();
+        world.add_file("wand.xml", FakeEntityFile::default().with_tag("wand"));
+        world.add_file(
+            "spell.xml",
+            FakeEntityFile::default().with_tag("card_action"),
+        );
+        LuaState::new_fake(Rc::new(RefCell::new(world))).make_current();
+
+        let wand = EntityBuilder::load("wand.xml")
+            .at(5.0, 5.0)
+            .add_component::<AbilityComponent>(|ability| {
+                ability.set_ui_name("Wand".into())?;
+                ability.gun_config().set("deck_capacity", 26)?;
+                ability.gun_config().set("shuffle_deck_when_empty", true)?;
+                ability.gunaction_config().set("fire_rate_wait", 5)?;
+                ability.gunaction_config().set("spread_degrees", -3.5)
+            })
+            .spawn()?;
+        let spell = EntityBuilder::load("spell.xml")
+            .add_component::<ItemActionComponent>(|action| action.set_action_id("LIGHT_BULLET".into()))
+            .spawn()?;
+        raw::entity_add_child(wand.raw() as i32, spell.raw() as i32)?;
+
+        let serialized = read_entity(wand)?;
+        assert!(serialized.components[0]
+            .fields
+            .contains(&("gun_config.deck_capacity".to_owned(), FieldValue::Int(26))));
+
+        let copy = spawn_entity(&serialized, None)?;
+        let ability = copy.get_first_component::<AbilityComponent>(None)?;
+        assert_eq!(ability.ui_name()?, "Wand");
+        assert_eq!(ability.gun_config().get::<i32>("deck_capacity")?, 26);
+        assert!(ability
+            .gun_config()
+            .get::<bool>("shuffle_deck_when_empty")?);
+        assert_eq!(ability.gunaction_config().get::<i32>("fire_rate_wait")?, 5);
+        assert_eq!(
+            ability.gunaction_config().get::<f32>("spread_degrees")?,
+            -3.5
+        );
+        let spell = copy.children()[0].get_first_component::<ItemActionComponent>(None)?;
+        assert_eq!(spell.action_id()?, "LIGHT_BULLET");
+        Ok(())
+    }
+
+    #[test]
+    fn entity_references_point_into_the_copy() -> eyre::Result<()> {
+        let mut world = FakeWorld::default();
+        world.add_file("player.xml", FakeEntityFile::default());
+        world.add_file("wand.xml", FakeEntityFile::default().with_tag("wand"));
+        LuaState::new_fake(Rc::new(RefCell::new(world))).make_current();
+
+        let outsider = EntityBuilder::load("wand.xml").spawn()?;
+        let player = EntityBuilder::load("player.xml")
+            .add_component::<Inventory2Component>(|_| Ok(()))
+            .spawn()?;
+        let wand = EntityBuilder::load("wand.xml").spawn()?;
+        raw::entity_add_child(player.raw() as i32, wand.raw() as i32)?;
+        let inventory = player.get_first_component::<Inventory2Component>(None)?;
+        inventory.set_m_active_item(Some(wand))?;
+        inventory.set_m_throw_item(Some(outsider))?;
+
+        let serialized = read_entity(player)?;
+        let fields = &serialized.components[0].fields;
+        assert!(fields.contains(&("mActiveItem".to_owned(), FieldValue::Entity(Some(1)))));
+        assert!(!fields.iter().any(|(name, _)| name == "mThrowItem"));
+
+        let copy = spawn_entity(&serialized, None)?;
+        let inventory = copy.get_first_component::<Inventory2Component>(None)?;
+        assert_eq!(inventory.m_active_item()?, Some(copy.children()[0]));
+        Ok(())
+    }
+}
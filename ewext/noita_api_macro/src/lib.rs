@@ -1,36 +1,70 @@
 use std::ffi::CString;
 
-use heck::ToSnekCase;
+use heck::{ToSnekCase, ToUpperCamelCase};
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
 use serde::Deserialize;
 
-#[derive(Deserialize)]
+/// Type of a component field, as it's written in `components.json`.
 enum Typ {
-    #[serde(rename = "int")]
     Int,
-    #[serde(rename = "uint32")]
     UInt,
-    #[serde(rename = "float")]
     Float,
-    #[serde(rename = "double")]
     Double,
-    #[serde(rename = "bool")]
     Bool,
-    #[serde(rename = "std::string")]
     StdString,
-    #[serde(rename = "vec2")]
     Vec2,
+    IVec2,
     EntityID,
-    #[serde(rename = "int64")]
     Int64,
-    #[serde(rename = "GAME_EFFECT::Enum")]
     GameEffectEnum,
-    #[serde(other)]
-    Other,
+    /// Enum with variants listed in `enums.json`.
+    Enum(proc_macro2::Ident),
+    /// Enum we don't know variants of, accessed as a string.
+    UnknownEnum,
+    /// `(min, max)` pair.
+    ValueRange,
+    ValueRangeInt,
+    /// `(min_x, min_y, max_x, max_y)`.
+    Aabb,
+    Vec(Box<Typ>),
+    /// Nested config object, accessed with `ComponentObjectGetValue2`.
+    Object,
 }
 
 impl Typ {
+    /// Returns None for types that can't be accessed from lua.
+    fn parse(typ: &str, enums: &[EnumDef]) -> Option<Self> {
+        Some(match typ {
+            "int" | "int32" | "LensValue<int>" => Typ::Int,
+            "uint32" | "uint32_t" | "unsigned" | "unsigned int" => Typ::UInt,
+            "float" | "LensValue<float>" => Typ::Float,
+            "double" => Typ::Double,
+            "bool" | "LensValue<bool>" => Typ::Bool,
+            "std::string" => Typ::StdString,
+            "vec2" => Typ::Vec2,
+            "ivec2" => Typ::IVec2,
+            "EntityID" => Typ::EntityID,
+            "int64" => Typ::Int64,
+            "GAME_EFFECT::Enum" => Typ::GameEffectEnum,
+            "ValueRange" => Typ::ValueRange,
+            "ValueRangeInt" => Typ::ValueRangeInt,
+            "types::aabb" => Typ::Aabb,
+            "VECTOR_FLOAT" | "std::vector<float>" => Typ::Vec(Box::new(Typ::Float)),
+            "VECTOR_INT" | "VECTOR_INT32" | "std::vector<int>" => Typ::Vec(Box::new(Typ::Int)),
+            "VECTOR_STR" | "VECTOR_STRING" => Typ::Vec(Box::new(Typ::StdString)),
+            "VECTOR_ENTITYID" => Typ::Vec(Box::new(Typ::EntityID)),
+            _ if typ.starts_with("Config") => Typ::Object,
+            _ => {
+                let enum_name = typ.strip_suffix("::Enum")?;
+                match enums.iter().find(|def| def.name == enum_name) {
+                    Some(def) => Typ::Enum(def.ident()),
+                    None => Typ::UnknownEnum,
+                }
+            }
+        })
+    }
+
     fn as_rust_type(&self) -> proc_macro2::TokenStream {
         match self {
             Typ::Int => quote!(i32),
@@ -38,23 +72,75 @@ impl Typ {
             Typ::Float => quote!(f32),
             Typ::Double => quote!(f64),
             Typ::Bool => quote!(bool),
-            Typ::StdString => quote!(Cow<'_, str>),
-            Typ::Vec2 => quote! {(f32, f32)},
+            Typ::StdString | Typ::UnknownEnum => quote!(Cow<'_, str>),
+            Typ::Vec2 | Typ::ValueRange => quote! {(f32, f32)},
+            Typ::IVec2 | Typ::ValueRangeInt => quote! {(i32, i32)},
             Typ::EntityID => quote! { Option<EntityID> },
             Typ::Int64 => quote! { i64 },
             Typ::GameEffectEnum => quote! { GameEffectEnum },
-            Typ::Other => todo!(),
+            Typ::Enum(ident) => quote! { #ident },
+            Typ::Aabb => quote! {(f32, f32, f32, f32)},
+            Typ::Vec(typ) => {
+                let typ = typ.as_rust_type();
+                quote! { &[#typ] }
+            }
+            Typ::Object => unreachable!("objects don't have a rust type"),
         }
     }
+    /// How the field is stored by the entity serializer, None if it isn't.
+    fn field_kind(&self) -> Option<proc_macro2::TokenStream> {
+        let kind = match self {
+            Typ::Int => quote!(Int),
+            Typ::UInt => quote!(UInt),
+            Typ::Float => quote!(Float),
+            Typ::Double => quote!(Double),
+            Typ::Bool => quote!(Bool),
+            Typ::StdString | Typ::UnknownEnum | Typ::Enum(_) | Typ::GameEffectEnum => {
+                quote!(String)
+            }
+            Typ::Vec2 | Typ::ValueRange => quote!(Vec2),
+            Typ::IVec2 | Typ::ValueRangeInt => quote!(IVec2),
+            Typ::Int64 => quote!(Int64),
+            Typ::Aabb => quote!(Aabb),
+            Typ::Vec(typ) => match **typ {
+                Typ::Float => quote!(Floats),
+                Typ::Int => quote!(Ints),
+                Typ::StdString => quote!(Strings),
+                _ => return None,
+            },
+            // Saved as an index into the serialized tree, as entity ids don't mean anything after deserializing.
+            Typ::EntityID => quote!(Entity),
+            // Objects are saved field by field, see `generate_serializable_fields`.
+            Typ::Object => return None,
+        };
+        Some(quote!(crate::serialize::FieldKind::#kind))
+    }
+
     fn as_rust_type_return(&self) -> proc_macro2::TokenStream {
         match self {
-            Typ::StdString => quote! {Cow<'static, str>},
+            Typ::StdString | Typ::UnknownEnum => quote! {Cow<'static, str>},
             Typ::EntityID => quote! {Option<EntityID>},
+            Typ::Vec(typ) => {
+                let typ = typ.as_rust_type_return();
+                quote! { Vec<#typ> }
+            }
             _ => self.as_rust_type(),
         }
     }
 }
 
+#[derive(Deserialize)]
+struct EnumDef {
+    name: String,
+    variants: Vec<String>,
+}
+
+impl EnumDef {
+    fn ident(&self) -> proc_macro2::Ident {
+        format_ident!("{}", self.name.to_upper_camel_case())
+    }
+}
+
 #[derive(Deserialize)]
 enum Typ2 {
     #[serde(rename = "int")]
@@ -105,7 +191,7 @@ impl Typ2 {
 #[derive(Deserialize)]
 struct Field {
     field: String,
-    typ: Typ,
+    typ: String,
     desc: String,
 }
 
@@ -157,9 +243,15 @@ struct ApiFn {
 #[proc_macro]
 pub fn generate_components(_item: TokenStream) -> TokenStream {
     let components: Vec<Component> = serde_json::from_str(include_str!("components.json")).unwrap();
-
-    let res = components.into_iter().map(generate_code_for_component);
-    quote! {#(#res)*}.into()
+    let enums: Vec<EnumDef> = serde_json::from_str(include_str!("enums.json")).unwrap();
+    let objects: Vec<Component> = serde_json::from_str(include_str!("objects.json")).unwrap();
+
+    let enum_res = enums.iter().map(generate_code_for_enum);
+    let serializable = generate_serializable_fields(&components, &enums, &objects);
+    let res = components
+        .into_iter()
+        .map(|com| generate_code_for_component(com, &enums));
+    quote! {#(#enum_res)* #serializable #(#res)*}.into()
 }
 
 fn convert_field_name(field_name: &str) -> String {
@@ -172,7 +264,116 @@ fn convert_field_name(field_name: &str) -> String {
     field_name.to_snek_case()
 }
 
-fn generate_code_for_component(com: Component) -> proc_macro2::TokenStream {
+fn generate_serializable_fields(
+    components: &[Component],
+    enums: &[EnumDef],
+    objects: &[Component],
+) -> proc_macro2::TokenStream {
+    let arms = components.iter().map(|com| {
+        let com_name = &com.name;
+        let fields = com.fields.iter().flat_map(|field| {
+            let field_name = &field.field;
+            match Typ::parse(&field.typ, enums) {
+                // Fields of objects with unknown layout are skipped.
+                Some(Typ::Object) => objects
+                    .iter()
+                    .find(|obj| obj.name == field.typ)
+                    .into_iter()
+                    .flat_map(|obj| &obj.fields)
+                    .filter_map(|obj_field| {
+                        let kind = Typ::parse(&obj_field.typ, enums)?.field_kind()?;
+                        let obj_field_name = &obj_field.field;
+                        Some(quote! {
+                            crate::serialize::SerializableField {
+                                object: Some(#field_name),
+                                field: #obj_field_name,
+                                kind: #kind,
+                            }
+                        })
+                    })
+                    .collect(),
+                typ => typ
+                    .and_then(|typ| typ.field_kind())
+                    .map(|kind| {
+                        quote! {
+                            crate::serialize::SerializableField {
+                                object: None,
+                                field: #field_name,
+                                kind: #kind,
+                            }
+                        }
+                    })
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+            }
+        });
+        quote! { #com_name => Some(&[#(#fields),*]), }
+    });
+    quote! {
+        /// Fields that the entity serializer saves, by component type name. None for unknown component types.
+        pub(crate) fn serializable_fields(
+            component_type: &str,
+        ) -> Option<&'static [crate::serialize::SerializableField]> {
+            match component_type {
+                #(#arms)*
+                _ => None,
+            }
+        }
+    }
+}
+
+fn generate_code_for_enum(def: &EnumDef) -> proc_macro2::TokenStream {
+    let enum_name = def.ident();
+    let enum_doc = format!("{}::Enum", def.name);
+    let variants: Vec<_> = def
+        .variants
+        .iter()
+        .map(|variant| format_ident!("{}", variant.to_upper_camel_case()))
+        .collect();
+    let variant_strs = &def.variants;
+    let unknown_err_msg = format!("Unknown {} value: {{}}", def.name);
+
+    quote! {
+        #[doc = #enum_doc]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum #enum_name {
+            #(#variants,)*
+        }
+
+        impl #enum_name {
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    #(Self::#variants => #variant_strs,)*
+                }
+            }
+        }
+
+        impl std::str::FromStr for #enum_name {
+            type Err = eyre::Report;
+
+            fn from_str(s: &str) -> eyre::Result<Self> {
+                match s {
+                    #(#variant_strs => Ok(Self::#variants),)*
+                    _ => Err(eyre!(#unknown_err_msg, s)),
+                }
+            }
+        }
+
+        impl crate::lua::LuaPutValue for #enum_name {
+            fn put(&self, lua: crate::lua::LuaState) {
+                lua.push_string(self.as_str());
+            }
+        }
+
+        impl crate::lua::LuaGetValue for #enum_name {
+            fn get(lua: crate::lua::LuaState, index: i32) -> eyre::Result<Self> {
+                lua.to_string(index)?.parse()
+            }
+        }
+    }
+}
+
+fn generate_code_for_component(com: Component, enums: &[EnumDef]) -> proc_macro2::TokenStream {
     let component_name = format_ident!("{}", com.name);
 
     let impls = com.fields.iter().filter_map(|field| {
@@ -181,11 +382,16 @@ fn generate_code_for_component(com: Component) -> proc_macro2::TokenStream {
         let field_name = format_ident!("{}", field_name_s);
         let field_doc = &field.desc;
         let set_method_name = format_ident!("set_{}", field_name);
-        match field.typ {
-            Typ::Other => None,
-            _ => {
-                let field_type = field.typ.as_rust_type();
-                let field_type_ret = field.typ.as_rust_type_return();
+        match Typ::parse(&field.typ, enums)? {
+            Typ::Object => Some(quote! {
+                #[doc = #field_doc]
+                pub fn #field_name(self) -> ComponentObject {
+                    ComponentObject::new(self.0, #field_name_raw)
+                }
+            }),
+            typ => {
+                let field_type = typ.as_rust_type();
+                let field_type_ret = typ.as_rust_type_return();
                 Some(quote! {
                     #[doc = #field_doc]
                     pub fn #field_name(self) -> eyre::Result<#field_type_ret> {
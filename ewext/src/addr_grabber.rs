@@ -1,18 +1,152 @@
-use std::{mem, os::raw::c_void, ptr, sync::OnceLock};
+use std::{ffi::CStr, os::raw::c_void, ptr, slice, sync::OnceLock};
 
+use eyre::OptionExt;
 use iced_x86::{Decoder, DecoderOptions, Mnemonic};
 use noita_api::lua::LuaState;
+use shared::{AddrReport, SymbolAddr};
 
 use crate::noita::ntypes::{EntityManager, ThiscallFn};
 
 static GRABBED: OnceLock<Grabbed> = OnceLock::new();
 
-pub(crate) unsafe fn grab_addr_from_instruction(
-    func: *const c_void,
-    offset: isize,
-    expected_mnemonic: Mnemonic,
-) -> *mut c_void {
-    let instruction_addr = func.wrapping_offset(offset);
+/// How many bytes after the start of a function are searched for signatures.
+const SCAN_LEN: usize = 0x600;
+/// Addresses further than this from the function they were found in are assumed to be wrong.
+const MAX_DISTANCE: usize = 0x0400_0000;
+
+/// Where the search for a symbol starts.
+enum Base {
+    /// Lua api function implemented by the game.
+    LuaFn(&'static CStr),
+    /// Function found earlier.
+    Symbol(&'static str),
+}
+
+enum Candidate {
+    /// Bytes in hex, `??` matches any byte. Has to match exactly once within [`SCAN_LEN`] bytes of base.
+    Signature {
+        pattern: &'static str,
+        /// From the start of the match to the instruction that references the symbol.
+        instruction_offset: usize,
+    },
+    /// Offset from base to the instruction, in the game build ewext was originally made for.
+    KnownOffset(isize),
+}
+
+struct SymbolDef {
+    name: &'static str,
+    base: Base,
+    /// Instruction that references the symbol, a `mov` for globals and a `call` for functions.
+    mnemonic: Mnemonic,
+    /// Tried in order, first one that passes sanity checks is used.
+    /// Known offset goes first, as it's known to be right on the build it was made for,
+    /// while a signature could also match a similar instruction nearby. Signatures are the fallback for other builds.
+    candidates: &'static [Candidate],
+}
+
+/// `mov ecx, [entity_manager]`, a push, then `call get_entity`.
+const GET_ENTITY_CALL: &str = "8B 0D ?? ?? ?? ?? ?? ?? ?? ?? E8";
+
+const SYMBOLS: &[SymbolDef] = &[
+    SymbolDef {
+        name: "world_state_entity",
+        base: Base::LuaFn(c"GameGetWorldStateEntity"),
+        mnemonic: Mnemonic::Mov,
+        candidates: &[
+            Candidate::KnownOffset(0x007aa7ce - 0x007aa540),
+            Candidate::Signature {
+                pattern: "A1 ?? ?? ?? ?? 85 C0",
+                instruction_offset: 0,
+            },
+            Candidate::Signature {
+                pattern: "8B 0D ?? ?? ?? ?? 85 C9",
+                instruction_offset: 0,
+            },
+        ],
+    },
+    SymbolDef {
+        name: "load_game_global",
+        base: Base::LuaFn(c"GameGetFrameNum"),
+        mnemonic: Mnemonic::Call,
+        candidates: &[
+            Candidate::KnownOffset(0x007bf3c9 - 0x007bf140),
+            Candidate::Signature {
+                pattern: "E8 ?? ?? ?? ?? 8B 40",
+                instruction_offset: 0,
+            },
+        ],
+    },
+    SymbolDef {
+        name: "game_global",
+        base: Base::Symbol("load_game_global"),
+        mnemonic: Mnemonic::Mov,
+        candidates: &[
+            Candidate::KnownOffset(0x00439c17 - 0x00439bb0),
+            Candidate::Signature {
+                pattern: "A1 ?? ?? ?? ?? 8B E5 5D C3",
+                instruction_offset: 0,
+            },
+        ],
+    },
+    SymbolDef {
+        name: "entity_manager",
+        base: Base::LuaFn(c"EntityGetFilename"),
+        mnemonic: Mnemonic::Mov,
+        candidates: &[
+            Candidate::KnownOffset(0x00797821 - 0x00797570),
+            Candidate::Signature {
+                pattern: GET_ENTITY_CALL,
+                instruction_offset: 0,
+            },
+        ],
+    },
+    SymbolDef {
+        name: "get_entity",
+        base: Base::LuaFn(c"EntityGetFilename"),
+        mnemonic: Mnemonic::Call,
+        candidates: &[
+            Candidate::KnownOffset(0x0079782b - 0x00797570),
+            Candidate::Signature {
+                pattern: GET_ENTITY_CALL,
+                instruction_offset: 10,
+            },
+        ],
+    },
+];
+
+fn parse_pattern(pattern: &str) -> Vec<Option<u8>> {
+    pattern
+        .split_whitespace()
+        .map(|byte| match byte {
+            "??" => None,
+            _ => Some(u8::from_str_radix(byte, 16).expect("valid hex in signature")),
+        })
+        .collect()
+}
+
+/// Returns the position of the only match of `pattern` in `haystack`.
+fn find_unique(haystack: &[u8], pattern: &[Option<u8>]) -> Result<usize, String> {
+    let mut matches = haystack
+        .windows(pattern.len())
+        .enumerate()
+        .filter(|(_, window)| {
+            window
+                .iter()
+                .zip(pattern)
+                .all(|(byte, expected)| expected.is_none_or(|expected| expected == *byte))
+        });
+    match (matches.next(), matches.count()) {
+        (None, _) => Err("no match".to_owned()),
+        (Some((pos, _)), 0) => Ok(pos),
+        (Some(_), more) => Err(format!("{} matches", more + 1)),
+    }
+}
+
+/// Decodes the instruction at `instruction_addr` and returns the address it references.
+unsafe fn decode_addr(
+    instruction_addr: *const c_void,
+    mnemonic: Mnemonic,
+) -> Result<usize, String> {
     // We don't really have an idea of how many bytes the instruction takes, so just take *enough* bytes for most cases.
     let instruction_bytes = ptr::read_unaligned(instruction_addr.cast::<[u8; 16]>());
     let mut decoder = Decoder::with_ip(
@@ -22,13 +156,65 @@ pub(crate) unsafe fn grab_addr_from_instruction(
         DecoderOptions::NONE,
     );
     let instruction = decoder.decode();
+    if instruction.mnemonic() != mnemonic {
+        return Err(format!("expected {mnemonic:?}, found `{instruction}`"));
+    }
+    Ok(match mnemonic {
+        Mnemonic::Call => instruction.near_branch32(),
+        _ => instruction.memory_displacement32(),
+    } as usize)
+}
 
-    if instruction.mnemonic() != expected_mnemonic {
-        println!("Encountered unexpected mnemonic: {}", instruction);
+fn check_sane(addr: usize, base: usize, mnemonic: Mnemonic) -> Result<(), String> {
+    if addr == 0 {
+        return Err("address is null".to_owned());
+    }
+    if addr.abs_diff(base) > MAX_DISTANCE {
+        return Err(format!("0x{addr:x} is too far from 0x{base:x}"));
     }
-    assert_eq!(instruction.mnemonic(), expected_mnemonic);
+    // Globals here are all pointers.
+    if mnemonic == Mnemonic::Mov && !addr.is_multiple_of(4) {
+        return Err(format!("0x{addr:x} isn't aligned"));
+    }
+    Ok(())
+}
 
-    instruction.memory_displacement32() as *mut c_void
+/// Tries every candidate of a symbol, returns the address and the candidate that found it, along with the ones that failed before it.
+fn find_symbol(def: &SymbolDef, base: usize) -> Result<(usize, String), String> {
+    let mut failures = Vec::new();
+    let mut signatures = 0;
+    for candidate in def.candidates {
+        let (description, found) = match candidate {
+            Candidate::Signature {
+                pattern,
+                instruction_offset,
+            } => {
+                let haystack = unsafe { slice::from_raw_parts(base as *const u8, SCAN_LEN) };
+                let found = find_unique(haystack, &parse_pattern(pattern)).and_then(|pos| unsafe {
+                    decode_addr(
+                        (base + pos + instruction_offset) as *const c_void,
+                        def.mnemonic,
+                    )
+                });
+                signatures += 1;
+                (format!("signature {signatures}"), found)
+            }
+            Candidate::KnownOffset(offset) => ("known offset".to_owned(), unsafe {
+                decode_addr((base as *const c_void).offset(*offset), def.mnemonic)
+            }),
+        };
+        match found.and_then(|addr| check_sane(addr, base, def.mnemonic).map(|_| addr)) {
+            Ok(addr) if failures.is_empty() => return Ok((addr, description)),
+            Ok(addr) => {
+                return Ok((
+                    addr,
+                    format!("{description}, after {}", failures.join("; ")),
+                ))
+            }
+            Err(err) => failures.push(format!("{description}: {err}")),
+        }
+    }
+    Err(failures.join("; "))
 }
 
 struct Grabbed {
@@ -51,59 +237,95 @@ pub(crate) struct GrabbedFns {
     pub(crate) get_entity: *const ThiscallFn, //unsafe extern "C" fn(*const EntityManager, u32) -> *mut Entity,
 }
 
-pub(crate) fn grab_addrs(lua: LuaState) {
-    lua.get_global(c"GameGetWorldStateEntity");
-    let base = lua.to_cfunction(-1).unwrap() as *const c_void;
-    let world_state_entity =
-        unsafe { grab_addr_from_instruction(base, 0x007aa7ce - 0x007aa540, Mnemonic::Mov).cast() };
-    println!(
-        "World state entity addr: 0x{:x}",
-        world_state_entity as usize
-    );
-    lua.pop_last();
-
-    lua.get_global(c"GameGetFrameNum");
-    let base = lua.to_cfunction(-1).unwrap() as *const c_void;
-    let load_game_global =
-        unsafe { grab_addr_from_instruction(base, 0x007bf3c9 - 0x007bf140, Mnemonic::Call) }; // CALL load_game_global
-    println!("Load game global addr: 0x{:x}", load_game_global as usize);
-    let game_global = unsafe {
-        grab_addr_from_instruction(load_game_global, 0x00439c17 - 0x00439bb0, Mnemonic::Mov).cast()
-    };
-    println!("Game global addr: 0x{:x}", game_global as usize);
-    lua.pop_last();
-
-    lua.get_global(c"EntityGetFilename");
-    let base = lua.to_cfunction(-1).unwrap() as *const c_void;
-    let get_entity = unsafe {
-        mem::transmute_copy(&grab_addr_from_instruction(
-            base,
-            0x0079782b - 0x00797570,
-            Mnemonic::Call,
-        ))
-    };
-    println!("get_entity addr: 0x{:x}", get_entity as usize);
-    let entity_manager =
-        unsafe { grab_addr_from_instruction(base, 0x00797821 - 0x00797570, Mnemonic::Mov).cast() };
-    println!("entity_manager addr: 0x{:x}", entity_manager as usize);
-    lua.pop_last();
+/// Looks up game addresses, they're only usable if every one of them was found.
+pub(crate) fn grab_addrs(lua: LuaState) -> AddrReport {
+    let mut report = AddrReport::default();
+    let mut found: Vec<(&str, usize)> = Vec::new();
+    for def in SYMBOLS {
+        let base = match def.base {
+            Base::LuaFn(name) => {
+                lua.get_global(name);
+                let base = lua.to_cfunction(-1).map(|f| f as usize);
+                lua.pop_last();
+                base.ok_or_else(|| format!("{name:?} isn't a C function"))
+            }
+            Base::Symbol(name) => found
+                .iter()
+                .find(|(found_name, _)| *found_name == name)
+                .map(|(_, addr)| *addr)
+                .ok_or_else(|| format!("{name} wasn't found")),
+        };
+        let res = base.and_then(|base| find_symbol(def, base));
+        let (addr, note) = match res {
+            Ok((addr, note)) => {
+                println!("{} addr: 0x{addr:x}, from {note}", def.name);
+                found.push((def.name, addr));
+                (Some(addr as u32), note)
+            }
+            Err(err) => {
+                println!("{} not found: {err}", def.name);
+                (None, err)
+            }
+        };
+        report.symbols.push(SymbolAddr {
+            name: def.name.to_owned(),
+            addr,
+            note,
+        });
+    }
 
-    GRABBED
-        .set(Grabbed {
+    let get = |name: &str| {
+        found
+            .iter()
+            .find(|(found_name, _)| *found_name == name)
+            .map(|(_, addr)| *addr)
+    };
+    let grabbed = (|| {
+        Some(Grabbed {
             globals: GrabbedGlobals {
-                _game_global: game_global,
-                _world_state_entity: world_state_entity,
-                entity_manager,
+                _game_global: get("game_global")? as *mut usize,
+                _world_state_entity: get("world_state_entity")? as *mut usize,
+                entity_manager: get("entity_manager")? as *const *mut EntityManager,
+            },
+            fns: GrabbedFns {
+                get_entity: get("get_entity")? as *const ThiscallFn,
             },
-            fns: GrabbedFns { get_entity },
         })
-        .ok();
+    })();
+    if let Some(grabbed) = grabbed {
+        GRABBED.set(grabbed).ok();
+    }
+    report
+}
+
+fn grabbed() -> eyre::Result<&'static Grabbed> {
+    GRABBED
+        .get()
+        .ok_or_eyre("Game addresses weren't found, this game build might be unsupported")
+}
+
+pub(crate) fn grabbed_fns() -> eyre::Result<&'static GrabbedFns> {
+    Ok(&grabbed()?.fns)
 }
 
-pub(crate) fn grabbed_fns() -> &'static GrabbedFns {
-    &GRABBED.get().expect("to be initialized early").fns
+pub(crate) fn grabbed_globals() -> eyre::Result<&'static GrabbedGlobals> {
+    Ok(&grabbed()?.globals)
 }
 
-pub(crate) fn grabbed_globals() -> &'static GrabbedGlobals {
-    &GRABBED.get().expect("to be initialized early").globals
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signature_has_to_match_once() {
+        let code = [
+            0x55, 0x8B, 0xEC, 0xA1, 0x10, 0x20, 0x30, 0x00, 0x85, 0xC0, 0xA1,
+        ];
+        assert_eq!(
+            find_unique(&code, &parse_pattern("A1 ?? ?? ?? ?? 85 C0")),
+            Ok(3)
+        );
+        assert!(find_unique(&code, &parse_pattern("A1")).is_err());
+        assert!(find_unique(&code, &parse_pattern("8B 0D ?? ?? ?? ??")).is_err());
+    }
 }
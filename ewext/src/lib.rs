@@ -3,10 +3,10 @@
 pub extern "C" fn _Unwind_Resume() {}
 
 use std::{
-    arch::asm,
     borrow::Cow,
     cell::{LazyCell, RefCell},
     ffi::{c_int, c_void},
+    mem, ptr,
     sync::{LazyLock, Mutex, OnceLock},
     thread,
     time::Instant,
@@ -16,23 +16,32 @@ use addr_grabber::{grab_addrs, grabbed_fns, grabbed_globals};
 use bimap::BiHashMap;
 use eyre::{bail, Context, OptionExt};
 
-use modules::{entity_sync::EntitySync, Module, ModuleCtx};
+use modules::{entity_sync::EntitySync, Module, ModuleCtx, ModuleInitCtx, ModuleRegistry};
 use net::NetManager;
-use noita::{ntypes::Entity, pixel::NoitaPixelRun, ParticleWorldState};
+use noita::{
+    ntypes::{ConstructCellFn, Entity, RemoveCellFn},
+    pixel::{EncodedArea, NoitaPixelRun},
+    ParticleWorldState,
+};
 use noita_api::{
     lua::{
         lua_bindings::{lua_State, LUA_REGISTRYINDEX},
         LuaFnRet, LuaGetValue, LuaState, RawString, ValuesOnStack, LUA,
     },
+    query::EntityQuery,
     DamageModelComponent, EntityID,
 };
 use noita_api_macro::add_lua_fn;
-use shared::{NoitaInbound, PeerId, ProxyKV};
+use shared::{
+    options::{GameOptions, OptionValue},
+    NoitaInbound, NoitaOutbound, PeerId,
+};
 
 mod addr_grabber;
 mod modules;
 mod net;
 pub mod noita;
+mod profiler;
 
 thread_local! {
     static STATE: LazyCell<RefCell<ExtState>> = LazyCell::new(|| {
@@ -53,16 +62,17 @@ pub(crate) fn my_peer_id() -> PeerId {
         .expect("peer id to be set by this point")
 }
 
-#[derive(Default)]
-struct Modules {
-    entity_sync: Option<EntitySync>,
-}
-
 #[derive(Default)]
 struct ExtState {
     particle_world_state: Option<ParticleWorldState>,
-    modules: Modules,
+    modules: ModuleRegistry,
     player_entity_map: BiHashMap<PeerId, EntityID>,
+    /// Entity tracking rules from lobby settings, received on connection.
+    lobby_tracking_rules: String,
+    /// Whether we only watch the run, which can change at any time.
+    spectator: bool,
+    /// Options received on connection, also passed on to lua.
+    options: GameOptions,
 }
 
 impl ExtState {
@@ -76,24 +86,38 @@ impl ExtState {
     }
 }
 
-fn init_particle_world_state(lua: LuaState) {
+fn init_particle_world_state(lua: LuaState) -> eyre::Result<()> {
     println!("\nInitializing particle world state");
     let world_pointer = lua.to_integer(1);
     let chunk_map_pointer = lua.to_integer(2);
     let material_list_pointer = lua.to_integer(3);
+    let construct_cell = lua.to_integer(4);
+    let remove_cell = lua.to_integer(5);
     println!("pws stuff: {world_pointer:?} {chunk_map_pointer:?}");
+    if construct_cell == 0 || remove_cell == 0 {
+        bail!("Cell functions not provided");
+    }
 
-    STATE.with(|state| {
-        state.borrow_mut().particle_world_state = Some(ParticleWorldState {
-            _world_ptr: world_pointer as *mut c_void,
+    let mut last_material_id = 0;
+    while noita_api::raw::cell_factory_get_name(last_material_id as i32 + 1)? != "unknown" {
+        last_material_id += 1;
+    }
+
+    ExtState::with_global(|state| {
+        state.particle_world_state = Some(ParticleWorldState {
+            world_ptr: world_pointer as *mut _,
             chunk_map_ptr: chunk_map_pointer as *mut c_void,
             material_list_ptr: material_list_pointer as _,
+            last_material_id,
+            construct_cell: unsafe { mem::transmute::<isize, ConstructCellFn>(construct_cell) },
+            remove_cell: unsafe { mem::transmute::<isize, RemoveCellFn>(remove_cell) },
             runner: Default::default(),
         });
-    });
+    })
 }
 
 fn encode_area(lua: LuaState) -> ValuesOnStack {
+    let _span = profiler::span("encode_area");
     let lua = lua.raw();
     let start_x = unsafe { LUA.lua_tointeger(lua, 1) } as i32;
     let start_y = unsafe { LUA.lua_tointeger(lua, 2) } as i32;
@@ -110,18 +134,42 @@ fn encode_area(lua: LuaState) -> ValuesOnStack {
     ValuesOnStack(1)
 }
 
+/// Applies an area received from the proxy, returns the bounds of changed cells, or nil if nothing changed.
+fn decode_area(lua: LuaState) -> eyre::Result<Option<ValuesOnStack>> {
+    let _span = profiler::span("decode_area");
+    let data = lua.to_raw_string(1)?;
+    let area = EncodedArea::parse(&data)?;
+    let dirty = ExtState::with_global(|state| {
+        let pws = state
+            .particle_world_state
+            .as_mut()
+            .ok_or_eyre("Particle world state not initialized")?;
+        eyre::Ok(unsafe { pws.decode_area(&area) })
+    })??;
+    Ok(dirty.map(|dirty| {
+        for val in [dirty.min_x, dirty.min_y, dirty.max_x, dirty.max_y] {
+            lua.push_integer(val as isize);
+        }
+        ValuesOnStack(4)
+    }))
+}
+
 fn make_ephemerial(lua: LuaState) -> eyre::Result<()> {
     unsafe {
         let entity_id = lua.to_integer(1) as u32;
 
-        let entity_manager = grabbed_globals().entity_manager.read();
-        let mut entity: *mut Entity;
-        asm!(
+        let entity_manager = grabbed_globals()?.entity_manager.read();
+        let mut entity: *mut Entity = ptr::null_mut();
+        // Noita is 32 bit, this doesn't assemble for other targets, e.g. when running tests on the host.
+        #[cfg(not(target_arch = "x86"))]
+        let _ = (entity_manager, grabbed_fns()?.get_entity, &mut entity);
+        #[cfg(target_arch = "x86")]
+        std::arch::asm!(
             "mov ecx, {entity_manager}",
             "push {entity_id:e}",
             "call {get_entity}",
             entity_manager = in(reg) entity_manager,
-            get_entity = in(reg) grabbed_fns().get_entity,
+            get_entity = in(reg) grabbed_fns()?.get_entity,
             entity_id = in(reg) entity_id,
             clobber_abi("C"),
             out("ecx") _,
@@ -137,59 +185,88 @@ fn make_ephemerial(lua: LuaState) -> eyre::Result<()> {
 
 struct InitKV {
     key: String,
-    value: String,
+    value: OptionValue,
 }
 
-impl From<ProxyKV> for InitKV {
-    fn from(value: ProxyKV) -> Self {
-        InitKV {
-            key: value.key,
-            value: value.value,
-        }
-    }
+/// Messages received during connection, and options as `{key, value}` pairs.
+struct Connected {
+    messages: Vec<RawString>,
+    options: Vec<InitKV>,
 }
 
-fn netmanager_connect(_lua: LuaState) -> eyre::Result<Vec<RawString>> {
+fn netmanager_connect(_lua: LuaState) -> eyre::Result<Connected> {
     println!("Connecting to proxy...");
     let mut netman = NetManager::new()?;
 
-    let mut kvs = Vec::new();
+    let mut messages = Vec::new();
+    let mut options = GameOptions::default();
 
     loop {
         match netman.recv()? {
-            NoitaInbound::RawMessage(msg) => kvs.push(msg.into()),
+            NoitaInbound::RawMessage(msg) => messages.push(msg.into()),
+            NoitaInbound::Options(received) => {
+                for key in received.unknown_keys() {
+                    println!("Unknown proxy option: {key}");
+                }
+                options = received;
+            }
             NoitaInbound::Ready { my_peer_id } => {
                 let _ = MY_PEER_ID.set(my_peer_id);
                 break;
             }
+            NoitaInbound::ProxyToDes(shared::des::ProxyToDes::SetTrackingRules(rules)) => {
+                ExtState::with_global(|state| state.lobby_tracking_rules = rules)?
+            }
+            NoitaInbound::ProxyToDes(shared::des::ProxyToDes::SetSpectator(spectator)) => {
+                ExtState::with_global(|state| state.spectator = spectator)?
+            }
             _ => bail!("Received unexpected value during init"),
         }
     }
 
     *NETMANAGER.lock().unwrap() = Some(netman);
     println!("Ok!");
-    Ok(kvs)
+    let kvs = options
+        .values()
+        .iter()
+        .cloned()
+        .map(|(key, value)| InitKV { key, value })
+        .collect();
+    ExtState::with_global(|state| state.options = options)?;
+    Ok(Connected {
+        messages,
+        options: kvs,
+    })
 }
 
 fn netmanager_recv(_lua: LuaState) -> eyre::Result<Option<RawString>> {
+    let _span = profiler::span("netmanager_recv");
     let mut binding = NETMANAGER.lock().unwrap();
     let netmanager = binding.as_mut().unwrap();
     while let Some(msg) = netmanager.try_recv()? {
         match msg {
             NoitaInbound::RawMessage(vec) => return Ok(Some(vec.into())),
             NoitaInbound::Ready { .. } => bail!("Unexpected Ready message"),
-            NoitaInbound::ProxyToDes(proxy_to_des) => ExtState::with_global(|state| {
-                let _lock = IN_MODULE_LOCK.lock().unwrap();
-                if let Some(entity_sync) = &mut state.modules.entity_sync {
-                    entity_sync.handle_proxytodes(proxy_to_des);
-                }
-            })?,
+            NoitaInbound::Options(_) => bail!("Unexpected Options message"),
+            NoitaInbound::ProxyToDes(proxy_to_des) => {
+                ExtState::with_global(|state| -> eyre::Result<()> {
+                    let _lock = IN_MODULE_LOCK.lock().unwrap();
+                    // Modules might not be created yet.
+                    if let shared::des::ProxyToDes::SetSpectator(spectator) = proxy_to_des {
+                        state.spectator = spectator;
+                    }
+                    if let Some(entity_sync) = state.modules.get_mut::<EntitySync>() {
+                        entity_sync.handle_proxytodes(proxy_to_des)?;
+                    }
+                    Ok(())
+                })??
+            }
             NoitaInbound::RemoteMessage {
                 source,
                 message: shared::RemoteMessage::RemoteDes(remote_des),
             } => ExtState::with_global(|state| {
                 let _lock = IN_MODULE_LOCK.lock().unwrap();
-                if let Some(entity_sync) = &mut state.modules.entity_sync {
+                if let Some(entity_sync) = state.modules.get_mut::<EntitySync>() {
                     entity_sync.handle_remotedes(source, remote_des);
                 }
             })?,
@@ -218,28 +295,47 @@ impl LuaFnRet for InitKV {
         lua.create_table(2, 0);
         lua.push_string(&self.key);
         lua.rawset_table(-2, 1);
-        lua.push_string(&self.value);
+        match self.value {
+            OptionValue::Bool(value) => lua.push_bool(value),
+            OptionValue::Num(value) => lua.push_number(value),
+            OptionValue::Str(value) => lua.push_string(&value),
+        }
         lua.rawset_table(-2, 2);
         1
     }
 }
 
-fn on_world_initialized(lua: LuaState) {
+impl LuaFnRet for Connected {
+    fn do_return(self, lua: LuaState) -> c_int {
+        self.messages.do_return(lua) + self.options.do_return(lua)
+    }
+}
+
+fn on_world_initialized(lua: LuaState) -> eyre::Result<()> {
     println!(
         "ewext on_world_initialized in thread {:?}",
         thread::current().id()
     );
-    grab_addrs(lua);
+    let addr_report = grab_addrs(lua);
+    // Lets the proxy warn about an unsupported game build.
+    if let Some(netmanager) = NETMANAGER.lock().unwrap().as_mut() {
+        netmanager.send(&NoitaOutbound::AddrReport(addr_report))?;
+    }
 
-    STATE.with(|state| {
-        let modules = &mut state.borrow_mut().modules;
-        modules.entity_sync = Some(EntitySync::default());
-    })
+    ExtState::with_global(|state| {
+        let ctx = ModuleInitCtx {
+            lobby_tracking_rules: &state.lobby_tracking_rules,
+            spectator: state.spectator,
+        };
+        state.modules = ModuleRegistry::load(&state.options, &ctx)?;
+        Ok(())
+    })?
 }
 
 static IN_MODULE_LOCK: Mutex<()> = Mutex::new(());
 
 fn with_every_module(
+    hook: &'static str,
     f: impl Fn(&mut ModuleCtx, &mut dyn Module) -> eyre::Result<()>,
 ) -> eyre::Result<()> {
     let _lock = IN_MODULE_LOCK.lock().unwrap();
@@ -250,12 +346,7 @@ fn with_every_module(
             net,
             player_map: &mut state.player_entity_map,
         };
-        let mut errs = Vec::new();
-        for module in state.modules.entity_sync.iter_mut() {
-            if let Err(e) = f(&mut ctx, module as &mut dyn Module) {
-                errs.push(e);
-            }
-        }
+        let mut errs = state.modules.run_each(hook, |module| f(&mut ctx, module));
         if errs.len() == 1 {
             return Err(errs.remove(0));
         }
@@ -267,11 +358,17 @@ fn with_every_module(
 }
 
 fn module_on_world_init(_lua: LuaState) -> eyre::Result<()> {
-    with_every_module(|ctx, module| module.on_world_init(ctx))
+    with_every_module("on_world_init", |ctx, module| module.on_world_init(ctx))
 }
 
 fn module_on_world_update(_lua: LuaState) -> eyre::Result<()> {
-    with_every_module(|ctx, module| module.on_world_update(ctx))
+    if let Some(summary) = profiler::next_frame() {
+        let mut binding = NETMANAGER.lock().unwrap();
+        let netmanager = binding.as_mut().ok_or_eyre("Netmanager not available")?;
+        netmanager.send(&NoitaOutbound::ProfileSummary(summary))?;
+    }
+    let _span = profiler::span("module_on_world_update");
+    with_every_module("on_world_update", |ctx, module| module.on_world_update(ctx))
 }
 
 fn module_on_projectile_fired(lua: LuaState) -> eyre::Result<()> {
@@ -283,7 +380,8 @@ fn module_on_projectile_fired(lua: LuaState) -> eyre::Result<()> {
         (shooter_id, projectile_id, initial_rng, position_x),
         (position_y, target_x, target_y, multicast_index),
     ) = noita_api::lua::LuaGetValue::get(lua, -1)?;
-    with_every_module(|ctx, module| {
+    let _span = profiler::span("module_on_projectile_fired");
+    with_every_module("on_projectile_fired", |ctx, module| {
         module.on_projectile_fired(
             ctx,
             shooter_id,
@@ -296,6 +394,11 @@ fn module_on_projectile_fired(lua: LuaState) -> eyre::Result<()> {
     })
 }
 
+/// Time spent in each module, so that slow ones can be found from lua console.
+fn module_timings(_lua: LuaState) -> eyre::Result<RawString> {
+    ExtState::with_global(|state| state.modules.timings_report().into_bytes().into())
+}
+
 fn bench_fn(_lua: LuaState) -> eyre::Result<()> {
     let start = Instant::now();
     let iters = 10000;
@@ -331,8 +434,13 @@ fn test_fn(_lua: LuaState) -> eyre::Result<()> {
         format!("Component: {:?}, Hp: {}", damage_model.0, hp * 25.0,).into(),
     )?;
 
-    let entities = noita_api::raw::entity_get_in_radius_with_tag(x, y, 300.0, "enemy".into())?;
-    noita_api::raw::game_print(format!("{:?}", entities).into())?;
+    let enemies: Vec<_> = EntityQuery::in_radius(x, y, 300.0)
+        .with_tag("enemy")
+        .components::<DamageModelComponent>()?
+        .into_iter()
+        .map(|(entity, damage)| (entity, damage.hp()))
+        .collect();
+    noita_api::raw::game_print(format!("{:?}", enemies).into())?;
 
     // noita::api::raw::entity_set_transform(player, 0.0, 0.0, 0.0, 1.0, 1.0)?;
 
@@ -398,6 +506,7 @@ pub unsafe extern "C" fn luaopen_ewext0(lua: *mut lua_State) -> c_int {
 
         add_lua_fn!(init_particle_world_state);
         add_lua_fn!(encode_area);
+        add_lua_fn!(decode_area);
         add_lua_fn!(make_ephemerial);
         add_lua_fn!(on_world_initialized);
         add_lua_fn!(test_fn);
@@ -412,13 +521,13 @@ pub unsafe extern "C" fn luaopen_ewext0(lua: *mut lua_State) -> c_int {
         add_lua_fn!(module_on_world_init);
         add_lua_fn!(module_on_world_update);
         add_lua_fn!(module_on_projectile_fired);
+        add_lua_fn!(module_timings);
 
         fn des_item_thrown(lua: LuaState) -> eyre::Result<()> {
             ExtState::with_global(|state| {
                 let entity_sync = state
                     .modules
-                    .entity_sync
-                    .as_mut()
+                    .get_mut::<EntitySync>()
                     .ok_or_eyre("No entity sync module loaded")?;
                 let mut temp = NETMANAGER.lock().unwrap();
                 let net = temp.as_mut().ok_or_eyre("Netmanager not available")?;
@@ -432,8 +541,7 @@ pub unsafe extern "C" fn luaopen_ewext0(lua: *mut lua_State) -> c_int {
             ExtState::with_global(|state| {
                 let entity_sync = state
                     .modules
-                    .entity_sync
-                    .as_mut()
+                    .get_mut::<EntitySync>()
                     .ok_or_eyre("No entity sync module loaded")?;
                 let (entity_killed, entity_responsible): (Option<EntityID>, Option<EntityID>) =
                     LuaGetValue::get(lua, -1)?;
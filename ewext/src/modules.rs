@@ -1,18 +1,71 @@
+use std::{any::Any, fmt::Write, time::Duration};
+
 use bimap::BiHashMap;
-use eyre::Ok;
+use eyre::{bail, Ok};
 use noita_api::EntityID;
-use shared::PeerId;
+use shared::{options::GameOptions, PeerId};
 
-use crate::net::NetManager;
+use crate::{net::NetManager, profiler};
 
 pub(crate) mod entity_sync;
 
+/// Every module ewext knows about. Adding a module only requires adding it here.
+const MODULE_DEFS: &[ModuleDef] = &[entity_sync::MODULE_DEF];
+
 pub(crate) struct ModuleCtx<'a> {
     pub(crate) net: &'a mut NetManager,
     pub(crate) player_map: &'a mut BiHashMap<PeerId, EntityID>,
 }
 
-pub(crate) trait Module {
+/// Owns what a [`ModuleCtx`] borrows, with a net manager that isn't connected to a proxy.
+#[cfg(test)]
+pub(crate) struct TestCtx {
+    net: NetManager,
+    player_map: BiHashMap<PeerId, EntityID>,
+    _received: std::sync::mpsc::Sender<shared::NoitaInbound>,
+}
+
+#[cfg(test)]
+impl TestCtx {
+    pub(crate) fn ctx(&mut self) -> ModuleCtx<'_> {
+        ModuleCtx {
+            net: &mut self.net,
+            player_map: &mut self.player_map,
+        }
+    }
+}
+
+/// Context to run modules in tests, along with encoded messages they send to the proxy.
+#[cfg(test)]
+pub(crate) fn test_ctx() -> (TestCtx, std::sync::mpsc::Receiver<Vec<u8>>) {
+    let (net, sent, received) = NetManager::in_memory();
+    let test = TestCtx {
+        net,
+        player_map: BiHashMap::new(),
+        _received: received,
+    };
+    (test, sent)
+}
+
+/// Things modules might need to be created, available once the world is initialized.
+pub(crate) struct ModuleInitCtx<'a> {
+    /// Entity tracking rules from lobby settings.
+    pub(crate) lobby_tracking_rules: &'a str,
+    /// Whether we only watch the run.
+    pub(crate) spectator: bool,
+}
+
+/// Describes a module, so that [`ModuleRegistry`] can create it.
+pub(crate) struct ModuleDef {
+    /// Module can be enabled or disabled with `ewext_module_<name>` proxy option.
+    pub(crate) name: &'static str,
+    /// Modules that have to be enabled for this one to work. They're always updated before this one.
+    pub(crate) depends_on: &'static [&'static str],
+    pub(crate) enabled_by_default: bool,
+    pub(crate) create: fn(&ModuleInitCtx) -> eyre::Result<Box<dyn Module>>,
+}
+
+pub(crate) trait Module: Any {
     // fn init() -> Self;
     fn on_world_init(&mut self, _ctx: &mut ModuleCtx) -> eyre::Result<()> {
         Ok(())
@@ -36,3 +89,193 @@ pub(crate) trait Module {
         Ok(())
     }
 }
+
+/// Time spent in one hook of a module.
+#[derive(Default, Clone, Copy)]
+pub(crate) struct HookTiming {
+    pub(crate) calls: u32,
+    pub(crate) total: Duration,
+    pub(crate) max: Duration,
+}
+
+struct LoadedModule {
+    name: &'static str,
+    module: Box<dyn Module>,
+    timings: Vec<(&'static str, HookTiming)>,
+}
+
+/// Enabled modules, in an order where every module comes after its dependencies.
+#[derive(Default)]
+pub(crate) struct ModuleRegistry {
+    modules: Vec<LoadedModule>,
+}
+
+impl ModuleRegistry {
+    /// Creates every module that is enabled by proxy options, or by default.
+    pub(crate) fn load(options: &GameOptions, ctx: &ModuleInitCtx) -> eyre::Result<Self> {
+        let modules = resolve_order(MODULE_DEFS, |def| {
+            options
+                .get_bool(&format!("ewext_module_{}", def.name))
+                .unwrap_or(def.enabled_by_default)
+        })?
+        .into_iter()
+        .map(|def| {
+            println!("Loading ewext module {}", def.name);
+            Ok(LoadedModule {
+                name: def.name,
+                module: (def.create)(ctx)?,
+                timings: Vec::new(),
+            })
+        })
+        .collect::<eyre::Result<_>>()?;
+        Ok(Self { modules })
+    }
+
+    pub(crate) fn get_mut<T: Module>(&mut self) -> Option<&mut T> {
+        self.modules
+            .iter_mut()
+            .find_map(|loaded| (loaded.module.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    /// Calls `f` for every module in order, recording how long `hook` took.
+    /// Errors don't stop the rest of modules from running, and are returned together.
+    pub(crate) fn run_each(
+        &mut self,
+        hook: &'static str,
+        mut f: impl FnMut(&mut dyn Module) -> eyre::Result<()>,
+    ) -> Vec<eyre::Report> {
+        let mut errs = Vec::new();
+        for loaded in &mut self.modules {
+            // Module time shows up in the profiler too, as a span named after the module.
+            let span = profiler::span(loaded.name);
+            let res = f(loaded.module.as_mut());
+            let elapsed = span.finish();
+
+            let timing = match loaded.timings.iter_mut().find(|(name, _)| *name == hook) {
+                Some((_, timing)) => timing,
+                None => {
+                    loaded.timings.push((hook, HookTiming::default()));
+                    &mut loaded.timings.last_mut().unwrap().1
+                }
+            };
+            timing.calls += 1;
+            timing.total += elapsed;
+            timing.max = timing.max.max(elapsed);
+
+            if let Err(err) = res {
+                errs.push(err.wrap_err(format!("In module {}", loaded.name)));
+            }
+        }
+        errs
+    }
+
+    /// Human readable table of time spent in every hook of every module.
+    pub(crate) fn timings_report(&self) -> String {
+        let mut report = String::new();
+        for loaded in &self.modules {
+            for (hook, timing) in &loaded.timings {
+                let avg = timing.total / timing.calls.max(1);
+                writeln!(
+                    report,
+                    "{}.{}: {} calls, avg {}us, max {}us, total {}ms",
+                    loaded.name,
+                    hook,
+                    timing.calls,
+                    avg.as_micros(),
+                    timing.max.as_micros(),
+                    timing.total.as_millis(),
+                )
+                .unwrap();
+            }
+        }
+        report
+    }
+}
+
+/// Picks enabled modules and orders them after their dependencies.
+/// Modules that depend on a disabled module are skipped.
+fn resolve_order(
+    defs: &[ModuleDef],
+    is_enabled: impl Fn(&ModuleDef) -> bool,
+) -> eyre::Result<Vec<&ModuleDef>> {
+    for def in defs {
+        for dep in def.depends_on {
+            if !defs.iter().any(|other| other.name == *dep) {
+                bail!("Module {} depends on unknown module {}", def.name, dep);
+            }
+        }
+    }
+
+    let mut ordered: Vec<&ModuleDef> = Vec::new();
+    let mut skipped: Vec<&str> = defs
+        .iter()
+        .filter(|def| !is_enabled(def))
+        .map(|def| def.name)
+        .collect();
+    while ordered.len() + skipped.len() < defs.len() {
+        let mut progressed = false;
+        for def in defs {
+            if ordered.iter().any(|o| o.name == def.name) || skipped.contains(&def.name) {
+                continue;
+            }
+            if let Some(dep) = def.depends_on.iter().find(|dep| skipped.contains(dep)) {
+                println!("Not loading module {}, as {} is disabled", def.name, dep);
+                skipped.push(def.name);
+                progressed = true;
+            } else if def
+                .depends_on
+                .iter()
+                .all(|dep| ordered.iter().any(|o| o.name == *dep))
+            {
+                ordered.push(def);
+                progressed = true;
+            }
+        }
+        if !progressed {
+            bail!("Modules have circular dependencies");
+        }
+    }
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn def(name: &'static str, depends_on: &'static [&'static str]) -> ModuleDef {
+        ModuleDef {
+            name,
+            depends_on,
+            enabled_by_default: true,
+            create: |_| bail!("not used"),
+        }
+    }
+
+    fn names(defs: &[&ModuleDef]) -> Vec<&'static str> {
+        defs.iter().map(|def| def.name).collect()
+    }
+
+    #[test]
+    fn modules_come_after_dependencies() -> eyre::Result<()> {
+        let defs = [
+            def("item_sync", &["player_sync", "entity_sync"]),
+            def("player_sync", &["entity_sync"]),
+            def("entity_sync", &[]),
+            def("stats", &[]),
+        ];
+        assert_eq!(
+            names(&resolve_order(&defs, |_| true)?),
+            ["entity_sync", "stats", "player_sync", "item_sync"]
+        );
+        // Disabling a module disables everything that depends on it.
+        assert_eq!(
+            names(&resolve_order(&defs, |def| def.name != "player_sync")?),
+            ["entity_sync", "stats"]
+        );
+
+        let cycle = [def("a", &["b"]), def("b", &["a"])];
+        assert!(resolve_order(&cycle, |_| true).is_err());
+        assert!(resolve_order(&[def("a", &["missing"])], |_| true).is_err());
+        Ok(())
+    }
+}
@@ -2,40 +2,30 @@
 //! The idea is that we completely disregard the normal saving system for entities we sync.
 //! Also, each entity gets an owner.
 //! Each peer broadcasts an "Interest" zone. If it intersects any peer they receive all information about entities this peer owns.
+//! Owners hand entities off to peers that are a lot closer to them, together with their current state.
 
-use std::sync::{Arc, LazyLock};
-
-use diff_model::{entity_is_item, LocalDiffModel, RemoteDiffModel, DES_TAG};
+use diff_model::{decline_authority, LocalDiffModel, RemoteDiffModel, DES_TAG};
 use eyre::{Context, OptionExt};
 use interest::InterestTracker;
-use noita_api::serialize::serialize_entity;
 use noita_api::{game_print, EntityID, ProjectileComponent};
-use rustc_hash::{FxHashMap, FxHashSet};
+use projectiles::{serialize_shot, ProjectileBatcher};
+use rustc_hash::FxHashMap;
 use shared::{
     des::{
-        Gid, InterestRequest, ProjectileFired, RemoteDes, INTEREST_REQUEST_RADIUS,
+        tracking_rules::{TrackingRules, TRACKING_RULES_FILE},
+        Gid, InterestRequest, RemoteDes, SyncMode, INTEREST_REQUEST_RADIUS,
         REQUEST_AUTHORITY_RADIUS,
     },
     Destination, NoitaOutbound, PeerId, RemoteMessage, WorldPos,
 };
 
-use super::{Module, NetManager};
+use crate::print_error;
+
+use super::{Module, ModuleDef, NetManager};
 
 mod diff_model;
 mod interest;
-
-static ENTITY_EXCLUDES: LazyLock<FxHashSet<String>> = LazyLock::new(|| {
-    let mut hs = FxHashSet::default();
-    hs.insert("data/entities/items/pickup/perk.xml".to_string());
-    hs.insert("data/entities/items/pickup/spell_refresh.xml".to_string());
-    hs.insert("data/entities/items/pickup/heart.xml".to_string());
-    hs.insert("data/entities/items/pickup/heart_better.xml".to_string());
-    hs.insert("data/entities/items/pickup/heart_evil.xml".to_string());
-    hs.insert("data/entities/items/pickup/heart_fullhp.xml".to_string());
-    hs.insert("data/entities/items/pickup/heart_fullhp_temple.xml".to_string());
-    hs.insert("data/entities/items/pickup/perk_reroll.xml".to_string());
-    hs
-});
+mod projectiles;
 
 pub(crate) struct EntitySync {
     /// Last entity we stopped looking for new tracked entities at.
@@ -45,37 +35,94 @@ pub(crate) struct EntitySync {
     local_diff_model: LocalDiffModel,
     remote_models: FxHashMap<PeerId, RemoteDiffModel>,
 
-    pending_fired_projectiles: Arc<Vec<ProjectileFired>>,
-}
+    projectile_batcher: ProjectileBatcher,
 
-impl Default for EntitySync {
-    fn default() -> Self {
-        Self {
-            look_current_entity: EntityID::try_from(1).unwrap(),
+    /// Decide which entities get tracked, and how they are synced.
+    tracking_rules: TrackingRules,
+    /// Spectators only receive entities, they never track them or take authority over them.
+    spectator: bool,
+}
 
-            interest_tracker: InterestTracker::new(512.0),
-            local_diff_model: LocalDiffModel::default(),
-            remote_models: Default::default(),
+pub(crate) const MODULE_DEF: ModuleDef = ModuleDef {
+    name: "entity_sync",
+    depends_on: &[],
+    enabled_by_default: true,
+    create: |ctx| {
+        Ok(Box::new(EntitySync::new(
+            ctx.lobby_tracking_rules,
+            ctx.spectator,
+        )?))
+    },
+};
 
-            pending_fired_projectiles: Vec::new().into(),
+/// Rules file as shipped with the mod, used when the rules file was broken by other mods.
+const DEFAULT_TRACKING_RULES: &str =
+    include_str!("../../../quant.ew/files/system/entity_sync_helper/tracking_rules.txt");
+
+/// Loads tracking rules from the mod's rules file, and applies lobby rules on top of them.
+fn load_tracking_rules(lobby_rules: &str) -> eyre::Result<TrackingRules> {
+    let mod_rules = noita_api::raw::mod_text_file_get_content(TRACKING_RULES_FILE.into())?;
+    let mut rules = match TrackingRules::parse(&mod_rules) {
+        Ok(rules) => rules,
+        Err(err) => {
+            print_error(err.wrap_err("Invalid tracking rules of the mod, using default ones"))?;
+            TrackingRules::parse(DEFAULT_TRACKING_RULES)
+                .wrap_err("Failed to parse default tracking rules")?
         }
+    };
+    match TrackingRules::parse(lobby_rules) {
+        Ok(lobby_rules) => rules.extend(lobby_rules),
+        Err(err) => print_error(err.wrap_err("Ignoring invalid lobby tracking rules"))?,
     }
+    Ok(rules)
 }
 
-fn entity_is_excluded(entity: EntityID) -> eyre::Result<bool> {
+/// Returns how entity should be synced according to the rules, or None if it shouldn't be tracked.
+pub(crate) fn sync_mode_for(
+    rules: &TrackingRules,
+    entity: EntityID,
+) -> eyre::Result<Option<SyncMode>> {
     let filename = entity.filename()?;
-    Ok(entity.has_tag("ew_no_enemy_sync")
-        || entity.has_tag("polymorphed_player")
-        || entity.has_tag("gold_nugget")
-        || ENTITY_EXCLUDES.contains(&filename))
+    Ok(rules.evaluate(
+        &filename,
+        |tag| entity.has_tag(tag),
+        |component| {
+            noita_api::raw::entity_get_first_component_including_disabled(
+                entity,
+                component.into(),
+                None,
+            )
+            .ok()
+            .flatten()
+            .flatten()
+            .is_some()
+        },
+    ))
+}
+
+/// Sync mode for entities that were synced already, which have to stay tracked even if the rules exclude them.
+/// Nobody else would sync them or give their authority back otherwise.
+pub(crate) fn sync_mode_or_default(
+    rules: &TrackingRules,
+    entity: EntityID,
+) -> eyre::Result<SyncMode> {
+    Ok(sync_mode_for(rules, entity)?.unwrap_or_default())
 }
 
 impl EntitySync {
-    fn should_be_tracked(&mut self, entity: EntityID) -> eyre::Result<bool> {
-        let should_be_tracked =
-            entity.has_tag("enemy") || entity.has_tag("ew_synced") || entity_is_item(entity)?;
+    pub(crate) fn new(lobby_rules: &str, spectator: bool) -> eyre::Result<Self> {
+        Ok(Self {
+            look_current_entity: EntityID::try_from(1).unwrap(),
 
-        Ok(should_be_tracked && !entity_is_excluded(entity)?)
+            interest_tracker: InterestTracker::new(512.0),
+            local_diff_model: LocalDiffModel::default(),
+            remote_models: Default::default(),
+
+            projectile_batcher: ProjectileBatcher::new(),
+
+            tracking_rules: load_tracking_rules(lobby_rules)?,
+            spectator,
+        })
     }
 
     /// Looks for newly spawned entities that might need to be tracked.
@@ -90,10 +137,13 @@ impl EntitySync {
                 entity.kill();
                 continue;
             }
-            if self.should_be_tracked(entity)? {
+            if self.spectator {
+                continue;
+            }
+            if let Some(sync_mode) = sync_mode_for(&self.tracking_rules, entity)? {
                 let gid = Gid(rand::random());
                 self.local_diff_model
-                    .track_and_upload_entity(ctx.net, entity, gid)?;
+                    .track_and_upload_entity(ctx.net, entity, gid, sync_mode)?;
             }
         }
 
@@ -101,12 +151,25 @@ impl EntitySync {
         Ok(())
     }
 
-    pub(crate) fn handle_proxytodes(&mut self, proxy_to_des: shared::des::ProxyToDes) {
+    pub(crate) fn handle_proxytodes(
+        &mut self,
+        proxy_to_des: shared::des::ProxyToDes,
+    ) -> eyre::Result<()> {
         match proxy_to_des {
             shared::des::ProxyToDes::GotAuthority(full_entity_data) => {
                 self.local_diff_model.got_authority(full_entity_data);
             }
+            shared::des::ProxyToDes::SetTrackingRules(lobby_rules) => {
+                self.tracking_rules = load_tracking_rules(&lobby_rules)?;
+            }
+            shared::des::ProxyToDes::SetSpectator(spectator) => {
+                self.spectator = spectator;
+            }
+            shared::des::ProxyToDes::LostAuthority(gid) => {
+                self.local_diff_model.lost_authority(gid);
+            }
         }
+        Ok(())
     }
 
     pub(crate) fn handle_remotedes(&mut self, source: PeerId, remote_des: RemoteDes) {
@@ -123,12 +186,16 @@ impl EntitySync {
             RemoteDes::ExitedInterest => {
                 self.remote_models.remove(&source);
             }
-            RemoteDes::Reset => self.interest_tracker.reset_interest_for(source),
-            RemoteDes::Projectiles(vec) => {
+            RemoteDes::Reset => {
+                self.interest_tracker.reset_interest_for(source);
+                self.local_diff_model.reset_diff_encoding_for(source);
+                self.projectile_batcher.forget_peer(source);
+            }
+            RemoteDes::Projectiles(batch) => {
                 self.remote_models
                     .entry(source)
                     .or_default()
-                    .spawn_projectiles(&vec);
+                    .spawn_projectiles(&batch);
             }
             RemoteDes::RequestGrab(lid) => {
                 self.local_diff_model.entity_grabbed(source, lid);
@@ -142,8 +209,17 @@ impl EntitySync {
         entity: Option<EntityID>,
     ) -> eyre::Result<()> {
         let entity = entity.ok_or_eyre("Passed entity 0 into cross call")?;
-        self.local_diff_model
-            .track_and_upload_entity(net, entity, Gid(rand::random()))?;
+        if self.spectator {
+            return Ok(());
+        }
+        if let Some(sync_mode) = sync_mode_for(&self.tracking_rules, entity)? {
+            self.local_diff_model.track_and_upload_entity(
+                net,
+                entity,
+                Gid(rand::random()),
+                sync_mode,
+            )?;
+        }
         Ok(())
     }
 
@@ -167,6 +243,10 @@ impl Module for EntitySync {
     fn on_world_update(&mut self, ctx: &mut super::ModuleCtx) -> eyre::Result<()> {
         self.look_for_tracked(ctx)
             .wrap_err("Error in look_for_tracked")?;
+        if self.spectator && self.local_diff_model.release_all(ctx)? {
+            // Peers drop their copies, and get them again from whoever takes authority next.
+            send_remotedes(ctx, true, Destination::Broadcast, RemoteDes::ExitedInterest)?;
+        }
 
         let (x, y) = noita_api::raw::game_get_camera_pos()?;
         self.interest_tracker.set_center(x, y);
@@ -184,6 +264,8 @@ impl Module for EntitySync {
         }
 
         for lost in self.interest_tracker.drain_lost_interest() {
+            self.local_diff_model.reset_diff_encoding_for(lost);
+            self.projectile_batcher.forget_peer(lost);
             send_remotedes(
                 ctx,
                 true,
@@ -192,45 +274,74 @@ impl Module for EntitySync {
             )?;
         }
 
-        self.local_diff_model.update_pending_authority()?;
+        self.local_diff_model
+            .update_pending_authority(&self.tracking_rules)?;
 
-        if frame_num % 2 == 0 {
+        if frame_num % 2 == 0 && self.spectator {
+            // Spectators have no entities of their own to send.
+            self.projectile_batcher.clear();
+        } else if frame_num % 2 == 0 {
             self.local_diff_model
                 .update_tracked_entities(ctx)
                 .wrap_err("Failed to update locally tracked entities")?;
-            if self.interest_tracker.got_any_new_interested() {
+            self.local_diff_model
+                .update_authority_transfers(ctx, self.interest_tracker.iter_interested())
+                .wrap_err("Failed to hand off authority")?;
+            for peer in self.interest_tracker.drain_new_interested() {
                 game_print("Got new interested");
-                self.local_diff_model.reset_diff_encoding();
+                self.local_diff_model.reset_diff_encoding_for(peer);
+                self.projectile_batcher.forget_peer(peer);
             }
-            let diff = self.local_diff_model.make_diff(ctx);
-            // FIXME (perf): allow a Destination that can send to several peers at once, to prevent cloning and stuff.
-            for peer in self.interest_tracker.iter_interested() {
-                send_remotedes(
-                    ctx,
-                    true,
-                    Destination::Peer(peer),
-                    RemoteDes::Projectiles(self.pending_fired_projectiles.clone()),
-                )?;
+            let diffs = self
+                .local_diff_model
+                .make_diffs(ctx, self.interest_tracker.iter_interested());
+            self.projectile_batcher.apply_rate_cap();
+            for (peer, diff) in diffs {
+                // Only send projectiles whose shooter this peer can actually see.
+                let batch = self.projectile_batcher.batch_for(peer, |shooter_lid| {
+                    self.local_diff_model.was_sent_to(peer, shooter_lid)
+                });
+                if let Some(batch) = batch {
+                    send_remotedes(
+                        ctx,
+                        true,
+                        Destination::Peer(peer),
+                        RemoteDes::Projectiles(batch),
+                    )?;
+                }
                 send_remotedes(
                     ctx,
                     true,
                     Destination::Peer(peer),
-                    RemoteDes::EntityUpdate(diff.clone()),
+                    RemoteDes::EntityUpdate(diff),
                 )?;
             }
-            Arc::make_mut(&mut self.pending_fired_projectiles).clear();
+            self.projectile_batcher.clear();
         } else {
             for (owner, remote_model) in &mut self.remote_models {
                 remote_model
                     .apply_entities(ctx)
                     .wrap_err("Failed to apply entity infos")?;
                 for entity in remote_model.drain_backtrack() {
+                    if self.spectator {
+                        continue;
+                    }
+                    let sync_mode = sync_mode_or_default(&self.tracking_rules, entity)?;
                     self.local_diff_model.track_and_upload_entity(
                         ctx.net,
                         entity,
                         Gid(rand::random()),
+                        sync_mode,
                     )?;
                 }
+                for (remote_entity, gid, info) in remote_model.drain_adopted() {
+                    if self.spectator {
+                        decline_authority(ctx.net, remote_entity, gid, &info)?;
+                    } else {
+                        self.local_diff_model
+                            .adopt_entity(ctx.net, remote_entity, gid, info)?;
+                    }
+                }
                 for lid in remote_model.drain_grab_request() {
                     send_remotedes(
                         ctx,
@@ -242,7 +353,7 @@ impl Module for EntitySync {
             }
         }
 
-        if frame_num % 60 == 0 {
+        if frame_num % 60 == 0 && !self.spectator {
             let (x, y) = noita_api::raw::game_get_camera_pos()?;
             ctx.net.send(&NoitaOutbound::DesToProxy(
                 shared::des::DesToProxy::RequestAuthority {
@@ -286,14 +397,10 @@ impl Module for EntitySync {
             return Ok(());
         }
 
-        let serialized = serialize_entity(projectile)?;
+        let (serialized, velocity) = serialize_shot(projectile)?;
 
-        Arc::make_mut(&mut self.pending_fired_projectiles).push(ProjectileFired {
-            shooter_lid,
-            position,
-            target,
-            serialized,
-        });
+        self.projectile_batcher
+            .push(shooter_lid, position, velocity, target, serialized);
 
         //TODO initial_rng might need to be handled with np.SetProjectileSpreadRNG?
 
@@ -314,3 +421,142 @@ fn send_remotedes(
     })?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc, sync::mpsc::Receiver};
+
+    use interest::PeerInterest;
+    use noita_api::{
+        fake_world::{FakeEntityFile, FakeWorld},
+        lua::LuaState,
+    };
+    use shared::des::{DesToProxy, ProxyToDes};
+
+    use super::*;
+    use crate::modules::test_ctx;
+
+    const RAT: &str = "data/entities/animals/rat.xml";
+
+    fn fake_world() -> Rc<RefCell<FakeWorld>> {
+        let mut world = FakeWorld::default();
+        world.add_file(
+            RAT,
+            FakeEntityFile::default()
+                .with_tag("enemy")
+                .with_component(
+                    "VelocityComponent",
+                    [("mVelocity", vec![0.0.into(), 0.0.into()])],
+                )
+                .with_component("DamageModelComponent", [("hp", vec![4.0.into()])]),
+        );
+        world.set_handler("ModTextFileGetContent", |_, _| {
+            Ok(vec![DEFAULT_TRACKING_RULES.into()])
+        });
+        world.set_handler("EwextPrintError", |_, args| panic!("{}", args[0]));
+        // Same as in game, first entity isn't looked at as it's the world itself.
+        world.create_entity("world", "");
+        let world = Rc::new(RefCell::new(world));
+        LuaState::new_fake(world.clone()).make_current();
+        world
+    }
+
+    fn sent_to_proxy(sent: &Receiver<Vec<u8>>) -> Vec<DesToProxy> {
+        sent.try_iter()
+            .filter_map(|msg| match bitcode::decode(&msg).unwrap() {
+                NoitaOutbound::DesToProxy(msg) => Some(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spectator_never_takes_authority() -> eyre::Result<()> {
+        let world = fake_world();
+        let (mut test, sent) = test_ctx();
+        let mut ctx = test.ctx();
+        let mut sync = EntitySync::new("", true)?;
+
+        // Enemies spawned in the spectator's world aren't tracked, and nothing is requested.
+        let wild = EntityID::load(RAT, Some(0.0), Some(0.0))?;
+        for frame in 0..=60 {
+            world.borrow_mut().frame_num = frame;
+            sync.on_world_update(&mut ctx)?;
+        }
+        assert!(sync.local_diff_model.lid_by_entity(wild).is_none());
+        assert!(sent_to_proxy(&sent).is_empty());
+
+        // An entity handed off to the spectator goes back to the proxy instead.
+        let (mut owner_test, _owner_sent) = test_ctx();
+        let mut owner_ctx = owner_test.ctx();
+        let (owner, spectator) = (PeerId(2), PeerId(3));
+        let interest = PeerInterest {
+            x: 500.0,
+            y: 0.0,
+            radius: 1000.0,
+        };
+        let mut owner_model = LocalDiffModel::default();
+        let rat = EntityID::load(RAT, Some(15.0), Some(25.0))?;
+        owner_model.track_entity(rat, Gid(7), SyncMode::SpawnOnly)?;
+        for (_, diff) in owner_model.make_diffs(&mut owner_ctx, [(spectator, interest)].into_iter())
+        {
+            sync.handle_remotedes(owner, RemoteDes::EntityUpdate(diff));
+        }
+        rat.set_position(450.0, 0.0)?;
+        owner_model.update_tracked_entities(&mut owner_ctx)?;
+        owner_model
+            .update_authority_transfers(&mut owner_ctx, [(spectator, interest)].into_iter())?;
+        assert!(!rat.is_alive());
+        for (_, diff) in owner_model.make_diffs(&mut owner_ctx, [(spectator, interest)].into_iter())
+        {
+            sync.handle_remotedes(owner, RemoteDes::EntityUpdate(diff));
+        }
+
+        world.borrow_mut().frame_num = 61;
+        sync.on_world_update(&mut ctx)?;
+        let sent = sent_to_proxy(&sent);
+        assert!(matches!(
+            sent[..],
+            [
+                DesToProxy::UpdatePositions(ref positions),
+                DesToProxy::ReleaseAuthority(Gid(7)),
+            ] if positions[0].gid == Gid(7)
+        ));
+        let entities: Vec<_> = world
+            .borrow()
+            .entities()
+            .map(|(entity, _)| entity)
+            .collect();
+        assert!(entities
+            .into_iter()
+            .all(|entity| sync.local_diff_model.lid_by_entity(entity).is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn becoming_spectator_releases_entities() -> eyre::Result<()> {
+        let world = fake_world();
+        let (mut test, sent) = test_ctx();
+        let mut ctx = test.ctx();
+        let mut sync = EntitySync::new("", false)?;
+
+        let rat = EntityID::load(RAT, Some(0.0), Some(0.0))?;
+        world.borrow_mut().frame_num = 1;
+        sync.on_world_update(&mut ctx)?;
+        assert!(sync.local_diff_model.lid_by_entity(rat).is_some());
+        assert!(matches!(
+            sent_to_proxy(&sent)[..],
+            [DesToProxy::InitOrUpdateEntity(_)]
+        ));
+
+        sync.handle_proxytodes(ProxyToDes::SetSpectator(true))?;
+        world.borrow_mut().frame_num = 2;
+        sync.on_world_update(&mut ctx)?;
+        assert!(!rat.is_alive());
+        assert!(sync.local_diff_model.lid_by_entity(rat).is_none());
+        assert!(sent_to_proxy(&sent)
+            .iter()
+            .any(|msg| matches!(msg, DesToProxy::ReleaseAuthority(_))));
+        Ok(())
+    }
+}
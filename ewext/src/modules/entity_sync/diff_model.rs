@@ -12,30 +12,48 @@ use noita_api::{
 use rustc_hash::FxHashMap;
 use shared::{
     des::{
-        EntityInfo, EntityKind, EntitySpawnInfo, EntityUpdate, FullEntityData, Gid, Lid,
-        PhysBodyInfo, ProjectileFired, UpdatePosition, AUTHORITY_RADIUS,
+        tracking_rules::TrackingRules, EntityInfo, EntityKind, EntitySpawnInfo, EntityUpdate,
+        FullEntityData, Gid, Lid, PhysBodyInfo, ProjectileBatch, SyncMode, UpdatePosition,
+        AUTHORITY_HANDOFF_HYSTERESIS, AUTHORITY_RADIUS,
     },
     NoitaOutbound, PeerId, WorldPos,
 };
 
 use crate::{modules::ModuleCtx, my_peer_id, print_error};
 
-use super::NetManager;
+use super::{
+    interest::PeerInterest, projectiles::ProjectileCache, sync_mode_or_default, NetManager,
+};
 
 pub(crate) static DES_TAG: &str = "ew_des";
 pub(crate) static DES_SCRIPTS_TAG: &str = "ew_des_lua";
 
+/// Entities stay visible to a peer until they are this much further away than the peer's interest radius.
+const VISIBILITY_HYSTERESIS: f32 = 64.0;
+
 struct EntityEntryPair {
-    last: Option<EntityInfo>,
+    /// State that was last sent to each peer that can see this entity.
+    last: FxHashMap<PeerId, EntityInfo>,
     current: EntityInfo,
     gid: Gid,
 }
 
+/// Entity that is being handed off to a different peer. It's already untracked locally.
+struct PendingTransfer {
+    lid: Lid,
+    gid: Gid,
+    to: PeerId,
+    info: EntityInfo,
+    /// Peers that have this entity spawned.
+    sent_to: Vec<PeerId>,
+}
+
 struct LocalDiffModelTracker {
     tracked: BiHashMap<Lid, EntityID>,
     pending_removal: Vec<Lid>,
     pending_authority: Vec<FullEntityData>,
     pending_localize: Vec<(Lid, PeerId)>,
+    pending_transfer: Vec<PendingTransfer>,
     /// Stores pairs of entity killed and optionally the responsible entity.
     pending_death_notify: Vec<(EntityID, Option<EntityID>)>,
     authority_radius: f32,
@@ -43,6 +61,8 @@ struct LocalDiffModelTracker {
 
 pub(crate) struct LocalDiffModel {
     next_lid: Lid,
+    /// Incremented every time diffs are made, used to spread out updates of far away entities.
+    diff_tick: u32,
     entity_entries: FxHashMap<Lid, EntityEntryPair>,
     tracker: LocalDiffModelTracker,
 }
@@ -53,21 +73,26 @@ pub(crate) struct RemoteDiffModel {
     entity_infos: FxHashMap<Lid, EntityInfo>,
     /// Entities that we want to track again. Typically when we move authority locally from a different peer.
     backtrack: Vec<EntityID>,
+    /// Entities we got authority over from their owner, along with their last state.
+    adopted: Vec<(Option<EntityID>, Gid, EntityInfo)>,
     grab_request: Vec<Lid>,
     pending_remove: Vec<Lid>,
     pending_death_notify: Vec<(Lid, Option<PeerId>)>,
+    projectile_cache: ProjectileCache,
 }
 
 impl Default for LocalDiffModel {
     fn default() -> Self {
         Self {
             next_lid: Lid(0),
+            diff_tick: 0,
             entity_entries: Default::default(),
             tracker: LocalDiffModelTracker {
                 tracked: Default::default(),
                 pending_removal: Vec::with_capacity(16),
                 pending_authority: Vec::new(),
                 pending_localize: Vec::with_capacity(4),
+                pending_transfer: Vec::new(),
                 pending_death_notify: Vec::with_capacity(4),
                 authority_radius: AUTHORITY_RADIUS,
             },
@@ -107,7 +132,7 @@ impl LocalDiffModelTracker {
         };
 
         let (x, y) = entity.position()?;
-        if should_send_position {
+        if should_send_position && info.sync_mode != SyncMode::SpawnOnly {
             info.x = x;
             info.y = y;
         }
@@ -119,11 +144,20 @@ impl LocalDiffModelTracker {
             return Ok(());
         }
 
+        if info.sync_mode == SyncMode::SpawnOnly {
+            return Ok(());
+        }
+
         if let Some(vel) = entity.try_get_first_component::<VelocityComponent>(None)? {
             let (vx, vy) = vel.m_velocity()?;
             info.vx = vx;
             info.vy = vy;
         }
+
+        if info.sync_mode == SyncMode::PositionOnly {
+            return Ok(());
+        }
+
         if let Some(damage) = entity.try_get_first_component::<DamageModelComponent>(None)? {
             let hp = damage.hp()?;
             info.hp = hp as f32;
@@ -210,7 +244,12 @@ impl LocalDiffModel {
         ret
     }
 
-    pub(crate) fn track_entity(&mut self, entity: EntityID, gid: Gid) -> eyre::Result<Lid> {
+    pub(crate) fn track_entity(
+        &mut self,
+        entity: EntityID,
+        gid: Gid,
+        sync_mode: SyncMode,
+    ) -> eyre::Result<Lid> {
         let lid = self.alloc_lid();
         entity.remove_all_components_of_type::<CameraBoundComponent>()?;
         entity.add_tag(DES_TAG)?;
@@ -236,10 +275,11 @@ impl LocalDiffModel {
         self.entity_entries.insert(
             lid,
             EntityEntryPair {
-                last: None,
+                last: Default::default(),
                 current: EntityInfo {
                     spawn_info,
                     kind: entity_kind,
+                    sync_mode,
                     x,
                     y,
                     vx: 0.0,
@@ -262,8 +302,9 @@ impl LocalDiffModel {
         net: &mut NetManager,
         entity: EntityID,
         gid: Gid,
+        sync_mode: SyncMode,
     ) -> eyre::Result<Lid> {
-        let lid = self.track_entity(entity, gid)?;
+        let lid = self.track_entity(entity, gid, sync_mode)?;
         net.send(&NoitaOutbound::DesToProxy(
             shared::des::DesToProxy::InitOrUpdateEntity(
                 self.full_entity_data_for(lid)
@@ -273,20 +314,141 @@ impl LocalDiffModel {
         Ok(lid)
     }
 
-    pub(crate) fn reset_diff_encoding(&mut self) {
+    /// Forgets everything that was sent to this peer, so that it gets full entity info again.
+    pub(crate) fn reset_diff_encoding_for(&mut self, peer: PeerId) {
         for entry_pair in &mut self.entity_entries.values_mut() {
-            entry_pair.last = None;
+            entry_pair.last.remove(&peer);
         }
     }
 
-    pub(crate) fn update_pending_authority(&mut self) -> eyre::Result<()> {
+    pub(crate) fn update_pending_authority(&mut self, rules: &TrackingRules) -> eyre::Result<()> {
         for entity_data in mem::take(&mut self.tracker.pending_authority) {
             let entity = spawn_entity_by_data(
                 &entity_data.data,
                 entity_data.pos.x as f32,
                 entity_data.pos.y as f32,
             )?;
-            self.track_entity(entity, entity_data.gid)?;
+            // Proxy already has us as the authority of this gid.
+            let sync_mode = sync_mode_or_default(rules, entity)?;
+            self.track_entity(entity, entity_data.gid, sync_mode)?;
+        }
+        Ok(())
+    }
+
+    /// Starts tracking an entity that authority was handed off to us for, restoring its state.
+    /// Remote copy of the entity had its AI stripped, so it's replaced with the entity as it was on the previous authority.
+    pub(crate) fn adopt_entity(
+        &mut self,
+        net: &mut NetManager,
+        remote_entity: Option<EntityID>,
+        gid: Gid,
+        info: EntityInfo,
+    ) -> eyre::Result<()> {
+        if let Some(remote_entity) = remote_entity {
+            safe_entitykill(remote_entity);
+        }
+        let entity = spawn_entity_by_data(&info.spawn_info, info.x, info.y)?;
+        apply_entity_info(entity, &info)?;
+        let lid = self.track_entity(entity, gid, info.sync_mode)?;
+        if let Some(entry) = self.entity_entries.get_mut(&lid) {
+            entry.current = info;
+        }
+        net.send(&NoitaOutbound::DesToProxy(
+            shared::des::DesToProxy::InitOrUpdateEntity(
+                self.full_entity_data_for(lid)
+                    .ok_or_eyre("entity just began being tracked")?,
+            ),
+        ))?;
+        Ok(())
+    }
+
+    /// Gives authority over every tracked entity back to the proxy, for when we become a spectator.
+    /// Returns false if there was nothing to release.
+    pub(crate) fn release_all(&mut self, ctx: &mut ModuleCtx) -> eyre::Result<bool> {
+        if self.entity_entries.is_empty() && self.tracker.pending_transfer.is_empty() {
+            return Ok(false);
+        }
+        for (&lid, entry) in &self.entity_entries {
+            if self.tracker.tracked.contains_left(&lid) {
+                self.tracker.release_authority(ctx, entry.gid, lid)?;
+            }
+        }
+        // New authority won't be told about these, so the proxy gets them back instead.
+        for transfer in mem::take(&mut self.tracker.pending_transfer) {
+            decline_authority(ctx.net, None, transfer.gid, &transfer.info)?;
+        }
+        // Nothing is sent to peers anymore, so there is no one to tell about these.
+        *self = Self {
+            next_lid: self.next_lid,
+            ..Default::default()
+        };
+        Ok(true)
+    }
+
+    /// Hands off authority over entities to interested peers that are a lot closer to them than we are.
+    /// Only peers that already have the entity spawned are considered.
+    pub(crate) fn update_authority_transfers(
+        &mut self,
+        ctx: &mut ModuleCtx,
+        peers: impl Iterator<Item = (PeerId, PeerInterest)>,
+    ) -> eyre::Result<()> {
+        let peers: Vec<_> = peers.collect();
+        if peers.is_empty() {
+            return Ok(());
+        }
+        let (cam_x, cam_y) = noita_api::raw::game_get_camera_pos()?;
+        let (cam_x, cam_y) = (cam_x as f32, cam_y as f32);
+
+        let mut transfers = Vec::new();
+        for (&lid, entry) in &self.entity_entries {
+            let (x, y) = if entry.current.sync_mode == SyncMode::SpawnOnly {
+                // Position of these isn't kept up to date.
+                match self.tracker.tracked.get_by_left(&lid) {
+                    Some(entity) => entity.position()?,
+                    None => continue,
+                }
+            } else {
+                (entry.current.x, entry.current.y)
+            };
+            let own_dist = ((x - cam_x).powi(2) + (y - cam_y).powi(2)).sqrt();
+            let closest = peers
+                .iter()
+                .filter(|(peer, _)| entry.last.contains_key(peer))
+                .map(|(peer, interest)| (*peer, interest.dist_sq(x, y).sqrt()))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((peer, dist)) = closest {
+                if dist + AUTHORITY_HANDOFF_HYSTERESIS < own_dist
+                    && dist < self.tracker.authority_radius
+                {
+                    transfers.push((lid, peer));
+                }
+            }
+        }
+
+        for (lid, to) in transfers {
+            let Some(mut entry) = self.entity_entries.remove(&lid) else {
+                continue;
+            };
+            if let Some((_, entity)) = self.tracker.tracked.remove_by_left(&lid) {
+                // New authority spawns the entity where it is right now, not where it was last synced.
+                (entry.current.x, entry.current.y) = entity.position()?;
+                // Whole entity is handed off, so that it keeps its AI state, wands and status effects.
+                entry.current.spawn_info = EntitySpawnInfo::Serialized {
+                    serialized_at: noita_api::raw::game_get_frame_num()?,
+                    data: serialize_entity(entity)?,
+                };
+                safe_entitykill(entity);
+            }
+            ctx.net.send(&NoitaOutbound::DesToProxy(
+                shared::des::DesToProxy::TransferAuthority { gid: entry.gid, to },
+            ))?;
+            self.tracker.pending_transfer.push(PendingTransfer {
+                lid,
+                gid: entry.gid,
+                to,
+                info: entry.current,
+                sent_to: entry.last.into_keys().collect(),
+            });
         }
         Ok(())
     }
@@ -316,7 +478,89 @@ impl LocalDiffModel {
         Ok(())
     }
 
-    pub(crate) fn make_diff(&mut self, ctx: &mut ModuleCtx) -> Vec<EntityUpdate> {
+    /// Makes a separate diff for every interested peer.
+    /// Peers only receive entities that are inside of their interest radius, and entities that are further away are updated less often.
+    pub(crate) fn make_diffs(
+        &mut self,
+        ctx: &mut ModuleCtx,
+        peers: impl Iterator<Item = (PeerId, PeerInterest)>,
+    ) -> Vec<(PeerId, Vec<EntityUpdate>)> {
+        self.diff_tick = self.diff_tick.wrapping_add(1);
+
+        let localized: Vec<_> = self.tracker.pending_localize.drain(..).collect();
+        let killed: Vec<_> = self
+            .tracker
+            .pending_death_notify
+            .drain(..)
+            .filter_map(|(killed, responsible)| {
+                let responsible_peer = responsible
+                    .and_then(|ent| ctx.player_map.get_by_right(&ent))
+                    .copied();
+                let lid = self.tracker.tracked.get_by_right(&killed).copied()?;
+                Some((lid, responsible_peer))
+            })
+            .collect();
+        let removed: Vec<_> = self.tracker.pending_removal.drain(..).collect();
+        let transferred = mem::take(&mut self.tracker.pending_transfer);
+
+        let mut diffs = Vec::new();
+        for (peer, interest) in peers {
+            let mut res = self.make_diff_for(peer, interest);
+
+            for transfer in &transferred {
+                if transfer.to == peer {
+                    res.push(EntityUpdate::TransferAuthority {
+                        lid: transfer.lid,
+                        gid: transfer.gid,
+                        info: transfer.info.clone(),
+                    });
+                } else if transfer.sent_to.contains(&peer) {
+                    res.push(EntityUpdate::LocalizeEntity(transfer.lid, transfer.to));
+                }
+            }
+
+            for &(lid, peer) in &localized {
+                res.push(EntityUpdate::LocalizeEntity(lid, peer));
+            }
+            for &(lid, responsible_peer) in &killed {
+                if self.was_sent_to(peer, lid) {
+                    res.push(EntityUpdate::KillEntity {
+                        lid,
+                        responsible_peer,
+                    });
+                }
+            }
+            for &lid in &removed {
+                if self.was_sent_to(peer, lid) {
+                    res.push(EntityUpdate::RemoveEntity(lid));
+                }
+            }
+            diffs.push((peer, res));
+        }
+
+        // New authority has to get the entity even if it stopped being interested in us in the meantime.
+        for transfer in transferred {
+            if !diffs.iter().any(|(peer, _)| *peer == transfer.to) {
+                diffs.push((
+                    transfer.to,
+                    vec![EntityUpdate::TransferAuthority {
+                        lid: transfer.lid,
+                        gid: transfer.gid,
+                        info: transfer.info,
+                    }],
+                ));
+            }
+        }
+
+        for lid in removed {
+            // "Untrack" entity
+            self.tracker.tracked.remove_by_left(&lid);
+            self.entity_entries.remove(&lid);
+        }
+        diffs
+    }
+
+    fn make_diff_for(&mut self, peer: PeerId, interest: PeerInterest) -> Vec<EntityUpdate> {
         let mut res = Vec::new();
         for (
             &lid,
@@ -327,12 +571,26 @@ impl LocalDiffModel {
             },
         ) in &mut self.entity_entries
         {
-            res.push(EntityUpdate::CurrentEntity(lid));
-            let Some(last) = last.as_mut() else {
-                res.push(EntityUpdate::Init(current.clone()));
-                *last = Some(current.clone());
+            let dist_sq = interest.dist_sq(current.x, current.y);
+            if dist_sq > (interest.radius + VISIBILITY_HYSTERESIS).powi(2) {
+                if last.remove(&peer).is_some() {
+                    res.push(EntityUpdate::RemoveEntity(lid));
+                }
+                continue;
+            }
+            let Some(last) = last.get_mut(&peer) else {
+                if dist_sq < interest.radius.powi(2) {
+                    res.push(EntityUpdate::CurrentEntity(lid));
+                    res.push(EntityUpdate::Init(current.clone()));
+                    last.insert(peer, current.clone());
+                }
                 continue;
             };
+            let interval = update_interval(dist_sq, interest.radius);
+            if (self.diff_tick.wrapping_add(lid.0)) % interval != 0 {
+                continue;
+            }
+            res.push(EntityUpdate::CurrentEntity(lid));
             let mut had_any_delta = false;
             if current.x != last.x || current.y != last.y {
                 res.push(EntityUpdate::SetPosition(current.x, current.y));
@@ -370,32 +628,17 @@ impl LocalDiffModel {
                 res.pop();
             }
         }
-        for (lid, peer) in self.tracker.pending_localize.drain(..) {
-            res.push(EntityUpdate::LocalizeEntity(lid, peer));
-        }
-
-        for (killed, responsible) in self.tracker.pending_death_notify.drain(..) {
-            let responsible_peer = responsible
-                .and_then(|ent| ctx.player_map.get_by_right(&ent))
-                .copied();
-            let Some(lid) = self.tracker.tracked.get_by_right(&killed).copied() else {
-                continue;
-            };
-            res.push(EntityUpdate::KillEntity {
-                lid,
-                responsible_peer,
-            });
-        }
-
-        for lid in self.tracker.pending_removal.drain(..) {
-            res.push(EntityUpdate::RemoveEntity(lid));
-            // "Untrack" entity
-            self.tracker.tracked.remove_by_left(&lid);
-            self.entity_entries.remove(&lid);
-        }
         res
     }
 
+    /// Checks if this peer has received this entity and thus has it spawned.
+    /// Entities that we don't know about anymore are assumed to be sent to everyone.
+    pub(crate) fn was_sent_to(&self, peer: PeerId, lid: Lid) -> bool {
+        self.entity_entries
+            .get(&lid)
+            .is_none_or(|entry| entry.last.contains_key(&peer))
+    }
+
     pub(crate) fn lid_by_entity(&self, entity: EntityID) -> Option<Lid> {
         self.tracker.tracked.get_by_right(&entity).copied()
     }
@@ -404,6 +647,27 @@ impl LocalDiffModel {
         self.tracker.pending_authority.push(full_entity_data);
     }
 
+    /// Stops syncing an entity that the proxy took authority over away from us, and removes it here and on peers.
+    pub(crate) fn lost_authority(&mut self, gid: Gid) {
+        self.tracker
+            .pending_authority
+            .retain(|data| data.gid != gid);
+        let Some(lid) = self
+            .entity_entries
+            .iter()
+            .find(|(_, entry)| entry.gid == gid)
+            .map(|(&lid, _)| lid)
+        else {
+            return;
+        };
+        // Entry goes away right now, so that it isn't updated and deleted from the proxy in the meantime.
+        self.entity_entries.remove(&lid);
+        if let Some((_, entity)) = self.tracker.tracked.remove_by_left(&lid) {
+            safe_entitykill(entity);
+        }
+        self.tracker.pending_removal.push(lid);
+    }
+
     pub(crate) fn full_entity_data_for(&self, lid: Lid) -> Option<FullEntityData> {
         let entry_pair = self.entity_entries.get(&lid)?;
         Some(FullEntityData {
@@ -441,6 +705,40 @@ impl LocalDiffModel {
     }
 }
 
+/// Spectators don't adopt entities handed off to them; the entity goes back to the proxy where it is,
+/// for a player to take authority over it.
+pub(crate) fn decline_authority(
+    net: &mut NetManager,
+    remote_entity: Option<EntityID>,
+    gid: Gid,
+    info: &EntityInfo,
+) -> eyre::Result<()> {
+    if let Some(remote_entity) = remote_entity {
+        safe_entitykill(remote_entity);
+    }
+    net.send(&NoitaOutbound::DesToProxy(
+        shared::des::DesToProxy::UpdatePositions(vec![UpdatePosition {
+            gid,
+            pos: WorldPos::from_f32(info.x, info.y),
+        }]),
+    ))?;
+    net.send(&NoitaOutbound::DesToProxy(
+        shared::des::DesToProxy::ReleaseAuthority(gid),
+    ))?;
+    Ok(())
+}
+
+/// How often (in diffs) an entity at this distance should be updated.
+fn update_interval(dist_sq: f32, radius: f32) -> u32 {
+    if dist_sq < (radius / 3.0).powi(2) {
+        1
+    } else if dist_sq < (radius * 2.0 / 3.0).powi(2) {
+        2
+    } else {
+        4
+    }
+}
+
 fn check_all_phys_init(entity: EntityID) -> eyre::Result<bool> {
     for phys_c in entity.iter_all_components_of_type::<PhysicsBody2Component>(None)? {
         if !phys_c.m_initialized()? {
@@ -526,6 +824,11 @@ impl RemoteDiffModel {
                     }
                     self.entity_infos.remove(lid);
                 }
+                EntityUpdate::TransferAuthority { lid, gid, info } => {
+                    let entity = self.tracked.remove_by_left(lid).map(|(_, entity)| entity);
+                    self.entity_infos.remove(lid);
+                    self.adopted.push((entity, *gid, info.clone()));
+                }
                 EntityUpdate::KillEntity {
                     lid,
                     responsible_peer,
@@ -554,6 +857,10 @@ impl RemoteDiffModel {
                         self.grab_request.push(*lid);
                     }
 
+                    if entity_info.sync_mode == SyncMode::SpawnOnly {
+                        continue;
+                    }
+
                     entity.set_position(entity_info.x, entity_info.y)?;
                     if let Some(vel) = entity.try_get_first_component::<VelocityComponent>(None)? {
                         vel.set_m_velocity((entity_info.vx, entity_info.vy))?;
@@ -563,6 +870,10 @@ impl RemoteDiffModel {
                     {
                         vel.set_m_velocity((entity_info.vx, entity_info.vy))?;
                     }
+
+                    if entity_info.sync_mode == SyncMode::PositionOnly {
+                        continue;
+                    }
                     if let Some(damage) =
                         entity.try_get_first_component::<DamageModelComponent>(None)?
                     {
@@ -585,27 +896,7 @@ impl RemoteDiffModel {
                     }
 
                     if !entity_info.phys.is_empty() && check_all_phys_init(entity)? {
-                        let phys_bodies =
-                            noita_api::raw::physics_body_id_get_from_entity(entity, None)?;
-                        for (p, physics_body_id) in entity_info.phys.iter().zip(phys_bodies.iter())
-                        {
-                            let Some(p) = p else {
-                                continue;
-                            };
-                            let (x, y) = noita_api::raw::game_pos_to_physics_pos(
-                                p.x.into(),
-                                Some(p.y.into()),
-                            )?;
-                            noita_api::raw::physics_body_id_set_transform(
-                                *physics_body_id,
-                                x,
-                                y,
-                                p.angle.into(),
-                                p.vx.into(),
-                                p.vy.into(),
-                                p.av.into(),
-                            )?;
-                        }
+                        apply_phys_info(entity, &entity_info.phys)?;
                     }
 
                     if let Some(cost) = entity.try_get_first_component::<ItemCostComponent>(None)? {
@@ -709,13 +1000,11 @@ impl RemoteDiffModel {
         Ok(())
     }
 
-    pub(crate) fn spawn_projectiles(&self, projectiles: &[ProjectileFired]) {
-        for projectile in projectiles {
-            let Ok(deserialized) = deserialize_entity(
-                &projectile.serialized,
-                projectile.position.0,
-                projectile.position.1,
-            ) else {
+    pub(crate) fn spawn_projectiles(&mut self, batch: &ProjectileBatch) {
+        for (projectile, serialized) in self.projectile_cache.resolve(batch) {
+            let Ok(deserialized) =
+                deserialize_entity(&serialized, projectile.position.0, projectile.position.1)
+            else {
                 game_print("uhh something went wrong when spawning projectile: deserialize");
                 continue;
             };
@@ -733,6 +1022,12 @@ impl RemoteDiffModel {
                 None,
                 None,
             );
+            // Serialized projectile has no velocity, and shooting only sets a random one of the right direction.
+            if let Ok(Some(velocity)) =
+                deserialized.try_get_first_component::<VelocityComponent>(None)
+            {
+                let _ = velocity.set_m_velocity(projectile.velocity);
+            }
         }
     }
 
@@ -740,11 +1035,61 @@ impl RemoteDiffModel {
         self.backtrack.drain(..)
     }
 
+    pub(crate) fn drain_adopted(
+        &mut self,
+    ) -> impl Iterator<Item = (Option<EntityID>, Gid, EntityInfo)> + '_ {
+        self.adopted.drain(..)
+    }
+
     pub(crate) fn drain_grab_request(&mut self) -> impl Iterator<Item = Lid> + '_ {
         self.grab_request.drain(..)
     }
 }
 
+fn apply_phys_info(entity: EntityID, phys: &[Option<PhysBodyInfo>]) -> eyre::Result<()> {
+    let phys_bodies = noita_api::raw::physics_body_id_get_from_entity(entity, None)?;
+    for (p, physics_body_id) in phys.iter().zip(phys_bodies.iter()) {
+        let Some(p) = p else {
+            continue;
+        };
+        let (x, y) = noita_api::raw::game_pos_to_physics_pos(p.x.into(), Some(p.y.into()))?;
+        noita_api::raw::physics_body_id_set_transform(
+            *physics_body_id,
+            x,
+            y,
+            p.angle.into(),
+            p.vx.into(),
+            p.vy.into(),
+            p.av.into(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Restores state of a freshly spawned entity that we are going to be the authority of.
+fn apply_entity_info(entity: EntityID, info: &EntityInfo) -> eyre::Result<()> {
+    if let Some(vel) = entity.try_get_first_component::<VelocityComponent>(None)? {
+        vel.set_m_velocity((info.vx, info.vy))?;
+    }
+    if let Some(vel) = entity.try_get_first_component::<CharacterDataComponent>(None)? {
+        vel.set_m_velocity((info.vx, info.vy))?;
+    }
+    if info.sync_mode != SyncMode::Full {
+        return Ok(());
+    }
+    if let Some(damage) = entity.try_get_first_component::<DamageModelComponent>(None)? {
+        damage.set_hp(info.hp.into())?;
+    }
+    // Physics bodies usually aren't initialized right after spawning, in which case they keep their spawn transform.
+    if !info.phys.is_empty() && check_all_phys_init(entity)? {
+        apply_phys_info(entity, &info.phys)?;
+    }
+    if let Some(cost) = entity.try_get_first_component::<ItemCostComponent>(None)? {
+        cost.set_cost(info.cost)?;
+    }
+    Ok(())
+}
+
 fn item_in_inventory(entity: EntityID) -> Result<bool, eyre::Error> {
     Ok(entity.parent()? != entity)
 }
@@ -771,7 +1116,7 @@ fn spawn_entity_by_data(entity_data: &EntitySpawnInfo, x: f32, y: f32) -> eyre::
     }
 }
 
-pub(crate) fn entity_is_item(entity: EntityID) -> eyre::Result<bool> {
+fn entity_is_item(entity: EntityID) -> eyre::Result<bool> {
     Ok(entity
         .try_get_first_component_including_disabled::<ItemComponent>(None)?
         .is_some())
@@ -807,3 +1152,272 @@ fn safe_entitykill(entity: EntityID) {
     // TODO
     entity.kill();
 }
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use noita_api::{
+        fake_world::{FakeEntityFile, FakeWorld},
+        lua::LuaState,
+    };
+
+    use super::*;
+    use crate::modules::test_ctx;
+
+    const RAT: &str = "data/entities/animals/rat.xml";
+
+    fn fake_world() -> Rc<RefCell<FakeWorld>> {
+        let mut world = FakeWorld::default();
+        world.add_file(
+            RAT,
+            FakeEntityFile::default()
+                .with_tag("enemy")
+                .with_component(
+                    "VelocityComponent",
+                    [("mVelocity", vec![0.0.into(), 0.0.into()])],
+                )
+                .with_component("DamageModelComponent", [("hp", vec![4.0.into()])]),
+        );
+        world.set_handler("EwextPrintError", |_, args| panic!("{}", args[0]));
+        let world = Rc::new(RefCell::new(world));
+        LuaState::new_fake(world.clone()).make_current();
+        world
+    }
+
+    #[test]
+    fn remote_copy_follows_local_entity() -> eyre::Result<()> {
+        let world = fake_world();
+        let (mut test, _sent) = test_ctx();
+        let mut ctx = test.ctx();
+        let interest = PeerInterest {
+            x: 0.0,
+            y: 0.0,
+            radius: 1000.0,
+        };
+
+        let mut local = LocalDiffModel::default();
+        let mut remote = RemoteDiffModel::default();
+        let rat = EntityID::load(RAT, Some(15.0), Some(25.0))?;
+        local.track_entity(rat, Gid(1), SyncMode::Full)?;
+
+        let mut sync = |ctx: &mut ModuleCtx| -> eyre::Result<()> {
+            local.update_tracked_entities(ctx)?;
+            for (_, diff) in local.make_diffs(ctx, [(PeerId(2), interest)].into_iter()) {
+                remote.apply_diff(&diff);
+            }
+            remote.apply_entities(ctx)
+        };
+
+        sync(&mut ctx)?;
+        let copy = world
+            .borrow()
+            .entities()
+            .map(|(entity, _)| entity)
+            .find(|&entity| entity != rat)
+            .expect("remote copy to be spawned");
+        assert_eq!(copy.filename()?, RAT);
+        assert_eq!(copy.position()?, (15.0, 25.0));
+        assert!(copy.has_tag(DES_TAG));
+
+        rat.set_position(40.0, 50.0)?;
+        rat.get_first_component::<DamageModelComponent>(None)?
+            .set_hp(2.0)?;
+        sync(&mut ctx)?;
+        assert_eq!(copy.position()?, (40.0, 50.0));
+        assert_eq!(
+            copy.get_first_component::<DamageModelComponent>(None)?
+                .hp()?,
+            2.0
+        );
+
+        rat.kill();
+        sync(&mut ctx)?;
+        assert!(!copy.is_alive());
+        Ok(())
+    }
+
+    #[test]
+    fn granted_entity_is_tracked_even_if_rules_exclude_it() -> eyre::Result<()> {
+        let world = fake_world();
+        let rules = TrackingRules::parse(&format!("include tag=enemy\nexclude filename={RAT}"))?;
+        let mut local = LocalDiffModel::default();
+        local.got_authority(FullEntityData {
+            gid: Gid(3),
+            pos: WorldPos::from_f32(15.0, 25.0),
+            data: EntitySpawnInfo::Filename(RAT.to_owned()),
+        });
+        local.update_pending_authority(&rules)?;
+
+        let entities: Vec<_> = world
+            .borrow()
+            .entities()
+            .map(|(entity, _)| entity)
+            .collect();
+        let rat = entities
+            .into_iter()
+            .find(|entity| entity.is_alive())
+            .expect("granted entity to be spawned");
+        let lid = local
+            .lid_by_entity(rat)
+            .expect("granted entity to be tracked");
+        let entry = &local.entity_entries[&lid];
+        assert_eq!(entry.gid, Gid(3));
+        assert_eq!(entry.current.sync_mode, SyncMode::Full);
+        Ok(())
+    }
+
+    #[test]
+    fn lost_entity_is_removed_here_and_on_peers() -> eyre::Result<()> {
+        let _world = fake_world();
+        let (mut test, _sent) = test_ctx();
+        let mut ctx = test.ctx();
+        let interest = PeerInterest {
+            x: 0.0,
+            y: 0.0,
+            radius: 1000.0,
+        };
+
+        let mut local = LocalDiffModel::default();
+        let rat = EntityID::load(RAT, Some(15.0), Some(25.0))?;
+        local.track_entity(rat, Gid(1), SyncMode::Full)?;
+        let lid = local.lid_by_entity(rat).unwrap();
+        local.update_tracked_entities(&mut ctx)?;
+        local.make_diffs(&mut ctx, [(PeerId(2), interest)].into_iter());
+
+        local.lost_authority(Gid(1));
+        assert!(!rat.is_alive());
+        local.update_tracked_entities(&mut ctx)?;
+        let diffs = local.make_diffs(&mut ctx, [(PeerId(2), interest)].into_iter());
+        assert!(matches!(diffs[0].1[..], [EntityUpdate::RemoveEntity(l)] if l == lid));
+        assert!(local.lid_by_entity(rat).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn peers_only_get_entities_in_their_interest() -> eyre::Result<()> {
+        let _world = fake_world();
+        let (mut test, _sent) = test_ctx();
+        let mut ctx = test.ctx();
+        let near = PeerInterest {
+            x: 0.0,
+            y: 0.0,
+            radius: 500.0,
+        };
+        let far = PeerInterest {
+            x: 5000.0,
+            y: 0.0,
+            radius: 500.0,
+        };
+        let (a, b) = (PeerId(2), PeerId(3));
+
+        let mut local = LocalDiffModel::default();
+        let rat = EntityID::load(RAT, Some(15.0), Some(25.0))?;
+        local.track_entity(rat, Gid(1), SyncMode::Full)?;
+        let lid = local.lid_by_entity(rat).unwrap();
+        local.update_tracked_entities(&mut ctx)?;
+
+        let diffs: FxHashMap<_, _> = local
+            .make_diffs(&mut ctx, [(a, near), (b, far)].into_iter())
+            .into_iter()
+            .collect();
+        assert!(
+            matches!(diffs[&a][..], [EntityUpdate::CurrentEntity(l), EntityUpdate::Init(_)] if l == lid)
+        );
+        assert!(diffs[&b].is_empty());
+        assert!(local.was_sent_to(a, lid));
+        assert!(!local.was_sent_to(b, lid));
+
+        // Peers swap places: a loses the entity, b gets it.
+        let diffs: FxHashMap<_, _> = local
+            .make_diffs(&mut ctx, [(a, far), (b, near)].into_iter())
+            .into_iter()
+            .collect();
+        assert!(matches!(diffs[&a][..], [EntityUpdate::RemoveEntity(l)] if l == lid));
+        assert!(
+            matches!(diffs[&b][..], [EntityUpdate::CurrentEntity(l), EntityUpdate::Init(_)] if l == lid)
+        );
+        assert!(!local.was_sent_to(a, lid));
+
+        // Entities at the edge of the interest radius are updated less often.
+        assert_eq!(update_interval(100.0f32.powi(2), 500.0), 1);
+        assert_eq!(update_interval(450.0f32.powi(2), 500.0), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn spawn_only_entity_is_handed_off_where_it_is() -> eyre::Result<()> {
+        let world = fake_world();
+        let (mut test, _sent) = test_ctx();
+        let mut ctx = test.ctx();
+        let (a, b) = (PeerId(2), PeerId(3));
+        let near = PeerInterest {
+            x: 500.0,
+            y: 0.0,
+            radius: 1000.0,
+        };
+        let other = PeerInterest {
+            x: -300.0,
+            y: 0.0,
+            radius: 1000.0,
+        };
+
+        let mut local = LocalDiffModel::default();
+        let mut remote = RemoteDiffModel::default();
+        let rat = EntityID::load(RAT, Some(15.0), Some(25.0))?;
+        local.track_entity(rat, Gid(1), SyncMode::SpawnOnly)?;
+        let lid = local.lid_by_entity(rat).unwrap();
+        for (peer, diff) in local.make_diffs(&mut ctx, [(a, near), (b, other)].into_iter()) {
+            if peer == a {
+                remote.apply_diff(&diff);
+            }
+        }
+
+        // Nobody is closer than we are yet.
+        local.update_authority_transfers(&mut ctx, [(a, near), (b, other)].into_iter())?;
+        assert!(rat.is_alive());
+
+        // Position of spawn only entities isn't synced, but it still has to be used for the hand-off.
+        rat.set_position(450.0, 0.0)?;
+        // Status effects aren't in the entity file, so they have to be handed off with the entity.
+        let effect = noita_api::raw::entity_create_new(Some("effect".into()))?
+            .ok_or_eyre("effect to be created")?;
+        noita_api::raw::entity_add_child(rat.raw() as i32, effect.raw() as i32)?;
+        local.update_tracked_entities(&mut ctx)?;
+        local.update_authority_transfers(&mut ctx, [(a, near), (b, other)].into_iter())?;
+        assert!(!rat.is_alive());
+        assert!(local.lid_by_entity(rat).is_none());
+
+        let diffs: FxHashMap<_, _> = local
+            .make_diffs(&mut ctx, [(a, near), (b, other)].into_iter())
+            .into_iter()
+            .collect();
+        assert!(matches!(
+            diffs[&a][..],
+            [EntityUpdate::TransferAuthority { lid: l, gid: Gid(1), ref info }]
+                if l == lid && (info.x, info.y) == (450.0, 0.0)
+        ));
+        assert!(
+            matches!(diffs[&b][..], [EntityUpdate::LocalizeEntity(l, to)] if l == lid && to == a)
+        );
+
+        // New authority replaces its copy with a tracked one at the same place.
+        remote.apply_diff(&diffs[&a]);
+        let mut new_local = LocalDiffModel::default();
+        for (remote_entity, gid, info) in remote.drain_adopted() {
+            new_local.adopt_entity(ctx.net, remote_entity, gid, info)?;
+        }
+        let entities: Vec<_> = world
+            .borrow()
+            .entities()
+            .map(|(entity, _)| entity)
+            .collect();
+        let adopted = entities
+            .into_iter()
+            .find(|&entity| new_local.lid_by_entity(entity).is_some())
+            .expect("adopted entity to be tracked");
+        assert_eq!(adopted.position()?, (450.0, 0.0));
+        assert_eq!(adopted.children().len(), 1);
+        Ok(())
+    }
+}
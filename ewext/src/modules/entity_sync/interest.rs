@@ -1,12 +1,26 @@
-use rustc_hash::FxHashSet;
+use rustc_hash::FxHashMap;
 use shared::{des::InterestRequest, PeerId};
 
+/// Interest zone of a single peer, as received in their last `InterestRequest`.
+#[derive(Clone, Copy)]
+pub(crate) struct PeerInterest {
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) radius: f32,
+}
+
+impl PeerInterest {
+    pub(crate) fn dist_sq(&self, x: f32, y: f32) -> f32 {
+        (x - self.x).powi(2) + (y - self.y).powi(2)
+    }
+}
+
 pub(crate) struct InterestTracker {
     radius_hysteresis: f64,
     x: f64,
     y: f64,
-    interested_peers: FxHashSet<PeerId>,
-    added_any: bool,
+    interested_peers: FxHashMap<PeerId, PeerInterest>,
+    new_interested: Vec<PeerId>,
     lost_interest: Vec<PeerId>,
 }
 
@@ -19,7 +33,7 @@ impl InterestTracker {
             y: 0.0,
             interested_peers: Default::default(),
             lost_interest: Vec::with_capacity(4),
-            added_any: false,
+            new_interested: Vec::with_capacity(4),
         }
     }
 
@@ -31,35 +45,44 @@ impl InterestTracker {
     pub(crate) fn handle_interest_request(&mut self, peer: PeerId, request: InterestRequest) {
         let rx = request.pos.x as f64;
         let ry = request.pos.y as f64;
+        let interest = PeerInterest {
+            x: rx as f32,
+            y: ry as f32,
+            radius: request.radius as f32,
+        };
 
         let dist_sq = (rx - self.x).powi(2) + (ry - self.y).powi(2);
-        if dist_sq < (request.radius as f64).powi(2) && self.interested_peers.insert(peer) {
-            self.added_any = true;
+        if let Some(current) = self.interested_peers.get_mut(&peer) {
+            *current = interest;
+        } else if dist_sq < (request.radius as f64).powi(2) {
+            self.interested_peers.insert(peer, interest);
+            self.new_interested.push(peer);
         }
 
         if dist_sq > ((request.radius as f64) + self.radius_hysteresis).powi(2)
-            && self.interested_peers.remove(&peer)
+            && self.interested_peers.remove(&peer).is_some()
         {
             self.lost_interest.push(peer);
         }
     }
 
-    pub(crate) fn got_any_new_interested(&mut self) -> bool {
-        let ret = self.added_any;
-        self.added_any = false;
-        ret
+    pub(crate) fn drain_new_interested(&mut self) -> impl Iterator<Item = PeerId> + '_ {
+        self.new_interested.drain(..)
     }
 
     pub(crate) fn drain_lost_interest(&mut self) -> impl Iterator<Item = PeerId> + '_ {
         self.lost_interest.drain(..)
     }
 
-    pub(crate) fn iter_interested(&mut self) -> impl Iterator<Item = PeerId> + '_ {
-        self.interested_peers.iter().copied()
+    pub(crate) fn iter_interested(&self) -> impl Iterator<Item = (PeerId, PeerInterest)> + '_ {
+        self.interested_peers
+            .iter()
+            .map(|(&peer, &interest)| (peer, interest))
     }
 
     pub(crate) fn reset_interest_for(&mut self, source: PeerId) {
         // No need to count peer as "lost_interest" in this case.
         self.interested_peers.remove(&source);
+        self.new_interested.retain(|&peer| peer != source);
     }
 }
@@ -0,0 +1,399 @@
+//! Batching, deduplication and rate limiting of fired projectiles.
+//! Wands can fire hundreds of projectiles per second, which would saturate the connection if each one was sent as is.
+
+use std::hash::BuildHasher;
+
+use noita_api::{raw, serialize::serialize_entity_engine, EntityID, VelocityComponent};
+use rustc_hash::{FxBuildHasher, FxHashMap, FxHashSet};
+use shared::{
+    des::{Lid, ProjectileBatch, ProjectileData, ProjectileFired},
+    PeerId,
+};
+
+/// Batches are made every other frame.
+const BATCHES_PER_SECOND: f32 = 30.0;
+/// Max amount of projectiles that are sent per second on average.
+const PROJECTILE_RATE_CAP: f32 = 300.0;
+/// Max amount of projectiles that can be sent in a single batch, after not shooting for a while.
+const PROJECTILE_BURST: f32 = 60.0;
+/// Caches of remotes are reset after they get this many different projectiles, so that they don't grow forever.
+const MAX_CACHED_PER_PEER: usize = 1024;
+
+struct PendingProjectile {
+    shooter_lid: Lid,
+    position: (f32, f32),
+    velocity: (f32, f32),
+    target: (f32, f32),
+    hash: u64,
+}
+
+pub(crate) struct ProjectileBatcher {
+    pending: Vec<PendingProjectile>,
+    /// Serialized projectiles of the current batch, by hash.
+    payloads: FxHashMap<u64, Vec<u8>>,
+    /// Hashes of projectiles each peer has cached.
+    cached_by: FxHashMap<PeerId, FxHashSet<u64>>,
+    /// How many projectiles can be sent right now.
+    budget: f32,
+}
+
+impl ProjectileBatcher {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            payloads: Default::default(),
+            cached_by: Default::default(),
+            budget: PROJECTILE_BURST,
+        }
+    }
+
+    /// Adds a fired projectile, `serialized` by [`serialize_shot`].
+    pub(crate) fn push(
+        &mut self,
+        shooter_lid: Lid,
+        position: (f32, f32),
+        velocity: (f32, f32),
+        target: (f32, f32),
+        serialized: Vec<u8>,
+    ) {
+        let hash = FxBuildHasher.hash_one(&serialized);
+        self.payloads.entry(hash).or_insert(serialized);
+        self.pending.push(PendingProjectile {
+            shooter_lid,
+            position,
+            velocity,
+            target,
+            hash,
+        });
+    }
+
+    /// Peer will get full projectiles and have its cache reset with the next batch, e.g. because it dropped everything it had from us.
+    pub(crate) fn forget_peer(&mut self, peer: PeerId) {
+        self.cached_by.remove(&peer);
+    }
+
+    /// Should be called once per batch, before making batches for peers.
+    /// Projectiles over the budget are dropped, evenly spread over the batch, so that spread patterns still look about right.
+    pub(crate) fn apply_rate_cap(&mut self) {
+        self.budget =
+            (self.budget + PROJECTILE_RATE_CAP / BATCHES_PER_SECOND).min(PROJECTILE_BURST);
+        let total = self.pending.len();
+        let allowed = (self.budget as usize).min(total);
+        if allowed < total {
+            let mut i = 0;
+            self.pending.retain(|_| {
+                let keep = (i + 1) * allowed / total > i * allowed / total;
+                i += 1;
+                keep
+            });
+        }
+        self.budget -= allowed as f32;
+    }
+
+    /// Makes a batch for a peer out of projectiles for which `filter` returns true, or None if there is nothing to send.
+    pub(crate) fn batch_for(
+        &mut self,
+        peer: PeerId,
+        filter: impl Fn(Lid) -> bool,
+    ) -> Option<ProjectileBatch> {
+        let to_send: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|p| filter(p.shooter_lid))
+            .collect();
+        if to_send.is_empty() {
+            return None;
+        }
+        // Hashes are of the contents, so entries that are left in the cache are never wrong, they just take up memory.
+        let reset_cache = self
+            .cached_by
+            .get(&peer)
+            .is_none_or(|cached| cached.len() >= MAX_CACHED_PER_PEER);
+        let cached = self.cached_by.entry(peer).or_default();
+        if reset_cache {
+            cached.clear();
+        }
+        let projectiles = to_send
+            .into_iter()
+            .map(|projectile| {
+                let data = if cached.insert(projectile.hash) {
+                    ProjectileData::Full {
+                        hash: projectile.hash,
+                        serialized: self.payloads[&projectile.hash].clone(),
+                    }
+                } else {
+                    ProjectileData::Cached(projectile.hash)
+                };
+                ProjectileFired {
+                    shooter_lid: projectile.shooter_lid,
+                    position: projectile.position,
+                    velocity: projectile.velocity,
+                    target: projectile.target,
+                    data,
+                }
+            })
+            .collect();
+        Some(ProjectileBatch {
+            reset_cache,
+            projectiles,
+        })
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+        self.payloads.clear();
+    }
+}
+
+/// Serializes a fired projectile without its position and velocity, which are sent separately for every shot.
+/// That way every shot of the same projectile is serialized the same, and is only sent once.
+/// Returns the serialized projectile and its velocity.
+pub(crate) fn serialize_shot(projectile: EntityID) -> eyre::Result<(Vec<u8>, (f32, f32))> {
+    let (x, y, rotation, scale_x, scale_y) = raw::entity_get_transform(projectile)?;
+    let velocity_component = projectile.try_get_first_component::<VelocityComponent>(None)?;
+    let velocity = match velocity_component {
+        Some(component) => component.m_velocity()?,
+        None => (0.0, 0.0),
+    };
+
+    raw::entity_set_transform(
+        projectile,
+        0.0,
+        Some(0.0),
+        Some(0.0),
+        Some(scale_x),
+        Some(scale_y),
+    )?;
+    if let Some(component) = velocity_component {
+        component.set_m_velocity((0.0, 0.0))?;
+    }
+    let serialized = serialize_entity_engine(projectile);
+    raw::entity_set_transform(
+        projectile,
+        x,
+        Some(y),
+        Some(rotation),
+        Some(scale_x),
+        Some(scale_y),
+    )?;
+    if let Some(component) = velocity_component {
+        component.set_m_velocity(velocity)?;
+    }
+    Ok((serialized?, velocity))
+}
+
+/// Serialized projectiles received from a single peer.
+#[derive(Default)]
+pub(crate) struct ProjectileCache {
+    payloads: FxHashMap<u64, Vec<u8>>,
+}
+
+impl ProjectileCache {
+    /// Resolves projectiles of a batch to their serialized data, skipping the ones that aren't cached.
+    pub(crate) fn resolve<'a>(
+        &'a mut self,
+        batch: &'a ProjectileBatch,
+    ) -> impl Iterator<Item = (&'a ProjectileFired, Vec<u8>)> + 'a {
+        if batch.reset_cache {
+            self.payloads.clear();
+        }
+        batch.projectiles.iter().filter_map(|projectile| {
+            let serialized = match &projectile.data {
+                ProjectileData::Full { hash, serialized } => {
+                    self.payloads.insert(*hash, serialized.clone());
+                    serialized.clone()
+                }
+                ProjectileData::Cached(hash) => self.payloads.get(hash)?.clone(),
+            };
+            Some((projectile, serialized))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use noita_api::{
+        fake_world::{FakeEntityFile, FakeWorld},
+        lua::LuaState,
+    };
+
+    use super::*;
+
+    fn push_n(batcher: &mut ProjectileBatcher, n: u32, serialized: &[u8]) {
+        for i in 0..n {
+            batcher.push(
+                Lid(i),
+                (0.0, 0.0),
+                (0.0, 0.0),
+                (1.0, 1.0),
+                serialized.to_vec(),
+            );
+        }
+    }
+
+    #[test]
+    fn same_projectile_is_sent_in_full_once_per_peer() {
+        let (a, b) = (PeerId(1), PeerId(2));
+        let mut batcher = ProjectileBatcher::new();
+        let mut cache = ProjectileCache::default();
+        push_n(&mut batcher, 2, b"spark_bolt");
+
+        let batch = batcher.batch_for(a, |_| true).unwrap();
+        assert!(batch.reset_cache);
+        assert!(matches!(
+            batch.projectiles[..],
+            [
+                ProjectileFired {
+                    data: ProjectileData::Full { .. },
+                    ..
+                },
+                ProjectileFired {
+                    data: ProjectileData::Cached(_),
+                    ..
+                }
+            ]
+        ));
+        let resolved: Vec<_> = cache.resolve(&batch).map(|(_, data)| data).collect();
+        assert_eq!(resolved, [b"spark_bolt".to_vec(), b"spark_bolt".to_vec()]);
+
+        batcher.clear();
+        push_n(&mut batcher, 1, b"spark_bolt");
+        let batch = batcher.batch_for(a, |_| true).unwrap();
+        assert!(!batch.reset_cache);
+        assert!(matches!(
+            batch.projectiles[0].data,
+            ProjectileData::Cached(_)
+        ));
+        assert_eq!(cache.resolve(&batch).count(), 1);
+
+        // Other peers have their own caches.
+        let batch = batcher.batch_for(b, |_| true).unwrap();
+        assert!(matches!(
+            batch.projectiles[0].data,
+            ProjectileData::Full { .. }
+        ));
+
+        batcher.forget_peer(a);
+        let batch = batcher.batch_for(a, |_| true).unwrap();
+        assert!(batch.reset_cache);
+        assert!(matches!(
+            batch.projectiles[0].data,
+            ProjectileData::Full { .. }
+        ));
+    }
+
+    #[test]
+    fn shots_from_different_places_share_a_payload() -> eyre::Result<()> {
+        const SPARK: &str = "data/entities/projectiles/deck/light_bullet.xml";
+        let mut world = FakeWorld::default();
+        world.add_file(
+            SPARK,
+            FakeEntityFile::default()
+                .with_component(
+                    "VelocityComponent",
+                    [("mVelocity", vec![0.0.into(), 0.0.into()])],
+                )
+                .with_component("ProjectileComponent", [("damage", vec![0.3.into()])]),
+        );
+        // Like the engine serializer, saves where the projectile is and how fast it's going.
+        world.set_handler("EwextSerialize", |world, args| {
+            let entity = args[0].to_entity().unwrap();
+            let ent = world.entity(entity).unwrap();
+            let velocity = world
+                .components_of(entity, "VelocityComponent", None, true)
+                .first()
+                .and_then(|com| world.field(*com, "mVelocity"))
+                .map(|v| (v[0].to_number(), v[1].to_number()));
+            Ok(vec![format!("{},{},{velocity:?}", ent.x, ent.y).into()])
+        });
+        LuaState::new_fake(Rc::new(RefCell::new(world))).make_current();
+
+        let mut batcher = ProjectileBatcher::new();
+        for (x, vx) in [(10.0f32, 300.0), (500.0, -200.0)] {
+            let shot = EntityID::load(SPARK, Some(x.into()), Some(20.0))?;
+            shot.get_first_component::<VelocityComponent>(None)?
+                .set_m_velocity((vx, 50.0))?;
+            let (serialized, velocity) = serialize_shot(shot)?;
+            assert_eq!(velocity, (vx, 50.0));
+            // Fired projectile itself is left as it was.
+            assert_eq!(shot.position()?, (x, 20.0));
+            assert_eq!(
+                shot.get_first_component::<VelocityComponent>(None)?
+                    .m_velocity()?,
+                (vx, 50.0)
+            );
+            batcher.push(Lid(0), (x, 20.0), velocity, (0.0, 0.0), serialized);
+        }
+        assert_eq!(batcher.payloads.len(), 1);
+
+        let batch = batcher.batch_for(PeerId(1), |_| true).unwrap();
+        assert!(matches!(
+            batch.projectiles[..],
+            [
+                ProjectileFired {
+                    data: ProjectileData::Full { .. },
+                    ..
+                },
+                ProjectileFired {
+                    data: ProjectileData::Cached(_),
+                    ..
+                }
+            ]
+        ));
+        assert_eq!(batch.projectiles[1].position, (500.0, 20.0));
+        assert_eq!(batch.projectiles[1].velocity, (-200.0, 50.0));
+        Ok(())
+    }
+
+    #[test]
+    fn cached_projectiles_are_skipped_after_cache_reset() {
+        let mut batcher = ProjectileBatcher::new();
+        let mut cache = ProjectileCache::default();
+        push_n(&mut batcher, 1, b"spark_bolt");
+        let first = batcher.batch_for(PeerId(1), |_| true).unwrap();
+        let second = batcher.batch_for(PeerId(1), |_| true).unwrap();
+        assert_eq!(cache.resolve(&first).count(), 1);
+
+        // The batch that filled the cache got lost.
+        cache.payloads.clear();
+        assert_eq!(cache.resolve(&second).count(), 0);
+    }
+
+    #[test]
+    fn batches_only_have_projectiles_that_pass_the_filter() {
+        let mut batcher = ProjectileBatcher::new();
+        push_n(&mut batcher, 4, b"spark_bolt");
+        assert!(batcher.batch_for(PeerId(1), |_| false).is_none());
+        let batch = batcher.batch_for(PeerId(1), |lid| lid.0 % 2 == 0).unwrap();
+        let lids: Vec<_> = batch.projectiles.iter().map(|p| p.shooter_lid.0).collect();
+        assert_eq!(lids, [0, 2]);
+    }
+
+    #[test]
+    fn rate_cap_allows_a_burst_then_limits_to_the_rate() {
+        let mut batcher = ProjectileBatcher::new();
+        push_n(&mut batcher, 120, b"spark_bolt");
+        batcher.apply_rate_cap();
+        assert_eq!(batcher.pending.len(), PROJECTILE_BURST as usize);
+        // Dropped projectiles are spread over the whole batch.
+        assert_eq!(batcher.pending[0].shooter_lid, Lid(1));
+        assert_eq!(batcher.pending[59].shooter_lid, Lid(119));
+
+        batcher.clear();
+        push_n(&mut batcher, 120, b"spark_bolt");
+        batcher.apply_rate_cap();
+        let per_batch = (PROJECTILE_RATE_CAP / BATCHES_PER_SECOND) as usize;
+        assert_eq!(batcher.pending.len(), per_batch);
+
+        // Budget builds back up while nothing is fired.
+        batcher.clear();
+        for _ in 0..10 {
+            batcher.apply_rate_cap();
+        }
+        push_n(&mut batcher, 120, b"spark_bolt");
+        batcher.apply_rate_cap();
+        assert_eq!(batcher.pending.len(), PROJECTILE_BURST as usize);
+    }
+}
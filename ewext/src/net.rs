@@ -2,8 +2,18 @@ use std::{env, net::SocketAddr};
 
 use shared::{message_socket::MessageSocket, NoitaInbound, NoitaOutbound};
 
+enum Connection {
+    Socket(MessageSocket<NoitaInbound, NoitaOutbound>),
+    /// In-memory connection, messages are sent encoded the same way they'd be sent over the socket.
+    #[cfg(test)]
+    Channel {
+        sent: std::sync::mpsc::Sender<Vec<u8>>,
+        received: std::sync::mpsc::Receiver<NoitaInbound>,
+    },
+}
+
 pub(crate) struct NetManager {
-    socket: MessageSocket<NoitaInbound, NoitaOutbound>,
+    connection: Connection,
 }
 
 impl NetManager {
@@ -15,22 +25,59 @@ impl NetManager {
         println!("Connecting to {address:?}");
         let socket = MessageSocket::connect(&address)?;
 
-        Ok(NetManager { socket })
+        Ok(NetManager {
+            connection: Connection::Socket(socket),
+        })
+    }
+
+    /// Net manager that isn't connected to a proxy, along with the other ends of its channels.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> (
+        Self,
+        std::sync::mpsc::Receiver<Vec<u8>>,
+        std::sync::mpsc::Sender<NoitaInbound>,
+    ) {
+        let (sent, sent_rx) = std::sync::mpsc::channel();
+        let (received_tx, received) = std::sync::mpsc::channel();
+        let netman = NetManager {
+            connection: Connection::Channel { sent, received },
+        };
+        (netman, sent_rx, received_tx)
     }
 
     pub(crate) fn send(&mut self, msg: &NoitaOutbound) -> eyre::Result<()> {
-        self.socket.write(msg)
+        match &mut self.connection {
+            Connection::Socket(socket) => socket.write(msg),
+            #[cfg(test)]
+            Connection::Channel { sent, .. } => {
+                // Other end of the channel is allowed to be dropped if the test doesn't care about sent messages.
+                let _ = sent.send(bitcode::encode(msg));
+                Ok(())
+            }
+        }
     }
 
     pub(crate) fn recv(&mut self) -> eyre::Result<NoitaInbound> {
-        self.socket.read()
+        match &mut self.connection {
+            Connection::Socket(socket) => socket.read(),
+            #[cfg(test)]
+            Connection::Channel { received, .. } => Ok(received.recv()?),
+        }
     }
 
     pub(crate) fn try_recv(&mut self) -> eyre::Result<Option<NoitaInbound>> {
-        self.socket.try_read()
+        match &mut self.connection {
+            Connection::Socket(socket) => socket.try_read(),
+            #[cfg(test)]
+            Connection::Channel { received, .. } => Ok(received.try_recv().ok()),
+        }
     }
 
     pub(crate) fn flush(&mut self) -> eyre::Result<()> {
-        self.socket.flush()
+        match &mut self.connection {
+            Connection::Socket(socket) => socket.flush(),
+            #[cfg(test)]
+            Connection::Channel { .. } => Ok(()),
+        }
     }
 }
@@ -1,18 +1,55 @@
-use std::{ffi::c_void, mem};
+use std::{
+    ffi::c_void,
+    mem,
+    ptr::{self, NonNull},
+};
 
 pub(crate) mod ntypes;
 pub(crate) mod pixel;
 
 pub(crate) struct ParticleWorldState {
-    pub(crate) _world_ptr: *mut c_void,
+    pub(crate) world_ptr: *mut ntypes::GridWorld,
     pub(crate) chunk_map_ptr: *mut c_void,
     pub(crate) material_list_ptr: *const c_void,
+    /// Materials with greater ids don't exist, and can't be placed.
+    pub(crate) last_material_id: u16,
+    pub(crate) construct_cell: ntypes::ConstructCellFn,
+    pub(crate) remove_cell: ntypes::RemoveCellFn,
 
     pub(crate) runner: pixel::PixelRunner<pixel::RawPixel>,
 }
 
+/// Inclusive bounds of cells changed by [`ParticleWorldState::decode_area`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DirtyRect {
+    pub(crate) min_x: i32,
+    pub(crate) min_y: i32,
+    pub(crate) max_x: i32,
+    pub(crate) max_y: i32,
+}
+
+impl DirtyRect {
+    fn include(rect: Option<Self>, x: i32, y: i32) -> Self {
+        match rect {
+            Some(rect) => Self {
+                min_x: rect.min_x.min(x),
+                min_y: rect.min_y.min(y),
+                max_x: rect.max_x.max(x),
+                max_y: rect.max_y.max(y),
+            },
+            None => Self {
+                min_x: x,
+                min_y: y,
+                max_x: x,
+                max_y: y,
+            },
+        }
+    }
+}
+
 impl ParticleWorldState {
-    fn get_cell_raw(&self, x: i32, y: i32) -> Option<&ntypes::Cell> {
+    /// Returns the place in chunk map that holds a pointer to the cell, or None if chunk isn't loaded.
+    fn get_cell_slot(&self, x: i32, y: i32) -> Option<NonNull<*mut ntypes::Cell>> {
         let x = x as isize;
         let y = y as isize;
         let chunk_index = (((((y) >> 9) - 256) & 511) * 512 + ((((x) >> 9) - 256) & 511)) * 4;
@@ -26,11 +63,11 @@ impl ParticleWorldState {
         // Deref 3/3
         let pixel_array = unsafe { chunk.cast::<*const c_void>().read() };
         let pixel = unsafe { pixel_array.offset((((y & 511) << 9) | x & 511) * 4) };
-        if pixel.is_null() {
-            return None;
-        }
+        NonNull::new(pixel.cast_mut().cast())
+    }
 
-        unsafe { pixel.cast::<*const ntypes::Cell>().read().as_ref() }
+    fn get_cell_raw(&self, x: i32, y: i32) -> Option<&ntypes::Cell> {
+        unsafe { self.get_cell_slot(x, y)?.read().as_ref() }
     }
 
     fn get_cell_material_id(&self, cell: &ntypes::Cell) -> u16 {
@@ -43,6 +80,42 @@ impl ParticleWorldState {
         unsafe { Some(cell.material_ptr().as_ref()?.cell_type) }
     }
 
+    /// Cells don't store their temperature, only whether they're burning.
+    fn encode_cell(&self, cell: &ntypes::Cell) -> pixel::RawPixel {
+        let Some(cell_type) = self.get_cell_type(cell) else {
+            return pixel::RawPixel {
+                material: 0,
+                flags: 0,
+            };
+        };
+        let mut flags = if cell.is_burning() {
+            pixel::FLAG_BURNING
+        } else {
+            0
+        };
+        match cell_type {
+            ntypes::CellType::None => {
+                return pixel::RawPixel {
+                    material: 0,
+                    flags: 0,
+                }
+            }
+            // Static solids are liquids with is_static set, so solid cells are always parts of box2d bodies.
+            ntypes::CellType::Solid => flags |= pixel::FLAG_BOX2D,
+            ntypes::CellType::Liquid => {
+                let cell: &ntypes::LiquidCell = unsafe { mem::transmute(cell) };
+                if cell.is_static {
+                    flags |= pixel::FLAG_LIQUID_STATIC;
+                }
+            }
+            ntypes::CellType::Gas | ntypes::CellType::Fire | ntypes::CellType::Invalid => {}
+        }
+        pixel::RawPixel {
+            material: self.get_cell_material_id(cell),
+            flags,
+        }
+    }
+
     pub(crate) unsafe fn encode_area(
         &mut self,
         start_x: i32,
@@ -59,29 +132,13 @@ impl ParticleWorldState {
 
         for y in start_y..end_y {
             for x in start_x..end_x {
-                let mut raw_pixel = pixel::RawPixel {
-                    material: 0,
-                    flags: 0,
+                let raw_pixel = match self.get_cell_raw(x, y) {
+                    Some(cell) => self.encode_cell(cell),
+                    None => pixel::RawPixel {
+                        material: 0,
+                        flags: 0,
+                    },
                 };
-                let cell = self.get_cell_raw(x, y);
-                if let Some(cell) = cell {
-                    let cell_type = self.get_cell_type(cell).unwrap_or(ntypes::CellType::None);
-                    match cell_type {
-                        ntypes::CellType::None => {}
-                        // Nobody knows how box2d pixels work.
-                        ntypes::CellType::Solid => {}
-                        ntypes::CellType::Liquid => {
-                            raw_pixel.material = self.get_cell_material_id(cell);
-                            let cell: &ntypes::LiquidCell = unsafe { mem::transmute(cell) };
-                            raw_pixel.flags = cell.is_static as u8;
-                        }
-                        ntypes::CellType::Gas | ntypes::CellType::Fire => {
-                            raw_pixel.material = self.get_cell_material_id(cell);
-                        }
-                        // ???
-                        _ => {}
-                    }
-                }
                 self.runner.put_pixel(raw_pixel);
             }
         }
@@ -98,4 +155,65 @@ impl ParticleWorldState {
         self.runner.clear();
         runs
     }
+
+    /// Applies an area received from the proxy to the world.
+    /// Pixels with unknown material are left as is, and so are cells that belong to box2d bodies.
+    pub(crate) unsafe fn decode_area(&mut self, area: &pixel::EncodedArea) -> Option<DirtyRect> {
+        let mut dirty = None;
+        let mut pixels = area.pixels();
+        for y in area.y..area.y + area.height {
+            for x in area.x..area.x + area.width {
+                let Some(pixel) = pixels.next() else {
+                    return dirty;
+                };
+                if self.decode_cell(x, y, pixel) {
+                    dirty = Some(DirtyRect::include(dirty, x, y));
+                }
+            }
+        }
+        dirty
+    }
+
+    /// Returns true if the cell was changed.
+    unsafe fn decode_cell(&mut self, x: i32, y: i32, pixel: pixel::RawPixel) -> bool {
+        if pixel.material == u16::MAX {
+            return false;
+        }
+        if pixel.material > self.last_material_id {
+            return false;
+        }
+        let Some(slot) = self.get_cell_slot(x, y) else {
+            return false;
+        };
+        let current = slot.read();
+        if let Some(cell) = current.as_ref() {
+            if self.get_cell_type(cell) == Some(ntypes::CellType::Solid)
+                || self.get_cell_material_id(cell) == pixel.material
+            {
+                return false;
+            }
+            (self.remove_cell)(self.world_ptr, current, x, y, false);
+        } else if pixel.material == 0 {
+            return false;
+        }
+        if pixel.material == 0 {
+            return true;
+        }
+
+        let material_ptr = self
+            .material_list_ptr
+            .byte_offset(ntypes::CELLDATA_SIZE * pixel.material as isize)
+            .cast();
+        let cell = (self.construct_cell)(self.world_ptr, x, y, material_ptr, ptr::null_mut());
+        // Happens when material's texture is transparent at this position.
+        let Some(cell_ref) = cell.as_ref() else {
+            return !current.is_null();
+        };
+        if self.get_cell_type(cell_ref) == Some(ntypes::CellType::Liquid) {
+            (*cell.cast::<ntypes::LiquidCell>()).is_static =
+                pixel.flags & pixel::FLAG_LIQUID_STATIC != 0;
+        }
+        slot.write(cell);
+        true
+    }
 }
@@ -64,6 +64,10 @@ impl Cell {
     pub(crate) fn material_ptr(&self) -> *const CellData {
         self.material_ptr
     }
+
+    pub(crate) fn is_burning(&self) -> bool {
+        self.is_burning
+    }
 }
 
 #[repr(C)]
@@ -81,3 +85,31 @@ pub(crate) struct EntityManager {
 
 #[repr(C)]
 pub(crate) struct ThiscallFn(c_void);
+
+/// `extern "thiscall"` only exists on x86, other targets get "C" so that tests still build on the host.
+macro_rules! thiscall_fn {
+    ($(#[$attr:meta])* $name:ident = fn($($arg:ty),*) $(-> $ret:ty)?) => {
+        $(#[$attr])*
+        #[cfg(target_arch = "x86")]
+        pub(crate) type $name = unsafe extern "thiscall" fn($($arg),*) $(-> $ret)?;
+        $(#[$attr])*
+        #[cfg(not(target_arch = "x86"))]
+        pub(crate) type $name = unsafe extern "C" fn($($arg),*) $(-> $ret)?;
+    };
+}
+
+#[repr(C)]
+pub(crate) struct GridWorld {
+    _vtable: *const c_void,
+    // Unknown
+}
+
+thiscall_fn!(
+    /// Makes a new cell of given material, or returns null if the material's texture is transparent at this position.
+    /// Doesn't place the cell into the world, that has to be done by writing it into the chunk map.
+    ConstructCellFn = fn(*mut GridWorld, i32, i32, *const CellData, *mut c_void) -> *mut Cell
+);
+thiscall_fn!(
+    /// Removes the cell at given position from the world and frees it.
+    RemoveCellFn = fn(*mut GridWorld, *mut Cell, i32, i32, bool)
+);
@@ -1,3 +1,7 @@
+use std::iter;
+
+use eyre::bail;
+
 #[allow(clippy::repr_packed_without_abi)]
 #[repr(packed)]
 pub(crate) struct NoitaPixelRun {
@@ -6,6 +10,18 @@ pub(crate) struct NoitaPixelRun {
     pub(crate) flags: u8,
 }
 
+/// Size of `EncodedAreaHeader` in world.lua.
+const AREA_HEADER_SIZE: usize = 12;
+/// Size of `PixelRun` in world.lua.
+const PIXEL_RUN_SIZE: usize = 5;
+
+/// Static liquid, e.g. terrain. Same as `LIQUID_FLAG_STATIC` in world.lua.
+pub(crate) const FLAG_LIQUID_STATIC: u8 = 1;
+/// Cell is on fire.
+pub(crate) const FLAG_BURNING: u8 = 2;
+/// Cell belongs to a box2d body, i.e. a physics object, instead of being part of the world.
+pub(crate) const FLAG_BOX2D: u8 = 4;
+
 /// Copied from proxy.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub(crate) struct RawPixel {
@@ -77,3 +93,80 @@ impl<Pixel: Eq + Copy> PixelRunner<Pixel> {
         self.runs.clear();
     }
 }
+
+/// Area received from the proxy, laid out as `EncodedAreaHeader` followed by `PixelRun`s in world.lua.
+pub(crate) struct EncodedArea<'a> {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) width: i32,
+    pub(crate) height: i32,
+    runs: &'a [u8],
+}
+
+impl<'a> EncodedArea<'a> {
+    pub(crate) fn parse(data: &'a [u8]) -> eyre::Result<Self> {
+        let Some((header, runs)) = data.split_first_chunk::<AREA_HEADER_SIZE>() else {
+            bail!("Encoded area too short for a header: {} bytes", data.len());
+        };
+        let run_count = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let Some(runs) = runs.get(..run_count * PIXEL_RUN_SIZE) else {
+            bail!(
+                "Encoded area has {run_count} runs, but only {} bytes of them",
+                runs.len()
+            );
+        };
+        Ok(Self {
+            x: i32::from_le_bytes(header[0..4].try_into().unwrap()),
+            y: i32::from_le_bytes(header[4..8].try_into().unwrap()),
+            width: header[8] as i32 + 1,
+            height: header[9] as i32 + 1,
+            runs,
+        })
+    }
+
+    /// Pixels of the area, row by row.
+    pub(crate) fn pixels(&self) -> impl Iterator<Item = RawPixel> + 'a {
+        self.runs.chunks_exact(PIXEL_RUN_SIZE).flat_map(|run| {
+            let length = u16::from_le_bytes([run[0], run[1]]) as usize + 1;
+            let pixel = RawPixel {
+                material: u16::from_le_bytes([run[2], run[3]]),
+                flags: run[4],
+            };
+            iter::repeat_n(pixel, length)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_encoded_area() -> eyre::Result<()> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(-128i32).to_le_bytes());
+        data.extend_from_slice(&256i32.to_le_bytes());
+        data.extend_from_slice(&[1, 0]);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&[2, 0, 5, 0, FLAG_LIQUID_STATIC]);
+        data.extend_from_slice(&[0, 0, 0xff, 0xff, 0]);
+
+        let area = EncodedArea::parse(&data)?;
+        assert_eq!((area.x, area.y, area.width, area.height), (-128, 256, 2, 1));
+        let static_rock = RawPixel {
+            material: 5,
+            flags: FLAG_LIQUID_STATIC,
+        };
+        let unknown = RawPixel {
+            material: u16::MAX,
+            flags: 0,
+        };
+        assert_eq!(
+            area.pixels().collect::<Vec<_>>(),
+            [static_rock, static_rock, static_rock, unknown]
+        );
+
+        assert!(EncodedArea::parse(&data[..data.len() - 1]).is_err());
+        Ok(())
+    }
+}
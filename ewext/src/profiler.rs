@@ -0,0 +1,189 @@
+//! Span profiler for ewext hooks.
+//! Spans get summed up per frame and kept in a ring buffer, and every [`SUMMARY_EVERY`] frames a summary is made to be sent to the proxy.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use shared::{ProfileSummary, SpanStats};
+
+/// How many frames are kept.
+const RING_SIZE: usize = 240;
+/// Frames between summaries, one second at 60 fps.
+const SUMMARY_EVERY: u32 = 60;
+
+thread_local! {
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::default());
+}
+
+/// Measures time until dropped. Time spent in spans with the same name is added together within a frame.
+pub(crate) struct Span {
+    name: &'static str,
+    start: Instant,
+}
+
+pub(crate) fn span(name: &'static str) -> Span {
+    Span {
+        name,
+        start: Instant::now(),
+    }
+}
+
+impl Span {
+    /// Ends the span early, returning how long it took.
+    pub(crate) fn finish(self) -> Duration {
+        let elapsed = self.start.elapsed();
+        record(self.name, elapsed);
+        // Already recorded.
+        std::mem::forget(self);
+        elapsed
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        record(self.name, self.start.elapsed());
+    }
+}
+
+fn record(name: &'static str, elapsed: Duration) {
+    // Profiler being unavailable isn't worth reporting.
+    let _ = PROFILER.try_with(|profiler| {
+        if let Ok(mut profiler) = profiler.try_borrow_mut() {
+            profiler.record(name, elapsed);
+        }
+    });
+}
+
+/// Should be called once per frame. Returns a summary every [`SUMMARY_EVERY`] frames.
+pub(crate) fn next_frame() -> Option<ProfileSummary> {
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        let now = Instant::now();
+        let frame_time = profiler.frame_start.map(|start| now - start);
+        profiler.frame_start = Some(now);
+        profiler.end_frame(frame_time?)
+    })
+}
+
+#[derive(Default)]
+struct Frame {
+    time: Duration,
+    spans: Vec<(&'static str, Duration)>,
+}
+
+#[derive(Default)]
+struct Profiler {
+    frames: VecDeque<Frame>,
+    current: Vec<(&'static str, Duration)>,
+    frame_start: Option<Instant>,
+    since_summary: u32,
+}
+
+impl Profiler {
+    fn record(&mut self, name: &'static str, elapsed: Duration) {
+        match self.current.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => *total += elapsed,
+            None => self.current.push((name, elapsed)),
+        }
+    }
+
+    fn end_frame(&mut self, time: Duration) -> Option<ProfileSummary> {
+        if self.frames.len() == RING_SIZE {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(Frame {
+            time,
+            spans: std::mem::take(&mut self.current),
+        });
+        self.since_summary += 1;
+        if self.since_summary < SUMMARY_EVERY {
+            return None;
+        }
+        self.since_summary = 0;
+        Some(self.summarize(SUMMARY_EVERY as usize))
+    }
+
+    /// Summarizes the last `frames` frames.
+    fn summarize(&self, frames: usize) -> ProfileSummary {
+        let frames: Vec<_> = self.frames.iter().rev().take(frames).collect();
+        let stats = |per_frame: &mut dyn Iterator<Item = Duration>| {
+            let (total, max) = per_frame
+                .fold((Duration::ZERO, Duration::ZERO), |(total, max), d| {
+                    (total + d, max.max(d))
+                });
+            SpanStats {
+                avg_us: (total / frames.len().max(1) as u32).as_micros() as u32,
+                max_us: max.as_micros() as u32,
+            }
+        };
+
+        let mut names: Vec<&'static str> = Vec::new();
+        for (name, _) in frames.iter().flat_map(|frame| &frame.spans) {
+            if !names.contains(name) {
+                names.push(name);
+            }
+        }
+        ProfileSummary {
+            frames: frames.len() as u32,
+            frame: stats(&mut frames.iter().map(|frame| frame.time)),
+            spans: names
+                .into_iter()
+                .map(|name| {
+                    let mut per_frame = frames.iter().map(|frame| {
+                        frame
+                            .spans
+                            .iter()
+                            .find(|(n, _)| *n == name)
+                            .map_or(Duration::ZERO, |(_, d)| *d)
+                    });
+                    (name.to_owned(), stats(&mut per_frame))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summary_averages_spans_over_frames() {
+        let mut profiler = Profiler::default();
+        for i in 0..SUMMARY_EVERY {
+            profiler.record("des", Duration::from_micros(100));
+            profiler.record("des", Duration::from_micros(100));
+            if i == 0 {
+                profiler.record("encode_area", Duration::from_micros(6000));
+            }
+            let summary = profiler.end_frame(Duration::from_micros(16000));
+            assert_eq!(summary.is_some(), i == SUMMARY_EVERY - 1);
+            if let Some(summary) = summary {
+                assert_eq!(summary.frames, SUMMARY_EVERY);
+                assert_eq!(summary.frame.avg_us, 16000);
+                assert_eq!(
+                    summary.spans,
+                    [
+                        (
+                            "des".to_owned(),
+                            SpanStats {
+                                avg_us: 200,
+                                max_us: 200
+                            }
+                        ),
+                        (
+                            "encode_area".to_owned(),
+                            SpanStats {
+                                avg_us: 100,
+                                max_us: 6000
+                            }
+                        ),
+                    ]
+                );
+            }
+        }
+    }
+}
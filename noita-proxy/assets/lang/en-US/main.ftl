@@ -34,6 +34,9 @@ modman_another_path = Select a different path
 player_host = Host
 player_me = Me
 player_player = Player
+player_spectator = Spectator
+player_make_spectator = Make spectator
+player_make_player = Make player
 
 version_latest = (latest)
 version_check_failed = (could not check for updates)
@@ -49,6 +52,7 @@ selfupdate_unpacking = Unpacking...
 noita_not_yet = Not yet ready. Please wait before starting noita.
 noita_can_connect = Awaiting Noita connection. It's time to start new game in Noita now!
 noita_connected = Local Noita instance connected.
+noita_build_unsupported = This Noita build might be unsupported, some features won't work. Game addresses not found: { $missing }
 
 netman_save_lobby = Save lobby id to clipboard
 netman_show_settings = Show settings screen
@@ -85,6 +89,11 @@ connect_settings_player_tether_desc = Player tether: Teleports clients to host i
 connect_settings_player_tether_length = Tether length
 connect_settings_item_dedup = Deduplicate (sync) items spawned by world generation.
 connect_settings_enemy_hp_scale = Enemy hp scale.
+connect_settings_des_tracking_rules = Entity sync rules
+connect_settings_disabled_ewext_modules = Disabled ewext modules, comma separated
+connect_settings_nice_terraforming = fix blackholes/explosions to work in unseen chunks
+connect_settings_share_gold = Share Gold
+connect_settings_perk_ban_list = perk ban list, comma seperated
 connect_settings_local = Local settings
 connect_settings_autostart = Start the game automatically
 
@@ -154,6 +163,9 @@ Info = Info
 ## Local settings
 
 connect_settings_random_ports = Don't use a predetermined port. Makes things a bit more robust and allows multiple proxies to be launched on the same computer, but Noita will have to be launched through the proxy.
+connect_settings_hide_lobby_chat_in_game = Don't show chat from the lobby screen in game
+connect_settings_join_as_spectator = Join as a spectator, watching the run without affecting it
+connect_settings_admin_api = Serve a local api that scripts can use to kick, ban and change settings while hosting. Its token is written to admin_token.txt next to the proxy.
 
 ## UX settings
 
@@ -164,4 +176,70 @@ ping-lifetime-tooltip = This parameter changes how much frames (seconds*60, sinc
 ping-scale-tooltip = This parameter changes size of ping arrow. I dont know which units it is, but range is 0-1.5 units.
 
 hide-cursors-checkbox = Disable others' cursors
-hide-cursors-checkbox-tooltip = Sometimes you can confuse your friends' cursors with yours. In that case, you can disable them altogether with this checkbox.
\ No newline at end of file
+hide-cursors-checkbox-tooltip = Sometimes you can confuse your friends' cursors with yours. In that case, you can disable them altogether with this checkbox.
+
+chat_title = Lobby chat
+chat_hint = Message
+chat_send = Send
+
+des_inspector_tab = Entity Storage
+des_inspector_refresh = Refresh
+des_inspector_filename = Filename
+des_inspector_filename_hint = Glob, '*' matches anything, '?' matches a single character
+des_inspector_sort_filename = By filename
+des_inspector_sort_size = By size
+des_inspector_region = Region
+des_inspector_min_x = min x
+des_inspector_min_y = min y
+des_inspector_max_x = max x
+des_inspector_max_y = max y
+des_inspector_showing = Showing { $shown } out of { $total } stored entities
+des_inspector_release = Release
+des_inspector_delete = Delete
+
+profiler_tab = Profiler
+profiler_no_data = No profiling data from ewext yet
+profiler_averages = Averages per frame, over the last { $frames } frames
+profiler_span = Span
+profiler_avg = Avg
+profiler_max = Max
+
+ban_list_tab = Ban List
+ban_list_player = Player
+ban_list_reason = Reason
+ban_list_expires = Expires
+ban_list_expires_in = in { $hours }h
+ban_list_never = never
+ban_list_unban = unban
+
+preset_label = Preset
+preset_apply = Apply
+preset_delete = Delete
+preset_save = Save settings as preset
+preset_copy = Copy share string
+preset_import_clipboard = Import from clipboard
+preset_export_file = Export to file
+preset_import_file = Import from file
+preset_unknown_fields = Ignored settings unknown to this version: { $fields }
+
+settings_differ = { $count } game settings of the host differ from yours, see Game Settings.
+settings_diff_title = Host settings that differ from yours
+settings_diff_setting = Setting
+settings_diff_host = Host
+settings_diff_mine = Yours
+settings_diff_on = On
+settings_diff_off = Off
+mods_missing = Enable these mods the host has before starting the game: { $mods }
+mods_extra = These mods aren't enabled by the host: { $mods }
+mods_mismatched = These mods have different files than the host's, update them: { $mods }
+mods_peer_differ = { $name } has different mods. Missing: { $missing }. Not enabled by you: { $extra }. Different files: { $mismatched }
+mods_client_only = client only
+launcher_ignore_mods = Start the game anyway
+run_report_summary = Run lasted { $minutes } min, world { $world }, { $sent } MB sent by this proxy.
+run_report_game_over = Everyone died.
+run_report_player = Player
+run_report_distance = Distance
+run_report_kills = Kills
+run_report_deaths = Deaths
+run_report_chunks = Chunks updated
+run_report_tab = Last Run
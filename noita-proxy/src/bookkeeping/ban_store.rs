@@ -0,0 +1,305 @@
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+const FILENAME: &str = "bans.ron";
+/// How often a running proxy checks the file for bans added by the `bans` command.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Identifies a banned player across sessions.
+/// Tangled peer ids are only assigned for a session, so tangled players are banned by ip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum BanKey {
+    Steam(u64),
+    Ip(IpAddr),
+}
+
+impl Display for BanKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanKey::Steam(id) => write!(f, "{id}"),
+            BanKey::Ip(ip) => write!(f, "{ip}"),
+        }
+    }
+}
+
+impl FromStr for BanKey {
+    type Err = String;
+
+    /// Parses either a steam id or an ip address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse() {
+            Ok(BanKey::Ip(ip))
+        } else if let Ok(id) = s.parse() {
+            Ok(BanKey::Steam(id))
+        } else {
+            Err(format!("{s} is neither a steam id nor an ip address"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Ban {
+    pub(crate) key: BanKey,
+    /// Nickname at the time of the ban, to know who it was.
+    pub(crate) nickname: Option<String>,
+    #[serde(default)]
+    pub(crate) reason: String,
+    /// Unix time, in seconds.
+    pub(crate) banned_at: u64,
+    /// Unix time, in seconds. Ban is permanent if not set.
+    #[serde(default)]
+    pub(crate) expires_at: Option<u64>,
+}
+
+impl Ban {
+    pub(crate) fn new(key: BanKey, nickname: Option<String>, duration_secs: Option<u64>) -> Self {
+        let now = now();
+        Self {
+            key,
+            nickname,
+            reason: String::new(),
+            banned_at: now,
+            expires_at: duration_secs.map(|duration| now + duration),
+        }
+    }
+
+    pub(crate) fn is_active(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Bans that persist between sessions, stored as RON next to the proxy executable.
+pub(crate) struct BanStore {
+    path: PathBuf,
+    bans: Vec<Ban>,
+    /// Modification time of the file when it was last loaded or saved.
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+    /// File couldn't be read when it was last loaded, so it's backed up before being saved over.
+    unreadable: bool,
+}
+
+impl BanStore {
+    pub(crate) fn load_default() -> Self {
+        let path = if let Ok(path) = std::env::current_exe() {
+            path.parent().unwrap().join(FILENAME)
+        } else {
+            FILENAME.into()
+        };
+        Self::load(path)
+    }
+
+    /// Loads bans from `path`, dropping ones that have expired.
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let mut store = Self {
+            path,
+            bans: Vec::new(),
+            modified: None,
+            checked_at: Instant::now(),
+            unreadable: false,
+        };
+        store.reload();
+        store
+    }
+
+    /// Keeps the bans it already has if the file can't be read, e.g. because of a typo after editing it by hand.
+    fn reload(&mut self) {
+        self.modified = modified_at(&self.path);
+        let bans = match fs::read_to_string(&self.path) {
+            Ok(bans) => ron::from_str::<Vec<Ban>>(&bans)
+                .map_err(|err| warn!("Could not parse {}: {err}", self.path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => {
+                warn!("Could not read {}: {err}", self.path.display());
+                Err(())
+            }
+        };
+        match bans {
+            Ok(mut bans) => {
+                let now = now();
+                bans.retain(|ban| ban.is_active(now));
+                info!("Loaded {} bans", bans.len());
+                self.bans = bans;
+                self.unreadable = false;
+            }
+            Err(()) => {
+                warn!("Keeping {} bans loaded before", self.bans.len());
+                self.unreadable = true;
+            }
+        }
+    }
+
+    /// Loads the file again if something else, like the `bans` command, changed it since.
+    /// Only checks once every [`RELOAD_INTERVAL`].
+    pub(crate) fn reload_if_changed(&mut self) {
+        if self.checked_at.elapsed() < RELOAD_INTERVAL {
+            return;
+        }
+        self.checked_at = Instant::now();
+        if modified_at(&self.path) != self.modified {
+            self.reload();
+        }
+    }
+
+    pub(crate) fn save(&mut self) {
+        if self.unreadable {
+            let backup = self.path.with_extension("ron.bak");
+            if let Err(err) = fs::copy(&self.path, &backup) {
+                warn!(
+                    "Could not back up {}, not saving bans over it: {err}",
+                    self.path.display()
+                );
+                return;
+            }
+            info!(
+                "Backed up unreadable {} to {}",
+                self.path.display(),
+                backup.display()
+            );
+            self.unreadable = false;
+        }
+        let bans = match ron::ser::to_string_pretty(&self.bans, Default::default()) {
+            Ok(bans) => bans,
+            Err(err) => {
+                warn!("Could not serialize bans: {err}");
+                return;
+            }
+        };
+        if let Err(err) = fs::write(&self.path, bans) {
+            warn!("Could not save bans to {}: {err}", self.path.display());
+        }
+        self.modified = modified_at(&self.path);
+    }
+
+    pub(crate) fn is_banned(&self, key: BanKey) -> bool {
+        let now = now();
+        self.bans
+            .iter()
+            .any(|ban| ban.key == key && ban.is_active(now))
+    }
+
+    /// Adds a ban, replacing an existing one with the same key.
+    pub(crate) fn ban(&mut self, ban: Ban) {
+        self.bans.retain(|old| old.key != ban.key);
+        info!("Banned {}", ban.key);
+        self.bans.push(ban);
+        self.save();
+    }
+
+    /// Returns false if there was no such ban.
+    pub(crate) fn unban(&mut self, key: BanKey) -> bool {
+        let len = self.bans.len();
+        self.bans.retain(|ban| ban.key != key);
+        if self.bans.len() == len {
+            return false;
+        }
+        info!("Unbanned {key}");
+        self.save();
+        true
+    }
+
+    /// Bans that haven't expired yet.
+    pub(crate) fn active(&self) -> impl Iterator<Item = &Ban> {
+        let now = now();
+        self.bans.iter().filter(move |ban| ban.is_active(now))
+    }
+
+    /// Changes to bans have to be saved with [`BanStore::save`].
+    pub(crate) fn active_mut(&mut self) -> impl Iterator<Item = &mut Ban> {
+        let now = now();
+        self.bans.iter_mut().filter(move |ban| ban.is_active(now))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.active().next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bans_persist_until_expired() {
+        let path = std::env::temp_dir().join(format!("ew_bans_{}.ron", std::process::id()));
+        let mut store = BanStore::load(path.clone());
+        let ip = "10.0.0.2".parse::<BanKey>().unwrap();
+        assert_eq!(ip, BanKey::Ip("10.0.0.2".parse().unwrap()));
+        assert_eq!(
+            "76561198000000000".parse(),
+            Ok(BanKey::Steam(76561198000000000))
+        );
+
+        store.ban(Ban::new(BanKey::Steam(1), Some("griefer".into()), None));
+        store.ban(Ban::new(ip, None, Some(3600)));
+        let mut expired = Ban::new(BanKey::Steam(2), None, None);
+        expired.expires_at = Some(expired.banned_at - 1);
+        store.ban(expired);
+
+        let store = BanStore::load(path.clone());
+        fs::remove_file(path).ok();
+        assert!(store.is_banned(BanKey::Steam(1)));
+        assert!(store.is_banned(ip));
+        assert!(!store.is_banned(BanKey::Steam(2)));
+        assert_eq!(store.active().count(), 2);
+    }
+
+    #[test]
+    fn picks_up_bans_from_other_stores() {
+        let path = std::env::temp_dir().join(format!("ew_bans_reload_{}.ron", std::process::id()));
+        let mut running = BanStore::load(path.clone());
+        BanStore::load(path.clone()).ban(Ban::new(BanKey::Steam(3), None, None));
+
+        running.reload_if_changed();
+        assert!(!running.is_banned(BanKey::Steam(3)));
+        running.checked_at -= RELOAD_INTERVAL;
+        running.reload_if_changed();
+        fs::remove_file(path).ok();
+        assert!(running.is_banned(BanKey::Steam(3)));
+    }
+
+    #[test]
+    fn malformed_file_is_not_lost() {
+        let path =
+            std::env::temp_dir().join(format!("ew_bans_malformed_{}.ron", std::process::id()));
+        let backup = path.with_extension("ron.bak");
+        let mut store = BanStore::load(path.clone());
+        store.ban(Ban::new(BanKey::Steam(4), None, None));
+
+        // Hand edit with a typo.
+        fs::write(&path, "[(key: Steam(4)").unwrap();
+        store.checked_at -= RELOAD_INTERVAL;
+        store.modified = None;
+        store.reload_if_changed();
+        assert!(store.is_banned(BanKey::Steam(4)));
+        assert!(BanStore::load(path.clone()).is_empty());
+
+        store.ban(Ban::new(BanKey::Steam(5), None, None));
+        let backed_up = fs::read_to_string(&backup).unwrap();
+        let reloaded = BanStore::load(path.clone());
+        fs::remove_file(path).ok();
+        fs::remove_file(backup).ok();
+        assert_eq!(backed_up, "[(key: Steam(4)");
+        assert!(reloaded.is_banned(BanKey::Steam(4)));
+        assert!(reloaded.is_banned(BanKey::Steam(5)));
+    }
+}
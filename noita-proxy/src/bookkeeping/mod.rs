@@ -1,6 +1,9 @@
 /// Contains modules related to self updates and automatic mod setup.
+pub mod ban_store;
+pub mod mod_compat;
 pub mod mod_manager;
 pub mod noita_launcher;
+pub mod presets;
 pub mod releases;
 pub mod save_state;
 pub mod self_update;
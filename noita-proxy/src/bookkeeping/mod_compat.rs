@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use bitcode::{Decode, Encode};
+use serde::Deserialize;
+use tracing::warn;
+
+use super::mod_manager::{get_mods, EnabledMod, ModmanagerSettings};
+
+/// Mods whose effect on gameplay is known, which takes priority over what mods declare themselves.
+const KNOWN_MODS: &[(&str, bool)] = &[
+    // (name, client only)
+    ("quant.ew", false),
+    ("daily_practice", false),
+    ("nightmare", false),
+    ("purgatory", false),
+];
+
+/// Directories that mods write to while the game runs, like the player sprites the proxy generates
+/// for quant.ew, so they differ between players even when the mods don't.
+const GENERATED_DIRS: &[&str] = &["files/system/player/tmp", "data/generated"];
+
+/// An enabled mod, as sent to other peers to check compatibility.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub(crate) struct ModInfo {
+    pub(crate) name: String,
+    pub(crate) workshop_item_id: u64,
+    /// Hash of every file of the mod, None if they couldn't be found.
+    pub(crate) hash: Option<u64>,
+    /// Doesn't affect gameplay, so other players don't need it.
+    pub(crate) client_only: bool,
+}
+
+impl ModInfo {
+    fn new(game_dir: &Path, enabled: EnabledMod) -> Self {
+        let dir = mod_dir(game_dir, &enabled);
+        let hash = hash_dir(&dir)
+            .inspect_err(|err| warn!("Could not hash mod {}: {err}", enabled.name))
+            .ok();
+        let client_only = KNOWN_MODS
+            .iter()
+            .find(|(name, _)| *name == enabled.name)
+            .map(|(_, client_only)| *client_only)
+            .or_else(|| declared_client_only(&dir))
+            .unwrap_or(false);
+        Self {
+            name: enabled.name,
+            workshop_item_id: enabled.workshop_item_id,
+            hash,
+            client_only,
+        }
+    }
+}
+
+/// Reads enabled mods and hashes their files.
+pub(crate) fn local_mods(settings: &ModmanagerSettings) -> eyre::Result<Vec<ModInfo>> {
+    let save_path = settings
+        .game_save_path
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Game save path is unknown"))?;
+    let game_dir = settings
+        .game_exe_path
+        .parent()
+        .ok_or_else(|| eyre::eyre!("Game path is unknown"))?;
+    let mods = get_mods(save_path).map_err(|err| eyre::eyre!("{err}"))?;
+    Ok(mods
+        .into_iter()
+        .map(|enabled| ModInfo::new(game_dir, enabled))
+        .collect())
+}
+
+fn mod_dir(game_dir: &Path, enabled: &EnabledMod) -> PathBuf {
+    if enabled.workshop_item_id != 0 {
+        // noita is in steamapps/common/Noita
+        game_dir
+            .join("../../workshop/content/881100")
+            .join(enabled.workshop_item_id.to_string())
+    } else {
+        game_dir.join("mods").join(&enabled.name)
+    }
+}
+
+#[derive(Deserialize)]
+struct Compatibility {
+    #[serde(rename = "@ew_client_only")]
+    ew_client_only: Option<u8>,
+}
+
+/// Mods can declare that they are client only with `ew_client_only="1"` in their compatibility.xml.
+fn declared_client_only(dir: &Path) -> Option<bool> {
+    let file = fs::File::open(dir.join("compatibility.xml")).ok()?;
+    let compatibility: Compatibility = quick_xml::de::from_reader(BufReader::new(file)).ok()?;
+    compatibility
+        .ew_client_only
+        .map(|client_only| client_only == 1)
+}
+
+/// FNV-1a, as it has to be the same for every build of the proxy.
+fn fnv1a(hash: &mut u64, data: &[u8]) {
+    for byte in data {
+        *hash ^= u64::from(*byte);
+        *hash = hash.wrapping_mul(0x100000001b3);
+    }
+}
+
+fn hash_dir(dir: &Path) -> std::io::Result<u64> {
+    fn collect(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_owned();
+            if path.is_dir() {
+                if !GENERATED_DIRS.iter().any(|dir| relative == Path::new(dir)) {
+                    collect(root, &path, files)?;
+                }
+            } else {
+                files.push(relative);
+            }
+        }
+        Ok(())
+    }
+    let mut files = Vec::new();
+    collect(dir, dir, &mut files)?;
+    files.sort();
+    let mut hash = 0xcbf29ce484222325;
+    for file in files {
+        // Separators differ between platforms.
+        for component in file.components() {
+            fnv1a(
+                &mut hash,
+                component.as_os_str().to_string_lossy().as_bytes(),
+            );
+            fnv1a(&mut hash, b"/");
+        }
+        fnv1a(&mut hash, &fs::read(dir.join(&file))?);
+    }
+    Ok(hash)
+}
+
+/// Differences in gameplay affecting mods between the host and a player.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ModDiff {
+    /// Enabled by the host, but not by the player.
+    pub(crate) missing: Vec<String>,
+    /// Enabled by the player, but not by the host.
+    pub(crate) extra: Vec<String>,
+    /// Enabled by both, but their files differ.
+    pub(crate) mismatched: Vec<String>,
+}
+
+impl ModDiff {
+    pub(crate) fn new(host: &[ModInfo], player: &[ModInfo]) -> Self {
+        let find =
+            |mods: &[ModInfo], name: &str| mods.iter().find(|info| info.name == name).cloned();
+        let mut diff = Self::default();
+        for info in host.iter().filter(|info| !info.client_only) {
+            match find(player, &info.name) {
+                None => diff.missing.push(info.name.clone()),
+                Some(other) => {
+                    if info.hash.is_some() && other.hash.is_some() && info.hash != other.hash {
+                        diff.mismatched.push(info.name.clone())
+                    }
+                }
+            }
+        }
+        diff.extra = player
+            .iter()
+            .filter(|info| !info.client_only && find(host, &info.name).is_none())
+            .map(|info| info.name.clone())
+            .collect();
+        diff
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info(name: &str, hash: u64, client_only: bool) -> ModInfo {
+        ModInfo {
+            name: name.to_owned(),
+            workshop_item_id: 0,
+            hash: Some(hash),
+            client_only,
+        }
+    }
+
+    #[test]
+    fn mod_diff_skips_client_only_mods() {
+        let host = [
+            info("quant.ew", 1, false),
+            info("grahamsperks", 2, false),
+            info("host_ui", 3, true),
+        ];
+        let player = [
+            info("quant.ew", 4, false),
+            info("cheatgui", 5, false),
+            info("player_ui", 6, true),
+        ];
+        let diff = ModDiff::new(&host, &player);
+        assert_eq!(diff.missing, ["grahamsperks"]);
+        assert_eq!(diff.extra, ["cheatgui"]);
+        assert_eq!(diff.mismatched, ["quant.ew"]);
+        assert!(ModDiff::new(&host, &host).is_empty());
+    }
+
+    #[test]
+    fn hashes_mod_files() {
+        let dir = std::env::temp_dir().join(format!("ew_mod_hash_{}", std::process::id()));
+        fs::create_dir_all(dir.join("files")).unwrap();
+        fs::write(dir.join("init.lua"), "print(1)").unwrap();
+        fs::write(
+            dir.join("compatibility.xml"),
+            r#"<Mod version_built_with="12" ew_client_only="1"></Mod>"#,
+        )
+        .unwrap();
+        let hash = hash_dir(&dir).unwrap();
+        assert_eq!(declared_client_only(&dir), Some(true));
+        fs::create_dir_all(dir.join("files/system/player/tmp")).unwrap();
+        fs::write(dir.join("files/system/player/tmp/no_crown"), "").unwrap();
+        assert_eq!(hash_dir(&dir).unwrap(), hash);
+        fs::write(dir.join("files/data.xml"), "<Entity/>").unwrap();
+        let changed = hash_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert_ne!(hash, changed);
+    }
+}
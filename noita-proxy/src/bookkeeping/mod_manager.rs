@@ -482,13 +482,23 @@ impl Mods {
     }
 }
 
-pub fn get_mods(saves_path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+/// A mod that is enabled in the game.
+pub struct EnabledMod {
+    pub name: String,
+    /// 0 for mods that aren't from the workshop.
+    pub workshop_item_id: u64,
+}
+
+pub fn get_mods(saves_path: &Path) -> Result<Vec<EnabledMod>, Box<dyn Error>> {
     let mod_config_path = saves_path.join("save00/mod_config.xml");
     let data: Mods = quick_xml::de::from_reader(BufReader::new(File::open(&mod_config_path)?))?;
     let mut res = Vec::new();
     for m in data.mod_entries {
         if m.enabled == 1 {
-            res.push(m.name)
+            res.push(EnabledMod {
+                name: m.name,
+                workshop_item_id: m.workshop_item_id,
+            })
         }
     }
     Ok(res)
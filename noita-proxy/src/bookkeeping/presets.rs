@@ -0,0 +1,290 @@
+use std::{fs, io, path::PathBuf};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use eyre::{bail, Context, OptionExt};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{GameMode, GameSettings, LocalHealthMode};
+
+const FILENAME: &str = "presets.ron";
+/// Prefix of settings shared as a string, followed by base64 of lz4 compressed json.
+const SHARE_PREFIX: &str = "ews1:";
+
+/// Named game settings. Run specific settings, like seed and progress, aren't part of a preset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Preset {
+    pub(crate) name: String,
+    pub(crate) settings: GameSettings,
+}
+
+impl Preset {
+    pub(crate) fn new(name: String, mut settings: GameSettings) -> Self {
+        settings.world_num = 0;
+        settings.progress.clear();
+        if !settings.use_constant_seed {
+            settings.seed = 0;
+        }
+        Self { name, settings }
+    }
+
+    /// Replaces `settings` with this preset, keeping the run specific ones.
+    pub(crate) fn apply_to(&self, settings: &mut GameSettings) {
+        let mut new_settings = self.settings.clone();
+        if !new_settings.use_constant_seed {
+            new_settings.seed = settings.seed;
+        }
+        new_settings.world_num = settings.world_num;
+        new_settings.progress = std::mem::take(&mut settings.progress);
+        *settings = new_settings;
+    }
+
+    /// Encodes this preset as a string that can be pasted into chat.
+    pub(crate) fn to_share_string(&self) -> eyre::Result<String> {
+        let json = serde_json::to_vec(self)?;
+        let compressed = lz4_flex::compress_prepend_size(&json);
+        Ok(format!(
+            "{SHARE_PREFIX}{}",
+            URL_SAFE_NO_PAD.encode(compressed)
+        ))
+    }
+
+    /// Reads a preset from a share string, or from the json written by [`Preset::to_json`].
+    pub(crate) fn import(text: &str) -> eyre::Result<Imported> {
+        let text = text.trim();
+        let value: serde_json::Value = if let Some(encoded) = text.strip_prefix(SHARE_PREFIX) {
+            let compressed = URL_SAFE_NO_PAD
+                .decode(encoded)
+                .wrap_err("Share string is damaged")?;
+            let json = lz4_flex::decompress_size_prepended(&compressed)
+                .wrap_err("Share string is damaged")?;
+            serde_json::from_slice(&json)?
+        } else if text.starts_with('{') {
+            serde_json::from_str(text)?
+        } else {
+            bail!("Not a settings preset")
+        };
+        let settings = value
+            .get("settings")
+            .and_then(|settings| settings.as_object())
+            .ok_or_eyre("Preset has no settings")?;
+        let known = serde_json::to_value(GameSettings::default())?;
+        let unknown_fields = settings
+            .keys()
+            .filter(|field| known.get(field.as_str()).is_none())
+            .cloned()
+            .collect();
+        // Unknown fields are skipped by serde, so settings from newer versions still load.
+        let preset = Self::new(
+            value
+                .get("name")
+                .and_then(|name| name.as_str())
+                .unwrap_or_default()
+                .to_owned(),
+            serde_json::from_value(settings.clone().into())?,
+        );
+        Ok(Imported {
+            preset,
+            unknown_fields,
+        })
+    }
+
+    pub(crate) fn to_json(&self) -> eyre::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+pub(crate) struct Imported {
+    pub(crate) preset: Preset,
+    /// Settings this version doesn't know about, which were ignored.
+    pub(crate) unknown_fields: Vec<String>,
+}
+
+pub(crate) fn builtin_presets() -> Vec<Preset> {
+    vec![
+        Preset::new("Default".to_owned(), GameSettings::default()),
+        Preset::new(
+            "Classic shared health".to_owned(),
+            GameSettings {
+                game_mode: Some(GameMode::SharedHealth),
+                share_gold: Some(true),
+                ..Default::default()
+            },
+        ),
+        Preset::new(
+            "Hardcore permadeath".to_owned(),
+            GameSettings {
+                game_mode: Some(GameMode::LocalHealth(LocalHealthMode::PermaDeath)),
+                friendly_fire: Some(true),
+                physics_damage: Some(true),
+                ..Default::default()
+            },
+        ),
+    ]
+}
+
+/// User defined presets, stored as RON next to the proxy executable.
+pub(crate) struct PresetStore {
+    path: PathBuf,
+    presets: Vec<Preset>,
+    /// File couldn't be read, so it's backed up before being saved over.
+    unreadable: bool,
+}
+
+impl PresetStore {
+    pub(crate) fn load_default() -> Self {
+        let path = if let Ok(path) = std::env::current_exe() {
+            path.parent().unwrap().join(FILENAME)
+        } else {
+            FILENAME.into()
+        };
+        Self::load(path)
+    }
+
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let presets = match fs::read_to_string(&path) {
+            Ok(presets) => ron::from_str(&presets)
+                .map_err(|err| warn!("Could not parse {}: {err}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => {
+                warn!("Could not read {}: {err}", path.display());
+                Err(())
+            }
+        };
+        Self {
+            unreadable: presets.is_err(),
+            presets: presets.unwrap_or_default(),
+            path,
+        }
+    }
+
+    fn save(&mut self) {
+        if self.unreadable {
+            let backup = self.path.with_extension("ron.bak");
+            if let Err(err) = fs::copy(&self.path, &backup) {
+                warn!(
+                    "Could not back up {}, not saving presets over it: {err}",
+                    self.path.display()
+                );
+                return;
+            }
+            info!(
+                "Backed up unreadable {} to {}",
+                self.path.display(),
+                backup.display()
+            );
+            self.unreadable = false;
+        }
+        let presets = match ron::ser::to_string_pretty(&self.presets, Default::default()) {
+            Ok(presets) => presets,
+            Err(err) => {
+                warn!("Could not serialize presets: {err}");
+                return;
+            }
+        };
+        if let Err(err) = fs::write(&self.path, presets) {
+            warn!("Could not save presets to {}: {err}", self.path.display());
+        }
+    }
+
+    /// User defined presets.
+    pub(crate) fn presets(&self) -> &[Preset] {
+        &self.presets
+    }
+
+    /// Built-in presets followed by user defined ones.
+    pub(crate) fn all(&self) -> impl Iterator<Item = Preset> + '_ {
+        builtin_presets()
+            .into_iter()
+            .chain(self.presets.iter().cloned())
+    }
+
+    /// User defined presets take priority over built-in ones with the same name.
+    pub(crate) fn get(&self, name: &str) -> Option<Preset> {
+        self.presets
+            .iter()
+            .find(|preset| preset.name == name)
+            .cloned()
+            .or_else(|| {
+                builtin_presets()
+                    .into_iter()
+                    .find(|preset| preset.name == name)
+            })
+    }
+
+    /// Adds a preset, replacing a user defined one with the same name.
+    pub(crate) fn add(&mut self, preset: Preset) {
+        self.presets.retain(|old| old.name != preset.name);
+        info!("Saved preset {}", preset.name);
+        self.presets.push(preset);
+        self.save();
+    }
+
+    /// Returns false if there was no such user defined preset.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        let len = self.presets.len();
+        self.presets.retain(|preset| preset.name != name);
+        if self.presets.len() == len {
+            return false;
+        }
+        self.save();
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn presets_roundtrip_through_share_string() {
+        let mut settings = builtin_presets()[2].settings.clone();
+        settings.seed = 42;
+        settings.progress = vec!["progress".to_owned()];
+        let preset = Preset::new("mine".to_owned(), settings);
+        assert_eq!(preset.settings.seed, 0);
+        assert!(preset.settings.progress.is_empty());
+
+        let imported = Preset::import(&preset.to_share_string().unwrap()).unwrap();
+        assert_eq!(imported.preset, preset);
+        assert!(imported.unknown_fields.is_empty());
+        let imported = Preset::import(&preset.to_json().unwrap()).unwrap();
+        assert_eq!(imported.preset, preset);
+
+        let mut current = GameSettings {
+            seed: 7,
+            ..Default::default()
+        };
+        preset.apply_to(&mut current);
+        assert_eq!(current.seed, 7);
+        assert_eq!(current.friendly_fire, Some(true));
+    }
+
+    #[test]
+    fn reports_unknown_fields() {
+        let json = r#"{"name": "newer", "version": 2, "settings": {"friendly_fire": true, "future_setting": 3}}"#;
+        let imported = Preset::import(json).unwrap();
+        assert_eq!(imported.preset.name, "newer");
+        assert_eq!(imported.preset.settings.friendly_fire, Some(true));
+        assert_eq!(imported.unknown_fields, vec!["future_setting".to_owned()]);
+        assert!(Preset::import("not a preset").is_err());
+    }
+
+    #[test]
+    fn malformed_file_is_not_lost() {
+        let path =
+            std::env::temp_dir().join(format!("ew_presets_malformed_{}.ron", std::process::id()));
+        let backup = path.with_extension("ron.bak");
+        fs::write(&path, "[(name: \"mine\"").unwrap();
+        let mut store = PresetStore::load(path.clone());
+        assert!(store.presets().is_empty());
+
+        store.add(Preset::new("other".to_owned(), GameSettings::default()));
+        let backed_up = fs::read_to_string(&backup).unwrap();
+        let reloaded = PresetStore::load(path.clone());
+        fs::remove_file(path).ok();
+        fs::remove_file(backup).ok();
+        assert_eq!(backed_up, "[(name: \"mine\"");
+        assert_eq!(reloaded.presets().len(), 1);
+    }
+}
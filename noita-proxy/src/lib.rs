@@ -1,6 +1,9 @@
 use bitcode::{Decode, Encode};
 use bookkeeping::{
+    ban_store::{self, Ban, BanKey, BanStore},
+    mod_compat::ModDiff,
     noita_launcher::{LaunchTokenResult, NoitaLauncher},
+    presets::{Imported, Preset, PresetStore},
     save_state::SaveState,
 };
 use clipboard::{ClipboardContext, ClipboardProvider};
@@ -9,11 +12,13 @@ use eframe::egui::{
     InnerResponse, Key, Margin, OpenUrl, Rect, RichText, ScrollArea, Slider, TextureOptions,
     ThemePreference, Ui, UiBuilder, Vec2, Visuals, Window,
 };
+use fluent_bundle::FluentValue;
 use image::DynamicImage::ImageRgba8;
 use image::RgbaImage;
-use lang::{set_current_locale, tr, LANGS};
+use lang::{set_current_locale, tr, tr_a, LANGS};
 use mod_manager::{Modmanager, ModmanagerSettings};
 use net::{
+    des::{DesCommand, DesEntityFilter, DesEntitySort},
     omni::PeerVariant,
     steam_networking::{ExtraPeerState, PerPeerStatusEntry},
     NetManagerInit, RunInfo,
@@ -21,6 +26,7 @@ use net::{
 use player_cosmetics::PlayerPngDesc;
 use self_update::SelfUpdateManager;
 use serde::{Deserialize, Serialize};
+use shared::des::tracking_rules::TrackingRules;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::str::FromStr;
@@ -31,7 +37,7 @@ use std::{
     ops::Deref,
     sync::{atomic::Ordering, Arc},
     thread::JoinHandle,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use std::{net::IpAddr, path::PathBuf};
 use steamworks::{LobbyId, SteamAPIInitError};
@@ -40,8 +46,8 @@ use tracing::info;
 use unic_langid::LanguageIdentifier;
 
 mod util;
-use util::args::Args;
 pub use util::{args, lang, steam_helper};
+use util::{args::Args, dedicated::DedicatedConfig};
 
 mod bookkeeping;
 use crate::net::messages::NetMsg;
@@ -92,8 +98,120 @@ pub struct GameSettings {
     physics_damage: Option<bool>,
     share_gold: Option<bool>,
     nice_terraforming: Option<bool>,
+    des_tracking_rules: Option<String>,
+    /// Comma separated names of ewext modules that shouldn't run.
+    disabled_ewext_modules: Option<String>,
 }
 impl GameSettings {
+    /// Continues the saved run, unless a constant seed is used.
+    fn pick_seed(&mut self, run_info: Option<RunInfo>) {
+        if !self.use_constant_seed {
+            if let Some(info) = run_info {
+                self.seed = info.seed;
+                info!("Using saved seed: {}", self.seed);
+            } else {
+                self.seed = rand::random();
+                info!("Using random seed: {}", self.seed);
+            }
+        } else {
+            info!("Using constant seed: {}", self.seed);
+        }
+    }
+
+    /// Fills in every setting that isn't set with its default, so that a config file shows all of them.
+    fn with_defaults(mut self) -> Self {
+        let def = DefaultSettings::default();
+        self.debug_mode.get_or_insert(def.debug_mode);
+        self.item_dedup.get_or_insert(def.item_dedup);
+        self.enemy_hp_mult.get_or_insert(def.enemy_hp_mult);
+        self.game_mode.get_or_insert(def.game_mode);
+        self.friendly_fire.get_or_insert(def.friendly_fire);
+        self.randomize_perks.get_or_insert(def.randomize_perks);
+        self.max_players.get_or_insert(def.max_players);
+        self.health_per_player.get_or_insert(def.health_per_player);
+        self.health_lost_on_revive
+            .get_or_insert(def.health_lost_on_revive);
+        self.no_material_damage
+            .get_or_insert(def.no_material_damage);
+        self.global_hp_loss.get_or_insert(def.global_hp_loss);
+        self.perk_ban_list.get_or_insert(def.perk_ban_list);
+        self.physics_damage.get_or_insert(def.physics_damage);
+        self.share_gold.get_or_insert(def.share_gold);
+        self.nice_terraforming.get_or_insert(def.nice_terraforming);
+        self.des_tracking_rules
+            .get_or_insert(def.des_tracking_rules);
+        self.disabled_ewext_modules
+            .get_or_insert(def.disabled_ewext_modules);
+        self
+    }
+
+    /// Settings that differ from `mine`, ignoring ones that only apply to the current run.
+    fn diff(&self, mine: &GameSettings) -> Vec<SettingDiff> {
+        let to_map = |settings: &GameSettings| match serde_json::to_value(
+            settings.clone().with_defaults(),
+        ) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => Default::default(),
+        };
+        let (theirs, mine) = (to_map(self), to_map(mine));
+        theirs
+            .into_iter()
+            .filter(|(name, _)| {
+                !["seed", "world_num", "use_constant_seed", "progress"].contains(&name.as_str())
+            })
+            .filter_map(|(name, value)| {
+                let my_value = mine.get(&name)?;
+                (value != *my_value).then(|| SettingDiff {
+                    host: setting_value(&name, &value),
+                    mine: setting_value(&name, my_value),
+                    name: setting_label(&name),
+                })
+            })
+            .collect()
+    }
+
+    /// Overrides settings with ones given as flags.
+    fn apply_overrides(&mut self, args: &args::HostArgs) -> Result<(), String> {
+        fn set<T: Clone>(setting: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                setting.clone_from(value);
+            }
+        }
+        if let Some(seed) = args.seed {
+            self.seed = seed;
+            self.use_constant_seed = true;
+        }
+        if let Some(world_num) = args.world_num {
+            self.world_num = world_num;
+        }
+        if let Some(game_mode) = &args.game_mode {
+            self.game_mode = Some(parse_game_mode(game_mode)?);
+        }
+        if !args.progress.is_empty() {
+            self.progress.clone_from(&args.progress);
+        }
+        set(&mut self.debug_mode, &args.debug_mode);
+        set(&mut self.item_dedup, &args.item_dedup);
+        set(&mut self.enemy_hp_mult, &args.enemy_hp_mult);
+        set(&mut self.friendly_fire, &args.friendly_fire);
+        set(&mut self.randomize_perks, &args.randomize_perks);
+        set(&mut self.max_players, &args.max_players);
+        set(&mut self.health_per_player, &args.health_per_player);
+        set(&mut self.health_lost_on_revive, &args.health_lost_on_revive);
+        set(&mut self.no_material_damage, &args.no_material_damage);
+        set(&mut self.global_hp_loss, &args.global_hp_loss);
+        set(&mut self.perk_ban_list, &args.perk_ban_list);
+        set(&mut self.physics_damage, &args.physics_damage);
+        set(&mut self.share_gold, &args.share_gold);
+        set(&mut self.nice_terraforming, &args.nice_terraforming);
+        set(&mut self.des_tracking_rules, &args.des_tracking_rules);
+        set(
+            &mut self.disabled_ewext_modules,
+            &args.disabled_ewext_modules,
+        );
+        Ok(())
+    }
+
     fn show_editor(&mut self, ui: &mut Ui, enabled: bool) {
         ui.add_enabled_ui(enabled, |ui| {
             let def = DefaultSettings::default();
@@ -247,10 +365,7 @@ impl GameSettings {
                     .nice_terraforming
                     .unwrap_or(def.nice_terraforming);
                 if ui
-                    .checkbox(
-                        &mut temp,
-                        "fix blackholes/explosions to work in unseen chunks",
-                    )
+                    .checkbox(&mut temp, tr("connect_settings_nice_terraforming"))
                     .changed()
                 {
                     game_settings.nice_terraforming = Some(temp)
@@ -273,7 +388,10 @@ impl GameSettings {
             }
             {
                 let mut temp = game_settings.share_gold.unwrap_or(def.share_gold);
-                if ui.checkbox(&mut temp, "Share Gold").changed() {
+                if ui
+                    .checkbox(&mut temp, tr("connect_settings_share_gold"))
+                    .changed()
+                {
                     game_settings.share_gold = Some(temp)
                 }
             }
@@ -296,7 +414,7 @@ impl GameSettings {
                     .perk_ban_list
                     .clone()
                     .unwrap_or(def.perk_ban_list);
-                ui.label("perk ban list, comma seperated");
+                ui.label(tr("connect_settings_perk_ban_list"));
                 if ui
                     .add_sized(
                         [ui.available_width() - 30.0, 20.0],
@@ -320,12 +438,102 @@ impl GameSettings {
                     game_settings.enemy_hp_mult = Some(temp)
                 }
             }
+            ui.add_space(10.0);
+            ui.label(tr("connect_settings_des_tracking_rules"));
+            {
+                let mut temp = game_settings
+                    .des_tracking_rules
+                    .clone()
+                    .unwrap_or(def.des_tracking_rules);
+                if ui
+                    .add_sized(
+                        [ui.available_width() - 30.0, 60.0],
+                        egui::TextEdit::multiline(&mut temp)
+                            .hint_text("include tag=my_boss sync=position_only"),
+                    )
+                    .changed()
+                {
+                    game_settings.des_tracking_rules = Some(temp.clone())
+                }
+                if let Err(err) = TrackingRules::parse(&temp) {
+                    ui.colored_label(Color32::RED, format!("{err:#}"));
+                }
+            }
+            {
+                let mut temp = game_settings
+                    .disabled_ewext_modules
+                    .clone()
+                    .unwrap_or(def.disabled_ewext_modules);
+                ui.label(tr("connect_settings_disabled_ewext_modules"));
+                if ui
+                    .add_sized(
+                        [ui.available_width() - 30.0, 20.0],
+                        egui::TextEdit::singleline(&mut temp).hint_text("entity_sync"),
+                    )
+                    .changed()
+                {
+                    game_settings.disabled_ewext_modules = Some(temp)
+                }
+            }
             if ui.button(tr("apply_default_settings")).clicked() {
                 *game_settings = GameSettings::default()
             }
         });
     }
 }
+/// A setting of the host that differs from own preferences, with its translated label.
+struct SettingDiff {
+    name: String,
+    host: String,
+    mine: String,
+}
+
+/// Translated label of a game setting, by its field name in `GameSettings`.
+fn setting_label(name: &str) -> String {
+    let text_id = match name {
+        "debug_mode" => "connect_settings_debug_en",
+        "item_dedup" => "connect_settings_item_dedup",
+        "enemy_hp_mult" => "connect_settings_enemy_hp_scale",
+        "game_mode" => "Game-mode",
+        "friendly_fire" => "Enable-friendly-fire",
+        "randomize_perks" => "Have-perk-pools-be-independent-of-each-other",
+        "max_players" => "connect_settings_max_players",
+        "health_per_player" => "Health-per-player",
+        "health_lost_on_revive" => "Health-percent-lost-on-reviving",
+        "no_material_damage" => "no_material_damage",
+        "global_hp_loss" => "global_hp_loss",
+        "perk_ban_list" => "connect_settings_perk_ban_list",
+        "physics_damage" => "physics_damage",
+        "share_gold" => "connect_settings_share_gold",
+        "nice_terraforming" => "connect_settings_nice_terraforming",
+        "des_tracking_rules" => "connect_settings_des_tracking_rules",
+        "disabled_ewext_modules" => "connect_settings_disabled_ewext_modules",
+        // Settings added by newer versions of the host.
+        _ => return name.to_owned(),
+    };
+    tr(text_id)
+}
+
+/// Formats a game setting's json value the way it's shown in game settings.
+fn setting_value(name: &str, value: &serde_json::Value) -> String {
+    if name == "game_mode" {
+        if let Ok(game_mode) = serde_json::from_value::<GameMode>(value.clone()) {
+            return tr(match game_mode {
+                GameMode::SharedHealth => "Shared-health",
+                GameMode::LocalHealth(LocalHealthMode::Normal) => "Local-health",
+                GameMode::LocalHealth(LocalHealthMode::Alternate) => "Local-health-alt",
+                GameMode::LocalHealth(LocalHealthMode::PermaDeath) => "Local-health-perma",
+            });
+        }
+    }
+    match value {
+        serde_json::Value::Bool(true) => tr("settings_diff_on"),
+        serde_json::Value::Bool(false) => tr("settings_diff_off"),
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
 pub struct DefaultSettings {
     debug_mode: bool,
     item_dedup: bool,
@@ -342,6 +550,8 @@ pub struct DefaultSettings {
     physics_damage: bool,
     share_gold: bool,
     nice_terraforming: bool,
+    des_tracking_rules: String,
+    disabled_ewext_modules: String,
 }
 
 impl Default for DefaultSettings {
@@ -364,6 +574,8 @@ impl Default for DefaultSettings {
             physics_damage: true,
             share_gold: false,
             nice_terraforming: true,
+            des_tracking_rules: String::new(),
+            disabled_ewext_modules: String::new(),
         }
     }
 }
@@ -410,6 +622,9 @@ enum ConnectedMenu {
     Mods,
     BanList,
     ConnectionInfo,
+    EntityStorage,
+    Profiler,
+    RunReport,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -537,6 +752,12 @@ struct AppSavedState {
     record_all: bool,
     spacewars: bool,
     random_ports: bool,
+    #[serde(default)]
+    admin_api: bool,
+    #[serde(default)]
+    hide_lobby_chat_in_game: bool,
+    #[serde(default)]
+    join_as_spectator: bool,
 }
 
 impl Default for AppSavedState {
@@ -552,6 +773,9 @@ impl Default for AppSavedState {
             record_all: false,
             spacewars: false,
             random_ports: false,
+            admin_api: false,
+            hide_lobby_chat_in_game: false,
+            join_as_spectator: false,
         }
     }
 }
@@ -621,6 +845,255 @@ impl EndRunButton {
     }
 }
 
+struct PresetPicker {
+    store: PresetStore,
+    selected: String,
+    new_name: String,
+    /// Result of the last import or export.
+    status: Option<String>,
+}
+
+impl Default for PresetPicker {
+    fn default() -> Self {
+        Self {
+            store: PresetStore::load_default(),
+            selected: "Default".to_owned(),
+            new_name: String::new(),
+            status: None,
+        }
+    }
+}
+
+impl PresetPicker {
+    fn show(&mut self, ui: &mut Ui, settings: &mut GameSettings) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label(tr("preset_label"))
+                .selected_text(self.selected.as_str())
+                .show_ui(ui, |ui| {
+                    for preset in self.store.all() {
+                        ui.selectable_value(
+                            &mut self.selected,
+                            preset.name.clone(),
+                            preset.name.as_str(),
+                        );
+                    }
+                });
+            if ui.button(tr("preset_apply")).clicked() {
+                if let Some(preset) = self.store.get(&self.selected) {
+                    preset.apply_to(settings);
+                }
+            }
+            let user_defined = self
+                .store
+                .presets()
+                .iter()
+                .any(|preset| preset.name == self.selected);
+            if user_defined && ui.button(tr("preset_delete")).clicked() {
+                self.store.remove(&self.selected);
+                self.selected = "Default".to_owned();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_name);
+            let name = self.new_name.trim();
+            if ui
+                .add_enabled(!name.is_empty(), Button::new(tr("preset_save")))
+                .clicked()
+            {
+                self.store
+                    .add(Preset::new(name.to_owned(), settings.clone()));
+                self.selected = name.to_owned();
+                self.new_name.clear();
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button(tr("preset_copy")).clicked() {
+                let preset = Preset::new(self.selected.clone(), settings.clone());
+                match preset.to_share_string() {
+                    Ok(text) => {
+                        ui.output_mut(|o| o.copied_text = text);
+                        self.status = None;
+                    }
+                    Err(err) => self.status = Some(format!("{err:#}")),
+                }
+            }
+            if ui.button(tr("preset_import_clipboard")).clicked() {
+                let text = ClipboardProvider::new()
+                    .and_then(|mut ctx: ClipboardContext| ctx.get_contents())
+                    .map_err(|err| eyre::eyre!("{err}"));
+                self.import(text);
+            }
+            if ui.button(tr("preset_export_file")).clicked() {
+                let preset = Preset::new(self.selected.clone(), settings.clone());
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("json", &["json"])
+                    .set_file_name(format!("{}.json", preset.name))
+                    .save_file()
+                {
+                    self.status = preset
+                        .to_json()
+                        .and_then(|json| Ok(std::fs::write(path, json)?))
+                        .err()
+                        .map(|err| format!("{err:#}"));
+                }
+            }
+            if ui.button(tr("preset_import_file")).clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("json", &["json", "txt"])
+                    .pick_file()
+                {
+                    self.import(std::fs::read_to_string(path).map_err(Into::into));
+                }
+            }
+        });
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+
+    /// Saves an imported preset, so that it can be applied.
+    fn import(&mut self, text: eyre::Result<String>) {
+        match text.and_then(|text| Preset::import(&text)) {
+            Ok(Imported {
+                mut preset,
+                unknown_fields,
+            }) => {
+                if preset.name.is_empty() {
+                    preset.name = "Imported".to_owned();
+                }
+                self.status = (!unknown_fields.is_empty()).then(|| {
+                    tr_a(
+                        "preset_unknown_fields",
+                        &[("fields".to_owned(), unknown_fields.join(", ").into())],
+                    )
+                });
+                self.selected = preset.name.clone();
+                self.store.add(preset);
+            }
+            Err(err) => self.status = Some(format!("{err:#}")),
+        }
+    }
+}
+
+/// How often the inspection panel asks for a fresh list of stored entities.
+const DES_INSPECT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct DesInspector {
+    filter: DesEntityFilter,
+    use_region: bool,
+    region: (i32, i32, i32, i32),
+    last_refresh: Option<Instant>,
+}
+
+impl DesInspector {
+    fn show(&mut self, ui: &mut Ui, netman: &NetManStopOnDrop) {
+        let refresh_clicked = ui.button(tr("des_inspector_refresh")).clicked();
+        if refresh_clicked
+            || self
+                .last_refresh
+                .is_none_or(|last| last.elapsed() >= DES_INSPECT_INTERVAL)
+        {
+            netman.des_inspect_requested.store(true, Ordering::Relaxed);
+            self.last_refresh = Some(Instant::now());
+        }
+        ui.horizontal(|ui| {
+            ui.label(tr("des_inspector_filename"));
+            ui.text_edit_singleline(&mut self.filter.filename)
+                .on_hover_text(tr("des_inspector_filename_hint"));
+            ui.selectable_value(
+                &mut self.filter.sort,
+                DesEntitySort::Filename,
+                tr("des_inspector_sort_filename"),
+            );
+            ui.selectable_value(
+                &mut self.filter.sort,
+                DesEntitySort::Size,
+                tr("des_inspector_sort_size"),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.use_region, tr("des_inspector_region"));
+            ui.add_enabled_ui(self.use_region, |ui| {
+                ui.add(
+                    DragValue::new(&mut self.region.0)
+                        .prefix(format!("{}: ", tr("des_inspector_min_x"))),
+                );
+                ui.add(
+                    DragValue::new(&mut self.region.1)
+                        .prefix(format!("{}: ", tr("des_inspector_min_y"))),
+                );
+                ui.add(
+                    DragValue::new(&mut self.region.2)
+                        .prefix(format!("{}: ", tr("des_inspector_max_x"))),
+                );
+                ui.add(
+                    DragValue::new(&mut self.region.3)
+                        .prefix(format!("{}: ", tr("des_inspector_max_y"))),
+                );
+            });
+        });
+        self.filter.region = self.use_region.then_some(self.region);
+
+        let mut entities = netman.des_entities.lock().unwrap().clone();
+        let total = entities.len();
+        self.filter.apply(&mut entities);
+        ui.label(tr_a(
+            "des_inspector_showing",
+            &[
+                ("shown".to_owned(), entities.len().to_string().into()),
+                ("total".to_owned(), total.to_string().into()),
+            ],
+        ));
+        ui.separator();
+
+        let nicknames = netman.nicknames.lock().unwrap().clone();
+        let row_height = ui.spacing().interact_size.y;
+        ScrollArea::vertical().auto_shrink(false).show_rows(
+            ui,
+            row_height,
+            entities.len(),
+            |ui, range| {
+                egui::Grid::new("des_entities")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for entity in &entities[range] {
+                            ui.label(format!("{:016x}", entity.gid.0));
+                            ui.label(format!("{}, {}", entity.pos.x, entity.pos.y));
+                            ui.label(format!("{}b", entity.size));
+                            ui.label(entity.filename_or_serialized());
+                            match entity.authority {
+                                Some(peer) => {
+                                    let peer_str = peer.to_string();
+                                    ui.label(nicknames.get(&peer).unwrap_or(&peer_str));
+                                    if ui.small_button(tr("des_inspector_release")).clicked() {
+                                        netman
+                                            .des_commands
+                                            .lock()
+                                            .unwrap()
+                                            .push(DesCommand::ForceRelease(entity.gid));
+                                    }
+                                }
+                                None => {
+                                    ui.label("-");
+                                    ui.label("");
+                                }
+                            }
+                            if ui.small_button(tr("des_inspector_delete")).clicked() {
+                                netman
+                                    .des_commands
+                                    .lock()
+                                    .unwrap()
+                                    .push(DesCommand::Delete(entity.gid));
+                            }
+                            ui.end_row();
+                        }
+                    });
+            },
+        );
+    }
+}
+
 pub struct App {
     state: AppState,
     modmanager: Modmanager,
@@ -636,9 +1109,14 @@ pub struct App {
     can_start_automatically: bool,
     player_image: RgbaImage,
     end_run_button: EndRunButton,
+    des_inspector: DesInspector,
     appearance: PlayerAppearance,
     connected_menu: ConnectedMenu,
     show_host_settings: bool,
+    chat_field: String,
+    preset_picker: PresetPicker,
+    /// Lets the game start even if gameplay affecting mods differ from the host's.
+    start_with_incompatible_mods: bool,
 }
 
 fn filled_group<R>(ui: &mut Ui, add_contents: impl FnOnce(&mut Ui) -> R) -> InnerResponse<R> {
@@ -780,9 +1258,13 @@ impl App {
             run_save_state,
             player_image,
             end_run_button: EndRunButton::default(),
+            des_inspector: DesInspector::default(),
             appearance,
             connected_menu: ConnectedMenu::Normal,
             show_host_settings: false,
+            chat_field: String::new(),
+            preset_picker: PresetPicker::default(),
+            start_with_incompatible_mods: false,
         }
     }
 
@@ -845,6 +1327,12 @@ impl App {
                 colors: self.appearance.player_color,
             },
             noita_port,
+            headless: false,
+            admin_port: self
+                .app_saved_state
+                .admin_api
+                .then_some(net::admin_api::DEFAULT_PORT),
+            spectator: self.app_saved_state.join_as_spectator,
         }
     }
 
@@ -870,20 +1358,9 @@ impl App {
     }
 
     fn set_netman_settings(&mut self, netman: &Arc<net::NetManager>) {
-        let run_info: Option<RunInfo> = self.run_save_state.load();
         let mut settings = netman.settings.lock().unwrap();
         *settings = self.app_saved_state.game_settings.clone();
-        if !self.app_saved_state.game_settings.use_constant_seed {
-            if let Some(info) = run_info {
-                settings.seed = info.seed;
-                info!("Using saved seed: {}", settings.seed);
-            } else {
-                settings.seed = rand::random();
-                info!("Using random seed: {}", settings.seed);
-            }
-        } else {
-            info!("Using constant seed: {}", settings.seed);
-        }
+        settings.pick_seed(self.run_save_state.load());
         settings.progress = self.modmanager_settings.get_progress().unwrap_or_default();
         *netman.pending_settings.lock().unwrap() = settings.clone();
     }
@@ -1008,6 +1485,8 @@ impl App {
                                 self.show_host_settings = !self.show_host_settings
                             }
                             if self.show_host_settings {
+                                self.preset_picker
+                                    .show(ui, &mut self.app_saved_state.game_settings);
                                 self.app_saved_state.game_settings.show_editor(ui, true)
                             }
                         });
@@ -1170,6 +1649,18 @@ impl App {
             &mut self.app_saved_state.random_ports,
             tr("connect_settings_random_ports"),
         );
+        ui.checkbox(
+            &mut self.app_saved_state.admin_api,
+            tr("connect_settings_admin_api"),
+        );
+        ui.checkbox(
+            &mut self.app_saved_state.hide_lobby_chat_in_game,
+            tr("connect_settings_hide_lobby_chat_in_game"),
+        );
+        ui.checkbox(
+            &mut self.app_saved_state.join_as_spectator,
+            tr("connect_settings_join_as_spectator"),
+        );
         if self.player_image.width() == 1 {
             self.player_image = image::open(player_path(self.modmanager_settings.mod_path()))
                 .unwrap_or(ImageRgba8(RgbaImage::new(20, 20)))
@@ -1317,26 +1808,45 @@ impl App {
                         "Connection Info",
                     );
                 }
-                if !netman.ban_list.lock().unwrap().is_empty() {
+                if !netman.bans.lock().unwrap().is_empty() {
                     ui.selectable_value(
                         &mut self.connected_menu,
                         ConnectedMenu::BanList,
-                        "Ban List",
+                        tr("ban_list_tab"),
                     );
                 }
-                if !netman.active_mods.lock().unwrap().is_empty() {
+                let shown_mods = *netman.mods_shown_for.lock().unwrap();
+                if shown_mods
+                    .is_some_and(|peer| netman.peer_mods.lock().unwrap().contains_key(&peer))
+                {
                     ui.selectable_value(&mut self.connected_menu, ConnectedMenu::Mods, "Mod List");
                 }
+                if netman.peer.is_host() {
+                    ui.selectable_value(
+                        &mut self.connected_menu,
+                        ConnectedMenu::EntityStorage,
+                        tr("des_inspector_tab"),
+                    );
+                }
+                if !netman.profile_history.lock().unwrap().is_empty() {
+                    ui.selectable_value(
+                        &mut self.connected_menu,
+                        ConnectedMenu::Profiler,
+                        tr("profiler_tab"),
+                    );
+                }
+                if netman.new_run_report.swap(false, Ordering::Relaxed) {
+                    self.connected_menu = ConnectedMenu::RunReport;
+                }
+                if netman.last_run_report.lock().unwrap().is_some() {
+                    ui.selectable_value(
+                        &mut self.connected_menu,
+                        ConnectedMenu::RunReport,
+                        tr("run_report_tab"),
+                    );
+                }
                 if last == ConnectedMenu::Settings && last != self.connected_menu {
-                    let new_settings = self.app_saved_state.game_settings.clone();
-                    *netman.pending_settings.lock().unwrap() = new_settings.clone();
-                    let mut old_settings = netman.settings.lock().unwrap().clone();
-                    old_settings.progress.clear();
-                    old_settings.seed = new_settings.seed;
-                    old_settings.world_num = new_settings.world_num;
-                    netman
-                        .dirty
-                        .store(old_settings != new_settings, Ordering::Relaxed)
+                    netman.set_pending_settings(self.app_saved_state.game_settings.clone());
                 }
                 ui.add_space(ui.available_width() - 56.0);
                 if ui.button("Back out").clicked() {
@@ -1352,6 +1862,22 @@ impl App {
                 }
                 ui.separator();
             }
+            if let Some(report) = netman.addr_report.lock().unwrap().as_ref() {
+                let missing: Vec<_> = report
+                    .missing()
+                    .map(|symbol| symbol.name.as_str())
+                    .collect();
+                if !missing.is_empty() {
+                    ui.colored_label(
+                        Color32::LIGHT_RED,
+                        tr_a(
+                            "noita_build_unsupported",
+                            &[("missing".to_owned(), missing.join(", ").into())],
+                        ),
+                    );
+                    ui.separator();
+                }
+            }
             match self.connected_menu {
                 ConnectedMenu::Normal => {
                     if netman.peer.is_steam() {
@@ -1378,12 +1904,42 @@ impl App {
                         ui.label("requires noita restart")
                     });
                     ui.add_space(15.0);
+                    if !netman.peer.is_host() {
+                        let differ = netman
+                            .settings
+                            .lock()
+                            .unwrap()
+                            .diff(&self.app_saved_state.game_settings)
+                            .len();
+                        if differ > 0 {
+                            ui.label(tr_a(
+                                "settings_differ",
+                                &[("count".to_owned(), differ.to_string().into())],
+                            ));
+                        }
+                    }
+                    let mods_compatible = show_mod_warnings(ui, netman);
+                    if !mods_compatible {
+                        ui.checkbox(
+                            &mut self.start_with_incompatible_mods,
+                            tr("launcher_ignore_mods"),
+                        );
+                    }
+                    let can_start = mods_compatible || self.start_with_incompatible_mods;
                     if accept_local && !local_connected {
                         match noita_launcher.launch_token() {
                             LaunchTokenResult::Ok(mut token) => {
                                 let start_auto = self.can_start_automatically
-                                    && self.app_saved_state.start_game_automatically;
-                                if start_auto || ui.button(tr("launcher_start_game")).clicked() {
+                                    && self.app_saved_state.start_game_automatically
+                                    && mods_compatible;
+                                if start_auto
+                                    || ui
+                                        .add_enabled(
+                                            can_start,
+                                            Button::new(tr("launcher_start_game")),
+                                        )
+                                        .clicked()
+                                {
                                     info!("Starting the game now");
                                     token.start_game(
                                         netman.actual_noita_port.load(Ordering::Relaxed),
@@ -1415,33 +1971,92 @@ impl App {
                             netman.no_more_players.store(temp, Ordering::Relaxed);
                         }
                     }
+                    ui.add_space(15.0);
+                    show_chat(ui, netman, &mut self.chat_field);
                 }
                 ConnectedMenu::Mods => {
-                    let mods_list = netman.active_mods.lock().unwrap();
-                    for mods in mods_list.iter() {
-                        ui.label(mods);
-                    }
-                    if mods_list.is_empty() {
+                    let peer_mods = netman.peer_mods.lock().unwrap();
+                    let mods_list = netman
+                        .mods_shown_for
+                        .lock()
+                        .unwrap()
+                        .and_then(|peer| peer_mods.get(&peer));
+                    if let Some(mods_list) = mods_list {
+                        egui::Grid::new("mod_list").striped(true).show(ui, |ui| {
+                            for info in mods_list.iter() {
+                                ui.label(info.name.as_str());
+                                ui.label(
+                                    info.hash
+                                        .map(|hash| format!("{hash:016x}"))
+                                        .unwrap_or_default(),
+                                );
+                                if info.client_only {
+                                    ui.label(tr("mods_client_only"));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    } else {
                         self.connected_menu = ConnectedMenu::Normal
                     }
                 }
                 ConnectedMenu::BanList => {
-                    let mut ban_list = netman.ban_list.lock().unwrap();
-                    let mut i = ban_list.len();
-                    while i != 0 {
-                        i -= 1;
-                        if ui.button(format!("unban {}", ban_list[i])).clicked() {
-                            ban_list.remove(i);
+                    let mut bans = netman.bans.lock().unwrap();
+                    let mut unban = None;
+                    let mut changed = false;
+                    egui::Grid::new("ban list").striped(true).show(ui, |ui| {
+                        ui.label(tr("ban_list_player"));
+                        ui.label(tr("ban_list_reason"));
+                        ui.label(tr("ban_list_expires"));
+                        ui.end_row();
+                        let now = ban_store::now();
+                        for ban in bans.active_mut() {
+                            match &ban.nickname {
+                                Some(nickname) => ui.label(format!("{nickname} ({})", ban.key)),
+                                None => ui.label(ban.key.to_string()),
+                            };
+                            changed |= ui.text_edit_singleline(&mut ban.reason).lost_focus();
+                            match ban.expires_at {
+                                Some(expires_at) => ui.label(tr_a(
+                                    "ban_list_expires_in",
+                                    &[(
+                                        "hours".to_owned(),
+                                        expires_at
+                                            .saturating_sub(now)
+                                            .div_ceil(3600)
+                                            .to_string()
+                                            .into(),
+                                    )],
+                                )),
+                                None => ui.label(tr("ban_list_never")),
+                            };
+                            if ui.button(tr("ban_list_unban")).clicked() {
+                                unban = Some(ban.key);
+                            }
+                            ui.end_row();
                         }
+                    });
+                    if changed {
+                        bans.save();
                     }
-                    if ban_list.is_empty() {
+                    if let Some(key) = unban {
+                        bans.unban(key);
+                    }
+                    if bans.is_empty() {
                         self.connected_menu = ConnectedMenu::Normal
                     }
                 }
                 ConnectedMenu::Settings => {
                     if netman.peer.is_host() {
+                        self.preset_picker
+                            .show(ui, &mut self.app_saved_state.game_settings);
                         self.app_saved_state.game_settings.show_editor(ui, true);
                     } else {
+                        show_settings_diff(
+                            ui,
+                            &netman.settings.lock().unwrap(),
+                            &self.app_saved_state.game_settings,
+                        );
                         netman.settings.lock().unwrap().show_editor(ui, false);
                     }
                 }
@@ -1460,6 +2075,19 @@ impl App {
                         ctx.request_repaint_after(Duration::from_millis(16));
                     }
                 },
+                ConnectedMenu::EntityStorage => self.des_inspector.show(ui, netman),
+                ConnectedMenu::Profiler => {
+                    util::profile_graph::show_profile_graph(
+                        ui,
+                        &netman.profile_history.lock().unwrap(),
+                    );
+                    ctx.request_repaint_after(Duration::from_millis(500));
+                }
+                ConnectedMenu::RunReport => {
+                    if let Some(report) = netman.last_run_report.lock().unwrap().as_ref() {
+                        show_run_report(ui, report);
+                    }
+                }
             }
             if self.app_saved_state.show_extra_debug_stuff {
                 if self.show_map_plot {
@@ -1503,6 +2131,10 @@ impl App {
         netman
             .enable_recorder
             .store(self.app_saved_state.record_all, Ordering::Relaxed);
+        netman.forward_chat_to_game.store(
+            !self.app_saved_state.hide_lobby_chat_in_game,
+            Ordering::Relaxed,
+        );
         if goto_menu {
             self.state = AppState::Connect;
         }
@@ -1651,7 +2283,12 @@ fn show_player_list(ui: &mut Ui, netman: &mut NetManStopOnDrop) {
     ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
         let nicknames = netman.nicknames.lock().unwrap().clone();
         let minas = netman.minas.lock().unwrap().clone();
+        let headless_host = netman.headless_host.load(Ordering::Relaxed);
         for peer in netman.peer.iter_peer_ids().clone() {
+            // Dedicated server isn't a player.
+            if headless_host && peer == netman.peer.host_id() {
+                continue;
+            }
             let role = peer_role(peer, netman);
             let peer_str = peer.to_string().clone();
             let username = nicknames.get(&peer).unwrap_or(&peer_str);
@@ -1671,10 +2308,12 @@ fn show_player_list(ui: &mut Ui, netman: &mut NetManStopOnDrop) {
                                     netman.kick_list.lock().unwrap().push(peer)
                                 }
                                 if ui.button("Ban").clicked() {
-                                    netman.ban_list.lock().unwrap().push(peer)
+                                    netman.ban(peer, String::new(), None);
                                 }
+                                spectator_button(ui, netman, peer);
                             }
                             if ui.button("Mods").clicked() {
+                                *netman.mods_shown_for.lock().unwrap() = Some(peer);
                                 netman.send(peer, &NetMsg::RequestMods, Reliability::Reliable);
                             }
                         });
@@ -1684,6 +2323,154 @@ fn show_player_list(ui: &mut Ui, netman: &mut NetManStopOnDrop) {
         }
     });
 }
+/// Lets the host turn a player into a spectator and back.
+fn spectator_button(ui: &mut Ui, netman: &NetManStopOnDrop, peer: OmniPeerId) {
+    let spectator = netman.spectators.lock().unwrap().contains(&peer);
+    let label = if spectator {
+        tr("player_make_player")
+    } else {
+        tr("player_make_spectator")
+    };
+    if ui.button(label).clicked() {
+        netman.set_spectator(peer, !spectator);
+    }
+}
+
+fn show_settings_diff(ui: &mut Ui, host: &GameSettings, mine: &GameSettings) {
+    let diff = host.diff(mine);
+    if diff.is_empty() {
+        return;
+    }
+    ui.label(tr("settings_diff_title"));
+    egui::Grid::new("settings_diff")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label(tr("settings_diff_setting"));
+            ui.label(tr("settings_diff_host"));
+            ui.label(tr("settings_diff_mine"));
+            ui.end_row();
+            for setting in diff {
+                ui.label(setting.name);
+                ui.colored_label(Color32::YELLOW, setting.host);
+                ui.label(setting.mine);
+                ui.end_row();
+            }
+        });
+    ui.separator();
+}
+
+/// Warns about mods that differ between the host and players, before the run starts.
+/// Returns false if this player's mods aren't compatible with the host's.
+fn show_mod_warnings(ui: &mut Ui, netman: &NetManStopOnDrop) -> bool {
+    let local_mods = netman.local_mods.lock().unwrap();
+    let peer_mods = netman.peer_mods.lock().unwrap();
+    let list = |mods: &[String]| FluentValue::from(mods.join(", "));
+    if netman.peer.is_host() {
+        let nicknames = netman.nicknames.lock().unwrap();
+        for (peer, mods) in peer_mods.iter() {
+            let diff = ModDiff::new(&local_mods, mods);
+            if diff.is_empty() {
+                continue;
+            }
+            let name = nicknames
+                .get(peer)
+                .cloned()
+                .unwrap_or_else(|| peer.to_string());
+            ui.colored_label(
+                Color32::YELLOW,
+                tr_a(
+                    "mods_peer_differ",
+                    &[
+                        ("name".to_owned(), name.into()),
+                        ("missing".to_owned(), list(&diff.missing)),
+                        ("extra".to_owned(), list(&diff.extra)),
+                        ("mismatched".to_owned(), list(&diff.mismatched)),
+                    ],
+                ),
+            );
+        }
+        true
+    } else if let Some(host_mods) = peer_mods.get(&netman.peer.host_id()) {
+        let diff = ModDiff::new(host_mods, &local_mods);
+        for (key, mods) in [
+            ("mods_missing", &diff.missing),
+            ("mods_extra", &diff.extra),
+            ("mods_mismatched", &diff.mismatched),
+        ] {
+            if !mods.is_empty() {
+                ui.colored_label(
+                    Color32::LIGHT_RED,
+                    tr_a(key, &[("mods".to_owned(), list(mods))]),
+                );
+            }
+        }
+        diff.is_empty()
+    } else {
+        true
+    }
+}
+
+fn show_run_report(ui: &mut Ui, report: &net::run_stats::RunReport) {
+    ui.label(tr_a(
+        "run_report_summary",
+        &[
+            (
+                "minutes".to_owned(),
+                (report.duration_secs / 60).to_string().into(),
+            ),
+            ("world".to_owned(), report.world_num.to_string().into()),
+            (
+                "sent".to_owned(),
+                format!("{:.1}", report.bytes_sent as f64 / 1_000_000.0).into(),
+            ),
+        ],
+    ));
+    if report.game_over {
+        ui.colored_label(Color32::LIGHT_RED, tr("run_report_game_over"));
+    }
+    egui::Grid::new("run_report").striped(true).show(ui, |ui| {
+        ui.label(tr("run_report_player"));
+        ui.label(tr("run_report_distance"));
+        ui.label(tr("run_report_kills"));
+        ui.label(tr("run_report_deaths"));
+        ui.label(tr("run_report_chunks"));
+        ui.end_row();
+        for player in &report.players {
+            ui.label(player.nickname.as_str());
+            ui.label(player.distance.to_string());
+            ui.label(player.kills.to_string());
+            ui.label(player.deaths.to_string());
+            ui.label(player.chunks_updated.to_string());
+            ui.end_row();
+        }
+    });
+}
+
+fn show_chat(ui: &mut Ui, netman: &NetManStopOnDrop, chat_field: &mut String) {
+    ui.label(tr("chat_title"));
+    egui::ScrollArea::vertical()
+        .max_height(200.0)
+        .stick_to_bottom(true)
+        .auto_shrink([false, true])
+        .show(ui, |ui| {
+            for message in netman.chat.lock().unwrap().history() {
+                ui.label(format!("{}: {}", message.nickname, message.text));
+            }
+        });
+    ui.horizontal(|ui| {
+        let response = ui.add(
+            egui::TextEdit::singleline(chat_field)
+                .char_limit(net::chat::MAX_MESSAGE_LEN)
+                .hint_text(tr("chat_hint")),
+        );
+        let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+        if (ui.button(tr("chat_send")).clicked() || entered) && !chat_field.trim().is_empty() {
+            netman.send_chat(chat_field, false);
+            chat_field.clear();
+            response.request_focus();
+        }
+    });
+}
 fn display_with_labels(
     img: RgbaImage,
     ui: &mut Ui,
@@ -1704,10 +2491,12 @@ fn display_with_labels(
                                 netman.kick_list.lock().unwrap().push(peer)
                             }
                             if ui.button("Ban").clicked() {
-                                netman.ban_list.lock().unwrap().push(peer)
+                                netman.ban(peer, String::new(), None);
                             }
+                            spectator_button(ui, netman, peer);
                         }
                         if ui.button("Mods").clicked() {
+                            *netman.mods_shown_for.lock().unwrap() = Some(peer);
                             netman.send(peer, &NetMsg::RequestMods, Reliability::Reliable);
                         }
                     });
@@ -1783,6 +2572,8 @@ fn add_per_status_ui(
 fn peer_role(peer: OmniPeerId, netman: &Arc<net::NetManager>) -> String {
     if peer == netman.peer.host_id() {
         tr("player_host")
+    } else if netman.spectators.lock().unwrap().contains(&peer) {
+        tr("player_spectator")
     } else if peer == netman.peer.my_id() {
         tr("player_me")
     } else {
@@ -1802,11 +2593,7 @@ fn cli_setup() -> (steam_helper::SteamState, NetManagerInit) {
 
     mod_manager.try_find_game_path(Some(&mut state));
     mod_manager.try_find_save_path();
-    let run_save_state = if let Ok(path) = std::env::current_exe() {
-        SaveState::new(path.parent().unwrap().join("save_state"))
-    } else {
-        SaveState::new("./save_state/".into())
-    };
+    let run_save_state = SaveState::new(default_save_state_path());
     let player_path = player_path(mod_manager.mod_path());
     let mut cosmetics = (false, false, false);
     if let Some(path) = &mod_manager.game_save_path {
@@ -1836,12 +2623,16 @@ fn cli_setup() -> (steam_helper::SteamState, NetManagerInit) {
             colors: appearance.player_color,
         },
         noita_port: 21251,
+        headless: false,
+        admin_port: None,
+        spectator: false,
     };
     (state, netmaninit)
 }
 
-pub fn connect_cli(lobby: String) {
-    let (state, netmaninit) = cli_setup();
+pub fn connect_cli(lobby: String, spectate: bool) {
+    let (state, mut netmaninit) = cli_setup();
+    netmaninit.spectator = spectate;
     let varient = if lobby.contains('.') {
         PeerVariant::Tangled(Peer::connect(SocketAddr::from_str(&lobby).unwrap(), None).unwrap())
     } else {
@@ -1856,21 +2647,508 @@ pub fn connect_cli(lobby: String) {
     netman.start_inner(player_path, true).unwrap();
 }
 
-pub fn host_cli(port: u16) {
-    let (state, netmaninit) = cli_setup();
-    let varient = if port != 0 {
-        let bind_addr = SocketAddr::new("0.0.0.0".parse().unwrap(), port);
+/// Hosts with game settings from the config file, or the ones saved by the gui without one, overridden by flags.
+/// A dedicated server's config file also has server settings, see [`DedicatedConfig`], and it doesn't use the ones saved by the gui.
+pub fn host_cli(args: args::HostArgs) {
+    if args.dedicated {
+        if args.steam {
+            println!("A dedicated server can't host a steam lobby, drop --steam");
+            return;
+        }
+        let mut config = match &args.config {
+            Some(path) => match DedicatedConfig::load(path) {
+                Ok(config) => config,
+                Err(err) => {
+                    println!("Could not load {}: {err}", path.display());
+                    return;
+                }
+            },
+            None => DedicatedConfig::default(),
+        };
+        if !apply_setting_args(&mut config.game_settings, &args) {
+            return;
+        }
+        config.apply_overrides(&args);
+        if args.print_config {
+            config.game_settings = config.game_settings.with_defaults();
+            match ron::ser::to_string_pretty(&config, Default::default()) {
+                Ok(config) => println!("{config}"),
+                Err(err) => println!("Could not serialize config: {err}"),
+            }
+            return;
+        }
+        run_dedicated(config);
+        return;
+    }
+
+    let mut game_settings = match &args.config {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|config| ron::from_str(&config).map_err(|err| err.to_string()))
+        {
+            Ok(game_settings) => game_settings,
+            Err(err) => {
+                println!("Could not load {}: {err}", path.display());
+                return;
+            }
+        },
+        None => settings_get().app.game_settings,
+    };
+    if !apply_setting_args(&mut game_settings, &args) {
+        return;
+    }
+    if args.print_config {
+        match ron::ser::to_string_pretty(&game_settings.with_defaults(), Default::default()) {
+            Ok(config) => println!("{config}"),
+            Err(err) => println!("Could not serialize game settings: {err}"),
+        }
+        return;
+    }
+
+    let (state, mut netmaninit) = cli_setup();
+    if let Some(path) = args.save_state {
+        netmaninit.save_state = SaveState::new(path);
+    }
+    netmaninit.admin_port = args.admin_port;
+    let varient = if !args.steam {
+        let bind_addr = SocketAddr::new(
+            "0.0.0.0".parse().unwrap(),
+            args.port.unwrap_or(DEFAULT_PORT),
+        );
         let peer = Peer::host(bind_addr, None).unwrap();
         PeerVariant::Tangled(peer)
     } else {
         let peer = net::steam_networking::SteamPeer::new_host(
             steamworks::LobbyType::Private,
             state.client,
-            250,
+            game_settings
+                .max_players
+                .unwrap_or(DefaultSettings::default().max_players),
         );
         PeerVariant::Steam(peer)
     };
+    game_settings.pick_seed(netmaninit.save_state.load());
+    if game_settings.progress.is_empty() {
+        game_settings.progress = netmaninit
+            .modmanager_settings
+            .get_progress()
+            .unwrap_or_default();
+    }
     let player_path = netmaninit.player_path.clone();
     let netman = net::NetManager::new(varient, netmaninit);
+    *netman.settings.lock().unwrap() = game_settings.clone();
+    *netman.pending_settings.lock().unwrap() = game_settings;
     netman.start_inner(player_path, true).unwrap();
 }
+
+/// Applies `--preset`, then the game setting flags. Returns false, after saying why, if they're invalid.
+fn apply_setting_args(game_settings: &mut GameSettings, args: &args::HostArgs) -> bool {
+    if let Some(name) = &args.preset {
+        match PresetStore::load_default().get(name) {
+            Some(preset) => preset.apply_to(game_settings),
+            None => {
+                println!("No preset named {name}, see `presets`");
+                return false;
+            }
+        }
+    }
+    if let Err(err) = game_settings.apply_overrides(args) {
+        println!("Invalid game settings: {err}");
+        return false;
+    }
+    true
+}
+
+fn run_dedicated(config: DedicatedConfig) {
+    let save_state = SaveState::new(config.save_state.unwrap_or_else(default_save_state_path));
+    let bind_addr = SocketAddr::new("0.0.0.0".parse().unwrap(), config.port);
+    let peer = match Peer::host(bind_addr, None) {
+        Ok(peer) => peer,
+        Err(err) => {
+            println!("Could not host on {bind_addr}: {err}");
+            return;
+        }
+    };
+    let netmaninit = NetManagerInit {
+        my_nickname: config.name,
+        save_state: save_state.clone(),
+        cosmetics: (false, false, false),
+        mod_path: PathBuf::new(),
+        player_path: PathBuf::new(),
+        modmanager_settings: ModmanagerSettings::default(),
+        player_png_desc: PlayerPngDesc::default(),
+        noita_port: 0,
+        headless: true,
+        admin_port: config.admin_port,
+        spectator: false,
+    };
+    let netman = net::NetManager::new(PeerVariant::Tangled(peer), netmaninit);
+    {
+        let mut settings = netman.settings.lock().unwrap();
+        *settings = config.game_settings;
+        settings.pick_seed(save_state.load());
+        *netman.pending_settings.lock().unwrap() = settings.clone();
+    }
+
+    let stop_netman = netman.clone();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim() == "stop" {
+                stop_netman.continue_running.store(false, Ordering::Relaxed);
+                return;
+            }
+            println!("Type \"stop\" to save and exit");
+        }
+    });
+    println!("Hosting on {bind_addr}");
+    if let Err(err) = netman.start_inner(PathBuf::new(), false) {
+        println!("Server stopped: {err}");
+    }
+}
+
+fn default_save_state_path() -> PathBuf {
+    if let Ok(path) = std::env::current_exe() {
+        path.parent().unwrap().join("save_state")
+    } else {
+        "./save_state/".into()
+    }
+}
+
+pub fn inspect_save_cli(args: args::InspectSaveArgs) {
+    let save_state = SaveState::new(args.save_state.unwrap_or_else(default_save_state_path));
+    match save_state.load::<net::RunInfo>() {
+        Some(info) => println!("Seed: {}", info.seed),
+        None => println!("No run info, a new run will be started"),
+    }
+    match net::world::stored_chunk_count(&save_state) {
+        Some(count) => println!("World chunks: {count}"),
+        None => println!("No world chunks"),
+    }
+    match net::des::stored_entity_count(&save_state) {
+        Some(count) => println!("DES entities: {count}"),
+        None => println!("No DES entities"),
+    }
+}
+
+pub fn render_map_cli(args: args::RenderMapArgs) {
+    let region = match args.region.as_deref().map(parse_region).transpose() {
+        Ok(region) => region,
+        Err(err) => {
+            println!("Invalid region: {err}");
+            return;
+        }
+    };
+    let save_state = SaveState::new(args.save_state.unwrap_or_else(default_save_state_path));
+    match net::world::render_stored_chunks(&save_state, region) {
+        Ok(image) => match image.save(&args.output) {
+            Ok(()) => println!(
+                "Saved {}x{} map to {}",
+                image.width(),
+                image.height(),
+                args.output.display()
+            ),
+            Err(err) => println!("Could not save {}: {err}", args.output.display()),
+        },
+        Err(err) => println!("Could not render map: {err}"),
+    }
+}
+
+/// Lists public steam lobbies that have a mod version set.
+pub fn list_lobbies_cli(_args: args::ListLobbiesArgs) {
+    let state = match steam_helper::SteamState::new(false) {
+        Ok(state) => state,
+        Err(err) => {
+            println!("Could not init steam: {err}");
+            return;
+        }
+    };
+    let (sender, receiver) = crossbeam::channel::bounded(1);
+    state
+        .client
+        .matchmaking()
+        .request_lobby_list(move |lobbies| {
+            sender.send(lobbies).ok();
+        });
+    let lobbies = match receiver.recv_timeout(Duration::from_secs(10)) {
+        Ok(Ok(lobbies)) => lobbies,
+        Ok(Err(err)) => {
+            println!("Could not get lobby list: {err}");
+            return;
+        }
+        Err(_) => {
+            println!("Timed out waiting for lobby list");
+            return;
+        }
+    };
+    let matchmaking = state.client.matchmaking();
+    let mut shown = 0;
+    for id in lobbies {
+        let Some(version) = matchmaking.lobby_data(id, "ew_version") else {
+            continue;
+        };
+        let compatible = if releases::Version::parse_from_diplay(version)
+            == Some(releases::Version::current())
+        {
+            ""
+        } else {
+            " (incompatible)"
+        };
+        println!(
+            "{} {version}{compatible} {} players",
+            id.raw(),
+            matchmaking.lobby_member_count(id)
+        );
+        shown += 1;
+    }
+    println!("Found {shown} lobbies");
+}
+
+pub fn bans_cli(args: args::BansArgs) {
+    let mut store = BanStore::load_default();
+    if let Some(key) = args.ban {
+        match key.parse() {
+            Ok(key) => {
+                let mut ban = Ban::new(key, None, args.hours.map(|hours| hours * 3600));
+                ban.reason = args.reason.unwrap_or_default();
+                store.ban(ban);
+            }
+            Err(err) => println!("Can't ban: {err}"),
+        }
+    }
+    if let Some(key) = args.unban {
+        match key.parse::<BanKey>() {
+            Ok(key) if store.unban(key) => {}
+            Ok(key) => println!("{key} isn't banned"),
+            Err(err) => println!("Can't unban: {err}"),
+        }
+    }
+    let now = ban_store::now();
+    for ban in store.active() {
+        let expires = match ban.expires_at {
+            Some(expires_at) => format!("{}h", expires_at.saturating_sub(now).div_ceil(3600)),
+            None => "never".to_owned(),
+        };
+        println!(
+            "{} {} expires: {expires} reason: {}",
+            ban.key,
+            ban.nickname.as_deref().unwrap_or("-"),
+            ban.reason
+        );
+    }
+}
+
+pub fn presets_cli(args: args::PresetsArgs) {
+    let mut store = PresetStore::load_default();
+    if let Some(text) = args.import {
+        // Either a share string or a file with one.
+        let text = std::fs::read_to_string(&text).unwrap_or(text);
+        match Preset::import(&text) {
+            Ok(Imported {
+                mut preset,
+                unknown_fields,
+            }) => {
+                if !unknown_fields.is_empty() {
+                    println!(
+                        "Ignored settings unknown to this version: {}",
+                        unknown_fields.join(", ")
+                    );
+                }
+                if let Some(name) = args.name {
+                    preset.name = name;
+                } else if preset.name.is_empty() {
+                    preset.name = "Imported".to_owned();
+                }
+                println!("Saved preset {}", preset.name);
+                store.add(preset);
+            }
+            Err(err) => println!("Can't import preset: {err:#}"),
+        }
+    }
+    if let Some(name) = args.remove {
+        if !store.remove(&name) {
+            println!("No saved preset named {name}");
+        }
+    }
+    if let Some(name) = args.export {
+        match store.get(&name).map(|preset| preset.to_share_string()) {
+            Some(Ok(text)) => println!("{text}"),
+            Some(Err(err)) => println!("Can't export preset: {err:#}"),
+            None => println!("No preset named {name}"),
+        }
+        return;
+    }
+    for preset in store.all() {
+        println!("{}", preset.name);
+    }
+}
+
+pub fn inspect_des_cli(args: args::InspectDesArgs) {
+    let save_state_path = args.save_state.unwrap_or_else(default_save_state_path);
+    let region = match args.region.as_deref().map(parse_region).transpose() {
+        Ok(region) => region,
+        Err(err) => {
+            println!("Invalid region: {err}");
+            return;
+        }
+    };
+    let (Ok(show), Ok(delete)) = (parse_gids(&args.show), parse_gids(&args.delete)) else {
+        println!("Gids to show or delete should be in hex");
+        return;
+    };
+    let patch = match args
+        .patch
+        .iter()
+        .map(|patch| parse_patch(patch))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(patch) => patch,
+        Err(err) => {
+            println!("Invalid patch: {err}");
+            return;
+        }
+    };
+    let filter = DesEntityFilter {
+        filename: args.filename.unwrap_or_default(),
+        region,
+        sort: if args.by_size {
+            DesEntitySort::Size
+        } else {
+            DesEntitySort::Filename
+        },
+    };
+    net::des::inspect_storage_cli(save_state_path, &filter, &show, &patch, &delete);
+}
+
+fn parse_gids(gids: &[String]) -> Result<Vec<shared::des::Gid>, std::num::ParseIntError> {
+    gids.iter()
+        .map(|gid| u64::from_str_radix(gid, 16).map(shared::des::Gid))
+        .collect()
+}
+
+fn parse_patch(patch: &str) -> Result<net::des::DesPatch, String> {
+    let (gid, rest) = patch
+        .split_once(':')
+        .ok_or("expected gid:Component.field=value")?;
+    let (path, value) = rest
+        .split_once('=')
+        .ok_or("expected gid:Component.field=value")?;
+    Ok(net::des::DesPatch {
+        gid: shared::des::Gid(u64::from_str_radix(gid, 16).map_err(|err| err.to_string())?),
+        path: path.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn parse_game_mode(game_mode: &str) -> Result<GameMode, String> {
+    match game_mode {
+        "shared" => Ok(GameMode::SharedHealth),
+        "local" => Ok(GameMode::LocalHealth(LocalHealthMode::Normal)),
+        "local-alt" => Ok(GameMode::LocalHealth(LocalHealthMode::Alternate)),
+        "perma-death" => Ok(GameMode::LocalHealth(LocalHealthMode::PermaDeath)),
+        _ => Err(format!("unknown game mode {game_mode}")),
+    }
+}
+
+fn parse_region(region: &str) -> Result<(i32, i32, i32, i32), String> {
+    let coords = region
+        .split(',')
+        .map(|coord| coord.trim().parse::<i32>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match coords[..] {
+        [min_x, min_y, max_x, max_y] => Ok((min_x, min_y, max_x, max_y)),
+        _ => Err("expected 4 comma-separated numbers".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn game_modes() {
+        assert_eq!(parse_game_mode("shared"), Ok(GameMode::SharedHealth));
+        assert_eq!(
+            parse_game_mode("local"),
+            Ok(GameMode::LocalHealth(LocalHealthMode::Normal))
+        );
+        assert_eq!(
+            parse_game_mode("local-alt"),
+            Ok(GameMode::LocalHealth(LocalHealthMode::Alternate))
+        );
+        assert_eq!(
+            parse_game_mode("perma-death"),
+            Ok(GameMode::LocalHealth(LocalHealthMode::PermaDeath))
+        );
+        assert!(parse_game_mode("Shared").is_err());
+        assert!(parse_game_mode("").is_err());
+    }
+
+    #[test]
+    fn overrides_replace_only_given_settings() {
+        let mut game_settings = GameSettings {
+            seed: 1,
+            friendly_fire: Some(true),
+            progress: vec!["BOMB".to_owned()],
+            ..Default::default()
+        };
+        let args = args::HostArgs {
+            seed: Some(42),
+            game_mode: Some("local".to_owned()),
+            enemy_hp_mult: Some(2.0),
+            ..Default::default()
+        };
+        game_settings.apply_overrides(&args).unwrap();
+        assert_eq!(game_settings.seed, 42);
+        assert!(game_settings.use_constant_seed);
+        assert_eq!(
+            game_settings.game_mode,
+            Some(GameMode::LocalHealth(LocalHealthMode::Normal))
+        );
+        assert_eq!(game_settings.enemy_hp_mult, Some(2.0));
+        assert_eq!(game_settings.friendly_fire, Some(true));
+        assert_eq!(game_settings.progress, vec!["BOMB".to_owned()]);
+    }
+
+    #[test]
+    fn settings_diff_is_translated() {
+        let host = GameSettings {
+            friendly_fire: Some(true),
+            game_mode: Some(GameMode::SharedHealth),
+            seed: 1,
+            ..Default::default()
+        };
+        let mine = GameSettings {
+            friendly_fire: Some(false),
+            game_mode: Some(GameMode::LocalHealth(LocalHealthMode::PermaDeath)),
+            ..Default::default()
+        };
+        let diff = host.diff(&mine);
+        assert_eq!(diff.len(), 2);
+        let friendly_fire = diff
+            .iter()
+            .find(|setting| setting.name == tr("Enable-friendly-fire"))
+            .unwrap();
+        assert_eq!(friendly_fire.host, "On");
+        assert_eq!(friendly_fire.mine, "Off");
+        let game_mode = diff
+            .iter()
+            .find(|setting| setting.name == "Game mode")
+            .unwrap();
+        assert_eq!(game_mode.host, "Shared health");
+        assert_eq!(game_mode.mine, "Local health perma death");
+    }
+
+    #[test]
+    fn invalid_game_mode_is_rejected() {
+        let mut game_settings = GameSettings::default();
+        let args = args::HostArgs {
+            game_mode: Some("co-op".to_owned()),
+            ..Default::default()
+        };
+        assert!(game_settings.apply_overrides(&args).is_err());
+        assert_eq!(game_settings.game_mode, None);
+    }
+}
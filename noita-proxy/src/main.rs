@@ -11,7 +11,11 @@ use eframe::{
     egui::{IconData, ViewportBuilder},
     NativeOptions,
 };
-use noita_proxy::{args::Args, connect_cli, host_cli, App};
+use noita_proxy::{
+    args::{Args, Command, HostArgs},
+    bans_cli, connect_cli, host_cli, inspect_des_cli, inspect_save_cli, list_lobbies_cli,
+    presets_cli, render_map_cli, App,
+};
 use tracing::{error, info, level_filters::LevelFilter};
 use tracing_subscriber::EnvFilter;
 
@@ -76,15 +80,27 @@ async fn main() {
 
     info!("Launch command: {:?}", args.launch_cmd);
 
-    if let Some(host) = args.host {
-        let port = if host.eq_ignore_ascii_case("steam") {
-            0
-        } else {
-            host.parse::<u16>().unwrap_or(5123)
-        };
-        host_cli(port)
+    if let Some(command) = args.command {
+        match command {
+            Command::Host(host_args) => host_cli(host_args),
+            Command::Connect(connect_args) => {
+                connect_cli(connect_args.lobby, connect_args.spectate)
+            }
+            Command::InspectSave(inspect_args) => inspect_save_cli(inspect_args),
+            Command::InspectDes(inspect_args) => inspect_des_cli(inspect_args),
+            Command::RenderMap(render_args) => render_map_cli(render_args),
+            Command::ListLobbies(list_args) => list_lobbies_cli(list_args),
+            Command::Bans(bans_args) => bans_cli(bans_args),
+            Command::Presets(presets_args) => presets_cli(presets_args),
+        }
+    } else if let Some(host) = args.host {
+        host_cli(HostArgs {
+            steam: host.eq_ignore_ascii_case("steam"),
+            port: host.parse().ok(),
+            ..Default::default()
+        })
     } else if let Some(lobby) = args.lobby {
-        connect_cli(lobby)
+        connect_cli(lobby, false)
     } else {
         let icon = image::load_from_memory(include_bytes!("../assets/icon.png"))
             .unwrap()
@@ -1,14 +1,19 @@
 use bitcode::{Decode, Encode};
-use des::DesManager;
+use chat::{Chat, ChatMessage};
+use des::{DesCommand, DesEntitySummary, DesManager};
 use image::DynamicImage::ImageRgba8;
 use image::{ImageBuffer, Rgba, RgbaImage};
 use messages::{MessageRequest, NetMsg};
 use omni::OmniPeerId;
-use proxy_opt::ProxyOpt;
+use run_stats::{RunReport, RunStats};
+use shared::des::ProxyToDes;
 use shared::message_socket::MessageSocket;
-use shared::{Destination, NoitaInbound, NoitaOutbound, RemoteMessage};
+use shared::{
+    options::GameOptions, AddrReport, Destination, NoitaInbound, NoitaOutbound, ProfileSummary,
+    RemoteMessage,
+};
 use socket2::{Domain, Socket, Type};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{create_dir, remove_dir_all, File};
 use std::io::Write;
 use std::path::PathBuf;
@@ -27,19 +32,29 @@ use world::{world_info::WorldInfo, NoitaWorldUpdate, WorldManager};
 use tangled::Reliability;
 use tracing::{error, info, warn};
 
-use crate::mod_manager::{get_mods, ModmanagerSettings};
+use crate::mod_manager::ModmanagerSettings;
 use crate::net::world::world_model::chunk::{Pixel, PixelFlags};
 use crate::player_cosmetics::{create_player_png, get_player_skin, PlayerPngDesc};
 use crate::{
-    bookkeeping::save_state::{SaveState, SaveStateEntry},
+    bookkeeping::{
+        ban_store::{Ban, BanStore},
+        mod_compat::{self, ModInfo},
+        save_state::{SaveState, SaveStateEntry},
+    },
     DefaultSettings, GameMode, GameSettings, LocalHealthMode,
 };
-mod des;
+pub(crate) mod admin_api;
+pub(crate) mod chat;
+pub(crate) mod des;
 pub mod messages;
 mod proxy_opt;
+pub(crate) mod run_stats;
 pub mod steam_networking;
 pub mod world;
 
+/// Number of profiler summaries kept, a bit over 2 minutes as ewext sends one every second.
+pub const PROFILE_HISTORY_LEN: usize = 128;
+
 pub(crate) fn ws_encode_proxy(key: &'static str, value: impl Display) -> NoitaInbound {
     let mut buf = Vec::new();
     buf.push(2);
@@ -55,6 +70,39 @@ pub fn ws_encode_proxy_bin(key: u8, data: &[u8]) -> NoitaInbound {
     NoitaInbound::RawMessage(buf)
 }
 
+/// Spectators as a comma separated list for the mod, `-` if there are none.
+fn encode_spectators(spectators: &HashSet<OmniPeerId>) -> NoitaInbound {
+    let list = spectators
+        .iter()
+        .map(|peer| peer.as_hex())
+        .collect::<Vec<_>>()
+        .join(",");
+    ws_encode_proxy(
+        "spectators",
+        if list.is_empty() { "-" } else { list.as_str() },
+    )
+}
+
+/// Picks the peer that does what only the host's game should do, e.g. deduplicating item spawns.
+/// That's the host, unless it's a dedicated server without a game, then it's the player with the lowest id,
+/// so that every peer comes to the same choice without having to agree on it.
+fn elect_duty_peer(
+    host: OmniPeerId,
+    headless_host: bool,
+    peers: &[OmniPeerId],
+    spectators: &HashSet<OmniPeerId>,
+) -> OmniPeerId {
+    if !headless_host {
+        return host;
+    }
+    peers
+        .iter()
+        .copied()
+        .filter(|peer| *peer != host && !spectators.contains(peer))
+        .min_by_key(|peer| peer.0)
+        .unwrap_or(host)
+}
+
 pub(crate) fn ws_encode_mod(peer: OmniPeerId, data: &[u8]) -> NoitaInbound {
     let mut buf = Vec::new();
     buf.push(1u8);
@@ -96,13 +144,6 @@ impl NetInnerState {
             };
         }
     }
-    pub(crate) fn try_ws_write_option(&mut self, key: &str, value: impl ProxyOpt) {
-        let mut buf = Vec::new();
-        buf.push(2);
-        value.write_opt(&mut buf, key);
-        let message = NoitaInbound::RawMessage(buf);
-        self.try_ms_write(&message);
-    }
 }
 
 pub mod omni;
@@ -116,6 +157,12 @@ pub struct NetManagerInit {
     pub modmanager_settings: ModmanagerSettings,
     pub player_png_desc: PlayerPngDesc,
     pub noita_port: u16,
+    /// Dedicated server mode: no game connects to this proxy, so there is no local player and no appearance files.
+    pub headless: bool,
+    /// Port of the local admin api, which is only started by the host.
+    pub admin_port: Option<u16>,
+    /// Ask the host to join as a spectator, which never takes authority over chunks or entities.
+    pub spectator: bool,
 }
 
 pub struct NetManager {
@@ -132,17 +179,42 @@ pub struct NetManager {
     pub enable_recorder: AtomicBool,
     pub end_run: AtomicBool,
     pub debug_markers: Mutex<Vec<DebugMarker>>,
-    pub ban_list: Mutex<Vec<OmniPeerId>>,
+    /// Persisted between sessions, only enforced by the host.
+    pub bans: Mutex<BanStore>,
     pub kick_list: Mutex<Vec<OmniPeerId>>,
     pub no_more_players: AtomicBool,
     dont_kick: Mutex<Vec<OmniPeerId>>,
     pub dirty: AtomicBool,
     pub actual_noita_port: AtomicU16,
-    pub active_mods: Mutex<Vec<String>>,
+    /// Enabled mods of this game, sent to the host to compare.
+    pub(crate) local_mods: Mutex<Vec<ModInfo>>,
+    /// Last known enabled mods of other peers.
+    pub(crate) peer_mods: Mutex<HashMap<OmniPeerId, Vec<ModInfo>>>,
+    /// Peer whose mods are shown in the mod list.
+    pub mods_shown_for: Mutex<Option<OmniPeerId>>,
     pub nicknames: Mutex<HashMap<OmniPeerId, String>>,
+    /// Peers that only watch the run, decided by the host.
+    pub spectators: Mutex<HashSet<OmniPeerId>>,
+    /// Whether the host is a dedicated server without a game, told by the host with `HeadlessHost`.
+    pub headless_host: AtomicBool,
+    pub(crate) chat: Mutex<Chat>,
+    /// Whether chat from the lobby screen is shown in game.
+    pub forward_chat_to_game: AtomicBool,
+    pub(crate) run_stats: Mutex<RunStats>,
+    pub(crate) last_run_report: Mutex<Option<RunReport>>,
+    /// Set when a run ends, so that the lobby shows its report.
+    pub new_run_report: AtomicBool,
     #[allow(clippy::type_complexity)]
     pub minas: Mutex<HashMap<OmniPeerId, ImageBuffer<Rgba<u8>, Vec<u8>>>>,
     pub new_desc: Mutex<Option<PlayerPngDesc>>,
+    /// Set by the inspection panel to get `des_entities` refreshed.
+    pub des_inspect_requested: AtomicBool,
+    pub des_entities: Mutex<Vec<DesEntitySummary>>,
+    pub des_commands: Mutex<Vec<DesCommand>>,
+    /// Profiler summaries from ewext, oldest first.
+    pub profile_history: Mutex<VecDeque<ProfileSummary>>,
+    /// Game addresses ewext looked up, used to warn about unsupported game builds.
+    pub addr_report: Mutex<Option<AddrReport>>,
     loopback_channel: (
         crossbeam::channel::Sender<NetMsg>,
         crossbeam::channel::Receiver<NetMsg>,
@@ -165,16 +237,30 @@ impl NetManager {
             enable_recorder: AtomicBool::new(false),
             end_run: AtomicBool::new(false),
             debug_markers: Default::default(),
-            ban_list: Default::default(),
+            bans: Mutex::new(BanStore::load_default()),
             kick_list: Default::default(),
             no_more_players: AtomicBool::new(false),
             dont_kick: Default::default(),
             dirty: AtomicBool::new(false),
             actual_noita_port: AtomicU16::new(0),
-            active_mods: Default::default(),
+            local_mods: Default::default(),
+            peer_mods: Default::default(),
+            mods_shown_for: Default::default(),
             nicknames: Default::default(),
+            spectators: Default::default(),
+            headless_host: AtomicBool::new(init.headless),
+            chat: Default::default(),
+            forward_chat_to_game: AtomicBool::new(true),
+            run_stats: Default::default(),
+            last_run_report: Default::default(),
+            new_run_report: AtomicBool::new(false),
             minas: Default::default(),
             new_desc: Default::default(),
+            des_inspect_requested: AtomicBool::new(false),
+            des_entities: Default::default(),
+            des_commands: Default::default(),
+            profile_history: Default::default(),
+            addr_report: Default::default(),
             loopback_channel: crossbeam::channel::unbounded(),
         }
         .into()
@@ -186,6 +272,7 @@ impl NetManager {
             let _ = self.loopback_channel.0.send(msg.clone());
         } else {
             let encoded = lz4_flex::compress_prepend_size(&bitcode::encode(msg));
+            self.run_stats.lock().unwrap().bytes_sent(encoded.len());
             self.peer.send(peer, encoded.clone(), reliability).ok(); // TODO log
         }
     }
@@ -193,6 +280,8 @@ impl NetManager {
     pub(crate) fn broadcast(&self, msg: &NetMsg, reliability: Reliability) {
         let encoded = lz4_flex::compress_prepend_size(&bitcode::encode(msg));
         let len = encoded.len();
+        let peers = self.peer.iter_peer_ids().len().saturating_sub(1);
+        self.run_stats.lock().unwrap().bytes_sent(len * peers);
         if let Err(err) = self.peer.broadcast(encoded, reliability) {
             warn!("Error while broadcasting message of len {}: {}", len, err)
         }
@@ -234,15 +323,19 @@ impl NetManager {
         player_path: PathBuf,
         mut cli: bool,
     ) -> io::Result<()> {
-        Self::clean_dir(player_path.clone());
-        if !self.init_settings.cosmetics.0 {
-            File::create(player_path.parent().unwrap().join("tmp/no_crown"))?;
-        }
-        if !self.init_settings.cosmetics.1 {
-            File::create(player_path.parent().unwrap().join("tmp/no_amulet"))?;
-        }
-        if !self.init_settings.cosmetics.2 {
-            File::create(player_path.parent().unwrap().join("tmp/no_amulet_gem"))?;
+        let headless = self.init_settings.headless;
+        if !headless {
+            self.refresh_local_mods();
+            Self::clean_dir(player_path.clone());
+            if !self.init_settings.cosmetics.0 {
+                File::create(player_path.parent().unwrap().join("tmp/no_crown"))?;
+            }
+            if !self.init_settings.cosmetics.1 {
+                File::create(player_path.parent().unwrap().join("tmp/no_amulet"))?;
+            }
+            if !self.init_settings.cosmetics.2 {
+                File::create(player_path.parent().unwrap().join("tmp/no_amulet_gem"))?;
+            }
         }
 
         let socket = Socket::new(Domain::IPV4, Type::STREAM, None)?;
@@ -280,8 +373,19 @@ impl NetManager {
 
         let local_server: TcpListener = socket.into();
 
+        if let Some(port) = self.init_settings.admin_port.filter(|_| self.is_host()) {
+            if let Err(err) = admin_api::spawn(&self, port) {
+                error!("Could not start admin api: {err}");
+            }
+        }
+
         let is_host = self.is_host();
         info!("Is host: {is_host}");
+        if headless {
+            // There's no game to wait for, the run starts right away.
+            self.init_settings.save_state.mark_game_started();
+            self.run_stats.lock().unwrap().start();
+        }
         let mut state = NetInnerState {
             ms: None,
             world: WorldManager::new(
@@ -295,7 +399,7 @@ impl NetManager {
         };
         let mut last_iter = Instant::now();
         let path = crate::player_path(self.init_settings.modmanager_settings.mod_path());
-        let player_image = if path.exists() {
+        let player_image = if !headless && path.exists() {
             image::open(path)
                 .unwrap_or(ImageRgba8(RgbaImage::new(20, 20)))
                 .crop(1, 1, 8, 18)
@@ -303,22 +407,24 @@ impl NetManager {
         } else {
             RgbaImage::new(1, 1)
         };
-        // Create appearance files for local player.
-        create_player_png(
-            self.peer.my_id(),
-            &self.init_settings.mod_path,
-            &self.init_settings.player_path,
-            &self.init_settings.player_png_desc,
-            self.is_host(),
-        );
         self.nicknames
             .lock()
             .unwrap()
             .insert(self.peer.my_id(), self.init_settings.my_nickname.clone());
-        self.minas.lock().unwrap().insert(
-            self.peer.my_id(),
-            get_player_skin(player_image.clone(), self.init_settings.player_png_desc),
-        );
+        if !headless {
+            // Create appearance files for local player.
+            create_player_png(
+                self.peer.my_id(),
+                &self.init_settings.mod_path,
+                &self.init_settings.player_path,
+                &self.init_settings.player_png_desc,
+                self.is_host(),
+            );
+            self.minas.lock().unwrap().insert(
+                self.peer.my_id(),
+                get_player_skin(player_image.clone(), self.init_settings.player_png_desc),
+            );
+        }
         while self.continue_running.load(Ordering::Relaxed) {
             if cli {
                 if let Some(n) = self.peer.lobby_id() {
@@ -336,7 +442,7 @@ impl NetManager {
             }
             self.local_connected
                 .store(state.ms.is_some(), Ordering::Relaxed);
-            if state.ms.is_none() && self.accept_local.load(Ordering::SeqCst) {
+            if state.ms.is_none() && !headless && self.accept_local.load(Ordering::SeqCst) {
                 thread::sleep(Duration::from_millis(10));
                 if let Ok((stream, addr)) = local_server.accept() {
                     info!("New stream incoming from {}", addr);
@@ -370,11 +476,17 @@ impl NetManager {
             } else {
                 dont_kick.clear()
             }
-            {
-                let list = self.ban_list.lock().unwrap();
-                for peer in list.iter() {
-                    if self.peer.iter_peer_ids().contains(peer) {
-                        to_kick.push(*peer)
+            if is_host {
+                let mut bans = self.bans.lock().unwrap();
+                bans.reload_if_changed();
+                for peer in self.peer.iter_peer_ids() {
+                    if peer != self.peer.my_id()
+                        && self
+                            .peer
+                            .ban_key(peer)
+                            .is_some_and(|key| bans.is_banned(key))
+                    {
+                        to_kick.push(peer)
                     }
                 }
             }
@@ -438,6 +550,17 @@ impl NetManager {
                 state.had_a_disconnect = false;
             }
 
+            if self.is_host() {
+                let mut commands = self.des_commands.lock().unwrap();
+                let had_commands = !commands.is_empty();
+                for command in commands.drain(..) {
+                    state.des.handle_command(command);
+                }
+                if had_commands || self.des_inspect_requested.swap(false, Ordering::Relaxed) {
+                    *self.des_entities.lock().unwrap() = state.des.summarize();
+                }
+            }
+
             let des_pending = state.des.pending_messages();
             for (dest, msg) in des_pending {
                 self.send(dest, &NetMsg::ForwardProxyToDes(msg), Reliability::Reliable);
@@ -473,8 +596,22 @@ impl NetManager {
                         },
                         Reliability::Reliable,
                     );
+                    if self.init_settings.headless {
+                        self.send(id, &NetMsg::HeadlessHost, Reliability::Reliable);
+                    }
+                    // Exchange mod lists, so that both sides can warn about differences.
+                    if id != self.peer.my_id() && !self.init_settings.headless {
+                        let mods = self.local_mods.lock().unwrap().clone();
+                        self.send(id, &NetMsg::Mods { mods }, Reliability::Reliable);
+                        self.send(id, &NetMsg::RequestMods, Reliability::Reliable);
+                    }
+                    let ids = self.spectators.lock().unwrap().iter().copied().collect();
+                    self.send(id, &NetMsg::Spectators { ids }, Reliability::Reliable);
+                } else if id == self.peer.host_id() && self.init_settings.spectator {
+                    info!("Asking host to join as a spectator");
+                    self.send(id, &NetMsg::Spectate, Reliability::Reliable);
                 }
-                if id != self.peer.my_id() {
+                if id != self.peer.my_id() && !self.init_settings.headless {
                     // Create temporary appearance files for new player.
                     info!("Created temporary appearance for {id}");
                     create_player_png(
@@ -497,10 +634,18 @@ impl NetManager {
                     );
                 }
                 state.try_ms_write(&ws_encode_proxy("join", id.as_hex()));
+                self.send_duty_peer(state);
             }
             omni::OmniNetworkEvent::PeerDisconnected(id) => {
+                self.peer_mods.lock().unwrap().remove(&id);
+                if self.spectators.lock().unwrap().remove(&id) {
+                    self.apply_spectators(state);
+                }
                 state.try_ms_write(&ws_encode_proxy("leave", id.as_hex()));
                 state.world.handle_peer_left(id);
+                // Peer might not have had the chance to say that its game disconnected.
+                state.des.noita_disconnected(id);
+                self.send_duty_peer(state);
             }
             omni::OmniNetworkEvent::Message { src, data } => {
                 let Some(net_msg) = lz4_flex::decompress_size_prepended(&data)
@@ -523,14 +668,12 @@ impl NetManager {
     ) {
         match net_msg {
             NetMsg::RequestMods => {
-                if let Some(n) = &self.init_settings.modmanager_settings.game_save_path {
-                    let res = get_mods(n);
-                    if let Ok(mods) = res {
-                        self.send(src, &NetMsg::Mods { mods }, Reliability::Reliable)
-                    }
-                }
+                let mods = self.local_mods.lock().unwrap().clone();
+                self.send(src, &NetMsg::Mods { mods }, Reliability::Reliable)
+            }
+            NetMsg::Mods { mods } => {
+                self.peer_mods.lock().unwrap().insert(src, mods);
             }
-            NetMsg::Mods { mods } => *self.active_mods.lock().unwrap() = mods,
             NetMsg::Welcome => {}
             NetMsg::Disconnect { id } => {
                 state.try_ms_write(&ws_encode_proxy("dc", id.as_hex()));
@@ -544,6 +687,10 @@ impl NetManager {
                 state.try_ms_write(&ws_encode_proxy("end_run", self.peer.my_id().to_string()))
             }
             NetMsg::StartGame { settings } => {
+                let new_run = self.settings.lock().unwrap().world_num != settings.world_num;
+                if new_run && !self.is_host() {
+                    self.finish_run_stats(state);
+                }
                 *self.settings.lock().unwrap() = settings;
                 info!("Settings updated");
                 self.accept_local.store(true, Ordering::SeqCst);
@@ -557,8 +704,15 @@ impl NetManager {
                     state.try_ms_write(&ws_encode_mod(src, &decompressed));
                 }
             }
-            NetMsg::WorldMessage(msg) => state.world.handle_msg(src, msg),
+            NetMsg::WorldMessage(msg) => {
+                self.run_stats.lock().unwrap().world_message(src, &msg);
+                state.world.handle_msg(src, msg)
+            }
             NetMsg::PlayerColor(rgb, host, pong, name) => {
+                self.nicknames.lock().unwrap().insert(src, name);
+                if self.init_settings.headless {
+                    return;
+                }
                 info!("Player appearance created for {}", src);
                 // Create proper appearance files for new player.
                 create_player_png(
@@ -568,7 +722,6 @@ impl NetManager {
                     &rgb,
                     host,
                 );
-                self.nicknames.lock().unwrap().insert(src, name);
                 self.minas
                     .lock()
                     .unwrap()
@@ -589,7 +742,13 @@ impl NetManager {
                 }
             }
             NetMsg::Kick => std::process::exit(0),
-            NetMsg::RemoteMsg(remote_message) => self.handle_remote_msg(state, src, remote_message),
+            NetMsg::RemoteMsg(remote_message) => {
+                self.run_stats
+                    .lock()
+                    .unwrap()
+                    .remote_message(src, &remote_message);
+                self.handle_remote_msg(state, src, remote_message)
+            }
             NetMsg::ForwardDesToProxy(des_to_proxy) => {
                 state.des.handle_noita_msg(src, des_to_proxy)
             }
@@ -597,9 +756,91 @@ impl NetManager {
                 state.try_ms_write(&NoitaInbound::ProxyToDes(proxy_to_des));
             }
             NetMsg::NoitaDisconnected => state.des.noita_disconnected(src),
+            NetMsg::Chat { text, from_game } => {
+                let Some(text) = chat::sanitize(&text) else {
+                    return;
+                };
+                let mut chat = self.chat.lock().unwrap();
+                if !chat.allow(src, Instant::now()) {
+                    warn!("Dropped chat message from {src}: sending too fast");
+                    return;
+                }
+                let nickname = self
+                    .nicknames
+                    .lock()
+                    .unwrap()
+                    .get(&src)
+                    .cloned()
+                    .unwrap_or_else(|| src.to_string());
+                // Messages from the in-game chat are already shown in game by the mod.
+                if !from_game && self.forward_chat_to_game.load(Ordering::Relaxed) {
+                    state.try_ms_write(&ws_encode_proxy_bin(
+                        2,
+                        format!("{nickname}\0{text}").as_bytes(),
+                    ));
+                }
+                chat.push(ChatMessage {
+                    sender: src,
+                    nickname,
+                    text,
+                    from_game,
+                });
+            }
+            NetMsg::Spectate => {
+                if self.is_host() {
+                    self.set_spectator(src, true);
+                }
+            }
+            NetMsg::Spectators { ids } => {
+                if src != self.peer.host_id() {
+                    warn!("{src} sent a spectator list, but isn't the host");
+                    return;
+                }
+                *self.spectators.lock().unwrap() = ids.into_iter().collect();
+                self.apply_spectators(state);
+            }
+            NetMsg::HeadlessHost => {
+                if src != self.peer.host_id() {
+                    warn!("{src} claims to be a headless host, but isn't the host");
+                    return;
+                }
+                info!("Host is a dedicated server, host duties are done by a player");
+                self.headless_host.store(true, Ordering::Relaxed);
+                self.send_duty_peer(state);
+            }
         }
     }
 
+    /// Tells world and entity sync, as well as the mod, who the spectators are.
+    fn apply_spectators(&self, state: &mut NetInnerState) {
+        let spectators = self.spectators.lock().unwrap().clone();
+        state
+            .world
+            .set_spectators(spectators.iter().copied().collect());
+        state
+            .des
+            .set_spectators(spectators.iter().copied().collect());
+        state.try_ms_write(&encode_spectators(&spectators));
+        state.try_ms_write(&NoitaInbound::ProxyToDes(ProxyToDes::SetSpectator(
+            spectators.contains(&self.peer.my_id()),
+        )));
+        self.send_duty_peer(state);
+    }
+
+    /// See [`elect_duty_peer`].
+    pub(crate) fn duty_peer(&self) -> OmniPeerId {
+        elect_duty_peer(
+            self.peer.host_id(),
+            self.headless_host.load(Ordering::Relaxed),
+            &self.peer.iter_peer_ids(),
+            &self.spectators.lock().unwrap(),
+        )
+    }
+
+    fn send_duty_peer(&self, state: &mut NetInnerState) {
+        state.try_ms_write(&ws_encode_proxy("duty_id", self.duty_peer().as_hex()));
+    }
+
     fn handle_remote_msg(
         &self,
         state: &mut NetInnerState,
@@ -627,6 +868,7 @@ impl NetManager {
 
     fn on_ms_connection(self: &Arc<NetManager>, state: &mut NetInnerState) {
         self.init_settings.save_state.mark_game_started();
+        self.run_stats.lock().unwrap().start();
         info!("New stream connected");
 
         let settings = self.settings.lock().unwrap();
@@ -638,57 +880,61 @@ impl NetManager {
             "host_id",
             format!("{:016x}", self.peer.host_id().0),
         ));
+        state.try_ms_write(&encode_spectators(&self.spectators.lock().unwrap()));
+        self.send_duty_peer(state);
         info!("Chosen nickname: {}", self.init_settings.my_nickname);
-        state.try_ws_write_option("name", self.init_settings.my_nickname.as_str());
-        state.try_ws_write_option("world_num", settings.world_num as u32);
-        state.try_ws_write_option(
+        let mut options = GameOptions::default();
+        options.set("name", self.init_settings.my_nickname.as_str());
+        options.set("world_num", settings.world_num as u32);
+        options.set(
             "friendly_fire",
             settings.friendly_fire.unwrap_or(def.friendly_fire),
         );
-        state.try_ws_write_option("share_gold", settings.share_gold.unwrap_or(def.share_gold));
-        state.try_ws_write_option("debug", settings.debug_mode.unwrap_or(def.debug_mode));
-        state.try_ws_write_option("item_dedup", settings.item_dedup.unwrap_or(def.item_dedup));
-        state.try_ws_write_option(
+        options.set("share_gold", settings.share_gold.unwrap_or(def.share_gold));
+        options.set("debug", settings.debug_mode.unwrap_or(def.debug_mode));
+        options.set("item_dedup", settings.item_dedup.unwrap_or(def.item_dedup));
+        options.set(
             "randomize_perks",
             settings.randomize_perks.unwrap_or(def.randomize_perks),
         );
-        state.try_ws_write_option(
+        options.set(
             "enemy_hp_scale",
             settings.enemy_hp_mult.unwrap_or(def.enemy_hp_mult),
         );
         let mode = settings.game_mode.unwrap_or(def.game_mode);
-        state.try_ws_write_option("game_mode", mode);
-        if let GameMode::LocalHealth(mode) = mode {
-            match mode {
-                LocalHealthMode::Normal => {}
-                LocalHealthMode::PermaDeath => state.try_ws_write_option("perma_death", true),
-                LocalHealthMode::Alternate => state.try_ws_write_option("no_notplayer", true),
-            }
-        }
-        state.try_ws_write_option(
+        options.set("game_mode", mode);
+        options.set(
+            "perma_death",
+            mode == GameMode::LocalHealth(LocalHealthMode::PermaDeath),
+        );
+        options.set(
+            "no_notplayer",
+            mode == GameMode::LocalHealth(LocalHealthMode::Alternate),
+        );
+        options.set(
             "health_per_player",
             settings.health_per_player.unwrap_or(def.health_per_player),
         );
-        state.try_ws_write_option(
+        options.set(
             "global_hp_loss",
             settings.global_hp_loss.unwrap_or(def.global_hp_loss),
         );
-        state.try_ws_write_option(
+        options.set(
             "physics_damage",
             settings.physics_damage.unwrap_or(def.physics_damage),
         );
         let lst = settings.clone();
-        state.try_ws_write_option(
+        options.set(
             "perk_ban_list",
             lst.perk_ban_list.unwrap_or(def.perk_ban_list).as_str(),
         );
-        state.try_ws_write_option(
+        options.set(
             "no_material_damage",
             settings
                 .no_material_damage
                 .unwrap_or(def.no_material_damage),
         );
-        state.try_ws_write_option(
+        options.set(
             "health_lost_on_revive",
             settings
                 .health_lost_on_revive
@@ -702,7 +948,7 @@ impl NetManager {
             .unwrap_or(self.init_settings.player_png_desc)
             .colors
             .player_main;
-        state.try_ws_write_option(
+        options.set(
             "mina_color",
             rgb[0] as u32 + ((rgb[1] as u32) << 8) + ((rgb[2] as u32) << 16),
         );
@@ -714,13 +960,36 @@ impl NetManager {
             .unwrap_or(self.init_settings.player_png_desc)
             .colors
             .player_alt;
-        state.try_ws_write_option(
+        options.set(
             "mina_color_alt",
             rgb[0] as u32 + ((rgb[1] as u32) << 8) + ((rgb[2] as u32) << 16),
         );
 
+        for module in settings
+            .disabled_ewext_modules
+            .as_deref()
+            .unwrap_or(&def.disabled_ewext_modules)
+            .split(',')
+            .map(str::trim)
+            .filter(|module| !module.is_empty())
+        {
+            options.set(format!("ewext_module_{module}"), false);
+        }
+
         let progress = settings.progress.join(",");
-        state.try_ws_write_option("progress", progress.as_str());
+        options.set("progress", progress.as_str());
+
+        state.try_ms_write(&NoitaInbound::Options(options));
+
+        state.try_ms_write(&NoitaInbound::ProxyToDes(ProxyToDes::SetTrackingRules(
+            settings
+                .des_tracking_rules
+                .clone()
+                .unwrap_or(def.des_tracking_rules),
+        )));
+        state.try_ms_write(&NoitaInbound::ProxyToDes(ProxyToDes::SetSpectator(
+            self.spectators.lock().unwrap().contains(&self.peer.my_id()),
+        )));
 
         state.try_ms_write(&NoitaInbound::Ready {
             my_peer_id: self.peer.my_id().into(),
@@ -762,6 +1031,19 @@ impl NetManager {
                     }
                 }
             }
+            NoitaOutbound::ProfileSummary(summary) => {
+                let mut history = self.profile_history.lock().unwrap();
+                if history.len() == PROFILE_HISTORY_LEN {
+                    history.pop_front();
+                }
+                history.push_back(summary);
+            }
+            NoitaOutbound::AddrReport(report) => {
+                for symbol in report.missing() {
+                    warn!("ewext couldn't find {}: {}", symbol.name, symbol.note);
+                }
+                *self.addr_report.lock().unwrap() = Some(report);
+            }
             NoitaOutbound::DesToProxy(des_to_proxy) => {
                 if self.is_host() {
                     state.des.handle_noita_msg(self.peer.my_id(), des_to_proxy)
@@ -780,6 +1062,10 @@ impl NetManager {
             } => {
                 let destination = destination.convert::<OmniPeerId>();
                 let reliability = Reliability::from_reliability_bool(reliable);
+                self.run_stats
+                    .lock()
+                    .unwrap()
+                    .remote_message(self.peer.my_id(), &message);
                 match destination {
                     Destination::Peer(peer) => {
                         self.send(peer, &NetMsg::RemoteMsg(message), reliability)
@@ -809,6 +1095,78 @@ impl NetManager {
         })
     }
 
+    /// Bans a connected peer, who then gets kicked. Returns false if the peer can't be identified.
+    pub(crate) fn ban(&self, peer: OmniPeerId, reason: String, duration_secs: Option<u64>) -> bool {
+        let Some(key) = self.peer.ban_key(peer) else {
+            warn!("Can't ban {peer}: not connected directly");
+            return false;
+        };
+        let nickname = self.nicknames.lock().unwrap().get(&peer).cloned();
+        let mut ban = Ban::new(key, nickname, duration_secs);
+        ban.reason = reason;
+        self.bans.lock().unwrap().ban(ban);
+        true
+    }
+
+    /// Reads enabled mods from the game's save and hashes their files on another thread, as that takes a while for big mods.
+    /// Peers that connected in the meantime got an empty list, so they are sent the mods again.
+    fn refresh_local_mods(self: &Arc<Self>) {
+        let netman = self.clone();
+        thread::spawn(move || {
+            match mod_compat::local_mods(&netman.init_settings.modmanager_settings) {
+                Ok(mods) => {
+                    *netman.local_mods.lock().unwrap() = mods.clone();
+                    netman.broadcast(&NetMsg::Mods { mods }, Reliability::Reliable);
+                }
+                Err(err) => warn!("Could not read enabled mods: {err}"),
+            }
+        });
+    }
+
+    /// Sends a chat message to everyone in the lobby, including ourselves.
+    pub(crate) fn send_chat(&self, text: &str, from_game: bool) {
+        let Some(text) = chat::sanitize(text) else {
+            return;
+        };
+        let msg = NetMsg::Chat { text, from_game };
+        self.broadcast(&msg, Reliability::Reliable);
+        self.send(self.peer.my_id(), &msg, Reliability::Reliable);
+    }
+
+    /// Makes `peer` a spectator, or promotes it to a player. Only the host decides who spectates.
+    pub fn set_spectator(&self, peer: OmniPeerId, spectator: bool) {
+        if !self.is_host() || peer == self.peer.host_id() {
+            return;
+        }
+        let ids = {
+            let mut spectators = self.spectators.lock().unwrap();
+            if spectator {
+                spectators.insert(peer);
+            } else {
+                spectators.remove(&peer);
+            }
+            spectators.iter().copied().collect()
+        };
+        info!(
+            "{peer} is now a {}",
+            if spectator { "spectator" } else { "player" }
+        );
+        let msg = NetMsg::Spectators { ids };
+        self.broadcast(&msg, Reliability::Reliable);
+        self.send(self.peer.my_id(), &msg, Reliability::Reliable);
+    }
+
+    /// Sets settings to be used from the next run, and whether the run has to be ended for them to apply.
+    pub(crate) fn set_pending_settings(&self, new_settings: GameSettings) {
+        *self.pending_settings.lock().unwrap() = new_settings.clone();
+        let mut old_settings = self.settings.lock().unwrap().clone();
+        old_settings.progress.clear();
+        old_settings.seed = new_settings.seed;
+        old_settings.world_num = new_settings.world_num;
+        self.dirty
+            .store(old_settings != new_settings, Ordering::Relaxed)
+    }
+
     fn resend_game_settings(&self) {
         let settings = self.settings.lock().unwrap().clone();
         self.broadcast(&NetMsg::StartGame { settings }, Reliability::Reliable);
@@ -824,6 +1182,7 @@ impl NetManager {
         let key = msg.next();
         match key {
             Some("game_over") => {
+                self.run_stats.lock().unwrap().game_over();
                 if self.is_host() {
                     info!("Game over, resending game settings");
                     self.end_run(state)
@@ -833,8 +1192,13 @@ impl NetManager {
                 let peer_id = msg.next().and_then(OmniPeerId::from_hex);
                 let x: Option<f64> = msg.next().and_then(|s| s.parse().ok());
                 let y: Option<f64> = msg.next().and_then(|s| s.parse().ok());
+                let is_notplayer = msg.next() == Some("1");
                 if let (Some(peer_id), Some(x), Some(y)) = (peer_id, x, y) {
                     self.world_info.update_player_pos(peer_id, x, y);
+                    self.run_stats
+                        .lock()
+                        .unwrap()
+                        .peer_pos(peer_id, x, y, is_notplayer);
                 }
             }
             Some("reset_world") => state.world.reset(),
@@ -979,15 +1343,44 @@ impl NetManager {
                     .split(|b| *b == b':')
                     .map(|s| String::from_utf8_lossy(s).parse::<i32>().unwrap_or(0))
                     .collect::<Vec<i32>>();
-                state.world.add_end(data[0], &pos);
+                let updated = state.world.add_end(data[0], &pos);
+                self.run_stats
+                    .lock()
+                    .unwrap()
+                    .chunks_updated(self.peer.my_id(), updated);
             }
+            // chat message typed in game
+            2 => self.send_chat(&String::from_utf8_lossy(data), true),
             key => {
                 error!("Unknown bin msg from mod: {:?}", key)
             }
         }
     }
 
+    /// Writes the report of the run that just ended, which is then shown in the lobby.
+    fn finish_run_stats(&self, state: &mut NetInnerState) {
+        let (seed, world_num) = {
+            let settings = self.settings.lock().unwrap();
+            (settings.seed, settings.world_num)
+        };
+        let nicknames = self.nicknames.lock().unwrap().clone();
+        let report = self
+            .run_stats
+            .lock()
+            .unwrap()
+            .finish(seed, world_num, &nicknames);
+        if let Some(report) = report {
+            report.save();
+            *self.last_run_report.lock().unwrap() = Some(report);
+            self.new_run_report.store(true, Ordering::Relaxed);
+        }
+        if self.init_settings.headless || state.ms.is_some() {
+            self.run_stats.lock().unwrap().start();
+        }
+    }
+
     fn end_run(&self, state: &mut NetInnerState) {
+        self.finish_run_stats(state);
         self.init_settings.save_state.reset();
         {
             let mut settings = self.pending_settings.lock().unwrap();
@@ -1050,6 +1443,7 @@ impl ExplosionData {
             mat: Pixel {
                 flags: PixelFlags::Normal,
                 material: mat,
+                burning: false,
             },
             prob,
         }
@@ -1106,3 +1500,70 @@ impl Drop for NetManager {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn headless_host_hands_duties_to_lowest_player() {
+        let (host, a, b, c) = (OmniPeerId(1), OmniPeerId(5), OmniPeerId(3), OmniPeerId(4));
+        let spectators = HashSet::from([b]);
+        let peers = [host, a, b, c];
+        assert_eq!(elect_duty_peer(host, false, &peers, &spectators), host);
+        // Spectators don't play, so they can't do host duties either.
+        assert_eq!(elect_duty_peer(host, true, &peers, &spectators), c);
+        // Elected player left.
+        assert_eq!(elect_duty_peer(host, true, &[host, a], &spectators), a);
+        assert_eq!(elect_duty_peer(host, true, &[host], &spectators), host);
+    }
+
+    #[tokio::test]
+    async fn end_run_makes_report() {
+        let save_path = env::temp_dir().join(format!("ew_end_run_{}", std::process::id()));
+        let save_state = SaveState::new(save_path.clone());
+        let peer = tangled::Peer::host("127.0.0.1:0".parse().unwrap(), None).unwrap();
+        let netman = NetManager::new(
+            omni::PeerVariant::Tangled(peer),
+            NetManagerInit {
+                my_nickname: "host".to_owned(),
+                save_state: save_state.clone(),
+                cosmetics: (false, false, false),
+                mod_path: PathBuf::new(),
+                player_path: PathBuf::new(),
+                modmanager_settings: ModmanagerSettings::default(),
+                player_png_desc: PlayerPngDesc::default(),
+                noita_port: 0,
+                headless: true,
+                admin_port: None,
+                spectator: false,
+            },
+        );
+        netman.settings.lock().unwrap().world_num = 3;
+        netman.pending_settings.lock().unwrap().world_num = 3;
+        let mut state = NetInnerState {
+            ms: None,
+            world: WorldManager::new(true, netman.peer.my_id(), save_state.clone()),
+            explosion_data: Vec::new(),
+            des: DesManager::new(true, save_state),
+            had_a_disconnect: false,
+        };
+
+        netman.run_stats.lock().unwrap().start();
+        netman
+            .run_stats
+            .lock()
+            .unwrap()
+            .peer_pos(netman.peer.my_id(), 0.0, 0.0, false);
+        netman.end_run(&mut state);
+
+        assert!(netman.new_run_report.load(Ordering::Relaxed));
+        let report = netman.last_run_report.lock().unwrap().clone().unwrap();
+        assert_eq!(report.world_num, 3);
+        assert_eq!(report.players.len(), 1);
+        assert_eq!(netman.settings.lock().unwrap().world_num, 4);
+        // Saves run info on drop, so it has to go before the save directory.
+        drop(netman);
+        let _ = remove_dir_all(save_path);
+    }
+}
@@ -0,0 +1,431 @@
+//! Local HTTP/JSON API to control a hosted lobby, so that moderation can be scripted and overlays or bots can be built.
+//!
+//! Only listens on localhost. Every request needs an `Authorization: Bearer <token>` header, with the token from
+//! [`TOKEN_FILE`] next to the proxy executable. Custom headers also keep web pages from sending requests to it.
+//!
+//! - `GET /players`: players, bans and whether the lobby is locked.
+//! - `GET /connections`: connection status of every peer; only steam lobbies have ping and quality.
+//! - `GET /settings`: current settings and pending ones, that will be used from the next run.
+//! - `POST /settings`: replaces pending settings with the ones in the body.
+//! - `POST /kick/<id>`
+//! - `POST /ban/<id>`: bans a connected peer, optionally with `{"reason": "...", "duration_secs": 3600}` in the body.
+//! - `POST /unban/<key>`: removes a ban, by the steam id or ip address from its `key`.
+//! - `POST /lock`, `POST /unlock`: same as "no more players" in the gui.
+//! - `POST /end_run`
+//!
+//! Peer ids are decimal strings, as they don't fit into a javascript number.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    omni::{OmniPeerId, PeerVariant},
+    steam_networking::PerPeerStatus,
+    NetManager,
+};
+use crate::GameSettings;
+
+pub(crate) const DEFAULT_PORT: u16 = 21252;
+const TOKEN_FILE: &str = "admin_token.txt";
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct PlayerInfo {
+    id: String,
+    nickname: Option<String>,
+    is_host: bool,
+    is_me: bool,
+}
+
+#[derive(Serialize)]
+struct Players {
+    players: Vec<PlayerInfo>,
+    bans: Vec<BanInfo>,
+    locked: bool,
+}
+
+#[derive(Serialize)]
+struct BanInfo {
+    key: String,
+    nickname: Option<String>,
+    reason: String,
+    banned_at: u64,
+    expires_at: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct BanRequest {
+    reason: String,
+    duration_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ConnectionInfo {
+    id: String,
+    status: &'static str,
+    ping_ms: Option<i32>,
+    quality_local: Option<f32>,
+    quality_remote: Option<f32>,
+    in_bytes_per_sec: Option<f32>,
+    out_bytes_per_sec: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct Settings {
+    settings: GameSettings,
+    pending: GameSettings,
+    /// Pending settings differ from current ones, so the run has to be ended for them to apply.
+    dirty: bool,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: &impl Serialize) -> Self {
+        match serde_json::to_string(body) {
+            Ok(body) => Self {
+                status: "200 OK",
+                body,
+            },
+            Err(err) => Self::error("500 Internal Server Error", err),
+        }
+    }
+
+    fn no_content() -> Self {
+        Self {
+            status: "204 No Content",
+            body: String::new(),
+        }
+    }
+
+    fn error(status: &'static str, message: impl ToString) -> Self {
+        #[derive(Serialize)]
+        struct Error {
+            error: String,
+        }
+        Self {
+            status,
+            body: serde_json::to_string(&Error {
+                error: message.to_string(),
+            })
+            .unwrap_or_default(),
+        }
+    }
+}
+
+fn token_path() -> PathBuf {
+    if let Ok(path) = std::env::current_exe() {
+        path.parent().unwrap().join(TOKEN_FILE)
+    } else {
+        TOKEN_FILE.into()
+    }
+}
+
+/// Starts serving the api on localhost in a separate thread, which stops once netmanager does.
+pub(crate) fn spawn(netman: &Arc<NetManager>, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(SocketAddr::new("127.0.0.1".parse().unwrap(), port))?;
+    listener.set_nonblocking(true)?;
+    let token = format!("{:032x}", rand::random::<u128>());
+    let path = token_path();
+    fs::write(&path, &token)?;
+    info!(
+        "Admin api listening on {}, token is in {}",
+        listener.local_addr()?,
+        path.display()
+    );
+
+    let netman = Arc::downgrade(netman);
+    thread::spawn(move || serve(listener, netman, token));
+    Ok(())
+}
+
+fn serve(listener: TcpListener, netman: Weak<NetManager>, token: String) {
+    loop {
+        let Some(netman) = netman.upgrade() else {
+            return;
+        };
+        if !netman.continue_running.load(Ordering::Relaxed) {
+            return;
+        }
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = handle_connection(stream, &netman, &token) {
+                    warn!("Admin api request failed: {err}");
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                drop(netman);
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(err) => warn!("Admin api could not accept connection: {err}"),
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, netman: &NetManager, token: &str) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let response = match read_request(&mut reader)? {
+        Some(request) if request.token.as_deref() == Some(token) => handle(netman, request),
+        Some(_) => Response::error("401 Unauthorized", "Invalid or missing token"),
+        None => Response::error("400 Bad Request", "Malformed request"),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+/// Reads a request, returning None if it isn't valid http.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_ascii_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut token = None;
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Ok(None);
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            token = value.strip_prefix("Bearer ").map(str::to_owned);
+        } else if name.eq_ignore_ascii_case("content-length") {
+            let Ok(length) = value.parse() else {
+                return Ok(None);
+            };
+            content_length = length;
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Ok(None);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        token,
+        body,
+    }))
+}
+
+fn handle(netman: &NetManager, request: Request) -> Response {
+    let path: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), path.as_slice()) {
+        ("GET", ["players"]) => Response::ok(&players(netman)),
+        ("GET", ["connections"]) => Response::ok(&connections(netman)),
+        ("GET", ["settings"]) => Response::ok(&Settings {
+            settings: netman.settings.lock().unwrap().clone(),
+            pending: netman.pending_settings.lock().unwrap().clone(),
+            dirty: netman.dirty.load(Ordering::Relaxed),
+        }),
+        ("POST", ["settings"]) => match serde_json::from_slice(&request.body) {
+            Ok(settings) => {
+                netman.set_pending_settings(settings);
+                Response::no_content()
+            }
+            Err(err) => Response::error("400 Bad Request", err),
+        },
+        ("POST", ["kick", id]) => {
+            let Some(id) = parse_peer(netman, id) else {
+                return Response::error("404 Not Found", "No such peer");
+            };
+            info!("Admin api: kick {id}");
+            netman.kick_list.lock().unwrap().push(id);
+            Response::no_content()
+        }
+        ("POST", ["ban", id]) => {
+            let Some(id) = parse_peer(netman, id) else {
+                return Response::error("404 Not Found", "No such peer");
+            };
+            let ban = if request.body.is_empty() {
+                BanRequest::default()
+            } else {
+                match serde_json::from_slice(&request.body) {
+                    Ok(ban) => ban,
+                    Err(err) => return Response::error("400 Bad Request", err),
+                }
+            };
+            info!("Admin api: ban {id}");
+            if netman.ban(id, ban.reason, ban.duration_secs) {
+                Response::no_content()
+            } else {
+                Response::error("409 Conflict", "Peer can't be identified to be banned")
+            }
+        }
+        ("POST", ["unban", key]) => {
+            let key = match key.parse() {
+                Ok(key) => key,
+                Err(err) => return Response::error("400 Bad Request", err),
+            };
+            if netman.bans.lock().unwrap().unban(key) {
+                Response::no_content()
+            } else {
+                Response::error("404 Not Found", "No such ban")
+            }
+        }
+        ("POST", [lock @ ("lock" | "unlock")]) => {
+            netman
+                .no_more_players
+                .store(*lock == "lock", Ordering::Relaxed);
+            Response::no_content()
+        }
+        ("POST", ["end_run"]) => {
+            info!("Admin api: end run");
+            netman.end_run.store(true, Ordering::Relaxed);
+            Response::no_content()
+        }
+        _ => Response::error("404 Not Found", "Unknown endpoint"),
+    }
+}
+
+/// Parses id of a connected peer.
+fn parse_peer(netman: &NetManager, id: &str) -> Option<OmniPeerId> {
+    let id = OmniPeerId(id.parse().ok()?);
+    netman.peer.iter_peer_ids().contains(&id).then_some(id)
+}
+
+fn players(netman: &NetManager) -> Players {
+    let nicknames = netman.nicknames.lock().unwrap();
+    let my_id = netman.peer.my_id();
+    let host_id = netman.peer.host_id();
+    Players {
+        players: netman
+            .peer
+            .iter_peer_ids()
+            .into_iter()
+            .map(|id| PlayerInfo {
+                id: id.to_string(),
+                nickname: nicknames.get(&id).cloned(),
+                is_host: id == host_id,
+                is_me: id == my_id,
+            })
+            .collect(),
+        bans: netman
+            .bans
+            .lock()
+            .unwrap()
+            .active()
+            .map(|ban| BanInfo {
+                key: ban.key.to_string(),
+                nickname: ban.nickname.clone(),
+                reason: ban.reason.clone(),
+                banned_at: ban.banned_at,
+                expires_at: ban.expires_at,
+            })
+            .collect(),
+        locked: netman.no_more_players.load(Ordering::Relaxed),
+    }
+}
+
+fn connections(netman: &NetManager) -> Vec<ConnectionInfo> {
+    let PeerVariant::Steam(peer) = &netman.peer else {
+        let my_id = netman.peer.my_id();
+        return netman
+            .peer
+            .iter_peer_ids()
+            .into_iter()
+            .filter(|id| *id != my_id)
+            .map(|id| ConnectionInfo {
+                id: id.to_string(),
+                status: "connected",
+                ping_ms: None,
+                quality_local: None,
+                quality_remote: None,
+                in_bytes_per_sec: None,
+                out_bytes_per_sec: None,
+            })
+            .collect();
+    };
+    peer.generate_report()
+        .per_peer_statuses
+        .into_iter()
+        .map(|entry| {
+            let mut info = ConnectionInfo {
+                id: entry.peer.to_string(),
+                status: "connected",
+                ping_ms: None,
+                quality_local: None,
+                quality_remote: None,
+                in_bytes_per_sec: None,
+                out_bytes_per_sec: None,
+            };
+            match entry.status {
+                PerPeerStatus::Connected { realtimeinfo } => {
+                    info.ping_ms = Some(realtimeinfo.ping());
+                    info.quality_local = Some(realtimeinfo.connection_quality_local());
+                    info.quality_remote = Some(realtimeinfo.connection_quality_remote());
+                    info.in_bytes_per_sec = Some(realtimeinfo.in_bytes_per_sec());
+                    info.out_bytes_per_sec = Some(realtimeinfo.out_bytes_per_sec());
+                }
+                PerPeerStatus::AwaitingIncoming => info.status = "awaiting_incoming",
+                PerPeerStatus::ConnectionPending => info.status = "pending",
+                PerPeerStatus::NoFurtherInfo => info.status = "no_info",
+            }
+            info
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_requests() -> io::Result<()> {
+        let raw =
+            b"POST /kick/42 HTTP/1.1\r\nAuthorization: Bearer abc\r\nContent-Length: 2\r\n\r\n{}";
+        let request = read_request(&mut &raw[..])?.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/kick/42");
+        assert_eq!(request.token.as_deref(), Some("abc"));
+        assert_eq!(request.body, b"{}");
+
+        assert!(read_request(&mut &b"garbage\r\n\r\n"[..])?.is_none());
+        assert!(
+            read_request(&mut &b"GET /players HTTP/1.1\r\nbroken header\r\n\r\n"[..])?.is_none()
+        );
+        Ok(())
+    }
+}
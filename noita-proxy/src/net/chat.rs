@@ -0,0 +1,89 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use super::omni::OmniPeerId;
+
+const HISTORY_LEN: usize = 200;
+pub(crate) const MAX_MESSAGE_LEN: usize = 500;
+const RATE_LIMIT_COUNT: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub(crate) struct ChatMessage {
+    pub(crate) sender: OmniPeerId,
+    pub(crate) nickname: String,
+    pub(crate) text: String,
+    /// Sent from the in-game chat, which already shows it to players in game.
+    pub(crate) from_game: bool,
+}
+
+/// Lobby chat history, shared between the lobby screen and the game.
+#[derive(Default)]
+pub(crate) struct Chat {
+    history: VecDeque<ChatMessage>,
+    recent: HashMap<OmniPeerId, VecDeque<Instant>>,
+}
+
+impl Chat {
+    /// Returns false if `peer` sent too many messages recently.
+    pub(crate) fn allow(&mut self, peer: OmniPeerId, now: Instant) -> bool {
+        let recent = self.recent.entry(peer).or_default();
+        while recent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= RATE_LIMIT_WINDOW)
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= RATE_LIMIT_COUNT {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+
+    pub(crate) fn push(&mut self, message: ChatMessage) {
+        if self.history.len() >= HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(message);
+    }
+
+    /// Oldest first.
+    pub(crate) fn history(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.history.iter()
+    }
+}
+
+/// Trims whitespace and cuts overly long messages, returns None if nothing is left.
+pub(crate) fn sanitize(text: &str) -> Option<String> {
+    let text: String = text
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_MESSAGE_LEN)
+        .collect();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_limits_per_peer() {
+        let mut chat = Chat::default();
+        let start = Instant::now();
+        let (a, b) = (OmniPeerId(1), OmniPeerId(2));
+        for _ in 0..RATE_LIMIT_COUNT {
+            assert!(chat.allow(a, start));
+        }
+        assert!(!chat.allow(a, start));
+        assert!(chat.allow(b, start));
+        assert!(chat.allow(a, start + RATE_LIMIT_WINDOW));
+
+        assert_eq!(sanitize("  hi\0 "), Some("hi".to_owned()));
+        assert_eq!(sanitize(" \n "), None);
+    }
+}
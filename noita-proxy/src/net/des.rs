@@ -1,9 +1,15 @@
-use std::mem;
+use std::{mem, path::PathBuf};
 
 use bitcode::{Decode, Encode};
 use rstar::{primitives::GeomWithData, RTree};
-use rustc_hash::FxHashMap;
-use shared::des::{DesToProxy, FullEntityData, Gid, ProxyToDes, UpdatePosition};
+use rustc_hash::{FxHashMap, FxHashSet};
+use shared::{
+    des::{
+        serialized::SerializedEntity, tracking_rules::glob_match, DesToProxy, EntitySpawnInfo,
+        FullEntityData, Gid, ProxyToDes, UpdatePosition,
+    },
+    WorldPos,
+};
 use tracing::{info, warn};
 
 use crate::bookkeeping::save_state::{SaveState, SaveStateEntry};
@@ -19,11 +25,101 @@ impl SaveStateEntry for EntityStorage {
     const FILENAME: &'static str = "des_entity_storage";
 }
 
+/// Summary of a stored entity, used by the inspection panel and cli.
+#[derive(Clone)]
+pub struct DesEntitySummary {
+    pub gid: Gid,
+    pub pos: WorldPos,
+    /// None for entities that are stored serialized.
+    pub filename: Option<String>,
+    /// Size of the stored spawn info, in bytes.
+    pub size: usize,
+    pub authority: Option<OmniPeerId>,
+}
+
+impl DesEntitySummary {
+    fn new(entity: &FullEntityData, authority: Option<OmniPeerId>) -> Self {
+        let filename = match &entity.data {
+            EntitySpawnInfo::Filename(filename) => Some(filename.clone()),
+            EntitySpawnInfo::Serialized { .. } => None,
+        };
+        Self {
+            gid: entity.gid,
+            pos: entity.pos,
+            filename,
+            size: bitcode::encode(&entity.data).len(),
+            authority,
+        }
+    }
+
+    pub fn filename_or_serialized(&self) -> &str {
+        self.filename.as_deref().unwrap_or("<serialized>")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum DesEntitySort {
+    #[default]
+    Filename,
+    Size,
+}
+
+/// Which entities to show when inspecting entity storage.
+#[derive(Clone, Default)]
+pub struct DesEntityFilter {
+    /// Glob to match filenames against, serialized entities are matched as `<serialized>`. Empty matches everything.
+    pub filename: String,
+    /// Inclusive `(min_x, min_y, max_x, max_y)` rectangle.
+    pub region: Option<(i32, i32, i32, i32)>,
+    pub sort: DesEntitySort,
+}
+
+impl DesEntityFilter {
+    fn matches(&self, entity: &DesEntitySummary) -> bool {
+        if !self.filename.is_empty() && !glob_match(&self.filename, entity.filename_or_serialized())
+        {
+            return false;
+        }
+        match self.region {
+            Some((min_x, min_y, max_x, max_y)) => {
+                (min_x..=max_x).contains(&entity.pos.x) && (min_y..=max_y).contains(&entity.pos.y)
+            }
+            None => true,
+        }
+    }
+
+    pub fn apply(&self, entities: &mut Vec<DesEntitySummary>) {
+        entities.retain(|entity| self.matches(entity));
+        match self.sort {
+            DesEntitySort::Filename => entities.sort_by(|a, b| {
+                a.filename_or_serialized()
+                    .cmp(b.filename_or_serialized())
+                    .then(a.gid.0.cmp(&b.gid.0))
+            }),
+            DesEntitySort::Size => entities.sort_by_key(|entity| std::cmp::Reverse(entity.size)),
+        }
+    }
+}
+
+/// Actions that can be taken from the inspection panel.
+#[derive(Clone, Copy)]
+pub enum DesCommand {
+    /// Removes entity from storage. Its authority removes it too, other loaded copies aren't affected.
+    Delete(Gid),
+    /// Takes authority away from whoever has it, so that the next peer that requests it gets it.
+    /// The previous authority removes its copy, meant for entities that got stuck.
+    ForceRelease(Gid),
+}
+
 pub(crate) struct DesManager {
     is_host: bool,
     entity_storage: EntityStorage,
     rtree: RTree<GeomWithData<[i64; 2], Gid>>,
     authority: FxHashMap<Gid, OmniPeerId>,
+    /// Entities handed off to a peer that didn't adopt them yet, by the peer they're handed off to.
+    pending_transfers: FxHashMap<Gid, OmniPeerId>,
+    /// Peers that never get authority over entities.
+    spectators: FxHashSet<OmniPeerId>,
     pending_messages: Vec<(OmniPeerId, ProxyToDes)>,
     save_state: SaveState,
 }
@@ -46,6 +142,8 @@ impl DesManager {
             entity_storage,
             rtree,
             authority: Default::default(),
+            pending_transfers: Default::default(),
+            spectators: Default::default(),
             pending_messages: Vec::new(),
             save_state,
             is_host,
@@ -68,19 +166,35 @@ impl DesManager {
     }
 
     pub(crate) fn handle_noita_msg(&mut self, source: OmniPeerId, msg: DesToProxy) {
-        // TODO maybe check that authorities are correct?
+        // Spectators never have authority, anything they send is left over from before they became one.
+        if self.spectators.contains(&source) {
+            return;
+        }
+        // Messages about entities are only accepted from their authority.
+        // Late ones from a previous authority would otherwise undo what happened since, e.g. pull re-added entities out of the tree.
+        let is_authority = |this: &Self, gid: Gid| this.authority.get(&gid) == Some(&source);
         match msg {
             DesToProxy::InitOrUpdateEntity(full_entity_data) => {
+                // Also means that the entity was adopted, if it was handed off.
+                self.pending_transfers.remove(&full_entity_data.gid);
                 self.authority.insert(full_entity_data.gid, source);
                 self.entity_storage
                     .entities
                     .insert(full_entity_data.gid, full_entity_data);
             }
             DesToProxy::DeleteEntity(gid) => {
+                if !is_authority(self, gid) {
+                    return;
+                }
+                self.pending_transfers.remove(&gid);
                 self.authority.remove(&gid);
                 self.entity_storage.entities.remove(&gid);
             }
             DesToProxy::ReleaseAuthority(gid) => {
+                if !is_authority(self, gid) {
+                    return;
+                }
+                self.pending_transfers.remove(&gid);
                 self.authority.remove(&gid);
                 self.add_gid_to_tree(gid);
             }
@@ -103,8 +217,21 @@ impl DesManager {
                     }
                 }
             }
+            DesToProxy::TransferAuthority { gid, to } => {
+                if self.spectators.contains(&OmniPeerId::from(to)) {
+                    warn!("{source} tried to transfer authority over {gid:?} to a spectator");
+                } else if self.authority.get(&gid) == Some(&source) {
+                    self.authority.insert(gid, to.into());
+                    self.pending_transfers.insert(gid, to.into());
+                } else {
+                    warn!("{source} tried to transfer authority over {gid:?} it didn't have");
+                }
+            }
             DesToProxy::UpdatePositions(updates) => {
                 for UpdatePosition { gid, pos } in updates {
+                    if !is_authority(self, gid) {
+                        continue;
+                    }
                     self.remove_gid_from_tree(gid);
                     if let Some(entity) = self.entity_storage.entities.get_mut(&gid) {
                         entity.pos = pos;
@@ -114,13 +241,37 @@ impl DesManager {
         }
     }
 
+    /// Frees entities of a peer whose game disconnected, or that left the lobby.
     pub(crate) fn noita_disconnected(&mut self, source: OmniPeerId) {
         // TODO also remove entities from affected clients faster.
         info!("Peer {source} disconnected, freeing entities that were under authority");
+        self.free_entities_of(source);
+    }
+
+    /// Frees entities of peers that became spectators.
+    pub(crate) fn set_spectators(&mut self, spectators: FxHashSet<OmniPeerId>) {
+        let new_spectators: Vec<_> = spectators.difference(&self.spectators).copied().collect();
+        for peer in new_spectators {
+            info!("{peer} is now a spectator, freeing entities that were under authority");
+            self.free_entities_of(peer);
+        }
+        self.spectators = spectators;
+    }
+
+    fn free_entities_of(&mut self, source: OmniPeerId) {
+        // Entities handed off to this peer are freed too, as they won't be adopted.
+        self.pending_transfers.retain(|gid, to| {
+            if *to == source {
+                info!("{source} didn't adopt {gid:?} that was handed off to it");
+            }
+            *to != source
+        });
         let mut free_again = Vec::new();
         self.authority.retain(|gid, authority| {
             let remove = source == *authority;
-            free_again.push(*gid);
+            if remove {
+                free_again.push(*gid);
+            }
             !remove
         });
         for gid in free_again {
@@ -128,6 +279,47 @@ impl DesManager {
         }
     }
 
+    pub(crate) fn summarize(&self) -> Vec<DesEntitySummary> {
+        self.entity_storage
+            .entities
+            .values()
+            .map(|entity| DesEntitySummary::new(entity, self.authority.get(&entity.gid).copied()))
+            .collect()
+    }
+
+    pub(crate) fn handle_command(&mut self, command: DesCommand) {
+        match command {
+            DesCommand::Delete(gid) => {
+                info!("Deleting entity {gid:?} from storage");
+                self.remove_gid_from_tree(gid);
+                self.take_authority(gid);
+                self.entity_storage.entities.remove(&gid);
+            }
+            DesCommand::ForceRelease(gid) => {
+                // Entities without authority are in the tree already.
+                let Some(peer) = self.take_authority(gid) else {
+                    return;
+                };
+                info!("Forcefully releasing entity {gid:?} from {peer}");
+                if self.entity_storage.entities.contains_key(&gid) {
+                    self.add_gid_to_tree(gid);
+                } else {
+                    warn!("Released entity {gid:?} has no stored position");
+                }
+            }
+        }
+    }
+
+    /// Removes authority over entity, telling the peer that had it to stop syncing it.
+    /// Otherwise it would keep the entity, and it would be duplicated once someone else takes it.
+    fn take_authority(&mut self, gid: Gid) -> Option<OmniPeerId> {
+        self.pending_transfers.remove(&gid);
+        let peer = self.authority.remove(&gid)?;
+        self.pending_messages
+            .push((peer, ProxyToDes::LostAuthority(gid)));
+        Some(peer)
+    }
+
     pub(crate) fn pending_messages(&mut self) -> Vec<(OmniPeerId, ProxyToDes)> {
         mem::take(&mut self.pending_messages)
     }
@@ -136,6 +328,7 @@ impl DesManager {
         self.entity_storage = Default::default();
         self.rtree = RTree::default();
         self.authority.clear();
+        self.pending_transfers.clear();
         self.pending_messages.clear();
     }
 }
@@ -147,3 +340,217 @@ impl Drop for DesManager {
         }
     }
 }
+
+/// Change to a field of a stored serialized entity, see [`SerializedEntity::set_field`].
+pub struct DesPatch {
+    pub gid: Gid,
+    pub path: String,
+    pub value: String,
+}
+
+/// Number of entities in the entity storage of a save state, if there is one.
+pub(crate) fn stored_entity_count(save_state: &SaveState) -> Option<usize> {
+    save_state
+        .load::<EntityStorage>()
+        .map(|storage| storage.entities.len())
+}
+
+/// Prints entities from the entity storage of a save state, optionally showing, patching or deleting some of them.
+///
+/// Authority only exists while the game is running, so it isn't shown here.
+pub fn inspect_storage_cli(
+    save_state_path: PathBuf,
+    filter: &DesEntityFilter,
+    show: &[Gid],
+    patch: &[DesPatch],
+    delete: &[Gid],
+) {
+    let save_state = SaveState::new(save_state_path);
+    let Some(mut storage) = save_state.load::<EntityStorage>() else {
+        println!("No entity storage found");
+        return;
+    };
+    let mut entities: Vec<_> = storage
+        .entities
+        .values()
+        .map(|entity| DesEntitySummary::new(entity, None))
+        .collect();
+    let total = entities.len();
+    filter.apply(&mut entities);
+    for entity in &entities {
+        println!(
+            "{:016x} {:>8} {:>8} {:>8}b {}",
+            entity.gid.0,
+            entity.pos.x,
+            entity.pos.y,
+            entity.size,
+            entity.filename_or_serialized()
+        );
+    }
+    println!("Shown {} out of {} entities", entities.len(), total);
+
+    for gid in show {
+        match storage.entities.get(gid).map(|entity| &entity.data) {
+            None => println!("No entity with gid {:016x}", gid.0),
+            Some(EntitySpawnInfo::Filename(filename)) => {
+                println!("{:016x} is spawned from {filename}", gid.0)
+            }
+            Some(EntitySpawnInfo::Serialized { data, .. }) => {
+                match SerializedEntity::decode(data) {
+                    Ok(serialized) => println!("{:016x}:\n{serialized}", gid.0),
+                    Err(err) => println!("Can't show {:016x}: {err}", gid.0),
+                }
+            }
+        }
+    }
+
+    let mut changed = false;
+    for DesPatch { gid, path, value } in patch {
+        let Some(FullEntityData {
+            data: EntitySpawnInfo::Serialized { data, .. },
+            ..
+        }) = storage.entities.get_mut(gid)
+        else {
+            println!("No serialized entity with gid {:016x}", gid.0);
+            continue;
+        };
+        let patched = SerializedEntity::decode(data).and_then(|mut serialized| {
+            serialized.set_field(path, value)?;
+            Ok(serialized.encode())
+        });
+        match patched {
+            Ok(patched) => {
+                *data = patched;
+                changed = true;
+            }
+            Err(err) => println!("Can't patch {:016x}: {err}", gid.0),
+        }
+    }
+
+    for gid in delete {
+        if storage.entities.remove(gid).is_some() {
+            changed = true;
+        } else {
+            println!("No entity with gid {:016x}", gid.0);
+        }
+    }
+    if changed {
+        save_state.mark_game_started();
+        save_state.save(&storage);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn des_manager(name: &str) -> DesManager {
+        let path = std::env::temp_dir().join(format!("ew_des_{name}_{}", std::process::id()));
+        DesManager::new(false, SaveState::new(path))
+    }
+
+    fn entity(gid: Gid) -> FullEntityData {
+        FullEntityData {
+            gid,
+            pos: WorldPos::from_f32(10.0, 20.0),
+            data: EntitySpawnInfo::Filename("data/entities/animals/rat.xml".to_owned()),
+        }
+    }
+
+    #[test]
+    fn authority_is_told_about_admin_commands() {
+        let mut des = des_manager("admin");
+        let (a, b) = (OmniPeerId(1), OmniPeerId(2));
+        des.handle_noita_msg(a, DesToProxy::InitOrUpdateEntity(entity(Gid(1))));
+        des.handle_noita_msg(a, DesToProxy::InitOrUpdateEntity(entity(Gid(2))));
+
+        des.handle_command(DesCommand::ForceRelease(Gid(1)));
+        des.handle_command(DesCommand::Delete(Gid(2)));
+        assert!(matches!(
+            des.pending_messages()[..],
+            [
+                (p1, ProxyToDes::LostAuthority(Gid(1))),
+                (p2, ProxyToDes::LostAuthority(Gid(2))),
+            ] if p1 == a && p2 == a
+        ));
+        assert_eq!(des.summarize().len(), 1);
+
+        // Released entity goes to the next peer that asks for it.
+        des.handle_noita_msg(
+            b,
+            DesToProxy::RequestAuthority {
+                pos: WorldPos::from_f32(10.0, 20.0),
+                radius: 100,
+            },
+        );
+        assert!(matches!(
+            des.pending_messages()[..],
+            [(p, ProxyToDes::GotAuthority(ref data))] if p == b && data.gid == Gid(1)
+        ));
+    }
+
+    #[test]
+    fn handed_off_entity_is_freed_if_new_authority_leaves() {
+        let mut des = des_manager("handoff");
+        let (a, b, c) = (OmniPeerId(1), OmniPeerId(2), OmniPeerId(3));
+        let request = DesToProxy::RequestAuthority {
+            pos: WorldPos::from_f32(10.0, 20.0),
+            radius: 100,
+        };
+        des.handle_noita_msg(a, DesToProxy::InitOrUpdateEntity(entity(Gid(1))));
+        des.handle_noita_msg(
+            a,
+            DesToProxy::TransferAuthority {
+                gid: Gid(1),
+                to: b.into(),
+            },
+        );
+
+        des.noita_disconnected(b);
+        des.handle_noita_msg(c, request.clone());
+        assert!(matches!(
+            des.pending_messages()[..],
+            [(p, ProxyToDes::GotAuthority(ref data))] if p == c && data.gid == Gid(1)
+        ));
+        assert!(des.pending_transfers.is_empty());
+    }
+
+    #[test]
+    fn only_authority_can_change_entities() {
+        let mut des = des_manager("stale");
+        let (a, b, c) = (OmniPeerId(1), OmniPeerId(2), OmniPeerId(3));
+        let request = DesToProxy::RequestAuthority {
+            pos: WorldPos::from_f32(10.0, 20.0),
+            radius: 100,
+        };
+        let moved = |gid| {
+            DesToProxy::UpdatePositions(vec![UpdatePosition {
+                gid,
+                pos: WorldPos::from_f32(15.0, 20.0),
+            }])
+        };
+        des.handle_noita_msg(a, DesToProxy::InitOrUpdateEntity(entity(Gid(1))));
+        des.handle_noita_msg(b, DesToProxy::InitOrUpdateEntity(entity(Gid(2))));
+
+        // Messages that were sent before a released its entity, or became a spectator, arrive late.
+        des.handle_noita_msg(a, DesToProxy::ReleaseAuthority(Gid(1)));
+        des.handle_noita_msg(a, moved(Gid(1)));
+        des.set_spectators([b].into_iter().collect());
+        des.handle_noita_msg(b, moved(Gid(2)));
+        des.handle_noita_msg(b, DesToProxy::DeleteEntity(Gid(2)));
+        des.handle_noita_msg(b, request.clone());
+        assert!(des.pending_messages().is_empty());
+
+        des.handle_noita_msg(c, request);
+        let mut granted: Vec<_> = des
+            .pending_messages()
+            .into_iter()
+            .map(|(peer, msg)| match msg {
+                ProxyToDes::GotAuthority(data) if peer == c => data.gid.0,
+                _ => panic!("expected authority to be given to {c}"),
+            })
+            .collect();
+        granted.sort();
+        assert_eq!(granted, [1, 2]);
+    }
+}
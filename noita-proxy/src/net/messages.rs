@@ -1,6 +1,6 @@
 use bitcode::{Decode, Encode};
 
-use crate::{player_cosmetics::PlayerPngDesc, GameSettings};
+use crate::{bookkeeping::mod_compat::ModInfo, player_cosmetics::PlayerPngDesc, GameSettings};
 
 use super::{omni::OmniPeerId, world::WorldNetMessage};
 
@@ -17,7 +17,7 @@ pub(crate) enum NetMsg {
     Welcome,
     Disconnect { id: OmniPeerId },
     RequestMods,
-    Mods { mods: Vec<String> },
+    Mods { mods: Vec<ModInfo> },
     EndRun,
     Kick,
     PeerDisconnected { id: OmniPeerId },
@@ -30,6 +30,10 @@ pub(crate) enum NetMsg {
     ForwardDesToProxy(shared::des::DesToProxy),
     ForwardProxyToDes(shared::des::ProxyToDes),
     NoitaDisconnected,
+    Chat { text: String, from_game: bool },
+    Spectate,
+    Spectators { ids: Vec<OmniPeerId> },
+    HeadlessHost,
 }
 
 impl From<MessageRequest<WorldNetMessage>> for MessageRequest<NetMsg> {
@@ -1,4 +1,5 @@
 use super::steam_networking::{self, ExtraPeerState};
+use crate::bookkeeping::ban_store::BanKey;
 use bitcode::{Decode, Encode};
 use std::fmt::Display;
 use steamworks::{LobbyId, SteamId};
@@ -170,6 +171,14 @@ impl PeerVariant {
         }
     }
 
+    /// What to ban a peer by. Only known for peers that are connected directly, so basically only by the host.
+    pub(crate) fn ban_key(&self, peer: OmniPeerId) -> Option<BanKey> {
+        match self {
+            PeerVariant::Tangled(p) => p.remote_addr(peer.into()).map(|addr| BanKey::Ip(addr.ip())),
+            PeerVariant::Steam(_) => Some(BanKey::Steam(peer.0)),
+        }
+    }
+
     pub fn is_steam(&self) -> bool {
         matches!(self, PeerVariant::Steam(_))
     }
@@ -1,50 +1,12 @@
 use crate::GameMode;
-use std::io::Write;
-
-/// Trait that allows to pass value to mod as proxy options.
-pub(crate) trait ProxyOpt {
-    fn write_opt(self, buf: &mut Vec<u8>, key: &str);
-}
-
-impl ProxyOpt for bool {
-    fn write_opt(self, buf: &mut Vec<u8>, key: &str) {
-        write!(
-            buf,
-            "proxy_opt_bool {} {}",
-            key,
-            if self { "true" } else { "false" }
-        )
-        .unwrap();
-    }
-}
-
-impl ProxyOpt for u32 {
-    fn write_opt(self, buf: &mut Vec<u8>, key: &str) {
-        write!(buf, "proxy_opt_num {} {}", key, self).unwrap();
-    }
-}
-
-impl ProxyOpt for f32 {
-    fn write_opt(self, buf: &mut Vec<u8>, key: &str) {
-        write!(buf, "proxy_opt_num {} {}", key, self).unwrap();
-    }
-}
-
-impl ProxyOpt for &str {
-    fn write_opt(self, buf: &mut Vec<u8>, key: &str) {
-        write!(buf, "proxy_opt {} {}", key, self).unwrap();
-    }
-}
-
-impl ProxyOpt for GameMode {
-    fn write_opt(self, buf: &mut Vec<u8>, key: &str) {
-        match self {
-            GameMode::SharedHealth => {
-                write!(buf, "proxy_opt {} shared_health", key).unwrap();
-            }
-            GameMode::LocalHealth(_) => {
-                write!(buf, "proxy_opt {} local_health", key).unwrap();
-            }
+use shared::options::OptionValue;
+
+/// Allows to pass game mode to mod as a proxy option.
+impl From<GameMode> for OptionValue {
+    fn from(value: GameMode) -> Self {
+        match value {
+            GameMode::SharedHealth => "shared_health".into(),
+            GameMode::LocalHealth(_) => "local_health".into(),
         }
     }
 }
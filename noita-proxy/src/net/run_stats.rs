@@ -0,0 +1,269 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    time::Instant,
+};
+
+use serde::Serialize;
+use shared::{
+    des::{EntityUpdate, Lid, RemoteDes},
+    RemoteMessage,
+};
+use tracing::{info, warn};
+
+use crate::bookkeeping::ban_store::now;
+
+use super::{
+    omni::OmniPeerId,
+    world::{world_model::ChunkCoord, WorldNetMessage},
+};
+
+const REPORT_DIR: &str = "run_reports";
+/// Positions are sent twice a second, longer jumps are teleports and aren't counted as travelled.
+const MAX_STEP: f64 = 1000.0;
+
+#[derive(Default)]
+struct PeerTracker {
+    last_pos: Option<(f64, f64)>,
+    distance: f64,
+    is_notplayer: bool,
+    kills: u32,
+    deaths: u32,
+    chunks_updated: HashSet<ChunkCoord>,
+}
+
+/// Statistics of the current run, as seen by this proxy.
+#[derive(Default)]
+pub(crate) struct RunStats {
+    /// When the game connected, and unix time of that.
+    started: Option<(Instant, u64)>,
+    peers: HashMap<OmniPeerId, PeerTracker>,
+    /// Entity authority and lid of kills that were already counted, as they're sent to every interested peer.
+    counted_kills: HashSet<(OmniPeerId, Lid)>,
+    bytes_sent: u64,
+    game_over: bool,
+}
+
+impl RunStats {
+    /// Starts counting run duration, if it isn't counted already.
+    pub(crate) fn start(&mut self) {
+        self.started.get_or_insert_with(|| (Instant::now(), now()));
+    }
+
+    pub(crate) fn peer_pos(&mut self, peer: OmniPeerId, x: f64, y: f64, is_notplayer: bool) {
+        let tracker = self.peers.entry(peer).or_default();
+        if let Some((last_x, last_y)) = tracker.last_pos {
+            let step = (x - last_x).hypot(y - last_y);
+            if step <= MAX_STEP {
+                tracker.distance += step;
+            }
+        }
+        tracker.last_pos = Some((x, y));
+        if is_notplayer && !tracker.is_notplayer {
+            tracker.deaths += 1;
+        }
+        tracker.is_notplayer = is_notplayer;
+    }
+
+    /// Counts kills in entity updates sent by `authority`.
+    pub(crate) fn remote_message(&mut self, authority: OmniPeerId, message: &RemoteMessage) {
+        let RemoteMessage::RemoteDes(RemoteDes::EntityUpdate(updates)) = message else {
+            return;
+        };
+        for update in updates {
+            if let EntityUpdate::KillEntity {
+                lid,
+                responsible_peer: Some(peer),
+            } = update
+            {
+                if self.counted_kills.insert((authority, *lid)) {
+                    self.peers.entry((*peer).into()).or_default().kills += 1;
+                }
+            }
+        }
+    }
+
+    /// Counts chunks that `authority` sent changes of, be it from player edits or natural simulation.
+    pub(crate) fn world_message(&mut self, authority: OmniPeerId, message: &WorldNetMessage) {
+        match message {
+            WorldNetMessage::ListenUpdate { delta, .. } => {
+                self.chunks_updated(authority, [delta.chunk_coord])
+            }
+            WorldNetMessage::ChunkPacket { chunkpacket } => self.chunks_updated(
+                authority,
+                chunkpacket.iter().map(|(delta, _)| delta.chunk_coord),
+            ),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn chunks_updated(
+        &mut self,
+        peer: OmniPeerId,
+        chunks: impl IntoIterator<Item = ChunkCoord>,
+    ) {
+        self.peers
+            .entry(peer)
+            .or_default()
+            .chunks_updated
+            .extend(chunks);
+    }
+
+    pub(crate) fn bytes_sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as u64;
+    }
+
+    pub(crate) fn game_over(&mut self) {
+        self.game_over = true;
+    }
+
+    /// Makes a report of the run if it was started, and starts counting a new one.
+    pub(crate) fn finish(
+        &mut self,
+        seed: u64,
+        world_num: u16,
+        nicknames: &HashMap<OmniPeerId, String>,
+    ) -> Option<RunReport> {
+        let stats = std::mem::take(self);
+        let (started, started_at) = stats.started?;
+        let mut players: Vec<_> = stats
+            .peers
+            .into_iter()
+            .map(|(peer, tracker)| PlayerStats {
+                peer: peer.as_hex(),
+                nickname: nicknames
+                    .get(&peer)
+                    .cloned()
+                    .unwrap_or_else(|| peer.to_string()),
+                distance: tracker.distance.round() as u64,
+                kills: tracker.kills,
+                deaths: tracker.deaths,
+                chunks_updated: tracker.chunks_updated.len(),
+            })
+            .collect();
+        players.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+        Some(RunReport {
+            seed,
+            world_num,
+            started_at,
+            duration_secs: started.elapsed().as_secs(),
+            game_over: stats.game_over,
+            bytes_sent: stats.bytes_sent,
+            players,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PlayerStats {
+    pub(crate) peer: String,
+    pub(crate) nickname: String,
+    /// In pixels.
+    pub(crate) distance: u64,
+    pub(crate) kills: u32,
+    /// Times this player became a ghost, in local health modes.
+    pub(crate) deaths: u32,
+    /// Chunks that changed while this player had authority over them, not only ones the player edited.
+    pub(crate) chunks_updated: usize,
+}
+
+/// Summary of a finished run, written as json to the `run_reports` directory next to the proxy executable.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RunReport {
+    pub(crate) seed: u64,
+    pub(crate) world_num: u16,
+    /// Unix time.
+    pub(crate) started_at: u64,
+    pub(crate) duration_secs: u64,
+    /// Whether the run ended because everyone died, instead of being ended by the host.
+    pub(crate) game_over: bool,
+    /// By this proxy.
+    pub(crate) bytes_sent: u64,
+    pub(crate) players: Vec<PlayerStats>,
+}
+
+impl RunReport {
+    pub(crate) fn save(&self) {
+        let dir = if let Ok(path) = std::env::current_exe() {
+            path.parent().unwrap().join(REPORT_DIR)
+        } else {
+            PathBuf::from(REPORT_DIR)
+        };
+        let path = dir.join(format!("run_{}.json", self.started_at));
+        let report = match serde_json::to_string_pretty(self) {
+            Ok(report) => report,
+            Err(err) => {
+                warn!("Could not serialize run report: {err}");
+                return;
+            }
+        };
+        if let Err(err) = fs::create_dir_all(&dir).and_then(|_| fs::write(&path, report)) {
+            warn!("Could not save run report to {}: {err}", path.display());
+        } else {
+            info!("Saved run report to {}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kill(lid: u32, responsible: OmniPeerId) -> RemoteMessage {
+        RemoteMessage::RemoteDes(RemoteDes::EntityUpdate(vec![EntityUpdate::KillEntity {
+            lid: Lid(lid),
+            responsible_peer: Some(responsible.into()),
+        }]))
+    }
+
+    fn only_player(stats: &mut RunStats) -> PlayerStats {
+        let mut report = stats.finish(1, 2, &HashMap::new()).unwrap();
+        assert_eq!(report.players.len(), 1);
+        report.players.remove(0)
+    }
+
+    #[test]
+    fn not_started_run_has_no_report() {
+        let mut stats = RunStats::default();
+        stats.peer_pos(OmniPeerId(1), 0.0, 0.0, false);
+        assert!(stats.finish(0, 0, &HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn kills_are_counted_once() {
+        let mut stats = RunStats::default();
+        let (a, b) = (OmniPeerId(1), OmniPeerId(2));
+        stats.start();
+        // Same kill, sent to two interested peers.
+        stats.remote_message(a, &kill(7, b));
+        stats.remote_message(a, &kill(7, b));
+        // Same lid, but of an entity with another authority.
+        stats.remote_message(b, &kill(7, b));
+        assert_eq!(only_player(&mut stats).kills, 2);
+    }
+
+    #[test]
+    fn distance_skips_teleports() {
+        let mut stats = RunStats::default();
+        let a = OmniPeerId(1);
+        stats.start();
+        stats.peer_pos(a, 0.0, 0.0, false);
+        stats.peer_pos(a, 30.0, 40.0, false);
+        stats.peer_pos(a, 30000.0, 40.0, false);
+        stats.peer_pos(a, 30000.0, 140.0, false);
+        assert_eq!(only_player(&mut stats).distance, 150);
+    }
+
+    #[test]
+    fn deaths_count_on_becoming_notplayer() {
+        let mut stats = RunStats::default();
+        let a = OmniPeerId(1);
+        stats.start();
+        stats.peer_pos(a, 0.0, 0.0, true);
+        stats.peer_pos(a, 0.0, 0.0, true);
+        stats.peer_pos(a, 0.0, 0.0, false);
+        stats.peer_pos(a, 0.0, 0.0, true);
+        assert_eq!(only_player(&mut stats).deaths, 2);
+    }
+}
@@ -27,6 +27,9 @@ use super::{
 pub mod world_info;
 pub mod world_model;
 
+/// How many updates a spectator waits before asking for a chunk nobody had authority of again.
+const SPECTATOR_RETRY_UPDATES: u64 = 60;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum WorldUpdateKind {
     Update(NoitaWorldUpdate),
@@ -203,6 +206,10 @@ pub(crate) struct WorldManager {
     explosion_pointer: FxHashMap<ChunkCoord, Vec<usize>>,
     explosion_data: Vec<(usize, usize, ExTarget, u64)>,
     explosion_heap: Vec<ExplosionData>,
+    /// Peers that only listen to chunks, and never become an authority.
+    spectators: FxHashSet<OmniPeerId>,
+    /// Update after which a spectator asks again for chunks that had no authority.
+    spectator_retry: FxHashMap<ChunkCoord, u64>,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -238,6 +245,43 @@ impl WorldManager {
             explosion_pointer: Default::default(),
             explosion_data: Default::default(),
             explosion_heap: Default::default(),
+            spectators: Default::default(),
+            spectator_retry: Default::default(),
+        }
+    }
+
+    fn is_spectator(&self) -> bool {
+        self.spectators.contains(&self.my_peer_id)
+    }
+
+    /// Gives up authority over every chunk when becoming a spectator.
+    pub(crate) fn set_spectators(&mut self, spectators: FxHashSet<OmniPeerId>) {
+        let was_spectator = self.is_spectator();
+        self.spectators = spectators;
+        if !self.is_spectator() {
+            self.spectator_retry.clear();
+            return;
+        }
+        if was_spectator {
+            return;
+        }
+        let mut emit_queue = Vec::new();
+        for (&chunk, state) in self.chunk_state.iter_mut() {
+            match state {
+                ChunkState::Listening { .. } => continue,
+                ChunkState::Authority { .. } => {
+                    emit_queue.push(WorldNetMessage::RelinquishAuthority {
+                        chunk,
+                        chunk_data: self.outbound_model.get_chunk_data(chunk),
+                        world_num: self.world_num,
+                    })
+                }
+                _ => {}
+            }
+            *state = ChunkState::UnloadPending;
+        }
+        for msg in emit_queue {
+            self.emit_msg(Destination::Host, msg)
         }
     }
 
@@ -246,7 +290,8 @@ impl WorldManager {
             .apply_noita_update(&update, &mut self.is_storage_recent);
     }
 
-    pub(crate) fn add_end(&mut self, priority: u8, pos: &[i32]) {
+    /// Returns updated chunks that this peer is an authority of.
+    pub(crate) fn add_end(&mut self, priority: u8, pos: &[i32]) -> Vec<ChunkCoord> {
         let updated_chunks = self
             .outbound_model
             .updated_chunks()
@@ -281,6 +326,15 @@ impl WorldManager {
             self.emit_msg(dst, msg)
         }
         self.outbound_model.reset_change_tracking();
+        updated_chunks
+            .into_iter()
+            .filter(|chunk| {
+                matches!(
+                    self.chunk_state.get(chunk),
+                    Some(ChunkState::Authority { .. })
+                )
+            })
+            .collect()
     }
 
     fn chunk_updated_locally(
@@ -301,6 +355,16 @@ impl WorldManager {
             self.world_num = pos[0];
             self.reset();
         }
+        let is_spectator = self.is_spectator();
+        if is_spectator
+            && !self.chunk_state.contains_key(&chunk)
+            && self
+                .spectator_retry
+                .get(&chunk)
+                .is_some_and(|&retry_at| retry_at > self.current_update)
+        {
+            return Vec::new();
+        }
         let entry = self.chunk_state.entry(chunk).or_insert_with(|| {
             debug!("Created entry for {chunk:?}");
             ChunkState::RequestAuthority {
@@ -316,7 +380,7 @@ impl WorldManager {
                 authority,
                 priority: pri,
             } => {
-                if *pri > priority {
+                if *pri > priority && !is_spectator {
                     let cs = ChunkState::WantToGetAuth {
                         authority: *authority,
                         auth_priority: *pri,
@@ -567,6 +631,7 @@ impl WorldManager {
         self.authority_map.clear();
         self.chunk_last_update.clear();
         self.chunk_state.clear();
+        self.spectator_retry.clear();
     }
 
     pub(crate) fn get_emitted_msgs(&mut self) -> Vec<MessageRequest<WorldNetMessage>> {
@@ -655,6 +720,17 @@ impl WorldManager {
                     return;
                 }
                 let current_authority = self.authority_map.get(&chunk).copied();
+                if self.spectators.contains(&source) {
+                    // Spectators can only listen to chunks that someone else is an authority of.
+                    let msg = match current_authority {
+                        Some((authority, _)) if authority != source => {
+                            WorldNetMessage::AuthorityAlreadyTaken { chunk, authority }
+                        }
+                        _ => WorldNetMessage::UnloadChunk { chunk },
+                    };
+                    self.emit_msg(Destination::Peer(source), msg);
+                    return;
+                }
                 match current_authority {
                     Some((authority, priority_state)) => {
                         if source == authority {
@@ -703,6 +779,9 @@ impl WorldManager {
                 new_authority,
                 new_priority,
             } => {
+                if self.spectators.contains(&new_authority) {
+                    return;
+                }
                 if let Some(ChunkState::Authority {
                     new_authority: new_auth,
                     ..
@@ -766,7 +845,7 @@ impl WorldManager {
                 }
                 if let Some(chunk_data) = chunk_data {
                     self.chunk_storage.insert(chunk, chunk_data);
-                    if let Some(p) = priority {
+                    if let Some(p) = priority.filter(|_| !self.spectators.contains(&source)) {
                         self.cut_through_world_explosion_chunk(chunk);
                         self.emit_got_authority(chunk, source, p)
                     }
@@ -802,6 +881,10 @@ impl WorldManager {
                 )
             }
             WorldNetMessage::UnloadChunk { chunk } => {
+                if self.is_spectator() {
+                    self.spectator_retry
+                        .insert(chunk, self.current_update + SPECTATOR_RETRY_UPDATES);
+                }
                 self.chunk_state.insert(chunk, ChunkState::UnloadPending {});
             }
 
@@ -975,7 +1058,10 @@ impl WorldManager {
             }
             WorldNetMessage::RequestAuthorityTransfer { chunk } => {
                 debug!("Got a request for authority transfer");
-                let state = self.chunk_state.get(&chunk);
+                let state = self
+                    .chunk_state
+                    .get(&chunk)
+                    .filter(|_| !self.spectators.contains(&source));
                 if let Some(ChunkState::Authority { listeners, .. }) = state {
                     let chunk_data = self.outbound_model.get_chunk_data(chunk);
                     self.emit_msg(
@@ -1118,6 +1204,7 @@ impl WorldManager {
         let air_pixel = Pixel {
             flags: PixelFlags::Normal,
             material: 0,
+            burning: false,
         };
         let chunk_storage: Vec<(ChunkCoord, ChunkData)> = self
             .chunk_storage
@@ -1200,6 +1287,7 @@ impl WorldManager {
         let air_pixel = Pixel {
             flags: PixelFlags::Normal,
             material: 0,
+            burning: false,
         };
         let close_check = max_cx == min_cx || max_cy == min_cy;
         let iter_check = [
@@ -1350,6 +1438,7 @@ impl WorldManager {
         let air_pixel = Pixel {
             flags: PixelFlags::Normal,
             material: mat.unwrap_or(0),
+            burning: false,
         };
         let (chunkx, chunky) = (
             x.div_euclid(CHUNK_SIZE as i32),
@@ -1674,6 +1763,7 @@ impl WorldManager {
         let air_pixel = Pixel {
             flags: PixelFlags::Normal,
             material: 0,
+            burning: false,
         };
         let (chunkx, chunky) = (
             x.div_euclid(CHUNK_SIZE as i32),
@@ -1978,6 +2068,7 @@ impl WorldManager {
         let air_pixel = Pixel {
             flags: PixelFlags::Normal,
             material: 0,
+            burning: false,
         };
         let mut chunk = Chunk::default();
         let mut chunk_delta = Chunk::default();
@@ -2269,6 +2360,74 @@ impl Drop for WorldManager {
 impl SaveStateEntry for FxHashMap<ChunkCoord, ChunkData> {
     const FILENAME: &'static str = "world_chunks";
 }
+
+/// Number of chunks in the world storage of a save state, if there is one.
+pub(crate) fn stored_chunk_count(save_state: &SaveState) -> Option<usize> {
+    save_state
+        .load::<FxHashMap<ChunkCoord, ChunkData>>()
+        .map(|chunks| chunks.len())
+}
+
+/// Renders stored chunks within `region` (min_x, min_y, max_x, max_y in pixels), or all of them.
+/// Materials get arbitrary but stable colors, as material names are only known to the game.
+pub(crate) fn render_stored_chunks(
+    save_state: &SaveState,
+    region: Option<(i32, i32, i32, i32)>,
+) -> eyre::Result<image::RgbaImage> {
+    const MAX_SIDE: i64 = 16384;
+    let Some(chunks) = save_state.load::<FxHashMap<ChunkCoord, ChunkData>>() else {
+        eyre::bail!("No world chunks in save state");
+    };
+    let size = CHUNK_SIZE as i32;
+    let (min_x, min_y, max_x, max_y) = match region {
+        Some(region) => region,
+        None => chunks.keys().fold(
+            (i32::MAX, i32::MAX, i32::MIN, i32::MIN),
+            |(min_x, min_y, max_x, max_y), ChunkCoord(x, y)| {
+                (
+                    min_x.min(x * size),
+                    min_y.min(y * size),
+                    max_x.max((x + 1) * size),
+                    max_y.max((y + 1) * size),
+                )
+            },
+        ),
+    };
+    let (width, height) = (max_x as i64 - min_x as i64, max_y as i64 - min_y as i64);
+    if width <= 0 || height <= 0 {
+        eyre::bail!("Region is empty");
+    }
+    if width > MAX_SIDE || height > MAX_SIDE {
+        eyre::bail!("Map is {width}x{height}, pick a smaller region");
+    }
+
+    let mut image = image::RgbaImage::new(width as u32, height as u32);
+    for (&ChunkCoord(cx, cy), data) in &chunks {
+        let (left, top) = (cx * size, cy * size);
+        if left >= max_x || top >= max_y || left + size <= min_x || top + size <= min_y {
+            continue;
+        }
+        let mut chunk = Chunk::default();
+        data.apply_to_chunk(&mut chunk);
+        for y in top.max(min_y)..(top + size).min(max_y) {
+            for x in left.max(min_x)..(left + size).min(max_x) {
+                let offset = (y - top) as usize * CHUNK_SIZE + (x - left) as usize;
+                let material = chunk.pixel(offset).material;
+                if material == 0 {
+                    continue;
+                }
+                let [r, g, b, _] = (material as u32).wrapping_mul(0x9E37_79B1).to_be_bytes();
+                image.put_pixel(
+                    (x - min_x) as u32,
+                    (y - min_y) as u32,
+                    image::Rgba([r, g, b, 255]),
+                );
+            }
+        }
+    }
+    Ok(image)
+}
+
 pub(crate) struct ExRet {
     loaded: Option<(ChunkCoord, ChunkData, bool, bool)>,
     unloaded: Option<(ChunkCoord, Vec<usize>)>,
@@ -3268,3 +3427,43 @@ fn test_cut_perf() {
     }
     println!("total micros: {}", total / iters);
 }
+
+#[cfg(test)]
+#[test]
+#[serial]
+fn test_spectator_never_gets_authority() {
+    let mut world = WorldManager::new(
+        true,
+        OmniPeerId(0),
+        SaveState::new("/tmp/ew_tmp_save".parse().unwrap()),
+    );
+    let (spectator, player) = (OmniPeerId(1), OmniPeerId(2));
+    world.set_spectators([spectator].into_iter().collect());
+    let chunk = ChunkCoord(0, 0);
+    let request = |chunk| WorldNetMessage::RequestAuthority {
+        chunk,
+        priority: 0,
+        can_wait: false,
+    };
+
+    world.handle_msg(spectator, request(chunk));
+    assert!(matches!(
+        world.get_emitted_msgs()[..],
+        [MessageRequest {
+            msg: WorldNetMessage::UnloadChunk { .. },
+            ..
+        }]
+    ));
+
+    world.handle_msg(player, request(chunk));
+    world.get_emitted_msgs();
+    world.handle_msg(spectator, request(chunk));
+    assert!(matches!(
+        world.get_emitted_msgs()[..],
+        [MessageRequest {
+            msg: WorldNetMessage::AuthorityAlreadyTaken { authority, .. },
+            ..
+        }] if authority == player
+    ));
+    assert_eq!(world.authority_map[&chunk].0, player);
+}
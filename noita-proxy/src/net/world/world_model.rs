@@ -3,7 +3,9 @@ use std::sync::Arc;
 
 use bitcode::{Decode, Encode};
 use chunk::{Chunk, CompactPixel, Pixel, PixelFlags};
-use encoding::{NoitaWorldUpdate, PixelRun, PixelRunner};
+use encoding::{
+    NoitaWorldUpdate, PixelRun, PixelRunner, FLAG_BOX2D, FLAG_BURNING, FLAG_LIQUID_STATIC,
+};
 use rustc_hash::{FxHashMap, FxHashSet};
 use tracing::info;
 
@@ -45,6 +47,7 @@ impl ChunkData {
                 Pixel {
                     flags: PixelFlags::Normal,
                     material: (i as u16) % 512,
+                    burning: false,
                 }
                 .to_compact(),
             )
@@ -61,6 +64,7 @@ impl ChunkData {
                 Pixel {
                     flags: PixelFlags::Normal,
                     material: mat,
+                    burning: false,
                 }
                 .to_compact(),
             )
@@ -152,11 +156,18 @@ impl WorldModel {
         let (mut chunk_coord, _) = Self::get_chunk_coords(header.x, header.y);
         let mut chunk = self.chunks.entry(chunk_coord).or_default();
         for run in runs {
-            let flags = if run.data.flags > 0 {
+            let flags = if run.data.flags & FLAG_BOX2D != 0 {
+                PixelFlags::Box2d
+            } else if run.data.flags & FLAG_LIQUID_STATIC != 0 {
                 PixelFlags::Fluid
             } else {
                 PixelFlags::Normal
             };
+            let pixel = Pixel {
+                flags,
+                material: run.data.material,
+                burning: run.data.flags & FLAG_BURNING != 0,
+            };
             for _ in 0..run.length {
                 let xs = header.x + x;
                 let ys = header.y + y;
@@ -165,14 +176,7 @@ impl WorldModel {
                     chunk_coord = new_chunk_coord;
                     chunk = self.chunks.entry(chunk_coord).or_default();
                 }
-                if set_pixel(
-                    Pixel {
-                        material: run.data.material,
-                        flags,
-                    },
-                    chunk,
-                    offset,
-                ) {
+                if set_pixel(pixel, chunk, offset) {
                     self.updated_chunks.insert(chunk_coord);
                     if changed.contains(&chunk_coord) {
                         changed.remove(&chunk_coord);
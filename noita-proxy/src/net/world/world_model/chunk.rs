@@ -4,7 +4,7 @@ use bitcode::{Decode, Encode};
 use crossbeam::atomic::AtomicCell;
 
 use super::{
-    encoding::{PixelRunner, RawPixel},
+    encoding::{PixelRunner, RawPixel, FLAG_BURNING, FLAG_LIQUID_STATIC},
     ChunkData, CHUNK_SIZE,
 };
 
@@ -15,63 +15,73 @@ pub enum PixelFlags {
     Unknown,
     Normal,
     Fluid,
+    /// Part of a box2d body. Those are synced as entities, so the pixel is never written to the world.
+    Box2d,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
 pub struct Pixel {
     pub flags: PixelFlags,
     pub material: u16,
+    pub burning: bool,
 }
 
 impl Pixel {
     pub fn to_raw(self) -> RawPixel {
+        let written = matches!(self.flags, PixelFlags::Normal | PixelFlags::Fluid);
+        let mut flags = if self.flags == PixelFlags::Fluid {
+            FLAG_LIQUID_STATIC
+        } else {
+            0
+        };
+        if self.burning {
+            flags |= FLAG_BURNING;
+        }
         RawPixel {
-            material: if self.flags != PixelFlags::Unknown {
-                self.material
-            } else {
-                u16::MAX
-            },
-            flags: if self.flags == PixelFlags::Normal {
-                0
-            } else {
-                1
-            },
+            material: if written { self.material } else { u16::MAX },
+            flags,
         }
     }
     pub fn to_compact(self) -> CompactPixel {
-        let flag_bit = if self.flags == PixelFlags::Normal {
-            0
-        } else {
-            1
-        };
+        let flag_bits = match self.flags {
+            PixelFlags::Fluid => 1,
+            PixelFlags::Box2d => COMPACT_BOX2D,
+            _ => 0,
+        } | if self.burning { COMPACT_BURNING } else { 0 };
         let material = (self.material + 1) & 2047; // 11 bits for material
         let raw = if self.flags == PixelFlags::Unknown {
             CompactPixel::UNKNOWN_RAW
         } else {
-            (material << 1) | flag_bit
+            (material << 1) | flag_bits
         };
         CompactPixel(NonZeroU16::new(raw).unwrap())
     }
     fn from_compact(compact: CompactPixel) -> Self {
         let raw = u16::from(compact.0);
-        let material = (raw >> 1) - 1;
-        let flags = if raw & 1 == 1 {
+        if raw == CompactPixel::UNKNOWN_RAW {
+            return Pixel::default();
+        }
+        let material = ((raw & 4095) >> 1) - 1;
+        let flags = if raw & COMPACT_BOX2D != 0 {
+            PixelFlags::Box2d
+        } else if raw & 1 == 1 {
             PixelFlags::Fluid
         } else {
             PixelFlags::Normal
         };
-        if raw == CompactPixel::UNKNOWN_RAW {
-            Pixel {
-                flags: PixelFlags::Unknown,
-                material: 0,
-            }
-        } else {
-            Pixel { flags, material }
+        Pixel {
+            flags,
+            material,
+            burning: raw & COMPACT_BURNING != 0,
         }
     }
 }
 
-/// An entire pixel packed into 12 bits.
+/// Bits above the 12 bits of material and fluid flag.
+const COMPACT_BOX2D: u16 = 1 << 12;
+const COMPACT_BURNING: u16 = 1 << 13;
+
+/// An entire pixel packed into 14 bits.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
 #[repr(transparent)]
 pub struct CompactPixel(pub NonZeroU16);
@@ -159,3 +169,28 @@ impl Chunk {
         ChunkData { runs }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compact_pixel_keeps_flags() {
+        for flags in [PixelFlags::Normal, PixelFlags::Fluid, PixelFlags::Box2d] {
+            for burning in [false, true] {
+                let pixel = Pixel {
+                    flags,
+                    material: 2000,
+                    burning,
+                };
+                assert_eq!(Pixel::from_compact(pixel.to_compact()), pixel);
+            }
+        }
+        let box2d = Pixel {
+            flags: PixelFlags::Box2d,
+            material: 3,
+            burning: false,
+        };
+        assert_eq!(box2d.to_raw().material, u16::MAX);
+    }
+}
@@ -19,6 +19,15 @@ pub struct NoitaWorldUpdate {
     pub(crate) runs: Vec<PixelRun<RawPixel>>,
 }
 
+// Flags set by ewext's encode_area.
+
+/// Static liquid, e.g. terrain.
+pub(crate) const FLAG_LIQUID_STATIC: u8 = 1;
+/// Cell is on fire. Stored, but not applied when decoding, fire spreads on its own.
+pub(crate) const FLAG_BURNING: u8 = 2;
+/// Cell belongs to a box2d body, those are synced as entities and not as pixels.
+pub(crate) const FLAG_BOX2D: u8 = 4;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
 pub(crate) struct RawPixel {
     pub material: u16,
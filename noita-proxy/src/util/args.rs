@@ -14,7 +14,7 @@ pub struct Args {
     /// steam lobby code.
     #[argh(option)]
     pub lobby: Option<String>,
-    /// host either steam or ip.
+    /// host either steam or ip; same as the `host` command, so game settings are the ones saved by the gui.
     #[argh(option)]
     pub host: Option<String>,
     /// noita.exe path
@@ -23,4 +23,216 @@ pub struct Args {
     /// language for gui
     #[argh(option)]
     pub language: Option<String>,
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum Command {
+    Host(HostArgs),
+    Connect(ConnectArgs),
+    InspectSave(InspectSaveArgs),
+    InspectDes(InspectDesArgs),
+    RenderMap(RenderMapArgs),
+    ListLobbies(ListLobbiesArgs),
+    Bans(BansArgs),
+    Presets(PresetsArgs),
+}
+
+#[derive(FromArgs, PartialEq, Debug, Default)]
+/// Host a lobby without the gui. Game settings come from the config file, or from the gui when there isn't one, and flags override both.
+#[argh(subcommand, name = "host")]
+pub struct HostArgs {
+    /// host a steam lobby instead of a tangled one.
+    #[argh(switch)]
+    pub steam: bool,
+    /// port of the tangled lobby; default is 5123.
+    #[argh(option)]
+    pub port: Option<u16>,
+    /// run a dedicated server: headless, without a game, gui or steam. Can't be combined with --steam.
+    #[argh(switch)]
+    pub dedicated: bool,
+    /// name of a dedicated server.
+    #[argh(option)]
+    pub name: Option<String>,
+    /// save state directory; defaults to the one next to the proxy executable.
+    #[argh(option)]
+    pub save_state: Option<PathBuf>,
+    /// serve the local admin api on this port.
+    #[argh(option)]
+    pub admin_port: Option<u16>,
+    /// config file with game settings, in RON. With --dedicated, it also has the port, name, save_state and admin_port of the server, see --print-config.
+    #[argh(option)]
+    pub config: Option<PathBuf>,
+    /// name of a built-in or saved preset to apply to the settings, before other flags.
+    #[argh(option)]
+    pub preset: Option<String>,
+    /// print resulting game settings as RON, with every default filled in, and exit.
+    #[argh(switch)]
+    pub print_config: bool,
+    /// use a constant seed instead of continuing the saved run or picking a random one.
+    #[argh(option)]
+    pub seed: Option<u64>,
+    /// world number.
+    #[argh(option)]
+    pub world_num: Option<u16>,
+    /// one of "shared", "local", "local-alt" or "perma-death".
+    #[argh(option)]
+    pub game_mode: Option<String>,
+    /// enable debug mode.
+    #[argh(option)]
+    pub debug_mode: Option<bool>,
+    /// deduplicate items.
+    #[argh(option)]
+    pub item_dedup: Option<bool>,
+    /// enemy hp multiplier.
+    #[argh(option)]
+    pub enemy_hp_mult: Option<f32>,
+    /// enable friendly fire.
+    #[argh(option)]
+    pub friendly_fire: Option<bool>,
+    /// give players different perks.
+    #[argh(option)]
+    pub randomize_perks: Option<bool>,
+    /// unlocked spells and perks; can be repeated. Defaults to the progress of the local save.
+    #[argh(option)]
+    pub progress: Vec<String>,
+    /// max players in a steam lobby.
+    #[argh(option)]
+    pub max_players: Option<u32>,
+    /// health added per player in shared health mode.
+    #[argh(option)]
+    pub health_per_player: Option<u32>,
+    /// percentage of max health lost on revive.
+    #[argh(option)]
+    pub health_lost_on_revive: Option<u32>,
+    /// disable material damage.
+    #[argh(option)]
+    pub no_material_damage: Option<bool>,
+    /// damage taken by one player hurts everyone.
+    #[argh(option)]
+    pub global_hp_loss: Option<bool>,
+    /// comma separated ids of banned perks.
+    #[argh(option)]
+    pub perk_ban_list: Option<String>,
+    /// enable physics damage.
+    #[argh(option)]
+    pub physics_damage: Option<bool>,
+    /// share gold between players.
+    #[argh(option)]
+    pub share_gold: Option<bool>,
+    /// enable nice terraforming.
+    #[argh(option)]
+    pub nice_terraforming: Option<bool>,
+    /// entity tracking rules of DES.
+    #[argh(option)]
+    pub des_tracking_rules: Option<String>,
+    /// comma separated names of ewext modules that shouldn't run.
+    #[argh(option)]
+    pub disabled_ewext_modules: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Connect to a lobby without the gui.
+#[argh(subcommand, name = "connect")]
+pub struct ConnectArgs {
+    /// steam lobby code or ip:port.
+    #[argh(positional)]
+    pub lobby: String,
+    /// join as a spectator, which watches the run without affecting it.
+    #[argh(switch)]
+    pub spectate: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show what a save state contains.
+#[argh(subcommand, name = "inspect-save")]
+pub struct InspectSaveArgs {
+    /// save state directory; defaults to the one next to the proxy executable.
+    #[argh(option)]
+    pub save_state: Option<PathBuf>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Render stored world chunks of a save state to a png, with a color per material.
+#[argh(subcommand, name = "render-map")]
+pub struct RenderMapArgs {
+    /// save state directory; defaults to the one next to the proxy executable.
+    #[argh(option)]
+    pub save_state: Option<PathBuf>,
+    /// only render pixels within "min_x,min_y,max_x,max_y"; defaults to every stored chunk.
+    #[argh(option)]
+    pub region: Option<String>,
+    /// png to write; default is map.png.
+    #[argh(option, default = "PathBuf::from(\"map.png\")")]
+    pub output: PathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List public steam lobbies of this mod.
+#[argh(subcommand, name = "list-lobbies")]
+pub struct ListLobbiesArgs {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List entities stored by DES in a save state.
+#[argh(subcommand, name = "inspect-des")]
+pub struct InspectDesArgs {
+    /// save state directory; defaults to the one next to the proxy executable.
+    #[argh(option)]
+    pub save_state: Option<PathBuf>,
+    /// glob to filter entity filenames with.
+    #[argh(option)]
+    pub filename: Option<String>,
+    /// only show entities within "min_x,min_y,max_x,max_y".
+    #[argh(option)]
+    pub region: Option<String>,
+    /// sort by serialized size instead of filename.
+    #[argh(switch)]
+    pub by_size: bool,
+    /// gid (in hex) of a serialized entity to print components of; can be repeated.
+    #[argh(option)]
+    pub show: Vec<String>,
+    /// change a field of a serialized entity, as "gid:Component.field=value" or "gid:Component[i].field=value"; can be repeated.
+    #[argh(option)]
+    pub patch: Vec<String>,
+    /// gid (in hex) of an entity to delete from storage; can be repeated.
+    #[argh(option)]
+    pub delete: Vec<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List persistent bans, and add or remove them.
+#[argh(subcommand, name = "bans")]
+pub struct BansArgs {
+    /// steam id or ip address to ban.
+    #[argh(option)]
+    pub ban: Option<String>,
+    /// reason of the ban.
+    #[argh(option)]
+    pub reason: Option<String>,
+    /// ban duration in hours; bans are permanent without it.
+    #[argh(option)]
+    pub hours: Option<u64>,
+    /// steam id or ip address to unban.
+    #[argh(option)]
+    pub unban: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List game settings presets, import and export them.
+#[argh(subcommand, name = "presets")]
+pub struct PresetsArgs {
+    /// print a share string of the named preset.
+    #[argh(option)]
+    pub export: Option<String>,
+    /// share string, or path to a file with a share string or json, of a preset to save.
+    #[argh(option)]
+    pub import: Option<String>,
+    /// save the imported preset under this name.
+    #[argh(option)]
+    pub name: Option<String>,
+    /// name of a saved preset to delete.
+    #[argh(option)]
+    pub remove: Option<String>,
 }
@@ -0,0 +1,82 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{args::HostArgs, GameSettings, DEFAULT_PORT};
+
+/// Settings of a dedicated server, see `host --dedicated`. Stored as RON, missing fields take their defaults.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct DedicatedConfig {
+    /// Port of the tangled lobby.
+    pub(crate) port: u16,
+    /// Name of the server, shown to players instead of a host nickname.
+    pub(crate) name: String,
+    /// Directory for the save state; defaults to `save_state` next to the proxy executable.
+    pub(crate) save_state: Option<PathBuf>,
+    /// Port of the local admin api; it isn't started if not set.
+    pub(crate) admin_port: Option<u16>,
+    pub(crate) game_settings: GameSettings,
+}
+
+impl Default for DedicatedConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            name: "Dedicated server".to_owned(),
+            save_state: None,
+            admin_port: None,
+            game_settings: GameSettings::default(),
+        }
+    }
+}
+
+impl DedicatedConfig {
+    pub(crate) fn load(path: &Path) -> eyre::Result<Self> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Overrides server settings with the ones given as flags. Game settings are overridden separately, by `GameSettings::apply_overrides`.
+    pub(crate) fn apply_overrides(&mut self, args: &HostArgs) {
+        if let Some(port) = args.port {
+            self.port = port;
+        }
+        if let Some(name) = &args.name {
+            self.name = name.clone();
+        }
+        if let Some(save_state) = &args.save_state {
+            self.save_state = Some(save_state.clone());
+        }
+        if let Some(admin_port) = args.admin_port {
+            self.admin_port = Some(admin_port);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_override_config() {
+        let mut config: DedicatedConfig = ron::from_str(
+            r#"(port: 6000, name: "Lobby", admin_port: Some(21252), game_settings: (seed: 7))"#,
+        )
+        .unwrap();
+        assert_eq!(config.save_state, None);
+        assert_eq!(config.game_settings.seed, 7);
+
+        config.apply_overrides(&HostArgs {
+            port: Some(6001),
+            save_state: Some("saves".into()),
+            ..Default::default()
+        });
+        assert_eq!(config.port, 6001);
+        assert_eq!(config.name, "Lobby");
+        assert_eq!(config.save_state, Some(PathBuf::from("saves")));
+        assert_eq!(config.admin_port, Some(21252));
+    }
+}
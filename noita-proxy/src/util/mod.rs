@@ -1,3 +1,5 @@
 pub mod args;
+pub mod dedicated;
 pub mod lang;
+pub mod profile_graph;
 pub mod steam_helper;
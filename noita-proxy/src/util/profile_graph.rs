@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use eframe::egui::{self, Align2, Color32, FontId, Pos2, Sense, Shape, Stroke, Ui};
+use shared::{ProfileSummary, SpanStats};
+
+use crate::lang::{tr, tr_a};
+
+const COLORS: [Color32; 8] = [
+    Color32::LIGHT_GRAY,
+    Color32::from_rgb(230, 100, 100),
+    Color32::from_rgb(100, 200, 100),
+    Color32::from_rgb(100, 150, 240),
+    Color32::from_rgb(230, 200, 80),
+    Color32::from_rgb(200, 120, 230),
+    Color32::from_rgb(80, 210, 210),
+    Color32::from_rgb(240, 150, 70),
+];
+
+/// Draws average time per frame of every span over time, with a table of the latest values below.
+pub(crate) fn show_profile_graph(ui: &mut Ui, history: &VecDeque<ProfileSummary>) {
+    let Some(latest) = history.back() else {
+        ui.label(tr("profiler_no_data"));
+        return;
+    };
+
+    let mut names = vec!["frame"];
+    for summary in history {
+        for (name, _) in &summary.spans {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+    }
+    let stats_of = |summary: &ProfileSummary, name: &str| -> SpanStats {
+        if name == "frame" {
+            return summary.frame;
+        }
+        summary
+            .spans
+            .iter()
+            .find(|(n, _)| n == name)
+            .map_or_else(SpanStats::default, |(_, stats)| *stats)
+    };
+    let color = |i: usize| COLORS[i % COLORS.len()];
+
+    let top_us = history
+        .iter()
+        .map(|summary| summary.frame.avg_us)
+        .max()
+        .unwrap_or(0)
+        .max(1000) as f32;
+    let (response, painter) =
+        ui.allocate_painter(egui::vec2(ui.available_width(), 200.0), Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    let step = rect.width() / (crate::net::PROFILE_HISTORY_LEN - 1) as f32;
+    for (i, name) in names.iter().enumerate() {
+        let points = history
+            .iter()
+            .enumerate()
+            .map(|(x, summary)| {
+                let y = stats_of(summary, name).avg_us as f32 / top_us;
+                Pos2::new(
+                    rect.left() + x as f32 * step,
+                    rect.bottom() - y.min(1.0) * rect.height(),
+                )
+            })
+            .collect();
+        painter.add(Shape::line(points, Stroke::new(1.5, color(i))));
+    }
+    painter.text(
+        rect.left_top(),
+        Align2::LEFT_TOP,
+        format!("{:.1}ms", top_us / 1000.0),
+        FontId::monospace(12.0),
+        ui.visuals().text_color(),
+    );
+
+    ui.label(tr_a(
+        "profiler_averages",
+        &[("frames".to_owned(), latest.frames.to_string().into())],
+    ));
+    egui::Grid::new("profile legend")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label(tr("profiler_span"));
+            ui.label(tr("profiler_avg"));
+            ui.label(tr("profiler_max"));
+            ui.end_row();
+            for (i, name) in names.iter().enumerate() {
+                let stats = stats_of(latest, name);
+                ui.colored_label(color(i), *name);
+                ui.label(format!("{:.2}ms", stats.avg_us as f32 / 1000.0));
+                ui.label(format!("{:.2}ms", stats.max_us as f32 / 1000.0));
+                ui.end_row();
+            }
+        });
+}
@@ -59,6 +59,7 @@ enum DirectConnectionError {
 struct DirectPeer {
     my_id: PeerId,
     remote_id: PeerId,
+    remote_addr: SocketAddr,
     send_stream: message_stream::SendMessageStream<InternalMessage>,
 }
 
@@ -107,6 +108,7 @@ impl DirectPeer {
         Ok(Self {
             my_id: PeerId::HOST,
             remote_id: assigned_peer_id,
+            remote_addr: connection.remote_address(),
             send_stream: message_stream::SendMessageStream::new(send_stream),
         })
     }
@@ -133,6 +135,7 @@ impl DirectPeer {
         Ok(Self {
             my_id: PeerId(peer_id),
             remote_id: PeerId::HOST,
+            remote_addr: connection.remote_address(),
             send_stream: message_stream::SendMessageStream::new(send_stream),
         })
     }
@@ -175,6 +178,14 @@ pub(crate) struct Shared {
     internal_events_s: tokio::sync::mpsc::UnboundedSender<InternalEvent>,
 }
 
+impl Shared {
+    pub(crate) fn remote_addr(&self, peer: PeerId) -> Option<SocketAddr> {
+        self.direct_peers
+            .get(&peer)
+            .map(|direct| direct.remote_addr)
+    }
+}
+
 pub(crate) struct ConnectionManager {
     shared: Arc<Shared>,
     endpoint: Endpoint,
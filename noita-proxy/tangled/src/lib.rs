@@ -128,6 +128,11 @@ impl Peer {
         self.shared.peer_state.load()
     }
 
+    /// Address of a directly connected peer. Only the host is connected directly to every other peer.
+    pub fn remote_addr(&self, peer: PeerId) -> Option<SocketAddr> {
+        self.shared.remote_addr(peer)
+    }
+
     /// Iterate over connected peers, returning ther `PeerId`.
     pub fn iter_peer_ids(&self) -> impl Iterator<Item = PeerId> + '_ {
         self.shared
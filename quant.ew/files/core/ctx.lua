@@ -14,8 +14,14 @@ setmetatable(ctx.hook, {
 
 ctx.init = function()
     ctx.host_id = 0
+    -- See `net_handling.proxy.duty_id`.
+    ctx.duty_id = 0
+    ctx.has_host_duties = false
     ctx.my_id = nil
     ctx.players = {}
+    -- Peers that only watch the run, by peer id.
+    ctx.spectators = {}
+    ctx.is_spectator = false
     ctx.entity_by_remote_id = {}
     ctx.run_ended = false
     ctx.player_data_by_local_entity = {}
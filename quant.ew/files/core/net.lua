@@ -175,11 +175,14 @@ function net.update()
 end
 
 function net.init()
-    local ok, res = util.tpcall(ewext.netmanager_connect)
+    local ok, res, opts = util.tpcall(ewext.netmanager_connect)
     if not ok then
         net.connect_failed = true
         return
     end
+    for _, kv in ipairs(opts) do
+        ctx.proxy_opt[kv[1]] = kv[2]
+    end
     for _, opt in ipairs(res) do
         handle_message(opt)
     end
@@ -20,6 +20,7 @@ function net_handling.proxy.peer_id(_, value)
     print("My peer_id: " .. value)
     ctx.my_id = value
     ctx.is_host = ctx.my_id == ctx.host_id
+    ctx.has_host_duties = ctx.my_id == ctx.duty_id
 end
 
 function net_handling.proxy.debug(_, value)
@@ -31,18 +32,28 @@ function net_handling.proxy.host_id(_, value)
     ctx.is_host = ctx.my_id == ctx.host_id
 end
 
-function net_handling.proxy.proxy_opt(_, key, value)
-    print("Proxy opt [str]: " .. key .. " = " .. tostring(value))
-    ctx.proxy_opt[key] = value
+-- Peer that does what only the host should, the host itself unless it's a dedicated server without a game.
+function net_handling.proxy.duty_id(_, value)
+    ctx.duty_id = value
+    ctx.has_host_duties = ctx.my_id == ctx.duty_id
 end
 
-function net_handling.proxy.proxy_opt_num(_, key, value)
-    ctx.proxy_opt[key] = tonumber(value)
-end
-
-function net_handling.proxy.proxy_opt_bool(_, key, value)
-    print("Proxy opt [bool]: " .. key .. " = " .. value)
-    ctx.proxy_opt[key] = value == "true"
+function net_handling.proxy.spectators(_, value)
+    local spectators = {}
+    if value ~= "-" then
+        for peer_id in string.gmatch(value, "[^,]+") do
+            spectators[peer_id] = true
+        end
+    end
+    for peer_id, _ in pairs(ctx.spectators) do
+        local player_data = ctx.players[peer_id]
+        if not spectators[peer_id] and player_data ~= nil then
+            GamePrint(player_data.name .. " is now a player")
+            player_fns.mark_seen(peer_id, player_data)
+        end
+    end
+    ctx.spectators = spectators
+    ctx.is_spectator = ctx.my_id ~= nil and spectators[ctx.my_id] == true
 end
 
 function net_handling.proxy.dc(_, peer_id)
@@ -485,6 +485,21 @@ function player_fns.get_player_data_by_local_entity_id(entity)
     return ctx.player_data_by_local_entity[entity]
 end
 
+-- Counts a player the first time they're seen in this run.
+function player_fns.mark_seen(peer_id, playerdata)
+    local global = "ew_seen_" .. peer_id
+    local seen = GlobalsGetValue(global, "0")
+    ctx.events.new_player_seen = seen ~= "1"
+    if ctx.events.new_player_seen then
+        local count = tonumber(GlobalsGetValue("ew_player_count", "1")) + 1
+        GlobalsSetValue("ew_player_count", tostring(count))
+        GamePrint("Player count " .. count)
+        print("Player count " .. count)
+        ctx.hook.on_new_player_seen(playerdata, count)
+    end
+    GlobalsSetValue(global, "1")
+end
+
 function player_fns.spawn_player_for(peer_id, x, y, existing_playerdata)
     if peer_id == ctx.my_id then
         util.print_traceback()
@@ -530,17 +545,10 @@ function player_fns.spawn_player_for(peer_id, x, y, existing_playerdata)
     ctx.player_data_by_local_entity[new] = new_playerdata
     ctx.events.new_player_just_spawned = true
 
-    local global = "ew_seen_" .. peer_id
-    local seen = GlobalsGetValue(global, "0")
-    ctx.events.new_player_seen = seen ~= "1"
-    if ctx.events.new_player_seen then
-        local count = tonumber(GlobalsGetValue("ew_player_count", "1")) + 1
-        GlobalsSetValue("ew_player_count", tostring(count))
-        GamePrint("Player count " .. count)
-        print("Player count " .. count)
-        ctx.hook.on_new_player_seen(new_playerdata, count)
+    -- Spectators are counted once they're promoted to players.
+    if not ctx.spectators[peer_id] then
+        player_fns.mark_seen(peer_id, new_playerdata)
     end
-    GlobalsSetValue(global, "1")
     -- np.SetPlayerEntity(new, peer_id+1)
     ctx.hook.on_client_spawned(peer_id, new_playerdata)
 end
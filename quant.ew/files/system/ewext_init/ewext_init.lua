@@ -20,7 +20,9 @@ function module.on_world_initialized()
     grid_world = tonumber(ffi.cast("intptr_t", grid_world))
     chunk_map = tonumber(ffi.cast("intptr_t", chunk_map))
     local material_list = tonumber(ffi.cast("intptr_t", world_ffi.get_material_ptr(0)))
-    ewext.init_particle_world_state(grid_world, chunk_map, material_list)
+    local construct_cell = tonumber(ffi.cast("intptr_t", world_ffi.construct_cell))
+    local remove_cell = tonumber(ffi.cast("intptr_t", world_ffi.remove_cell))
+    ewext.init_particle_world_state(grid_world, chunk_map, material_list, construct_cell, remove_cell)
     ewext.module_on_world_init()
 end
 
@@ -110,7 +110,7 @@ function nickname.add_label(player_entity, text, font_filename, scale, alpha)
 end
 
 function nickname.on_local_player_spawn(my_player)
-    if ctx.proxy_opt.name ~= nil then
+    if ctx.proxy_opt.name ~= nil and ctx.proxy_opt.name ~= "" then
         my_player.name = ctx.proxy_opt.name
     end
 end
@@ -124,7 +124,7 @@ end
 
 function nickname.on_should_send_updates()
     print("Should send nickname update")
-    if ctx.proxy_opt.name ~= nil then
+    if ctx.proxy_opt.name ~= nil and ctx.proxy_opt.name ~= "" then
         print("Sending name " .. ctx.proxy_opt.name)
         rpc.send_name(ctx.proxy_opt.name)
     end
@@ -132,11 +132,11 @@ end
 
 rpc.opts_reliable()
 function rpc.send_name(name, pong)
-    if name ~= nil then
+    if name ~= nil and name ~= "" then
         ctx.rpc_player_data.name = name
         nickname.add_label(ctx.rpc_player_data.entity, name, "data/fonts/font_pixel_white.xml", 0.75)
     end
-    if pong and ctx.proxy_opt.name ~= nil then
+    if pong and ctx.proxy_opt.name ~= nil and ctx.proxy_opt.name ~= "" then
         rpc.send_name(ctx.proxy_opt.name)
     end
 end
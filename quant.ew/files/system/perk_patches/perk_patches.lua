@@ -19,7 +19,7 @@ if ctx.proxy_opt.game_mode == "shared_health" then
     --ModLuaFileAppend("data/scripts/perks/perk_list.lua", "mods/quant.ew/files/system/perk_patches/append/perks_local.lua")
 end
 
-if not ctx.is_host or not ctx.proxy_opt.randomize_perks then
+if not ctx.has_host_duties or not ctx.proxy_opt.randomize_perks then
     --print("Hiding telekinesis")
     ModLuaFileAppend(
         "data/scripts/perks/perk_list.lua",
@@ -7,7 +7,8 @@ function module.on_world_update()
     for peer_id, player_data in pairs(ctx.players) do
         local x, y = EntityGetTransform(player_data.entity)
         if x ~= nil and y ~= nil then
-            net.proxy_send("peer_pos", peer_id .. " " .. x .. " " .. y)
+            local notplayer = EntityHasTag(player_data.entity, "ew_notplayer") and 1 or 0
+            net.proxy_send("peer_pos", peer_id .. " " .. x .. " " .. y .. " " .. notplayer)
         end
     end
 end
@@ -40,7 +40,7 @@ local function is_sync_item(ent_path)
 end
 
 util.add_cross_call("ew_spawn_hook_pre", function(ent_path, x, y)
-    if ctx.is_host then
+    if ctx.has_host_duties then
         if is_sync_item(ent_path) then
             local ent_id = EntityLoad(ent_path, x, y)
             ctx.cap.item_sync.globalize(ent_id, false)
@@ -58,7 +58,7 @@ util.add_cross_call("ew_spawn_hook_pre", function(ent_path, x, y)
 end)
 
 util.add_cross_call("ew_action_spawn_hook_pre", function()
-    return (not ctx.proxy_opt.item_dedup) or ctx.is_host
+    return (not ctx.proxy_opt.item_dedup) or ctx.has_host_duties
 end)
 
 util.add_cross_call("ew_action_spawn_hook", function(eid)
@@ -0,0 +1,66 @@
+-- Spectators only watch the run: their player can't move, act or get hurt, and nobody sees it.
+
+local module = {}
+
+-- Player entities that are currently disabled.
+local disabled = {}
+
+local function set_visible(entity, visible)
+    for _, sprite in ipairs(EntityGetComponentIncludingDisabled(entity, "SpriteComponent") or {}) do
+        EntitySetComponentIsEnabled(entity, sprite, visible)
+    end
+    for _, child in ipairs(EntityGetAllChildren(entity) or {}) do
+        if not EntityHasTag(child, "perk_entity") then
+            for _, sprite in ipairs(EntityGetComponentIncludingDisabled(child, "SpriteComponent") or {}) do
+                EntitySetComponentIsEnabled(child, sprite, visible)
+            end
+        end
+    end
+end
+
+local function disable_local_player(entity, disable)
+    local controls = EntityGetFirstComponentIncludingDisabled(entity, "ControlsComponent")
+    if controls ~= nil then
+        ComponentSetValue2(controls, "enabled", not disable)
+    end
+    local inv = EntityGetFirstComponentIncludingDisabled(entity, "Inventory2Component")
+    if inv ~= nil and disable then
+        ComponentSetValue2(inv, "mItemHolstered", true)
+    end
+    for _, child in ipairs(EntityGetAllChildren(entity, "ew_spectator_protection") or {}) do
+        EntityKill(child)
+    end
+    if disable then
+        local effect, effect_entity = GetGameEffectLoadTo(entity, "PROTECTION_ALL", true)
+        if effect ~= nil then
+            ComponentSetValue2(effect, "frames", -1)
+            EntityAddTag(effect_entity, "ew_spectator_protection")
+        end
+    end
+end
+
+local function update_player(peer_id, player_data)
+    local spectator = ctx.spectators[peer_id] == true
+    local entity = player_data.entity
+    if entity == nil or not EntityGetIsAlive(entity) or (disabled[entity] == true) == spectator then
+        return
+    end
+    disabled[entity] = spectator or nil
+    set_visible(entity, not spectator)
+    if peer_id == ctx.my_id then
+        disable_local_player(entity, spectator)
+    end
+end
+
+function module.on_world_update()
+    for peer_id, player_data in pairs(ctx.players) do
+        update_player(peer_id, player_data)
+    end
+    for entity, _ in pairs(disabled) do
+        if not EntityGetIsAlive(entity) then
+            disabled[entity] = nil
+        end
+    end
+end
+
+return module
@@ -10,6 +10,8 @@ local RPressed = false
 
 local unread_messages_counter = 0
 
+local KEY_CHAT = 2
+
 rpc.opts_everywhere()
 rpc.opts_reliable()
 
@@ -222,6 +224,17 @@ function rpc.text(msg, color, colorAlt)
     end
 end
 
+-- Chat sent from the lobby screen of the proxy.
+net.net_handling.proxy[KEY_CHAT] = function(_, value)
+    local sender, msg = value:match("^(.-)%z(.*)$")
+    if sender == nil or ModSettingGet("quant.ew.notext") then
+        return
+    end
+    GamePrint(sender .. ": " .. msg)
+    saveMessage(sender, msg)
+    unread_messages_counter = unread_messages_counter + 1
+end
+
 local function starttext()
     cursorPos = 0
 
@@ -246,7 +259,7 @@ local function stoptext()
     if g ~= nil then
         EntitySetComponentIsEnabled(ctx.my_player.entity, g, true)
     end
-    if not EntityHasTag(ctx.my_player.entity, "ew_notplayer") then
+    if not EntityHasTag(ctx.my_player.entity, "ew_notplayer") and not ctx.is_spectator then
         local controls = EntityGetFirstComponentIncludingDisabled(ctx.my_player.entity, "ControlsComponent")
         if controls ~= nil then
             ComponentSetValue2(controls, "enabled", true)
@@ -285,6 +298,7 @@ function module.on_world_update()
             end
             if non_white then
                 rpc.text(text, ctx.proxy_opt.mina_color, ctx.proxy_opt.mina_color_alt)
+                net.proxy_bin_send(KEY_CHAT, text)
             end
             stoptext()
         else
@@ -16,6 +16,9 @@ enum ENCODE_CONST {
     PIXEL_RUN_MAX = 16385,
 
     LIQUID_FLAG_STATIC = 1,
+    // Set by ewext.encode_area.
+    FLAG_BURNING = 2,
+    FLAG_BOX2D = 4,
 };
 
 struct __attribute__ ((__packed__)) EncodedAreaHeader {
@@ -215,105 +218,4 @@ function world.encode_area(chunk_map, start_x_ini, start_y_ini, end_x_ini, end_y
     return encoded_area
 end
 
---local PixelRun_const_ptr = ffi.typeof("struct PixelRun const*")
-
---- Load an encoded area back into the world.
--- @param grid_world
--- @tparam EncodedAreaHeader header header of the encoded area
--- @param received pointer or ffi array of PixelRun from the encoded area
--- @see encode_area
-function world.decode(grid_world, header, pixel_runs)
-    local chunk_map = grid_world.vtable.get_chunk_map(grid_world)
-
-    local top_left_x = header.x
-    local top_left_y = header.y
-    local width = header.width + 1
-    local height = header.height + 1
-    local bottom_right_x = top_left_x + width
-    local bottom_right_y = top_left_y + height
-
-    local current_run_ix = 0
-    local current_run = pixel_runs[current_run_ix]
-    local new_material = current_run.material
-    local flags = current_run.flags
-    local left = current_run.length + 1
-
-    local y = top_left_y
-    while y < bottom_right_y do
-        local x = top_left_x
-        while x < bottom_right_x do
-            if world_ffi.chunk_loaded(chunk_map, x, y) then
-                local ppixel = world_ffi.get_cell(chunk_map, x, y)
-
-                local current_material = 0
-
-                if new_material == -1 then
-                    goto next_pixel
-                end
-
-                if ppixel[0] ~= nil then
-                    local pixel = ppixel[0]
-                    local cell_type = pixel.vtable.get_cell_type(pixel)
-                    if cell_type == C.CELL_TYPE_SOLID then
-                        goto next_pixel
-                    end
-                    current_material = world_ffi.get_material_id(pixel.vtable.get_material(pixel))
-
-                    if new_material ~= current_material then
-                        world_ffi.remove_cell(grid_world, pixel, x, y, false)
-                    end
-                end
-
-                if current_material ~= new_material and new_material ~= 0 then
-                    if new_material > world.last_material_id then
-                        goto next_pixel
-                    end
-                    local mat_ptr = world_ffi.get_material_ptr(new_material)
-                    if mat_ptr == nil then
-                        goto next_pixel
-                    end
-                    local pixel = world_ffi.construct_cell(grid_world, x, y, mat_ptr, nil)
-                    if pixel == nil then
-                        -- TODO: This can happen when the material texture has a
-                        -- transparent pixel at the given coordinate. There's
-                        -- probably a better way to deal with this, but for now
-                        -- we skip positions like this.
-                        goto next_pixel
-                    end
-
-                    local cell_type = pixel.vtable.get_cell_type(pixel)
-
-                    if cell_type == C.CELL_TYPE_LIQUID then
-                        local liquid_cell = ffi.cast(pliquid_cell, pixel)
-                        liquid_cell.is_static = bit.band(flags, C.CELL_TYPE_LIQUID) == C.LIQUID_FLAG_STATIC
-                    end
-
-                    ppixel[0] = pixel
-                end
-            end
-
-            ::next_pixel::
-
-            left = left - 1
-            if left <= 0 then
-                current_run_ix = current_run_ix + 1
-                if current_run_ix >= header.pixel_run_count then
-                    -- No more runs, done
-                    assert(x == bottom_right_x - 1)
-                    assert(y == bottom_right_y - 1)
-                    return
-                end
-
-                current_run = pixel_runs[current_run_ix]
-                new_material = current_run.material
-                flags = current_run.flags
-                left = current_run.length + 1
-            end
-
-            x = x + 1
-        end
-        y = y + 1
-    end
-end
-
 return world
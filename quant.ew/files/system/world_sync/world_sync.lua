@@ -203,13 +203,25 @@ function world_sync.on_world_update()
     end
 end
 
-local PixelRun_const_ptr = ffi.typeof("struct PixelRun const*")
-
 function world_sync.handle_world_data(datum)
-    local grid_world = world_ffi.get_grid_world()
-    local header = ffi.cast("struct EncodedAreaHeader const*", ffi.cast("char const*", datum))
-    local runs = ffi.cast(PixelRun_const_ptr, ffi.cast("const char*", datum) + ffi.sizeof(world.EncodedAreaHeader))
-    world.decode(grid_world, header, runs)
+    local min_x, min_y, max_x, max_y = ewext.decode_area(datum)
+    if min_x ~= nil and ctx.proxy_opt.debug then
+        -- Mark chunks that were changed by other players.
+        for cy = math.floor(min_y / CHUNK_SIZE), math.floor(max_y / CHUNK_SIZE) do
+            for cx = math.floor(min_x / CHUNK_SIZE), math.floor(max_x / CHUNK_SIZE) do
+                GameCreateSpriteForXFrames(
+                    "mods/quant.ew/files/resource/debug/box_128x128.png",
+                    cx * CHUNK_SIZE + 64,
+                    cy * CHUNK_SIZE + 64,
+                    true,
+                    0,
+                    0,
+                    11,
+                    true
+                )
+            end
+        end
+    end
 end
 
 net.net_handling.proxy[0] = function(_, value)
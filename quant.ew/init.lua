@@ -109,6 +109,7 @@ local function load_modules()
     ctx.load_system("flag_sync")
     ctx.load_system("essence_sync")
     ctx.load_system("spectate")
+    ctx.load_system("spectator_role")
     ctx.load_system("effect_data_sync")
     if ctx.proxy_opt.item_dedup then
         -- ctx.load_system("gen_sync")
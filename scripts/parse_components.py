@@ -1,3 +1,4 @@
+import re
 import shlex
 import json
 
@@ -7,6 +8,82 @@ renames = {
     "std_string": "std::string",
 }
 
+# Variants of enums used by component fields, component documentation only has the default value of each field.
+# Enums that aren't listed here are accessed as strings.
+# GAME_EFFECT is defined in `shared` instead.
+enum_variants = {
+    "ARC_TYPE": ["LIGHTNING", "MATERIAL", "DISCHARGE"],
+    "DAMAGE_TYPES": [
+        "NONE", "DAMAGE_MELEE", "DAMAGE_PROJECTILE", "DAMAGE_EXPLOSION", "DAMAGE_BITE", "DAMAGE_FIRE",
+        "DAMAGE_MATERIAL", "DAMAGE_FALL", "DAMAGE_ELECTRICITY", "DAMAGE_DROWNING", "DAMAGE_PHYSICS_BODY_DAMAGED",
+        "DAMAGE_DRILL", "DAMAGE_SLICE", "DAMAGE_ICE", "DAMAGE_HEALING", "DAMAGE_PHYSICS_HIT", "DAMAGE_RADIOACTIVE",
+        "DAMAGE_POISON", "DAMAGE_MATERIAL_WITH_FLASH", "DAMAGE_OVEREATING", "DAMAGE_CURSE", "DAMAGE_HOLY",
+    ],
+    "HIT_EFFECT": [
+        "NONE", "CRITICAL_HIT_BOOST", "LOAD_ENTITY", "LOAD_UNIQUE_CHILD_ENTITY", "LOAD_CHILD_ENTITY", "INSTANT_DEATH",
+    ],
+    "INVENTORY_KIND": ["NONE", "QUICK", "FULL"],
+    "JOINT_TYPE": [
+        "REVOLUTE_JOINT", "WELD_JOINT", "REVOLUTE_JOINT_ATTACH_TO_NEARBY_SURFACE", "WELD_JOINT_ATTACH_TO_NEARBY_SURFACE",
+    ],
+    "LUA_VM_TYPE": ["SHARED_BY_MANY_COMPONENTS", "CREATE_NEW_EVERY_EXECUTION", "ONE_PER_COMPONENT_INSTANCE"],
+    "RAGDOLL_FX": [
+        "NONE", "NORMAL", "BLOOD_NOTHING", "BLOOD_SPRAY", "BLOOD_EXPLOSION", "CONVERT_TO_MATERIAL",
+        "CUSTOM_RAGDOLL_ENTITY", "DISINTEGRATED", "NO_RAGDOLL_FILE", "PLAYER_RAGDOLL_CAMERA",
+    ],
+    "VERLET_TYPE": ["CHAIN", "CLOTH"],
+}
+
+# Fields of config objects nested in components, component documentation doesn't list them.
+# Objects that aren't listed here aren't saved by the entity serializer.
+object_fields = {
+    "ConfigDamageCritical": [("chance", "int"), ("damage_multiplier", "float")],
+    "ConfigDamagesByType": [
+        (name, "float") for name in [
+            "melee", "projectile", "explosion", "electricity", "fire", "drill", "slice", "ice", "healing",
+            "physics_hit", "radioactive", "poison", "overeating", "curse", "holy",
+        ]
+    ],
+    "ConfigExplosion": [
+        ("never_cache", "bool"), ("explosion_radius", "float"), ("explosion_sprite", "std::string"),
+        ("explosion_sprite_lifetime", "float"), ("explosion_sprite_emissive", "bool"),
+        ("explosion_sprite_additive", "bool"), ("damage", "float"), ("damage_mortals", "bool"),
+        ("is_digger", "bool"), ("hole_enabled", "bool"), ("hole_destroy_liquid", "bool"),
+        ("create_cell_material", "std::string"), ("create_cell_probability", "int"), ("ray_energy", "int"),
+        ("max_durability_to_destroy", "int"), ("load_this_entity", "std::string"), ("light_enabled", "bool"),
+        ("stains_enabled", "bool"), ("stains_radius", "float"), ("shake_vegetation", "bool"),
+        ("physics_throw_enabled", "bool"), ("sparks_enabled", "bool"), ("audio_enabled", "bool"),
+        ("audio_event_name", "std::string"), ("camera_shake", "float"),
+    ],
+    "ConfigGun": [
+        ("actions_per_round", "int"), ("shuffle_deck_when_empty", "bool"), ("reload_time", "int"),
+        ("deck_capacity", "int"),
+    ],
+    "ConfigGunActionInfo": [
+        ("action_id", "std::string"), ("action_mana_drain", "float"), ("action_draw_many_count", "int"),
+        ("fire_rate_wait", "int"), ("speed_multiplier", "float"), ("child_speed_multiplier", "float"),
+        ("dampening", "float"), ("explosion_radius", "float"), ("spread_degrees", "float"),
+        ("pattern_degrees", "float"), ("screenshake", "float"), ("recoil", "float"),
+        ("damage_melee_add", "float"), ("damage_projectile_add", "float"), ("damage_electricity_add", "float"),
+        ("damage_fire_add", "float"), ("damage_explosion_add", "float"), ("damage_ice_add", "float"),
+        ("damage_slice_add", "float"), ("damage_healing_add", "float"), ("damage_curse_add", "float"),
+        ("damage_drill_add", "float"), ("damage_null_all", "float"), ("damage_critical_chance", "int"),
+        ("damage_critical_multiplier", "float"), ("explosion_damage_to_materials", "float"),
+        ("knockback_force", "float"), ("reload_time", "int"), ("lightning_count", "int"),
+        ("material", "std::string"), ("material_amount", "int"), ("trail_material", "std::string"),
+        ("trail_material_amount", "int"), ("bounces", "int"), ("gravity", "float"), ("light", "float"),
+        ("blood_count_multiplier", "float"), ("gore_particles", "int"), ("friendly_fire", "bool"),
+        ("physics_impulse_coeff", "float"), ("lifetime_add", "int"), ("sprite", "std::string"),
+        ("extra_entities", "std::string"), ("game_effect_entities", "std::string"),
+        ("sound_loop_tag", "std::string"), ("projectile_file", "std::string"),
+    ],
+    "ConfigLaser": [
+        ("max_length", "float"), ("beam_radius", "float"), ("beam_particle_chance", "int"),
+        ("beam_particle_fade", "int"), ("hit_particle_chance", "int"), ("audio_enabled", "bool"),
+        ("damage_to_entities", "float"), ("damage_to_cells", "float"), ("max_cell_durability_to_destroy", "int"),
+    ],
+}
+
 def parse_component(component):
     it = iter(component)
     c_name = next(it)
@@ -20,6 +97,9 @@ def parse_component(component):
         line = line.strip()
         if line.startswith("-"):
             continue
+        # Types with spaces in them would otherwise get split into type and name.
+        line = re.sub(r"std::vector<\s*(\w+)\s*>", r"std::vector<\1>", line)
+        line = re.sub(r"^unsigned int\b", "uint32", line)
         typ, name, *range_info, desc = shlex.split(line)
         name = name.strip("\n")
         if name == "-":
@@ -57,5 +137,24 @@ assert not current
 parsed = [parse_component(component) for component in components]
 json.dump(parsed, open("ewext/noita_api_macro/src/components.json", "w"), indent=None)
 
+used_enums = sorted({typ.removesuffix("::Enum") for typ in all_types if typ.endswith("::Enum")})
+enums = []
+for name in used_enums:
+    if name in enum_variants:
+        enums.append({"name": name, "variants": enum_variants[name]})
+    elif name != "GAME_EFFECT":
+        print(f"Variants of {name} aren't known, it will be accessed as a string")
+json.dump(enums, open("ewext/noita_api_macro/src/enums.json", "w"), indent=4)
+
+used_objects = sorted({typ for typ in all_types if typ.startswith("Config")})
+objects = []
+for name in used_objects:
+    if name in object_fields:
+        fields = [{"field": field, "typ": typ, "desc": ""} for field, typ in object_fields[name]]
+        objects.append({"name": name, "fields": fields})
+    else:
+        print(f"Fields of {name} aren't known, it won't be serialized")
+json.dump(objects, open("ewext/noita_api_macro/src/objects.json", "w"), indent=4)
+
 #print(*all_types, sep="\n")
 
@@ -1,12 +1,17 @@
-use std::sync::Arc;
-
 use bitcode::{Decode, Encode};
 
 use crate::{GameEffectData, PeerId, WorldPos};
 
+pub mod serialized;
+pub mod tracking_rules;
+
+pub use tracking_rules::SyncMode;
+
 pub const REQUEST_AUTHORITY_RADIUS: i32 = 400;
 pub const AUTHORITY_RADIUS: f32 = 600.0;
 pub const INTEREST_REQUEST_RADIUS: i32 = 900;
+/// Authority over an entity is handed off to a different peer only if they are this much closer to it than the current authority.
+pub const AUTHORITY_HANDOFF_HYSTERESIS: f32 = 128.0;
 
 /// 64 bit globally unique id. Assigned randomly, should only have 50% chance of collision with 2^32 entities at once.
 #[derive(Debug, Encode, Decode, Clone, Copy, Hash, PartialEq, Eq)]
@@ -42,12 +47,20 @@ pub enum DesToProxy {
     ReleaseAuthority(Gid),
     RequestAuthority { pos: WorldPos, radius: i32 },
     UpdatePositions(Vec<UpdatePosition>),
+    /// Authority is being handed off to a different peer, which will send `InitOrUpdateEntity` once it adopts the entity.
+    TransferAuthority { gid: Gid, to: PeerId },
 }
 
 #[derive(Encode, Decode, Clone)]
 pub enum ProxyToDes {
     /// Got authority over entity.
     GotAuthority(FullEntityData),
+    /// Lobby-specific tracking rules, in the format of `tracking_rules::TrackingRules::parse`.
+    SetTrackingRules(String),
+    /// Whether we are a spectator, which never takes authority over entities.
+    SetSpectator(bool),
+    /// Authority over entity was taken away by the host, so it has to be removed instead of synced further.
+    LostAuthority(Gid),
 }
 
 #[derive(Encode, Decode, Clone)]
@@ -78,6 +91,7 @@ pub enum EntityKind {
 pub struct EntityInfo {
     pub spawn_info: EntitySpawnInfo,
     pub kind: EntityKind,
+    pub sync_mode: SyncMode,
     pub x: f32,
     pub y: f32,
     pub vx: f32,
@@ -102,6 +116,13 @@ pub enum EntityUpdate {
     // TODO...
     RemoveEntity(Lid),
     LocalizeEntity(Lid, PeerId),
+    /// Only sent to the peer that becomes the new authority, others get `LocalizeEntity` instead.
+    /// Carries the latest state, as the new authority might have missed some of the updates.
+    TransferAuthority {
+        lid: Lid,
+        gid: Gid,
+        info: EntityInfo,
+    },
     KillEntity {
         lid: Lid,
         responsible_peer: Option<PeerId>,
@@ -116,14 +137,31 @@ pub enum RemoteDes {
     InterestRequest(InterestRequest),
     EntityUpdate(Vec<EntityUpdate>),
     ExitedInterest,
-    Projectiles(Arc<Vec<ProjectileFired>>),
+    Projectiles(ProjectileBatch),
     RequestGrab(Lid),
 }
 
+#[derive(Debug, Encode, Decode, Clone)]
+pub enum ProjectileData {
+    /// Serialized projectile, which the receiver should cache under this hash.
+    Full { hash: u64, serialized: Vec<u8> },
+    /// Projectile that was already sent to this peer before.
+    Cached(u64),
+}
+
 #[derive(Debug, Encode, Decode, Clone)]
 pub struct ProjectileFired {
     pub shooter_lid: Lid,
     pub position: (f32, f32),
+    pub velocity: (f32, f32),
     pub target: (f32, f32),
-    pub serialized: Vec<u8>,
+    /// Projectile without its position and velocity, so that it's the same for every shot.
+    pub data: ProjectileData,
+}
+
+#[derive(Debug, Encode, Decode, Clone)]
+pub struct ProjectileBatch {
+    /// Receiver should forget all cached projectiles before handling this batch.
+    pub reset_cache: bool,
+    pub projectiles: Vec<ProjectileFired>,
 }
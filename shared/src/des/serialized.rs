@@ -0,0 +1,379 @@
+//! Format of entities serialized by ewext, used for `EntitySpawnInfo::Serialized`.
+//!
+//! Layout: [`MAGIC`], format version as a little endian u16, then bitcode encoded `Vec<EntityNode>`:
+//! the entity and all of its descendants in pre-order, each with the index of its parent (root's is 0).
+//! The layout of a given version never changes. A new version is added instead, and older ones get converted to it in [`SerializedEntity::decode`],
+//! as serialized entities are kept in save states.
+//!
+//! Data without the magic was made by the engine serializer (`np.SerializeEntity`), which is still used for projectiles
+//! and is found in older save states. It can only be spawned, not inspected.
+
+use std::fmt::{self, Display};
+
+use bitcode::{Decode, Encode};
+use eyre::{bail, eyre, Context, OptionExt};
+
+pub const MAGIC: [u8; 4] = *b"EWSE";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerializedEntity {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub transform: Transform,
+    pub components: Vec<SerializedComponent>,
+    pub children: Vec<SerializedEntity>,
+}
+
+/// What's actually encoded, as bitcode can't encode recursive types.
+#[derive(Encode, Decode)]
+struct EntityNode {
+    parent: u32,
+    name: String,
+    tags: Vec<String>,
+    transform: Transform,
+    components: Vec<SerializedComponent>,
+}
+
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub x: f32,
+    pub y: f32,
+    pub rotation: f32,
+    pub scale_x: f32,
+    pub scale_y: f32,
+}
+
+/// Fields of config objects are saved as `object.field`. Fields of types that can't be read from lua
+/// keep the values the component gets when it's created.
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+pub struct SerializedComponent {
+    pub type_name: String,
+    pub tags: Vec<String>,
+    pub enabled: bool,
+    pub fields: Vec<(String, FieldValue)>,
+}
+
+/// Value of a component field. Enums are stored as strings.
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+pub enum FieldValue {
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Int64(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Vec2(f32, f32),
+    IVec2(i32, i32),
+    Aabb(f32, f32, f32, f32),
+    Floats(Vec<f32>),
+    Ints(Vec<i32>),
+    Strings(Vec<String>),
+    /// Reference to another entity, as its index in the serialized tree in pre-order (the root is 0).
+    /// None if the field was empty or pointed outside of the tree.
+    Entity(Option<u32>),
+}
+
+impl SerializedEntity {
+    /// Checks if data was made by this serializer, as opposed to the old lua one.
+    pub fn is_native(data: &[u8]) -> bool {
+        data.starts_with(&MAGIC)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        fn flatten(nodes: &mut Vec<EntityNode>, ent: &SerializedEntity, parent: u32) {
+            let index = nodes.len() as u32;
+            nodes.push(EntityNode {
+                parent,
+                name: ent.name.clone(),
+                tags: ent.tags.clone(),
+                transform: ent.transform,
+                components: ent.components.clone(),
+            });
+            for child in &ent.children {
+                flatten(nodes, child, index);
+            }
+        }
+        let mut nodes = Vec::new();
+        flatten(&mut nodes, self, 0);
+
+        let mut res = MAGIC.to_vec();
+        res.extend_from_slice(&VERSION.to_le_bytes());
+        res.extend_from_slice(&bitcode::encode(&nodes));
+        res
+    }
+
+    pub fn decode(data: &[u8]) -> eyre::Result<Self> {
+        let data = data
+            .strip_prefix(&MAGIC)
+            .ok_or_eyre("Not a natively serialized entity")?;
+        let (version, data) = data
+            .split_first_chunk::<2>()
+            .ok_or_eyre("Serialized entity is truncated")?;
+        let nodes: Vec<EntityNode> = match u16::from_le_bytes(*version) {
+            VERSION => bitcode::decode(data).wrap_err("Failed to decode serialized entity")?,
+            version => bail!("Unknown serialized entity version {version}"),
+        };
+        Self::unflatten(nodes)
+    }
+
+    fn unflatten(nodes: Vec<EntityNode>) -> eyre::Result<Self> {
+        let mut parents = Vec::with_capacity(nodes.len());
+        let mut entities: Vec<_> = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(i, node)| {
+                if i > 0 && node.parent as usize >= i {
+                    bail!("Serialized entity node {i} comes before its parent");
+                }
+                parents.push(node.parent as usize);
+                Ok(SerializedEntity {
+                    name: node.name,
+                    tags: node.tags,
+                    transform: node.transform,
+                    components: node.components,
+                    children: Vec::new(),
+                })
+            })
+            .collect::<eyre::Result<_>>()?;
+        if entities.is_empty() {
+            bail!("Serialized entity has no nodes");
+        }
+        // Children always come after their parents, so going backwards moves every subtree before its parent is moved.
+        for i in (1..entities.len()).rev() {
+            let child = entities.pop().ok_or_eyre("entity to exist")?;
+            entities[parents[i]].children.insert(0, child);
+        }
+        entities.pop().ok_or_eyre("root entity to exist")
+    }
+
+    /// Changes a field of a component, parsing `value` as the type the field already has.
+    /// `path` is either "Component.field" for the first component of that type, or "Component[i].field" for the i-th one.
+    pub fn set_field(&mut self, path: &str, value: &str) -> eyre::Result<()> {
+        let (component, field) = path
+            .split_once('.')
+            .ok_or_else(|| eyre!("Expected Component.field, got {path}"))?;
+        let (type_name, index) = match component.split_once('[') {
+            Some((type_name, index)) => (
+                type_name,
+                index
+                    .strip_suffix(']')
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .ok_or_else(|| eyre!("Invalid component index in {path}"))?,
+            ),
+            None => (component, 0),
+        };
+        let component = self
+            .components
+            .iter_mut()
+            .filter(|com| com.type_name == type_name)
+            .nth(index)
+            .ok_or_else(|| eyre!("Entity doesn't have {type_name}[{index}]"))?;
+        let (_, old) = component
+            .fields
+            .iter_mut()
+            .find(|(name, _)| name == field)
+            .ok_or_else(|| eyre!("{type_name} doesn't have a saved field {field}"))?;
+        *old = old
+            .parse_same_type(value)
+            .wrap_err_with(|| eyre!("Failed to parse value for {path}"))?;
+        Ok(())
+    }
+}
+
+impl FieldValue {
+    /// Parses `s` into a value of the same type as this one.
+    /// Tuples and lists are comma separated.
+    pub fn parse_same_type(&self, s: &str) -> eyre::Result<Self> {
+        fn list<T: std::str::FromStr>(s: &str) -> eyre::Result<Vec<T>>
+        where
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            if s.trim().is_empty() {
+                return Ok(Vec::new());
+            }
+            s.split(',').map(|part| Ok(part.trim().parse()?)).collect()
+        }
+        fn exactly<T, const N: usize>(v: Vec<T>) -> eyre::Result<[T; N]> {
+            let len = v.len();
+            v.try_into()
+                .map_err(|_| eyre!("Expected {N} comma separated values, got {len}"))
+        }
+        Ok(match self {
+            FieldValue::Bool(_) => FieldValue::Bool(s.trim().parse()?),
+            FieldValue::Int(_) => FieldValue::Int(s.trim().parse()?),
+            FieldValue::UInt(_) => FieldValue::UInt(s.trim().parse()?),
+            FieldValue::Int64(_) => FieldValue::Int64(s.trim().parse()?),
+            FieldValue::Float(_) => FieldValue::Float(s.trim().parse()?),
+            FieldValue::Double(_) => FieldValue::Double(s.trim().parse()?),
+            FieldValue::String(_) => FieldValue::String(s.to_owned()),
+            FieldValue::Vec2(..) => {
+                let [x, y] = exactly(list(s)?)?;
+                FieldValue::Vec2(x, y)
+            }
+            FieldValue::IVec2(..) => {
+                let [x, y] = exactly(list(s)?)?;
+                FieldValue::IVec2(x, y)
+            }
+            FieldValue::Aabb(..) => {
+                let [min_x, min_y, max_x, max_y] = exactly(list(s)?)?;
+                FieldValue::Aabb(min_x, min_y, max_x, max_y)
+            }
+            FieldValue::Floats(_) => FieldValue::Floats(list(s)?),
+            FieldValue::Ints(_) => FieldValue::Ints(list(s)?),
+            FieldValue::Strings(_) => {
+                FieldValue::Strings(s.split(',').map(|s| s.to_owned()).collect())
+            }
+            FieldValue::Entity(_) => FieldValue::Entity(match s.trim() {
+                "none" => None,
+                index => Some(index.parse()?),
+            }),
+        })
+    }
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T: Display>(f: &mut fmt::Formatter<'_>, values: &[T]) -> fmt::Result {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{value}")?;
+            }
+            Ok(())
+        }
+        match self {
+            FieldValue::Bool(v) => write!(f, "{v}"),
+            FieldValue::Int(v) => write!(f, "{v}"),
+            FieldValue::UInt(v) => write!(f, "{v}"),
+            FieldValue::Int64(v) => write!(f, "{v}"),
+            FieldValue::Float(v) => write!(f, "{v}"),
+            FieldValue::Double(v) => write!(f, "{v}"),
+            FieldValue::String(v) => write!(f, "{v}"),
+            FieldValue::Vec2(x, y) => write!(f, "{x},{y}"),
+            FieldValue::IVec2(x, y) => write!(f, "{x},{y}"),
+            FieldValue::Aabb(min_x, min_y, max_x, max_y) => {
+                write!(f, "{min_x},{min_y},{max_x},{max_y}")
+            }
+            FieldValue::Floats(v) => list(f, v),
+            FieldValue::Ints(v) => list(f, v),
+            FieldValue::Strings(v) => list(f, v),
+            FieldValue::Entity(Some(index)) => write!(f, "{index}"),
+            FieldValue::Entity(None) => write!(f, "none"),
+        }
+    }
+}
+
+impl Display for SerializedEntity {
+    /// Human readable tree of the entity, its components and children.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn entity(f: &mut fmt::Formatter<'_>, ent: &SerializedEntity, depth: usize) -> fmt::Result {
+            let indent = "  ".repeat(depth);
+            let Transform {
+                x,
+                y,
+                rotation,
+                scale_x,
+                scale_y,
+            } = ent.transform;
+            writeln!(
+                f,
+                "{indent}Entity {:?} [{}] at {x},{y} rotation {rotation} scale {scale_x},{scale_y}",
+                ent.name,
+                ent.tags.join(",")
+            )?;
+            for com in &ent.components {
+                writeln!(
+                    f,
+                    "{indent}  {}{} [{}]",
+                    com.type_name,
+                    if com.enabled { "" } else { " (disabled)" },
+                    com.tags.join(",")
+                )?;
+                for (name, value) in &com.fields {
+                    writeln!(f, "{indent}    {name} = {value}")?;
+                }
+            }
+            for child in &ent.children {
+                entity(f, child, depth + 1)?;
+            }
+            Ok(())
+        }
+        entity(f, self, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entity() -> SerializedEntity {
+        SerializedEntity {
+            name: "rat".into(),
+            tags: vec!["enemy".into()],
+            transform: Transform {
+                x: 1.0,
+                y: 2.0,
+                rotation: 0.0,
+                scale_x: 1.0,
+                scale_y: 1.0,
+            },
+            components: vec![SerializedComponent {
+                type_name: "DamageModelComponent".into(),
+                tags: Vec::new(),
+                enabled: true,
+                fields: vec![
+                    ("hp".into(), FieldValue::Double(4.0)),
+                    ("mVelocity".into(), FieldValue::Vec2(0.0, 0.0)),
+                    ("mLastDamageSource".into(), FieldValue::Entity(None)),
+                ],
+            }],
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut entity = entity();
+        let mut child = entity.clone();
+        child.name = "child".into();
+        child.children.push(entity.clone());
+        entity.children.push(child);
+        entity.children.push(entity.clone());
+        let data = entity.encode();
+        assert!(SerializedEntity::is_native(&data));
+        assert_eq!(SerializedEntity::decode(&data).unwrap(), entity);
+        assert!(SerializedEntity::decode(&data[..5]).is_err());
+    }
+
+    #[test]
+    fn set_field() {
+        let mut entity = entity();
+        entity.set_field("DamageModelComponent.hp", "10").unwrap();
+        entity
+            .set_field("DamageModelComponent[0].mVelocity", "1.5, -2")
+            .unwrap();
+        assert_eq!(
+            entity.components[0].fields,
+            [
+                ("hp".into(), FieldValue::Double(10.0)),
+                ("mVelocity".into(), FieldValue::Vec2(1.5, -2.0)),
+                ("mLastDamageSource".into(), FieldValue::Entity(None)),
+            ]
+        );
+        entity
+            .set_field("DamageModelComponent.mLastDamageSource", "1")
+            .unwrap();
+        assert_eq!(
+            entity.components[0].fields[2].1,
+            FieldValue::Entity(Some(1))
+        );
+        assert!(entity.set_field("DamageModelComponent.hp", "lots").is_err());
+        assert!(entity.set_field("DamageModelComponent[1].hp", "1").is_err());
+        assert!(entity
+            .set_field("DamageModelComponent.unknown", "1")
+            .is_err());
+    }
+}
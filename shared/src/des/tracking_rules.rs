@@ -0,0 +1,238 @@
+//! Data-driven rules that decide which entities are tracked by DES.
+//!
+//! Rules are written one per line:
+//!
+//! ```text
+//! # Comments start with '#'.
+//! include tag=enemy
+//! exclude filename=data/entities/items/pickup/heart*.xml
+//! include component=DamageModelComponent tag=boss sync=position_only
+//! ```
+//!
+//! Every matcher of a rule has to match for the rule to apply.
+//! Rules are evaluated in order and the last matching rule wins,
+//! which allows rules that are added later (by other mods or by lobby settings) to override earlier ones.
+
+use bitcode::{Decode, Encode};
+use eyre::{bail, eyre, Context};
+
+/// Path of the rules file in the mod. Other mods can append their rules to it with `ModTextFileSetContent`.
+pub const TRACKING_RULES_FILE: &str =
+    "mods/quant.ew/files/system/entity_sync_helper/tracking_rules.txt";
+
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Everything DES knows about is synced.
+    #[default]
+    Full,
+    /// Only position and velocity are synced.
+    PositionOnly,
+    /// Entity is only spawned on remotes, and is left alone afterwards.
+    SpawnOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleMatcher {
+    /// Matches entity filename against a glob, where `*` matches any sequence of characters and `?` matches any single character.
+    Filename(String),
+    Tag(String),
+    /// Matches if entity has a component of this type, even if it's disabled.
+    Component(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleAction {
+    Include(SyncMode),
+    Exclude,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackingRule {
+    pub action: RuleAction,
+    pub matchers: Vec<RuleMatcher>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackingRules {
+    pub rules: Vec<TrackingRule>,
+}
+
+impl TrackingRule {
+    fn parse(line: &str) -> eyre::Result<Self> {
+        let mut words = line.split_whitespace();
+        let action = words.next().ok_or_else(|| eyre!("Empty rule"))?;
+        let mut sync_mode = None;
+        let mut matchers = Vec::new();
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| eyre!("Expected key=value, got {word:?}"))?;
+            if value.is_empty() {
+                bail!("Empty value for {key:?}");
+            }
+            match key {
+                "filename" => matchers.push(RuleMatcher::Filename(value.to_owned())),
+                "tag" => matchers.push(RuleMatcher::Tag(value.to_owned())),
+                "component" => matchers.push(RuleMatcher::Component(value.to_owned())),
+                "sync" => {
+                    sync_mode = Some(match value {
+                        "full" => SyncMode::Full,
+                        "position_only" => SyncMode::PositionOnly,
+                        "spawn_only" => SyncMode::SpawnOnly,
+                        _ => bail!("Unknown sync mode {value:?}"),
+                    })
+                }
+                _ => bail!("Unknown rule key {key:?}"),
+            }
+        }
+        if matchers.is_empty() {
+            bail!("Rule has nothing to match on");
+        }
+        let action = match action {
+            "include" => RuleAction::Include(sync_mode.unwrap_or_default()),
+            "exclude" if sync_mode.is_some() => bail!("Exclude rules can't have a sync mode"),
+            "exclude" => RuleAction::Exclude,
+            _ => bail!("Unknown rule action {action:?}"),
+        };
+        Ok(Self { action, matchers })
+    }
+
+    fn matches(
+        &self,
+        filename: &str,
+        has_tag: &impl Fn(&str) -> bool,
+        has_component: &impl Fn(&str) -> bool,
+    ) -> bool {
+        self.matchers.iter().all(|matcher| match matcher {
+            RuleMatcher::Filename(pattern) => glob_match(pattern, filename),
+            RuleMatcher::Tag(tag) => has_tag(tag),
+            RuleMatcher::Component(component) => has_component(component),
+        })
+    }
+}
+
+impl TrackingRules {
+    pub fn parse(text: &str) -> eyre::Result<Self> {
+        let rules = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                TrackingRule::parse(line)
+                    .wrap_err_with(|| format!("Invalid rule on line {}", i + 1))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Adds rules that take priority over the current ones.
+    pub fn extend(&mut self, other: TrackingRules) {
+        self.rules.extend(other.rules);
+    }
+
+    /// Returns how an entity should be synced, or None if it shouldn't be tracked at all.
+    pub fn evaluate(
+        &self,
+        filename: &str,
+        has_tag: impl Fn(&str) -> bool,
+        has_component: impl Fn(&str) -> bool,
+    ) -> Option<SyncMode> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(filename, &has_tag, &has_component))
+            .and_then(|rule| match rule.action {
+                RuleAction::Include(sync_mode) => Some(sync_mode),
+                RuleAction::Exclude => None,
+            })
+    }
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    // Position of last '*' in pattern and the text position it was tried at.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                let Some((star_p, star_t)) = backtrack else {
+                    return false;
+                };
+                p = star_p + 1;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match(
+            "data/entities/*.xml",
+            "data/entities/animals/rat.xml"
+        ));
+        assert!(glob_match("heart?.xml", "heart2.xml"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("heart?.xml", "heart.xml"));
+        assert!(!glob_match("data/*.xml", "data/entities/rat.xml.bak"));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = TrackingRules::parse(
+            "include tag=enemy\n\
+             exclude tag=enemy filename=data/entities/animals/boss_*.xml # bosses are handled elsewhere\n\
+             include component=ItemComponent sync=spawn_only",
+        )
+        .unwrap();
+        let eval = |filename: &str, tags: &[&str], components: &[&str]| {
+            rules.evaluate(
+                filename,
+                |tag| tags.contains(&tag),
+                |com| components.contains(&com),
+            )
+        };
+        assert_eq!(
+            eval("data/entities/animals/rat.xml", &["enemy"], &[]),
+            Some(SyncMode::Full)
+        );
+        assert_eq!(
+            eval("data/entities/animals/boss_limbs.xml", &["enemy"], &[]),
+            None
+        );
+        assert_eq!(
+            eval(
+                "data/entities/animals/boss_limbs.xml",
+                &["enemy"],
+                &["ItemComponent"]
+            ),
+            Some(SyncMode::SpawnOnly)
+        );
+        assert_eq!(eval("data/entities/props/barrel.xml", &[], &[]), None);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(TrackingRules::parse("include").is_err());
+        assert!(TrackingRules::parse("track tag=enemy").is_err());
+        assert!(TrackingRules::parse("exclude tag=enemy sync=full").is_err());
+        assert!(TrackingRules::parse("include tag=").is_err());
+    }
+}
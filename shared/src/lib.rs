@@ -4,6 +4,7 @@ pub mod message_socket;
 
 pub mod basic_types;
 pub mod des;
+pub mod options;
 
 pub use basic_types::*;
 
@@ -37,6 +38,8 @@ pub enum NoitaInbound {
         my_peer_id: PeerId,
     },
     ProxyToDes(des::ProxyToDes),
+    /// Sent once on connection, before `Ready`.
+    Options(options::GameOptions),
     RemoteMessage {
         source: PeerId,
         message: RemoteMessage,
@@ -52,6 +55,49 @@ pub enum NoitaOutbound {
         destination: Destination<PeerId>,
         message: RemoteMessage,
     },
+    ProfileSummary(ProfileSummary),
+    AddrReport(AddrReport),
+}
+
+/// Game addresses ewext looked up on world init, and how they were found.
+#[derive(Encode, Decode, Clone, Default, Debug)]
+pub struct AddrReport {
+    pub symbols: Vec<SymbolAddr>,
+}
+
+#[derive(Encode, Decode, Clone, Debug)]
+pub struct SymbolAddr {
+    pub name: String,
+    /// None if no candidate worked.
+    pub addr: Option<u32>,
+    /// Which candidate was used, or why every candidate failed.
+    pub note: String,
+}
+
+impl AddrReport {
+    /// Symbols that weren't found, likely because of an unsupported game build.
+    pub fn missing(&self) -> impl Iterator<Item = &SymbolAddr> {
+        self.symbols.iter().filter(|symbol| symbol.addr.is_none())
+    }
+}
+
+/// Time spent in ewext over the last few frames, sent to the proxy periodically.
+#[derive(Encode, Decode, Clone, Default, Debug)]
+pub struct ProfileSummary {
+    /// Number of frames the summary covers.
+    pub frames: u32,
+    /// Time between world updates, includes everything the game and lua do in a frame.
+    pub frame: SpanStats,
+    /// Named spans measured in ewext. Spans can be nested, e.g. modules run inside `module_on_world_update`.
+    pub spans: Vec<(String, SpanStats)>,
+}
+
+#[derive(Encode, Decode, Clone, Copy, Default, Debug, PartialEq)]
+pub struct SpanStats {
+    /// Average time per frame, in microseconds.
+    pub avg_us: u32,
+    /// Time in the worst frame, in microseconds.
+    pub max_us: u32,
 }
 use strum::{EnumString, IntoStaticStr};
 
@@ -0,0 +1,134 @@
+//! Game options the proxy passes to the mod on connection.
+
+use bitcode::{Decode, Encode};
+
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub enum OptionValue {
+    Bool(bool),
+    Num(f64),
+    Str(String),
+}
+
+impl From<bool> for OptionValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<u32> for OptionValue {
+    fn from(value: u32) -> Self {
+        Self::Num(value.into())
+    }
+}
+
+impl From<f32> for OptionValue {
+    fn from(value: f32) -> Self {
+        Self::Num(value.into())
+    }
+}
+
+impl From<&str> for OptionValue {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_owned())
+    }
+}
+
+impl From<String> for OptionValue {
+    fn from(value: String) -> Self {
+        Self::Str(value)
+    }
+}
+
+/// Every option the mod knows about. The proxy always sends all of them, with defaults taken from its `DefaultSettings`.
+pub const KNOWN_OPTIONS: &[&str] = &[
+    "name",
+    "world_num",
+    "friendly_fire",
+    "share_gold",
+    "debug",
+    "item_dedup",
+    "randomize_perks",
+    "enemy_hp_scale",
+    "game_mode",
+    "perma_death",
+    "no_notplayer",
+    "health_per_player",
+    "global_hp_loss",
+    "physics_damage",
+    "perk_ban_list",
+    "no_material_damage",
+    "health_lost_on_revive",
+    "mina_color",
+    "mina_color_alt",
+    "progress",
+];
+
+/// Options with these prefixes are known as well, e.g. `ewext_module_entity_sync`.
+pub const KNOWN_PREFIXES: &[&str] = &["ewext_module_"];
+
+/// Options sent by the proxy.
+#[derive(Encode, Decode, Clone, Default, Debug, PartialEq)]
+pub struct GameOptions {
+    values: Vec<(String, OptionValue)>,
+}
+
+impl GameOptions {
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<OptionValue>) {
+        let key = key.into();
+        let value = value.into();
+        match self.values.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, old)) => *old = value,
+            None => self.values.push((key, value)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<OptionValue> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.clone())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            OptionValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn values(&self) -> &[(String, OptionValue)] {
+        &self.values
+    }
+
+    /// Options that were sent but the mod doesn't know about, e.g. when proxy is newer than the mod.
+    pub fn unknown_keys(&self) -> impl Iterator<Item = &str> {
+        self.values.iter().map(|(k, _)| k.as_str()).filter(|key| {
+            !KNOWN_OPTIONS.contains(key)
+                && !KNOWN_PREFIXES.iter().any(|prefix| key.starts_with(prefix))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unknown_keys() {
+        let mut options = GameOptions::default();
+        options.set("item_dedup", true);
+        options.set("item_dedup", false);
+        options.set("ewext_module_entity_sync", false);
+        options.set("from_the_future", 3u32);
+        let decoded: GameOptions = bitcode::decode(&bitcode::encode(&options)).unwrap();
+
+        assert_eq!(decoded.get_bool("item_dedup"), Some(false));
+        assert_eq!(decoded.get_bool("ewext_module_entity_sync"), Some(false));
+        assert_eq!(decoded.get("missing"), None);
+        assert_eq!(
+            decoded.unknown_keys().collect::<Vec<_>>(),
+            ["from_the_future"]
+        );
+        assert_eq!(decoded.values().len(), 3, "item_dedup isn't repeated");
+    }
+}